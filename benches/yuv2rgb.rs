@@ -0,0 +1,88 @@
+//! Benchmark of YUV to RGBA8 conversion of the synthetic 4:2:0 frame with
+//! every instruction set supported by the CPU, single-threaded. Run it with
+//! `cargo bench`, bit-exactness of the vectorized code is checked by the
+//! tests of `simd` module.
+
+// Modules are shared with the binary, most of them (tests included) are
+// unused here.
+#![allow(dead_code, unused_imports)]
+
+use std::time::Instant;
+
+#[path = "../src/simd.rs"]
+mod simd;
+#[path = "../src/colorspace.rs"]
+mod colorspace;
+
+use colorspace::{Matrix, Converter};
+use simd::{Level, Sample};
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+/// Conversions of the frame per instruction set.
+const ITERATIONS: usize = 20;
+
+/// Pseudo-random samples of the given bit depth.
+fn get_samples(count: usize, bit_depth: u32, seed: u32) -> Vec<u16> {
+    let mut state = seed;
+    (0..count).map(|_| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        ((state >> 8) & ((1 << bit_depth) - 1)) as u16
+    }).collect()
+}
+
+fn get_seconds(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9
+}
+
+/// Convert the frame several times and return average time in seconds
+/// together with the checksum of the output, the latter also keeps
+/// conversion from being optimized out.
+fn measure<T: Sample>(conv: &Converter, y: &[T], u: &[T], v: &[T]) -> (f64, u32) {
+    let c_width = WIDTH / 2;
+    let mut out = vec![0; WIDTH];
+    let mut checksum = 0u32;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for i in 0..HEIGHT {
+            let c_row = i / 2 * c_width;
+            conv.convert_row(&y[i * WIDTH..(i + 1) * WIDTH],
+                             &u[c_row..c_row + c_width], &v[c_row..c_row + c_width],
+                             1, &mut out);
+            checksum = checksum.rotate_left(1) ^ out[i % WIDTH];
+        }
+    }
+    (get_seconds(start) / ITERATIONS as f64, checksum)
+}
+
+fn run<T: Sample>(bit_depth: u32, y: &[T], u: &[T], v: &[T]) {
+    let mut conv = Converter::new(Matrix::Bt709, false, bit_depth);
+    let mut reference = None;
+    for level in Level::available() {
+        conv.level = level;
+        let (seconds, checksum) = measure(&conv, y, u, v);
+        let (scalar_seconds, scalar_checksum) = *reference.get_or_insert((seconds, checksum));
+        assert!(checksum == scalar_checksum, "{} output differs from scalar", level);
+        println!("{:>6}, {:>2}-bit: {:8.3} ms/frame, {:5.1}x",
+                 level, bit_depth, seconds * 1000.0, scalar_seconds / seconds);
+    }
+}
+
+fn main() {
+    let c_count = WIDTH / 2 * HEIGHT / 2;
+    println!("{}x{} 4:2:0, {} iterations", WIDTH, HEIGHT, ITERATIONS);
+    for &bit_depth in &[8, 10] {
+        let y = get_samples(WIDTH * HEIGHT, bit_depth, 1);
+        let u = get_samples(c_count, bit_depth, 2);
+        let v = get_samples(c_count, bit_depth, 3);
+        if bit_depth == 8 {
+            let narrow = |samples: &[u16]| -> Vec<u8> {
+                samples.iter().map(|&s| s as u8).collect()
+            };
+            run(bit_depth, &narrow(&y), &narrow(&u), &narrow(&v));
+        } else {
+            run(bit_depth, &y, &u, &v);
+        }
+    }
+}
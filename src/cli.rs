@@ -0,0 +1,505 @@
+//! Command line interface: subcommands, their options and the code shared
+//! by the headless ones.
+
+use std::fmt;
+use std::ops;
+use ::common;
+use ::demux::{self, Demuxer, Format};
+use ::export;
+use ::hdr;
+use ::superframe;
+use ::vpx;
+use ::vp9;
+use ::worker;
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    /// Option doesn't apply to the command.
+    WrongCommand(String, Command),
+    MissingOption(&'static str, Command),
+    ConflictingOptions(&'static str, &'static str),
+    UnexpectedArgument(String),
+    MissingInput,
+    /// Range doesn't include any frame of the file.
+    EmptyRange(Range, usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownOption(ref option) => write!(f, "Unknown option {}", option),
+            Error::MissingValue(ref option) => write!(f, "Option {} requires a value", option),
+            Error::InvalidValue(ref option, ref value) => {
+                write!(f, "Invalid value of {}: {}", option, value)
+            },
+            Error::WrongCommand(ref option, command) => {
+                write!(f, "Option {} doesn't apply to {} command", option, command)
+            },
+            Error::MissingOption(option, command) => {
+                write!(f, "Option {} is required by {} command", option, command)
+            },
+            Error::ConflictingOptions(first, second) => {
+                write!(f, "Options {} and {} can't be used together", first, second)
+            },
+            Error::UnexpectedArgument(ref arg) => write!(f, "Unexpected argument {}", arg),
+            Error::MissingInput => f.write_str("No input file given"),
+            Error::EmptyRange(range, count) => {
+                write!(f, "Frame range {} is outside of the file with {} frames", range, count)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    View,
+    Info,
+    Frames,
+    Export,
+    Hash,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "view" => Some(Command::View),
+            "info" => Some(Command::Info),
+            "frames" => Some(Command::Frames),
+            "export" => Some(Command::Export),
+            "hash" => Some(Command::Hash),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Command::View => "view",
+            Command::Info => "info",
+            Command::Frames => "frames",
+            Command::Export => "export",
+            Command::Hash => "hash",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Inclusive range of container frames, 0-based.
+#[derive(Debug, Clone, Copy)]
+pub struct Range {
+    pub first: usize,
+    /// Up to the end of file if not set.
+    pub last: Option<usize>,
+}
+
+impl Range {
+    pub fn all() -> Range {
+        Range {first: 0, last: None}
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.first == 0 && self.last.is_none()
+    }
+
+    /// Parse 1-based "first:last" where either bound can be omitted, single
+    /// number selects one frame.
+    fn parse(value: &str) -> Option<Range> {
+        let parse_bound = |bound: &str| -> Option<Option<usize>> {
+            if bound.is_empty() {
+                return Some(None);
+            }
+            match bound.parse::<usize>() {
+                Ok(number) if number > 0 => Some(Some(number - 1)),
+                _ => None,
+            }
+        };
+        let bounds = match value.find(':') {
+            Some(i) => parse_bound(&value[..i]).and_then(|first| {
+                parse_bound(&value[i + 1..]).map(|last| (first, last))
+            }),
+            None => match parse_bound(value) {
+                Some(Some(pos)) => Some((Some(pos), Some(pos))),
+                _ => None,
+            },
+        };
+        let (first, last) = match bounds {
+            Some(bounds) => bounds,
+            None => return None,
+        };
+        let first = first.unwrap_or(0);
+        match last {
+            Some(last) if last < first => None,
+            _ => Some(Range {first: first, last: last}),
+        }
+    }
+
+    /// Positions of the range frames in file with the given number of
+    /// frames.
+    pub fn positions(&self, count: usize) -> ops::Range<usize> {
+        let end = self.last.map_or(count, |last| if last < count { last + 1 } else { count });
+        self.first..if end > self.first { end } else { self.first }
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.last {
+            Some(last) => write!(f, "{}:{}", self.first + 1, last + 1),
+            None => write!(f, "{}:", self.first + 1),
+        }
+    }
+}
+
+pub struct Options {
+    pub command: Command,
+    pub filename: String,
+    /// Container format given by user, detected if not set.
+    pub format: Option<Format>,
+    /// Separate init segment of the DASH media segment input.
+    pub init_segment: Option<String>,
+    /// Decoder threads, 0 leaves the libvpx default.
+    pub threads: usize,
+    pub range: Range,
+    /// Output file of the export.
+    pub output: Option<String>,
+    /// Export format given by user, guessed by the output name if not set.
+    pub export_format: Option<export::Format>,
+    /// MD5 file to check the decoded frames against.
+    pub verify: Option<String>,
+    /// Print report as JSON, or JSON Lines for the per-frame one.
+    pub json: bool,
+    pub worker: worker::Config,
+    /// Peak luminance of HDR content used by tone mapping.
+    pub peak_nits: f32,
+}
+
+impl Options {
+    /// Open input file with all frames indexed.
+    pub fn open_reader(&self) -> Result<Box<Demuxer>, common::Error> {
+        let mut reader = try!(self.open_lazy_reader());
+        try!(reader.index_all());
+        Ok(reader)
+    }
+
+    /// Open input file with the demuxer of the given or detected format.
+    /// Frames may be indexed later, as they are requested.
+    pub fn open_lazy_reader(&self) -> Result<Box<Demuxer>, common::Error> {
+        if let Some(ref init) = self.init_segment {
+            return demux::open_segment(&self.filename, init);
+        }
+        match self.format {
+            Some(format) => demux::open_as(&self.filename, format),
+            None => demux::open(&self.filename),
+        }
+    }
+
+    pub fn init_decoder(&self, reader: &Demuxer) -> Result<vpx::Decoder, common::Error> {
+        Ok(try!(vpx::Decoder::init_with_threads(reader.get_fourcc(), self.threads)))
+    }
+}
+
+fn invalid(option: &str, value: &str) -> Error {
+    Error::InvalidValue(option.to_string(), value.to_string())
+}
+
+fn parse_number(option: &str, value: &str) -> Result<usize, Error> {
+    value.parse().map_err(|_| invalid(option, value))
+}
+
+fn parse_nits(option: &str, value: &str) -> Result<f32, Error> {
+    match value.parse::<f32>() {
+        Ok(nits) if nits.is_finite() && nits > 0.0 => Ok(nits),
+        _ => Err(invalid(option, value)),
+    }
+}
+
+/// Parse the command line. First argument is the program name. Command
+/// can be omitted, frames are viewed then.
+pub fn parse(args: &[String]) -> Result<Options, Error> {
+    let mut command = None;
+    let mut filename = None;
+    let mut format = None;
+    let mut init_segment = None;
+    let mut threads = 0;
+    let mut range = Range::all();
+    let mut output = None;
+    let mut export_format = None;
+    let mut verify = None;
+    let mut json = false;
+    let mut worker_config = worker::Config::default();
+    let mut peak_nits = hdr::DEFAULT_SOURCE_NITS;
+    // Options which apply only to some commands, checked once command is
+    // known.
+    let mut specific: Vec<(&str, &[Command])> = Vec::new();
+    let mut iter = args.iter().skip(1).map(|arg| &arg[..]);
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if command.is_none() && filename.is_none() {
+                if let Some(cmd) = Command::from_name(arg) {
+                    command = Some(cmd);
+                    continue;
+                }
+            }
+            if filename.is_some() {
+                return Err(Error::UnexpectedArgument(arg.to_string()));
+            }
+            filename = Some(arg.to_string());
+            continue;
+        }
+        let mut next_value = || iter.next().ok_or_else(|| Error::MissingValue(arg.to_string()));
+        match arg {
+            "--input-format" => {
+                let value = try!(next_value());
+                format = Some(try!(Format::from_name(value).ok_or_else(|| invalid(arg, value))));
+            },
+            "--init-segment" => init_segment = Some(try!(next_value()).to_string()),
+            "--threads" => threads = try!(parse_number(arg, try!(next_value()))),
+            "--range" => {
+                let value = try!(next_value());
+                range = try!(Range::parse(value).ok_or_else(|| invalid(arg, value)));
+            },
+            "--cache-size" => {
+                worker_config.cache_size = try!(parse_number(arg, try!(next_value()))) << 20;
+                specific.push((arg, &[Command::View]));
+            },
+            "--prefetch" => {
+                worker_config.prefetch = try!(parse_number(arg, try!(next_value())));
+                specific.push((arg, &[Command::View]));
+            },
+            "--peak-nits" => {
+                peak_nits = try!(parse_nits(arg, try!(next_value())));
+                specific.push((arg, &[Command::View]));
+            },
+            "--json" => {
+                json = true;
+                specific.push((arg, &[Command::Info, Command::Frames]));
+            },
+            "-o" | "--output" => {
+                output = Some(try!(next_value()).to_string());
+                specific.push((arg, &[Command::Export]));
+            },
+            "--to" => {
+                let value = try!(next_value());
+                export_format = Some(try!(export::Format::from_name(value)
+                                              .ok_or_else(|| invalid(arg, value))));
+                specific.push((arg, &[Command::Export]));
+            },
+            "--verify" => {
+                verify = Some(try!(next_value()).to_string());
+                specific.push((arg, &[Command::Hash]));
+            },
+            _ => return Err(Error::UnknownOption(arg.to_string())),
+        }
+    }
+    let command = command.unwrap_or(Command::View);
+    for &(option, commands) in &specific {
+        if !commands.contains(&command) {
+            return Err(Error::WrongCommand(option.to_string(), command));
+        }
+    }
+    if init_segment.is_some() && format.map_or(false, |format| format != Format::Mp4) {
+        return Err(Error::ConflictingOptions("--init-segment", "--input-format"));
+    }
+    if command == Command::Export && output.is_none() {
+        return Err(Error::MissingOption("--output", command));
+    }
+    Ok(Options {
+        command: command,
+        filename: try!(filename.ok_or(Error::MissingInput)),
+        format: format,
+        init_segment: init_segment,
+        threads: threads,
+        range: range,
+        output: output,
+        export_format: export_format,
+        verify: verify,
+        json: json,
+        worker: worker_config,
+        peak_nits: peak_nits,
+    })
+}
+
+pub fn print_usage(program: &str) {
+    let config = worker::Config::default();
+    printerr!("Usage: {} [command] [options] file.ivf|file.webm|file.mp4", program);
+    printerr!("Commands:");
+    printerr!("  view    show frames in the window (default)");
+    printerr!("  info    decode the file and print stream summary");
+    printerr!("  frames  decode the file and print metadata of every frame");
+    printerr!("  export  write decoded frames as PNG, Y4M or raw YUV");
+    printerr!("  hash    print MD5 of every decoded frame in libvpx .md5 format");
+    printerr!("Options:");
+    printerr!("  --input-format <ivf|webm|mp4>  container format (default: detected)");
+    printerr!("  --init-segment <file>          DASH init segment of the media segment input");
+    printerr!("  --threads <N>                  decoder threads (default: libvpx default)");
+    printerr!("  --range <first:last>           frames to process, numbered from 1, either");
+    printerr!("                                 bound can be omitted (default: all)");
+    printerr!("View options:");
+    printerr!("  --cache-size <MiB>  memory budget of decoded frames cache (default: {})",
+              config.cache_size >> 20);
+    printerr!("  --prefetch <N>      frames to decode ahead of the shown one (default: {})",
+              config.prefetch);
+    printerr!("  --peak-nits <N>     peak luminance of HDR content for tone mapping (default: {})",
+              hdr::DEFAULT_SOURCE_NITS);
+    printerr!("Info and frames options:");
+    printerr!("  --json  print summary as JSON, frames as JSON Lines instead of CSV");
+    printerr!("Export options:");
+    printerr!("  -o, --output <file>  output file, - for stdout; name of the several PNG images");
+    printerr!("                       should contain frame number pattern, e.g. frame%04d.png");
+    printerr!("  --to <png|y4m|yuv>   output format (default: by the file extension)");
+    printerr!("Hash options:");
+    printerr!("  --verify <file.md5>  check frames against libvpx .md5 file and report the");
+    printerr!("                       first mismatch");
+}
+
+/// Position of the keyframe decoding should start from to get the frame at
+/// the given position.
+pub fn find_keyframe(reader: &mut Demuxer, decoder: &vpx::Decoder,
+                     pos: usize) -> Result<usize, common::Error> {
+    for start in (0..pos + 1).rev() {
+        let keyframe = match reader.is_keyframe(start) {
+            Some(keyframe) => keyframe,
+            None => {
+                let data = try!(reader.read_frame(start));
+                !data.is_empty() && try!(decoder.is_keyframe(&data))
+            },
+        };
+        if keyframe {
+            return Ok(start);
+        }
+    }
+    Ok(0)
+}
+
+/// Decode frames of the range in order, starting from the preceding
+/// keyframe, and pass every output image to the callback together with
+/// the last VP9 frame header. Hidden frames don't produce images. Returns
+/// the number of images.
+pub fn decode_range<F>(reader: &mut Demuxer, decoder: &mut vpx::Decoder, range: Range,
+                       mut f: F) -> Result<usize, common::Error>
+    where F: FnMut(usize, &vpx::Image, Option<&vp9::UncompressedHeader>)
+                   -> Result<(), common::Error>
+{
+    let count = reader.get_frame_count();
+    let positions = range.positions(count);
+    if positions.start >= positions.end {
+        return Err(From::from(Error::EmptyRange(range, count)));
+    }
+    let start = try!(find_keyframe(reader, decoder, positions.start));
+    let mut headers = if reader.get_fourcc() == common::VP9_FOURCC {
+        Some(vp9::HeaderParser::new())
+    } else {
+        None
+    };
+    let mut images = 0;
+    for pos in start..positions.end {
+        let data = try!(reader.read_frame(pos));
+        if data.is_empty() {
+            continue;
+        }
+        if let Some(ref mut headers) = headers {
+            for subframe in superframe::split(&data) {
+                // Broken frames are reported by decoder.
+                let _ = headers.parse(&data[subframe.offset..subframe.offset + subframe.size]);
+            }
+        }
+        let hdr = headers.as_ref().and_then(|headers| headers.get_last());
+        for image in try!(decoder.decode(&data)) {
+            if pos >= positions.start {
+                try!(f(pos, &image, hdr));
+                images += 1;
+            }
+        }
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Options, Error> {
+        let mut all = vec!["vpxview".to_string()];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        parse(&all)
+    }
+
+    fn parse_range(value: &str) -> Option<(usize, Option<usize>)> {
+        Range::parse(value).map(|range| (range.first, range.last))
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("5"), Some((4, Some(4))));
+        assert_eq!(parse_range(":10"), Some((0, Some(9))));
+        assert_eq!(parse_range("3:"), Some((2, None)));
+        assert_eq!(parse_range("2:7"), Some((1, Some(6))));
+        assert_eq!(parse_range(""), None);
+        assert_eq!(parse_range("0"), None);
+        assert_eq!(parse_range("0:3"), None);
+        assert_eq!(parse_range("5:3"), None);
+        assert_eq!(parse_range("x"), None);
+        assert_eq!(Range::parse("3:").unwrap().to_string(), "3:");
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(Range::all().positions(5), 0..5);
+        assert_eq!(Range {first: 2, last: Some(3)}.positions(5), 2..4);
+        // Cut by the end of file.
+        assert_eq!(Range {first: 2, last: Some(9)}.positions(5), 2..5);
+        assert_eq!(Range {first: 4, last: None}.positions(5), 4..5);
+        // Nothing left.
+        assert_eq!(Range {first: 7, last: Some(9)}.positions(5), 7..7);
+        assert_eq!(Range {first: 0, last: None}.positions(0), 0..0);
+    }
+
+    #[test]
+    fn options() {
+        let opts = parse_args(&["frames", "--range", "2:4", "--json", "file.webm"]).unwrap();
+        assert_eq!(opts.command, Command::Frames);
+        assert_eq!(opts.filename, "file.webm");
+        assert_eq!((opts.range.first, opts.range.last), (1, Some(3)));
+        assert!(opts.json);
+        let opts = parse_args(&["file.webm", "--threads", "4"]).unwrap();
+        assert_eq!(opts.command, Command::View);
+        assert_eq!(opts.threads, 4);
+    }
+
+    #[test]
+    fn errors() {
+        match parse_args(&["export", "--json", "-o", "out.y4m", "file.webm"]) {
+            Err(Error::WrongCommand(ref option, Command::Export)) if option == "--json" => {},
+            _ => panic!("--json is accepted by export"),
+        }
+        match parse_args(&["export", "file.webm"]) {
+            Err(Error::MissingOption("--output", Command::Export)) => {},
+            _ => panic!("export without output is accepted"),
+        }
+        match parse_args(&["--init-segment", "init.mp4", "--input-format", "webm", "seg.m4s"]) {
+            Err(Error::ConflictingOptions("--init-segment", "--input-format")) => {},
+            _ => panic!("init segment of WebM is accepted"),
+        }
+        match parse_args(&["info", "a.webm", "b.webm"]) {
+            Err(Error::UnexpectedArgument(ref arg)) if arg == "b.webm" => {},
+            _ => panic!("second input is accepted"),
+        }
+        match parse_args(&["info", "--range", "0", "a.webm"]) {
+            Err(Error::InvalidValue(ref option, ref value)) if option == "--range" &&
+                                                               value == "0" => {},
+            _ => panic!("invalid range is accepted"),
+        }
+        match parse_args(&["info", "--threads"]) {
+            Err(Error::MissingValue(ref option)) if option == "--threads" => {},
+            _ => panic!("option without value is accepted"),
+        }
+        match parse_args(&["info"]) {
+            Err(Error::MissingInput) => {},
+            _ => panic!("no input is accepted"),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! YUV to RGB conversion for the different matrices and ranges.
+
+use std::fmt;
+use ::simd::{self, Level, Params, Sample};
+
+/// Fixed point precision of the conversion coefficients.
+pub const PRECISION: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Matrix {
+    /// Also used for SMPTE-170.
+    Bt601,
+    Bt709,
+    Smpte240,
+    /// Non-constant luminance variant.
+    Bt2020,
+    /// Planes hold G, B and R components (sRGB in VP9 terms).
+    Identity,
+}
+
+impl Matrix {
+    /// Matrix for the stream which doesn't specify one, the same heuristic
+    /// as mpv uses: HD content is likely BT.709.
+    pub fn guess(width: u16, height: u16) -> Matrix {
+        if width >= 1280 || height > 576 {
+            Matrix::Bt709
+        } else {
+            Matrix::Bt601
+        }
+    }
+
+    /// Next matrix in the cycle of user overrides.
+    pub fn next(&self) -> Matrix {
+        match *self {
+            Matrix::Bt601 => Matrix::Bt709,
+            Matrix::Bt709 => Matrix::Smpte240,
+            Matrix::Smpte240 => Matrix::Bt2020,
+            Matrix::Bt2020 => Matrix::Identity,
+            Matrix::Identity => Matrix::Bt601,
+        }
+    }
+
+    /// Red and blue luma coefficients.
+    fn coefficients(&self) -> (f64, f64) {
+        match *self {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722),
+            Matrix::Smpte240 => (0.212, 0.087),
+            Matrix::Bt2020 => (0.2627, 0.0593),
+            Matrix::Identity => (0.0, 0.0),
+        }
+    }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Matrix::Bt601 => "BT.601",
+            Matrix::Bt709 => "BT.709",
+            Matrix::Smpte240 => "SMPTE-240",
+            Matrix::Bt2020 => "BT.2020",
+            Matrix::Identity => "sRGB (GBR)",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Conversion of the samples of given bit depth to RGBA8.
+#[derive(Debug, Clone, Copy)]
+pub struct Converter {
+    pub matrix: Matrix,
+    pub full_range: bool,
+    pub bit_depth: u32,
+    /// Instruction set used for the row conversion.
+    pub level: Level,
+    y_offset: i32,
+    c_offset: i32,
+    /// Luma scale, also used for all planes of the identity matrix.
+    y_mul: i32,
+    /// Chroma contributions: V to R, U to G, V to G and U to B.
+    vr: i32,
+    ug: i32,
+    vg: i32,
+    ub: i32,
+}
+
+fn fixed(val: f64) -> i32 {
+    (val * (1 << PRECISION) as f64).round() as i32
+}
+
+#[inline]
+fn clamp0(val: i32) -> i32 {
+    (-val >> 31) & val
+}
+
+#[inline]
+fn clamp255(val: i32) -> i32 {
+    (((255 - val) >> 31) | val) & 255
+}
+
+// Branchless min/max should be faster than 2 ifs. See `YuvPixel` from
+// libyuv for details.
+#[inline]
+fn clamp(val: i32) -> u32 {
+    clamp255(clamp0(val)) as u32
+}
+
+/// Pack 8-bit components into the fully opaque RGBA8 pixel. R goes to the
+/// lowest byte and pixels are serialized little-endian (see
+/// `vpx::pixels_to_bytes`) so the output bytes are R, G, B and A on any
+/// architecture.
+#[inline]
+pub const fn pack_rgba(r: u32, g: u32, b: u32) -> u32 {
+    255 << 24 | b << 16 | g << 8 | r
+}
+
+impl Converter {
+    pub fn new(matrix: Matrix, full_range: bool, bit_depth: u32) -> Converter {
+        let depth_scale = (1 << (bit_depth - 8)) as f64;
+        let (y_offset, y_range, c_range) = if full_range {
+            let max = ((1 << bit_depth) - 1) as f64;
+            (0, max, max)
+        } else {
+            (16 << (bit_depth - 8), 219.0 * depth_scale, 224.0 * depth_scale)
+        };
+        let (kr, kb) = matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let (vr, ug, vg, ub) = if matrix == Matrix::Identity {
+            (0.0, 0.0, 0.0, 0.0)
+        } else {
+            (2.0 * (1.0 - kr),
+             2.0 * (1.0 - kb) * kb / kg,
+             2.0 * (1.0 - kr) * kr / kg,
+             2.0 * (1.0 - kb))
+        };
+        let c_mul = 255.0 / c_range;
+        Converter {
+            matrix: matrix,
+            full_range: full_range,
+            bit_depth: bit_depth,
+            level: Level::detect(),
+            y_offset: y_offset,
+            c_offset: 128 << (bit_depth - 8),
+            y_mul: fixed(255.0 / y_range),
+            vr: fixed(vr * c_mul),
+            ug: fixed(ug * c_mul),
+            vg: fixed(vg * c_mul),
+            ub: fixed(ub * c_mul),
+        }
+    }
+
+    /// Convert pixel to RGBA8 (full opacity), packed by `pack_rgba`.
+    #[inline]
+    pub fn convert(&self, y: u16, u: u16, v: u16) -> u32 {
+        let round = 1 << (PRECISION - 1);
+        let (r, g, b) = if self.matrix == Matrix::Identity {
+            let scale = |val: u16| ((val as i32 - self.y_offset) * self.y_mul + round) >>
+                                   PRECISION;
+            (scale(v), scale(y), scale(u))
+        } else {
+            let y1 = (y as i32 - self.y_offset) * self.y_mul + round;
+            let d = u as i32 - self.c_offset;
+            let e = v as i32 - self.c_offset;
+            ((y1 + self.vr * e) >> PRECISION,
+             (y1 - self.ug * d - self.vg * e) >> PRECISION,
+             (y1 + self.ub * d) >> PRECISION)
+        };
+        pack_rgba(clamp(r), clamp(g), clamp(b))
+    }
+
+    fn get_params(&self) -> Params {
+        Params {
+            y_offset: self.y_offset,
+            c_offset: self.c_offset,
+            y_mul: self.y_mul,
+            vr: self.vr,
+            ug: self.ug,
+            vg: self.vg,
+            ub: self.ub,
+        }
+    }
+
+    /// Convert the image row, chroma rows are horizontally subsampled by
+    /// `x_shift`.
+    pub fn convert_row<T: Sample>(&self, y: &[T], u: &[T], v: &[T], x_shift: usize,
+                                  out: &mut [u32]) {
+        // TODO(Kagami): Vectorize identity matrix too, it's rare though.
+        let start = if self.matrix == Matrix::Identity {
+            0
+        } else {
+            simd::convert_row(self.level, &self.get_params(), y, u, v, x_shift, out)
+        };
+        for j in start..out.len() {
+            out[j] = self.convert(y[j].widen(), u[j >> x_shift].widen(), v[j >> x_shift].widen());
+        }
+    }
+
+    /// Convert pixel to nonlinear RGB, 1.0 is the white level. Values are
+    /// not clamped.
+    pub fn convert_f32(&self, y: u16, u: u16, v: u16) -> [f32; 3] {
+        let norm = 1.0 / (255 << PRECISION) as f32;
+        if self.matrix == Matrix::Identity {
+            let scale = |val: u16| ((val as i32 - self.y_offset) * self.y_mul) as f32 * norm;
+            return [scale(v), scale(y), scale(u)];
+        }
+        let y1 = (y as i32 - self.y_offset) * self.y_mul;
+        let d = u as i32 - self.c_offset;
+        let e = v as i32 - self.c_offset;
+        [(y1 + self.vr * e) as f32 * norm,
+         (y1 - self.ug * d - self.vg * e) as f32 * norm,
+         (y1 + self.ub * d) as f32 * norm]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATRICES: [Matrix; 4] = [Matrix::Bt601, Matrix::Bt709, Matrix::Smpte240,
+                                   Matrix::Bt2020];
+    const BLACK: u32 = 0xff000000;
+    const WHITE: u32 = 0xffffffff;
+
+    #[test]
+    fn black_and_white() {
+        for &matrix in &MATRICES {
+            let conv = Converter::new(matrix, false, 8);
+            assert_eq!(conv.convert(16, 128, 128), BLACK, "{}", matrix);
+            assert_eq!(conv.convert(235, 128, 128), WHITE, "{}", matrix);
+            let conv = Converter::new(matrix, true, 8);
+            assert_eq!(conv.convert(0, 128, 128), BLACK, "{}", matrix);
+            assert_eq!(conv.convert(255, 128, 128), WHITE, "{}", matrix);
+            let conv = Converter::new(matrix, false, 10);
+            assert_eq!(conv.convert(64, 512, 512), BLACK, "{}", matrix);
+            assert_eq!(conv.convert(940, 512, 512), WHITE, "{}", matrix);
+            let conv = Converter::new(matrix, true, 10);
+            assert_eq!(conv.convert(1023, 512, 512), WHITE, "{}", matrix);
+        }
+    }
+
+    #[test]
+    fn colors() {
+        // Rounded results of the floating point conversion.
+        let expected = [(172, 106, 65), (178, 110, 62), (179, 111, 63), (175, 106, 61)];
+        for (&matrix, &(r, g, b)) in MATRICES.iter().zip(expected.iter()) {
+            let conv = Converter::new(matrix, false, 8);
+            assert_eq!(conv.convert(120, 100, 160), pack_rgba(r, g, b), "{}", matrix);
+            // The same color in 10 bits.
+            let conv = Converter::new(matrix, false, 10);
+            assert_eq!(conv.convert(480, 400, 640), pack_rgba(r, g, b), "{}", matrix);
+        }
+    }
+
+    #[test]
+    fn identity() {
+        // Planes are G, B and R.
+        let conv = Converter::new(Matrix::Identity, true, 8);
+        assert_eq!(conv.convert(10, 20, 30), pack_rgba(30, 10, 20));
+        let conv = Converter::new(Matrix::Identity, false, 8);
+        assert_eq!(conv.convert(235, 16, 16), pack_rgba(0, 255, 0));
+        let rgb = conv.convert_f32(235, 16, 16);
+        assert!(rgb[0] == 0.0 && (rgb[1] - 1.0).abs() < 1e-5 && rgb[2] == 0.0);
+    }
+
+    #[test]
+    fn guess() {
+        assert_eq!(Matrix::guess(720, 576), Matrix::Bt601);
+        assert_eq!(Matrix::guess(1024, 576), Matrix::Bt601);
+        assert_eq!(Matrix::guess(1024, 578), Matrix::Bt709);
+        assert_eq!(Matrix::guess(1280, 528), Matrix::Bt709);
+        assert_eq!(Matrix::guess(1920, 1080), Matrix::Bt709);
+    }
+}
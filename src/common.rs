@@ -1,36 +1,77 @@
 //! Common routines.
 
 use std::fmt;
+use std::io;
+use ::cli;
+use ::demux;
+use ::export;
+use ::hash;
 use ::ivf;
+use ::webm;
+use ::mp4;
 use ::gui;
 use ::vpx;
+use ::vp9;
 
 /// Universal error type across all submodules.
 #[derive(Debug)]
 pub enum Error {
+    DemuxError(demux::Error),
     IvfError(ivf::Error),
+    WebmError(webm::Error),
+    Mp4Error(mp4::Error),
     GuiError(gui::Error),
     VpxError(vpx::Error),
+    Vp9Error(vp9::Error),
+    CliError(cli::Error),
+    ExportError(export::Error),
+    HashError(hash::Error),
+    /// Report can't be written to stdout.
+    OutputError(io::Error),
 }
 
 // Boilerplate :/
 // At first we need to wrap error into common error type to make the `try!`
 // work, then we need to wrap it out before displaying.
+impl From<demux::Error> for Error { fn from(e: demux::Error) -> Error { Error::DemuxError(e) } }
 impl From<ivf::Error> for Error { fn from(e: ivf::Error) -> Error { Error::IvfError(e) } }
+impl From<webm::Error> for Error { fn from(e: webm::Error) -> Error { Error::WebmError(e) } }
+impl From<mp4::Error> for Error { fn from(e: mp4::Error) -> Error { Error::Mp4Error(e) } }
 impl From<gui::Error> for Error { fn from(e: gui::Error) -> Error { Error::GuiError(e) } }
 impl From<vpx::Error> for Error { fn from(e: vpx::Error) -> Error { Error::VpxError(e) } }
+impl From<vp9::Error> for Error { fn from(e: vp9::Error) -> Error { Error::Vp9Error(e) } }
+impl From<cli::Error> for Error { fn from(e: cli::Error) -> Error { Error::CliError(e) } }
+impl From<export::Error> for Error { fn from(e: export::Error) -> Error { Error::ExportError(e) } }
+impl From<hash::Error> for Error { fn from(e: hash::Error) -> Error { Error::HashError(e) } }
+impl From<io::Error> for Error { fn from(e: io::Error) -> Error { Error::OutputError(e) } }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let descr = match *self {
+            Error::DemuxError(ref err) => format!("{}", err),
             Error::IvfError(ref err) => format!("{}", err),
+            Error::WebmError(ref err) => format!("{}", err),
+            Error::Mp4Error(ref err) => format!("{}", err),
             Error::GuiError(ref err) => format!("{}", err),
             Error::VpxError(ref err) => format!("{}", err),
+            Error::Vp9Error(ref err) => format!("{}", err),
+            Error::CliError(ref err) => format!("{}", err),
+            Error::ExportError(ref err) => format!("{}", err),
+            Error::HashError(ref err) => format!("{}", err),
+            Error::OutputError(ref err) => format!("Cannot write output: {}", err),
         };
         f.write_str(&descr)
     }
 }
 
+pub const VP8_FOURCC: u32 = 0x30385056;
+pub const VP9_FOURCC: u32 = 0x30395056;
+
+/// Printable form of the FourCC code, e.g. "VP90".
+pub fn fourcc_to_string(fourcc: u32) -> String {
+    (0..4).map(|i| ((fourcc >> (i * 8)) & 0xff) as u8 as char).collect()
+}
+
 pub fn alloc<T>(size: usize) -> Box<[T]> {
     // Seems like there is no easier safe way (i.e. without losing auto memory
     // management) to allocate memory area.
@@ -39,6 +80,10 @@ pub fn alloc<T>(size: usize) -> Box<[T]> {
     buf.into_boxed_slice()
 }
 
+pub fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
 pub fn get_le32(buf: &[u8]) -> u32 {
     let mut val = (buf[3] as u32) << 24;
     val |= (buf[2] as u32) << 16;
@@ -47,12 +92,34 @@ pub fn get_le32(buf: &[u8]) -> u32 {
     val
 }
 
+pub fn get_le64(buf: &[u8]) -> u64 {
+    (get_le32(&buf[4..]) as u64) << 32 | get_le32(buf) as u64
+}
+
 pub fn get_le16(buf: &[u8]) -> u16 {
     let mut val = (buf[1] as u16) << 8;
     val |= buf[0] as u16;
     val
 }
 
+pub fn get_be64(buf: &[u8]) -> u64 {
+    (get_be32(buf) as u64) << 32 | get_be32(&buf[4..]) as u64
+}
+
+pub fn get_be32(buf: &[u8]) -> u32 {
+    let mut val = (buf[0] as u32) << 24;
+    val |= (buf[1] as u32) << 16;
+    val |= (buf[2] as u32) << 8;
+    val |= buf[3] as u32;
+    val
+}
+
+pub fn get_be16(buf: &[u8]) -> u16 {
+    let mut val = (buf[0] as u16) << 8;
+    val |= buf[1] as u16;
+    val
+}
+
 macro_rules! printerr {
     ($fmt:expr) =>
         (::std::io::Write
@@ -0,0 +1,177 @@
+//! Common interface of container readers.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek};
+use std::fs::File;
+use ::common;
+use ::ivf;
+use ::webm;
+use ::mp4;
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(io::Error),
+    UnknownFormat,
+    /// File is valid but has no frames to show.
+    NoFrames,
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::IoError(err) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descr = match *self {
+            Error::IoError(ref err) => format!("IO error: {}", err),
+            Error::UnknownFormat => format!("Unknown container format"),
+            Error::NoFrames => format!("No frames in file"),
+        };
+        f.write_str(&descr)
+    }
+}
+
+/// Location of the single frame in container file.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameEntry {
+    /// Offset of the frame data.
+    pub offset: u64,
+    pub size: u32,
+    /// Timestamp in timebase units.
+    pub pts: u64,
+    /// Whether frame is a keyframe, if container tells that.
+    pub keyframe: Option<bool>,
+}
+
+/// Seekable data of the container, the file or in-memory buffer.
+pub trait Source: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Source for T {}
+
+/// Frame source. Frames are indexed at open or, if container allows that,
+/// lazily as they are requested. Indexed frames can be fetched in any order.
+pub trait Demuxer: Send {
+    fn get_filename(&self) -> &str;
+    /// Short name of the container format.
+    fn get_format_name(&self) -> &'static str;
+    fn get_fourcc(&self) -> u32;
+    fn get_width(&self) -> u16;
+    fn get_height(&self) -> u16;
+    /// Time base as numerator/denominator pair, i.e. duration of one tick in
+    /// seconds.
+    fn get_timebase(&self) -> (u32, u32);
+    /// Number of frames as written in file header, if container has one.
+    fn get_declared_frame_count(&self) -> Option<u32> { None }
+    /// Table of the complete frames indexed so far.
+    fn get_frames(&self) -> &[FrameEntry];
+    /// Read frame data at the given position.
+    fn read_frame(&mut self, pos: usize) -> Result<Box<[u8]>, common::Error>;
+
+    /// Index frames up to the given position, as far as file has them. Does
+    /// nothing for the readers which index the whole file at open.
+    fn index_until(&mut self, _pos: usize) -> Result<(), common::Error> { Ok(()) }
+
+    /// Whether the frame table is complete.
+    fn is_indexed(&self) -> bool { true }
+
+    fn index_all(&mut self) -> Result<(), common::Error> {
+        self.index_until(usize::max_value())
+    }
+
+    fn get_frame_count(&self) -> usize {
+        self.get_frames().len()
+    }
+
+    fn get_frame_entry(&self, pos: usize) -> Option<&FrameEntry> {
+        self.get_frames().get(pos)
+    }
+
+    fn get_frame_pts(&self, pos: usize) -> Option<u64> {
+        self.get_frame_entry(pos).map(|entry| entry.pts)
+    }
+
+    /// Presentation time of the frame in seconds.
+    fn get_frame_time(&self, pos: usize) -> Option<f64> {
+        let (num, den) = self.get_timebase();
+        if den == 0 {
+            return None;
+        }
+        self.get_frame_pts(pos).map(|pts| pts as f64 * num as f64 / den as f64)
+    }
+
+    fn is_keyframe(&self, pos: usize) -> Option<bool> {
+        self.get_frame_entry(pos).and_then(|entry| entry.keyframe)
+    }
+
+    /// Container-specific codec details in human-readable form.
+    fn get_codec_details(&self) -> Vec<String> { Vec::new() }
+
+    /// Transfer characteristics code as defined by ISO/IEC 23001-8 (16 is
+    /// PQ and 18 is HLG), if container tells that.
+    fn get_transfer_characteristics(&self) -> Option<u8> { None }
+}
+
+/// Container format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ivf,
+    Webm,
+    Mp4,
+}
+
+impl Format {
+    /// Format by its short name as given on the command line.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match &name.to_lowercase()[..] {
+            "ivf" => Some(Format::Ivf),
+            "webm" | "mkv" => Some(Format::Webm),
+            "mp4" => Some(Format::Mp4),
+            _ => None,
+        }
+    }
+}
+
+/// Detect container format by the file signature.
+pub fn detect_format(filename: &str) -> Result<Format, common::Error> {
+    let mut signature = [0; 8];
+    {
+        let mut fh = try!(File::open(&filename).map_err(Error::IoError));
+        let mut collected = 0;
+        while collected < signature.len() {
+            let chunk_size = try!(fh.read(&mut signature[collected..]).map_err(Error::IoError));
+            if chunk_size == 0 {
+                return Err(From::from(Error::UnknownFormat));
+            }
+            collected += chunk_size;
+        }
+    }
+    if signature[..4] == ivf::SIGNATURE {
+        Ok(Format::Ivf)
+    } else if signature[..4] == webm::SIGNATURE {
+        Ok(Format::Webm)
+    } else if mp4::is_mp4(&signature) {
+        Ok(Format::Mp4)
+    } else {
+        Err(From::from(Error::UnknownFormat))
+    }
+}
+
+/// Open file with the demuxer of the given format.
+pub fn open_as(filename: &str, format: Format) -> Result<Box<Demuxer>, common::Error> {
+    match format {
+        Format::Ivf => Ok(Box::new(try!(ivf::Reader::open(filename)))),
+        Format::Webm => Ok(Box::new(try!(webm::Reader::open(filename)))),
+        Format::Mp4 => Ok(Box::new(try!(mp4::Reader::open(filename)))),
+    }
+}
+
+/// Open DASH media segment with the separate init segment.
+pub fn open_segment(filename: &str, init: &str) -> Result<Box<Demuxer>, common::Error> {
+    Ok(Box::new(try!(mp4::Reader::open_with_init(filename, Some(init)))))
+}
+
+/// Open file with the demuxer matching its signature.
+pub fn open(filename: &str) -> Result<Box<Demuxer>, common::Error> {
+    open_as(filename, try!(detect_format(filename)))
+}
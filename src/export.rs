@@ -0,0 +1,307 @@
+//! Export of the decoded frames as PNG images, Y4M stream or raw YUV.
+
+use std::fmt;
+use std::io;
+use std::io::{Write, BufWriter};
+use std::fs::File;
+use ::cli::{self, Options};
+use ::colorspace::{Matrix, Converter};
+use ::common;
+use ::demux::Demuxer;
+use ::png;
+use ::vpx;
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(io::Error),
+    /// Format can't be guessed by the output name.
+    UnknownFormat(String),
+    /// Name of the several PNG images has no frame number pattern.
+    MissingPattern(String),
+    /// Y4M can't hold images of different size and format.
+    ImageChanged(usize),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::IoError(err) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IoError(ref err) => write!(f, "Cannot write output: {}", err),
+            Error::UnknownFormat(ref name) => {
+                write!(f, "Cannot guess export format of {}, use --to option", name)
+            },
+            Error::MissingPattern(ref name) => {
+                write!(f, "Name of PNG images {} should contain frame number pattern, e.g. %04d",
+                       name)
+            },
+            Error::ImageChanged(pos) => {
+                write!(f, "Frame {} differs in size or format from the previous ones", pos + 1)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Png,
+    Y4m,
+    Yuv,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match &name.to_lowercase()[..] {
+            "png" => Some(Format::Png),
+            "y4m" => Some(Format::Y4m),
+            "yuv" => Some(Format::Yuv),
+            _ => None,
+        }
+    }
+
+    /// Format by the extension of the output file.
+    fn guess(filename: &str) -> Option<Format> {
+        filename.rfind('.').and_then(|i| Format::from_name(&filename[i + 1..]))
+    }
+}
+
+/// Substitute frame number into printf-like pattern, e.g. "%04d".
+fn expand_pattern(pattern: &str, number: usize) -> Option<String> {
+    let start = match pattern.find('%') {
+        Some(start) => start,
+        None => return None,
+    };
+    let rest = &pattern[start + 1..];
+    let digits = rest.chars().take_while(|c| c.is_digit(10)).count();
+    if !rest[digits..].starts_with('d') {
+        return None;
+    }
+    let width = rest[..digits].parse::<usize>().unwrap_or(0);
+    Some(format!("{}{:0width$}{}", &pattern[..start], number, &rest[digits + 1..], width = width))
+}
+
+/// Open the output file, "-" stands for stdout.
+fn create(filename: &str) -> Result<BufWriter<Box<Write>>, Error> {
+    let out: Box<Write> = if filename == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(try!(File::create(filename)))
+    };
+    Ok(BufWriter::new(out))
+}
+
+/// Frame rate as fraction, guessed by timestamps of all frames. NTSC-like
+/// rates are detected.
+fn get_frame_rate(reader: &Demuxer) -> (u32, u32) {
+    let count = reader.get_frame_count();
+    let first = reader.get_frame_time(0);
+    let last = count.checked_sub(1).and_then(|pos| reader.get_frame_time(pos));
+    let span = match (first, last) {
+        (Some(first), Some(last)) if count > 1 && last > first => last - first,
+        // NOTE(Kagami): Y4M requires frame rate, use the common one.
+        _ => return (30, 1),
+    };
+    let fps = (count - 1) as f64 / span;
+    let ntsc = fps * 1.001;
+    if (fps - fps.round()).abs() < 0.01 {
+        (fps.round() as u32, 1)
+    } else if (ntsc - ntsc.round()).abs() < 0.01 {
+        (ntsc.round() as u32 * 1000, 1001)
+    } else {
+        let num = (fps * 1000.0).round() as u32;
+        let div = common::gcd(num as u64, 1000) as u32;
+        (num / div, 1000 / div)
+    }
+}
+
+/// Write Y, U and V planes of the image one after another, without row
+/// padding. High bit depth samples take 2 little-endian bytes.
+fn write_planes(out: &mut Write, image: &vpx::Image) -> Result<(), common::Error> {
+    image.for_each_le_row(|row| {
+        try!(out.write_all(row).map_err(Error::IoError));
+        Ok(())
+    })
+}
+
+/// Colorspace tag of the Y4M header, the same as libvpx writes.
+fn get_y4m_colorspace(image: &vpx::Image) -> Result<String, common::Error> {
+    match format_y4m_colorspace(image.get_chroma_shift(), image.get_bit_depth()) {
+        Some(colorspace) => Ok(colorspace),
+        None => Err(From::from(vpx::Error::UnsupportedFormat(image.get_format_name()))),
+    }
+}
+
+fn format_y4m_colorspace(chroma_shift: (usize, usize), bit_depth: u32) -> Option<String> {
+    let subsampling = match chroma_shift {
+        (1, 1) => "420",
+        (1, 0) => "422",
+        (0, 1) => "440",
+        (0, 0) => "444",
+        _ => return None,
+    };
+    Some(match bit_depth {
+        8 if subsampling == "420" => "420jpeg".to_string(),
+        8 => subsampling.to_string(),
+        depth => format!("{}p{} XYSCSS={}P{}", subsampling, depth, subsampling, depth),
+    })
+}
+
+fn write_y4m_header(out: &mut Write, image: &vpx::Image, frame_rate: (u32, u32),
+                    full_range: Option<bool>) -> Result<(), common::Error> {
+    let range = match full_range {
+        Some(true) => " XCOLORRANGE=FULL",
+        Some(false) => " XCOLORRANGE=LIMITED",
+        None => "",
+    };
+    try!(writeln!(out, "YUV4MPEG2 W{} H{} F{}:{} Ip C{}{}",
+                  image.get_display_width(), image.get_display_height(),
+                  frame_rate.0, frame_rate.1, try!(get_y4m_colorspace(image)), range)
+             .map_err(Error::IoError));
+    Ok(())
+}
+
+fn write_png(out: &mut Write, image: &vpx::Image,
+             full_range: Option<bool>) -> Result<(), common::Error> {
+    let matrix = image.get_matrix().unwrap_or_else(|| {
+        Matrix::guess(image.get_display_width(), image.get_display_height())
+    });
+    let conv = Converter::new(matrix, full_range.unwrap_or(false), image.get_bit_depth());
+    let pixels = try!(image.get_rgba8(&conv));
+    try!(png::write_rgba8(out, image.get_display_width() as u32,
+                          image.get_display_height() as u32, &pixels)
+             .map_err(Error::IoError));
+    Ok(())
+}
+
+/// Decode frames of the range and write them in the given or guessed
+/// format.
+pub fn run(opts: &Options) -> Result<(), common::Error> {
+    // Checked by the option parser.
+    let filename = opts.output.as_ref().unwrap();
+    let format = match opts.export_format.or_else(|| Format::guess(filename)) {
+        Some(format) => format,
+        None => return Err(From::from(Error::UnknownFormat(filename.clone()))),
+    };
+    let mut reader = try!(opts.open_reader());
+    let mut decoder = try!(opts.init_decoder(&*reader));
+    // PNG images go to the separate files unless written to stdout.
+    let separate = format == Format::Png && filename != "-";
+    if separate && opts.range.positions(reader.get_frame_count()).len() > 1 &&
+       expand_pattern(filename, 0).is_none() {
+        return Err(From::from(Error::MissingPattern(filename.clone())));
+    }
+    let mut out = if separate { None } else { Some(try!(create(filename))) };
+    let frame_rate = get_frame_rate(&*reader);
+    // Size and format of the first Y4M image.
+    let mut y4m_image = None;
+    let images = try!(cli::decode_range(&mut *reader, &mut decoder, opts.range,
+                                        |pos, image, hdr| {
+        let full_range = hdr.map(|hdr| hdr.color.color_range);
+        match (format, out.as_mut()) {
+            (Format::Png, Some(out)) => write_png(out, image, full_range),
+            (Format::Png, None) => {
+                let name = expand_pattern(filename, pos + 1).unwrap_or_else(|| filename.clone());
+                let mut out = try!(create(&name));
+                try!(write_png(&mut out, image, full_range));
+                try!(out.flush().map_err(Error::IoError));
+                Ok(())
+            },
+            (Format::Y4m, Some(out)) => {
+                let current = (image.get_display_width(), image.get_display_height(),
+                               image.get_format_name(), image.get_bit_depth());
+                match y4m_image {
+                    None => try!(write_y4m_header(out, image, frame_rate, full_range)),
+                    Some(ref first) if *first != current => {
+                        return Err(From::from(Error::ImageChanged(pos)));
+                    },
+                    _ => {},
+                }
+                y4m_image = Some(current);
+                try!(out.write_all(b"FRAME\n").map_err(Error::IoError));
+                write_planes(out, image)
+            },
+            (Format::Yuv, Some(out)) => write_planes(out, image),
+            (_, None) => unreachable!(),
+        }
+    }));
+    if let Some(ref mut out) = out {
+        try!(out.flush().map_err(Error::IoError));
+    }
+    printerr!("Exported {} frames to {}",
+              images, if filename == "-" { "stdout" } else { filename });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::demux::FrameEntry;
+
+    /// Frames at the given timestamps in milliseconds.
+    struct FakeReader {
+        frames: Vec<FrameEntry>,
+    }
+
+    impl FakeReader {
+        fn new(timestamps: &[u64]) -> FakeReader {
+            let frames = timestamps.iter().map(|&pts| {
+                FrameEntry {offset: 0, size: 0, pts: pts, keyframe: None}
+            }).collect();
+            FakeReader {frames: frames}
+        }
+    }
+
+    impl Demuxer for FakeReader {
+        fn get_filename(&self) -> &str { "fake" }
+        fn get_format_name(&self) -> &'static str { "fake" }
+        fn get_fourcc(&self) -> u32 { common::VP9_FOURCC }
+        fn get_width(&self) -> u16 { 0 }
+        fn get_height(&self) -> u16 { 0 }
+        fn get_timebase(&self) -> (u32, u32) { (1, 1000) }
+        fn get_frames(&self) -> &[FrameEntry] { &self.frames }
+        fn read_frame(&mut self, _pos: usize) -> Result<Box<[u8]>, common::Error> {
+            unreachable!()
+        }
+    }
+
+    fn frame_rate(timestamps: &[u64]) -> (u32, u32) {
+        get_frame_rate(&FakeReader::new(timestamps))
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(expand_pattern("f%04d.png", 7).unwrap(), "f0007.png");
+        assert_eq!(expand_pattern("f%04d.png", 123456).unwrap(), "f123456.png");
+        assert_eq!(expand_pattern("%d", 12).unwrap(), "12");
+        assert_eq!(expand_pattern("frame.png", 1), None);
+        assert_eq!(expand_pattern("f%x.png", 1), None);
+        assert_eq!(expand_pattern("f%4.png", 1), None);
+    }
+
+    #[test]
+    fn frame_rates() {
+        let timestamps: Vec<u64> = (0..31).map(|i| i * 1000 / 30).collect();
+        assert_eq!(frame_rate(&timestamps), (30, 1));
+        // Millisecond timestamps of 29.97 fps video.
+        let timestamps: Vec<u64> = (0..301).map(|i| i * 1001 / 30).collect();
+        assert_eq!(frame_rate(&timestamps), (30000, 1001));
+        assert_eq!(frame_rate(&[0, 80, 160]), (25, 2));
+        // Not enough timestamps, the common rate is used.
+        assert_eq!(frame_rate(&[]), (30, 1));
+        assert_eq!(frame_rate(&[0]), (30, 1));
+        assert_eq!(frame_rate(&[100, 100]), (30, 1));
+        assert_eq!(frame_rate(&[100, 50]), (30, 1));
+    }
+
+    #[test]
+    fn y4m_colorspaces() {
+        assert_eq!(format_y4m_colorspace((1, 1), 8).unwrap(), "420jpeg");
+        assert_eq!(format_y4m_colorspace((1, 0), 8).unwrap(), "422");
+        assert_eq!(format_y4m_colorspace((0, 0), 8).unwrap(), "444");
+        assert_eq!(format_y4m_colorspace((1, 1), 10).unwrap(), "420p10 XYSCSS=420P10");
+        assert_eq!(format_y4m_colorspace((0, 1), 12).unwrap(), "440p12 XYSCSS=440P12");
+        assert_eq!(format_y4m_colorspace((2, 2), 8), None);
+    }
+}
@@ -0,0 +1,225 @@
+//! Per-frame metadata of the container frames as CSV or JSON Lines.
+
+use std::io::{self, Write};
+use ::cli::{self, Options};
+use ::common;
+use ::json;
+use ::superframe;
+use ::vp9;
+
+const COLUMNS: [&'static str; 15] = [
+    "frame", "offset", "size", "pts", "time", "subframes", "frame_type", "show_frame",
+    "decode_result", "decode_error", "images", "width", "height", "format", "bit_depth",
+];
+
+/// Metadata of the single container frame.
+struct Record {
+    pos: usize,
+    offset: u64,
+    size: u32,
+    pts: u64,
+    time: Option<f64>,
+    /// Number of VPx frames in the container frame.
+    subframes: usize,
+    /// Type of the first VPx frame.
+    frame_type: Option<&'static str>,
+    /// Whether any of VPx frames is shown.
+    show_frame: Option<bool>,
+    /// Decoder error, if any.
+    error: Option<String>,
+    /// Number of images output by decoder.
+    images: usize,
+    /// Dimensions, format and bit depth of the first image.
+    image: Option<(u16, u16, String, u32)>,
+}
+
+impl Record {
+    fn to_json(&self) -> json::Object {
+        let image = self.image.as_ref();
+        let mut record = json::Object::new();
+        record.add("frame", self.pos + 1);
+        record.add("offset", self.offset);
+        record.add("size", self.size);
+        record.add("pts", self.pts);
+        record.add("time", self.time);
+        record.add("subframes", self.subframes);
+        record.add("frame_type", self.frame_type);
+        record.add("show_frame", self.show_frame);
+        record.add("decode_result", if self.error.is_some() { "error" } else { "ok" });
+        record.add("decode_error", self.error.clone());
+        record.add("images", self.images);
+        record.add("width", image.map(|image| image.0));
+        record.add("height", image.map(|image| image.1));
+        record.add("format", image.map(|image| image.2.clone()));
+        record.add("bit_depth", image.map(|image| image.3));
+        record
+    }
+
+    fn to_csv(&self) -> String {
+        let or_empty = |val: Option<String>| val.unwrap_or_else(String::new);
+        let image = self.image.as_ref();
+        let fields = [
+            (self.pos + 1).to_string(),
+            self.offset.to_string(),
+            self.size.to_string(),
+            self.pts.to_string(),
+            or_empty(self.time.map(|time| time.to_string())),
+            self.subframes.to_string(),
+            or_empty(self.frame_type.map(|frame_type| frame_type.to_string())),
+            or_empty(self.show_frame.map(|show| show.to_string())),
+            (if self.error.is_some() { "error" } else { "ok" }).to_string(),
+            or_empty(self.error.clone()),
+            self.images.to_string(),
+            or_empty(image.map(|image| image.0.to_string())),
+            or_empty(image.map(|image| image.1.to_string())),
+            or_empty(image.map(|image| image.2.clone())),
+            or_empty(image.map(|image| image.3.to_string())),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| quote_csv(field)).collect();
+        fields.join(",")
+    }
+}
+
+/// Quote CSV field if needed (RFC 4180).
+fn quote_csv(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Frame type and visibility of the VPx frames.
+enum HeaderParser {
+    Vp8,
+    Vp9(vp9::HeaderParser),
+}
+
+impl HeaderParser {
+    /// Type of the first frame and whether any frame is shown, if headers
+    /// can be parsed.
+    fn parse(&mut self, data: &[u8],
+             subframes: &[superframe::SubFrame]) -> (Option<&'static str>, Option<bool>) {
+        match *self {
+            // Frame tag: inverted keyframe flag, 3 bits of version and show
+            // frame flag.
+            HeaderParser::Vp8 => {
+                let tag = data[0];
+                (Some(if tag & 1 == 0 { "key" } else { "inter" }), Some(tag & 0x10 != 0))
+            },
+            HeaderParser::Vp9(ref mut parser) => {
+                let mut frame_type = None;
+                let mut show_frame = None;
+                for (i, subframe) in subframes.iter().enumerate() {
+                    let hdr = match parser.parse(&data[subframe.offset..
+                                                       subframe.offset + subframe.size]) {
+                        Ok(hdr) => hdr,
+                        Err(_) => return (frame_type, show_frame),
+                    };
+                    if i == 0 {
+                        frame_type = Some(if hdr.show_existing_frame {
+                            "show-existing"
+                        } else if hdr.frame_type == vp9::FrameType::KeyFrame {
+                            "key"
+                        } else if hdr.intra_only {
+                            "intra-only"
+                        } else {
+                            "inter"
+                        });
+                    }
+                    let shown = hdr.show_frame || hdr.show_existing_frame;
+                    show_frame = Some(show_frame.unwrap_or(false) || shown);
+                }
+                (frame_type, show_frame)
+            },
+        }
+    }
+}
+
+/// Decode frames of the range, starting from the preceding keyframe, and
+/// print record of every one. Decoding errors don't stop the dump.
+pub fn run(opts: &Options) -> Result<(), common::Error> {
+    let mut reader = try!(opts.open_reader());
+    let mut decoder = try!(opts.init_decoder(&*reader));
+    let is_vp9 = reader.get_fourcc() == common::VP9_FOURCC;
+    let mut parser = if is_vp9 {
+        HeaderParser::Vp9(vp9::HeaderParser::new())
+    } else {
+        HeaderParser::Vp8
+    };
+    let count = reader.get_frame_count();
+    let positions = opts.range.positions(count);
+    if positions.start >= positions.end {
+        return Err(From::from(cli::Error::EmptyRange(opts.range, count)));
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if !opts.json {
+        try!(writeln!(out, "{}", COLUMNS.join(",")));
+    }
+    let start = try!(cli::find_keyframe(&mut *reader, &decoder, positions.start));
+    for pos in start..positions.end {
+        let entry = *reader.get_frame_entry(pos).unwrap();
+        let data = try!(reader.read_frame(pos));
+        let mut record = Record {
+            pos: pos,
+            offset: entry.offset,
+            size: entry.size,
+            pts: entry.pts,
+            time: reader.get_frame_time(pos),
+            subframes: 0,
+            frame_type: None,
+            show_frame: None,
+            error: None,
+            images: 0,
+            image: None,
+        };
+        if !data.is_empty() {
+            let subframes = if is_vp9 {
+                superframe::split(&data)
+            } else {
+                vec![superframe::SubFrame {offset: 0, size: data.len()}]
+            };
+            let (frame_type, show_frame) = parser.parse(&data, &subframes);
+            record.subframes = subframes.len();
+            record.frame_type = frame_type;
+            record.show_frame = show_frame;
+            match decoder.decode(&data) {
+                Ok(images) => for image in images {
+                    if record.image.is_none() {
+                        record.image = Some((image.get_display_width(),
+                                             image.get_display_height(),
+                                             image.get_format_name(),
+                                             image.get_bit_depth()));
+                    }
+                    record.images += 1;
+                },
+                Err(err) => record.error = Some(err.to_string()),
+            }
+        }
+        if pos < positions.start {
+            continue;
+        }
+        if opts.json {
+            try!(writeln!(out, "{}", record.to_json()));
+        } else {
+            try!(writeln!(out, "{}", record.to_csv()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(quote_csv(""), "");
+        assert_eq!(quote_csv("inter"), "inter");
+        assert_eq!(quote_csv("a,b"), "\"a,b\"");
+        assert_eq!(quote_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote_csv("two\nlines"), "\"two\nlines\"");
+        assert_eq!(quote_csv("cr\r"), "\"cr\r\"");
+    }
+}
@@ -1,21 +1,32 @@
 use std::fmt;
+use std::cmp;
 use std::marker::PhantomData;
+use std::sync::Arc;
 use gfx::{self, Resources, ProgramError};
-use gfx::traits::{IntoCanvas, Factory, FactoryExt, Stream};
+use gfx::traits::{IntoCanvas, Factory, FactoryExt, Stream, ToSlice};
 use gfx::shade::TextureParam;
-use gfx::device::tex::TextureError;
+use gfx::device::tex::{TextureError, SamplerInfo, FilterMethod, WrapMode};
 use gfx::extra::canvas::Canvas;
 use gfx::batch::OwnedBatch;
 use gfx::batch::Error as BatchError;
 use gfx_device_gl as dgl;
 use gfx_window_glutin as gfxw;
-use glutin::{CreationError, WindowBuilder, GL_CORE};
-use glutin::Event::{Closed, KeyboardInput};
-use glutin::ElementState::Pressed;
+use glutin::{CreationError, WindowBuilder, GL_CORE, MouseButton, MouseScrollDelta};
+use glutin::Event;
+use glutin::Event::{Closed, Resized, KeyboardInput, MouseMoved, MouseWheel, MouseInput};
+use glutin::ElementState::{Pressed, Released};
 use glutin::VirtualKeyCode as Key;
 use gfx_text;
-use ::ivf;
+use ::common;
+use ::colorspace::{Matrix, Converter};
+use ::hdr::{Transfer, ToneMap, ToneMapper};
+use ::overlay;
+use ::view::View;
+use ::demux::Demuxer;
+use ::superframe::SubFrame;
 use ::vpx;
+use ::vp9;
+use ::worker::{self, Worker, Request, Response, CacheStats};
 
 #[derive(Debug)]
 pub enum Error {
@@ -66,18 +77,33 @@ gfx_vertex!( Vertex {
 
 gfx_parameters!( ShaderParams/ParamsLink {
     t_Color@ color: TextureParam<R>,
+    u_Scale@ scale: [f32; 2],
+    u_Offset@ offset: [f32; 2],
+});
+
+gfx_parameters!( LineParams/LineParamsLink {
+    u_Scale@ scale: [f32; 2],
+    u_Offset@ offset: [f32; 2],
+});
+
+/// Overlay line vertex, position is in texture coordinates.
+gfx_vertex!( LineVertex {
+    a_Pos@ pos: [f32; 2],
+    a_Color@ color: [f32; 3],
 });
 
 static VERTEX_SRC: &'static [u8] = b"
     #version 150 core
 
-    in vec4 a_Pos;
+    in vec2 a_Pos;
     in vec2 a_TexCoord;
     out vec2 v_TexCoord;
+    uniform vec2 u_Scale;
+    uniform vec2 u_Offset;
 
     void main() {
         v_TexCoord = a_TexCoord;
-        gl_Position = a_Pos;
+        gl_Position = vec4(a_Pos * u_Scale + u_Offset, 0.0, 1.0);
     }
 ";
 
@@ -93,6 +119,33 @@ static FRAGMENT_SRC: &'static [u8] = b"
     }
 ";
 
+static LINE_VERTEX_SRC: &'static [u8] = b"
+    #version 150 core
+
+    in vec2 a_Pos;
+    in vec3 a_Color;
+    out vec3 v_Color;
+    uniform vec2 u_Scale;
+    uniform vec2 u_Offset;
+
+    void main() {
+        v_Color = a_Color;
+        vec2 pos = vec2(a_Pos.x * 2.0 - 1.0, 1.0 - a_Pos.y * 2.0);
+        gl_Position = vec4(pos * u_Scale + u_Offset, 0.0, 1.0);
+    }
+";
+
+static LINE_FRAGMENT_SRC: &'static [u8] = b"
+    #version 150 core
+
+    in vec3 v_Color;
+    out vec4 o_Color;
+
+    void main() {
+        o_Color = vec4(v_Color, 1.0);
+    }
+";
+
 const VERTEX_DATA: &'static [Vertex] = &[
     // 1
     // |\
@@ -114,30 +167,81 @@ const BACKGROUND: gfx::ClearData = gfx::ClearData {
     depth: 1.0,
     stencil: 0,
 };
+/// Reference white of SDR display as recommended by BT.2408.
+const DEFAULT_DISPLAY_NITS: f32 = 203.0;
+const DISPLAY_NITS_STEP: f32 = 1.25;
+const SOURCE_NITS_STEP: f32 = 1.25;
+const EXPOSURE_STEP: f32 = 0.5;
+/// Trackpad scrolling by this many pixels is one zoom step, like a wheel
+/// notch.
+const SCROLL_STEP_PIXELS: f32 = 20.0;
 const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
 const TEXT_HEIGHT: i32 = 16;
+/// Probability updates listed in the HUD, the rest are only counted.
+const PROB_UPDATES_PER_LINE: usize = 4;
+const MAX_PROB_UPDATE_LINES: usize = 24;
 
 type CanvasT = Canvas<gfxw::Output<dgl::Resources>, dgl::Device, dgl::Factory>;
 type BatchT = OwnedBatch<ShaderParams<dgl::Resources>>;
+type LineBatchT = OwnedBatch<LineParams<dgl::Resources>>;
 type TextRendererT = gfx_text::Renderer<dgl::Resources>;
 
 pub struct Gui {
-    reader: ivf::Reader,
-    decoder: vpx::Decoder,
-    viewport_width: u16,
-    viewport_height: u16,
+    /// Used for stream details only, frames are read by worker.
+    reader: Box<Demuxer>,
+    worker: Worker,
+    /// VPx frame currently shown.
+    frame: Option<Arc<worker::Frame>>,
+    /// Block counts of the current VP9 frame, computed once per frame.
+    block_stats: Option<vp9::BlockStats>,
+    cache_stats: CacheStats,
+    /// How the image is rendered, kept across frames.
+    display_mode: vpx::DisplayMode,
+    /// User choices of YUV to RGB conversion instead of the stream's ones.
+    matrix_override: Option<Matrix>,
+    range_override: Option<bool>,
+    /// User choice of transfer function instead of the container's one.
+    transfer_override: Option<Transfer>,
+    /// HDR to SDR conversion settings, kept across frames.
+    tone_map: ToneMap,
+    /// Assumed peak of the content since mastering metadata isn't parsed.
+    source_nits: f32,
+    display_nits: f32,
+    /// Exposure adjustment in stops.
+    exposure: f32,
+    /// Whether to paint colors outside of BT.709 gamut.
+    highlight_gamut: bool,
+    /// Zoom and pan, kept across frames. Also tracks the window size.
+    view: View,
+    /// Last known mouse position in window pixels.
+    cursor: [f32; 2],
+    /// Left mouse button is held.
+    dragging: bool,
+    /// Pixel scroll deltas not yet turned into zoom steps.
+    scroll_pixels: f32,
     canvas: CanvasT,
     batch: BatchT,
+    /// Used instead of the default one when zoomed in.
+    nearest_sampler: gfx::handle::Sampler<dgl::Resources>,
+    /// Shared by all line overlays.
+    line_program: gfx::handle::Program<dgl::Resources>,
+    /// Lines drawn over the current frame, if any overlay is enabled.
+    overlay: Option<LineBatchT>,
+    /// Whether to outline VP9 blocks.
+    show_partitions: bool,
+    /// Whether to draw motion vectors of VP9 inter blocks.
+    show_mvs: bool,
+    /// Whether to list probabilities updated by VP9 frame.
+    show_prob_updates: bool,
     text: TextRendererT,
 }
 
-pub fn init(reader: ivf::Reader, decoder: vpx::Decoder) -> Result<Gui, Error> {
-    let viewport_width = reader.get_width();
-    let viewport_height = reader.get_height();
+pub fn init(reader: Box<Demuxer>, worker: Worker, source_nits: f32) -> Result<Gui, Error> {
+    let (width, height) = (reader.get_width(), reader.get_height());
     let mut canvas = {
         // TODO(Kagami): Fullscreen.
         let window = try!(WindowBuilder::new()
-            .with_dimensions(viewport_width as u32, viewport_height as u32)
+            .with_dimensions(width as u32, height as u32)
             // Use simple initial title to allow to match the window in tiling
             // window managers.
             .with_title(format!("vpxview"))
@@ -152,99 +256,645 @@ pub fn init(reader: ivf::Reader, decoder: vpx::Decoder) -> Result<Gui, Error> {
         let texture = try!(canvas.factory.create_texture_rgba8(
             reader.get_width(),
             reader.get_height()));
-        let param = ShaderParams {color: (texture, None), _r: PhantomData};
+        let param = ShaderParams {
+            color: (texture, None),
+            scale: [1.0, 1.0],
+            offset: [0.0, 0.0],
+            _r: PhantomData,
+        };
         try!(OwnedBatch::new(mesh, program, param))
     };
+    let nearest_sampler = canvas.factory.create_sampler(
+        SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
+    let line_program = try!(canvas.factory.link_program(LINE_VERTEX_SRC, LINE_FRAGMENT_SRC));
     let text = try!(gfx_text::new(&mut canvas.factory).build());
     Ok(Gui {
         reader: reader,
-        decoder: decoder,
-        viewport_width: viewport_width,
-        viewport_height: viewport_height,
+        worker: worker,
+        frame: None,
+        block_stats: None,
+        cache_stats: Default::default(),
+        display_mode: vpx::DisplayMode::Color,
+        matrix_override: None,
+        range_override: None,
+        transfer_override: None,
+        tone_map: ToneMap::Bt2390,
+        source_nits: source_nits,
+        display_nits: DEFAULT_DISPLAY_NITS,
+        exposure: 0.0,
+        highlight_gamut: false,
+        view: View::new(width, height),
+        cursor: [0.0, 0.0],
+        dragging: false,
+        scroll_pixels: 0.0,
         canvas: canvas,
         batch: batch,
+        nearest_sampler: nearest_sampler,
+        line_program: line_program,
+        overlay: None,
+        show_partitions: false,
+        show_mvs: false,
+        show_prob_updates: false,
         text: text,
     })
 }
 
 impl Gui {
-    pub fn run(&mut self) {
-        self.next_video_frame();
+    /// Show frames starting from the given container frame until window is
+    /// closed.
+    pub fn run(&mut self, start: usize) {
+        self.worker.request(Request::Seek(start, Some(0)));
         loop {
-            // Skip all pending events except the first because in some cases frame
-            // decoding may take too long so interface will be brozen because of
-            // big events queue.
-            let maybe_event = {
-                let mut iter = self.canvas.output.window.poll_events();
-                let ev = iter.next();
-                iter.count();  // Consume entire iterator
-                ev
-            };
-            match maybe_event {
-                Some(Closed) => break,
-                Some(KeyboardInput(Pressed, _, Some(Key::Escape))) => break,
-                Some(KeyboardInput(Pressed, _, Some(Key::Q))) => break,
-                Some(KeyboardInput(Pressed, _, Some(Key::Left))) => {
-                    // TODO(Kagami).
-                },
-                Some(KeyboardInput(Pressed, _, Some(Key::Right))) => {
-                    self.next_video_frame();
-                },
-                _ => {},
+            // Decoding is done by worker so all events can be handled without
+            // freezing the interface.
+            let events: Vec<Event> = self.canvas.output.window.poll_events().collect();
+            for event in events {
+                if !self.handle_event(event) {
+                    return;
+                }
             }
+            while let Some(response) = self.worker.poll() {
+                match response {
+                    Response::Frame(frame) => self.show_frame(frame),
+                    Response::CacheStats(stats) => self.cache_stats = stats,
+                }
+            }
+            self.apply_view();
             self.canvas.clear(BACKGROUND);
             let draw_result = self.canvas.draw(&self.batch);
             try_print!(draw_result, "Error occured while drawing the frame: {:?}");
+            if let Some(ref overlay) = self.overlay {
+                let draw_result = self.canvas.draw(overlay);
+                try_print!(draw_result, "Error occured while drawing the overlay: {:?}");
+            }
             self.render_hud();
             self.canvas.present();
         }
     }
 
-    /// Read next IVF frame, decode VPx frame if possible and update the
-    /// texture.
-    fn next_video_frame(&mut self) {
-        let maybe_frame = self.reader.next();
-        self.update_title();
-        let ivf_frame = maybe_print!(maybe_frame, "End of file");
-        match self.decoder.decode_many(&ivf_frame) {
-            Ok(mut iter) => {
-                let image = maybe_print!(iter.next(), "No VPx frames in this IVF frame");
-                // TODO(Kagami): IVF frame may consist of several VPx frames, we
-                // correctly display only 1 IVF <-> 1 VPx case as for now.
-                let remaining = iter.count();
-                if remaining != 0 {
-                    printerr!("Skipping {} other VPx frames", remaining);
+    /// Handle input event, return false to quit.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Closed => return false,
+            // Minimized window may report zero size.
+            Resized(width, height) if width > 0 && height > 0 => {
+                self.view.set_window_size(width, height);
+            },
+            KeyboardInput(Pressed, _, Some(Key::Escape)) => return false,
+            KeyboardInput(Pressed, _, Some(Key::Q)) => return false,
+            KeyboardInput(Pressed, _, Some(Key::P)) => {
+                self.show_prob_updates = !self.show_prob_updates;
+            },
+            KeyboardInput(Pressed, _, Some(Key::Left)) => self.worker.request(Request::Prev),
+            KeyboardInput(Pressed, _, Some(Key::Right)) => self.worker.request(Request::Next),
+            KeyboardInput(Pressed, _, Some(Key::B)) => {
+                self.show_partitions = !self.show_partitions;
+                self.update_overlay();
+            },
+            KeyboardInput(Pressed, _, Some(Key::M)) => {
+                self.show_mvs = !self.show_mvs;
+                self.update_overlay();
+            },
+            KeyboardInput(Pressed, _, Some(Key::V)) => {
+                self.display_mode = self.display_mode.next();
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::C)) => {
+                self.matrix_override = match self.matrix_override {
+                    None => Some(Matrix::Bt601),
+                    Some(Matrix::Identity) => None,
+                    Some(matrix) => Some(matrix.next()),
+                };
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::R)) => {
+                self.range_override = match self.range_override {
+                    None => Some(false),
+                    Some(false) => Some(true),
+                    Some(true) => None,
+                };
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::H)) => {
+                self.transfer_override = match self.transfer_override {
+                    None => Some(Transfer::Pq),
+                    Some(Transfer::Pq) => Some(Transfer::Hlg),
+                    Some(Transfer::Hlg) => Some(Transfer::Sdr),
+                    Some(Transfer::Sdr) => None,
+                };
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::T)) => {
+                self.tone_map = self.tone_map.next();
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::LBracket)) => {
+                self.exposure -= EXPOSURE_STEP;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::RBracket)) => {
+                self.exposure += EXPOSURE_STEP;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::Comma)) => {
+                self.display_nits /= DISPLAY_NITS_STEP;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::Period)) => {
+                self.display_nits *= DISPLAY_NITS_STEP;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::Semicolon)) => {
+                self.source_nits /= SOURCE_NITS_STEP;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::Apostrophe)) => {
+                self.source_nits *= SOURCE_NITS_STEP;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::G)) => {
+                self.highlight_gamut = !self.highlight_gamut;
+                self.update_texture();
+            },
+            KeyboardInput(Pressed, _, Some(Key::F)) => self.view.toggle_fit(),
+            KeyboardInput(Pressed, _, Some(Key::Equals)) |
+            KeyboardInput(Pressed, _, Some(Key::Add)) => self.zoom_at_center(1),
+            KeyboardInput(Pressed, _, Some(Key::Minus)) |
+            KeyboardInput(Pressed, _, Some(Key::Subtract)) => self.zoom_at_center(-1),
+            MouseWheel(delta) => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, dy) if dy > 0.0 => 1,
+                    MouseScrollDelta::LineDelta(_, dy) if dy < 0.0 => -1,
+                    MouseScrollDelta::LineDelta(..) => 0,
+                    // Trackpads send lots of small deltas.
+                    MouseScrollDelta::PixelDelta(_, dy) => {
+                        self.scroll_pixels += dy;
+                        let steps = (self.scroll_pixels / SCROLL_STEP_PIXELS).trunc();
+                        self.scroll_pixels -= steps * SCROLL_STEP_PIXELS;
+                        steps as i32
+                    },
+                };
+                if steps != 0 {
+                    let cursor = self.cursor;
+                    self.view.zoom_at(steps, cursor);
+                }
+            },
+            MouseInput(state, MouseButton::Left) => self.dragging = state == Pressed,
+            MouseMoved((x, y)) => {
+                let pos = [x as f32, y as f32];
+                if self.dragging {
+                    self.view.pan(pos[0] - self.cursor[0], pos[1] - self.cursor[1]);
                 }
-                // TODO(Kagami): Dimensions of decoded VPx image can vary from
-                // frame to frame, we can adjust texture size accordingly.
-                assert_eq!(image.get_display_width(), self.viewport_width);
-                assert_eq!(image.get_display_height(), self.viewport_height);
-                let texture = &self.batch.param.color.0;
-                let update_result = self.canvas.factory.update_texture_raw(
-                    texture,
-                    &texture.get_info().to_image_info(),
-                    &image.get_rgba8(),
-                    None);
-                try_print!(update_result, "Error occured while updating texture: {:?}");
+                self.cursor = pos;
+            },
+            _ => {},
+        }
+        true
+    }
+
+    fn zoom_at_center(&mut self, steps: i32) {
+        let window_size = self.view.get_window_size();
+        let center = [window_size[0] / 2.0, window_size[1] / 2.0];
+        self.view.zoom_at(steps, center);
+    }
+
+    /// Pass zoom and pan to the frame and overlay shaders.
+    fn apply_view(&mut self) {
+        let (scale, offset) = self.view.transform();
+        self.batch.param.scale = scale;
+        self.batch.param.offset = offset;
+        self.batch.param.color.1 = if self.view.is_magnified() {
+            Some(self.nearest_sampler.clone())
+        } else {
+            None
+        };
+        if let Some(ref mut overlay) = self.overlay {
+            overlay.param.scale = scale;
+            overlay.param.offset = offset;
+        }
+    }
+
+    /// Recreate the texture if image size differs from the current one,
+    /// e.g. after resolution change in VP9 stream or if container tells the
+    /// wrong size.
+    fn resize_texture(&mut self, width: u16, height: u16) -> Result<(), Error> {
+        let info = *self.batch.param.color.0.get_info();
+        if info.width == width && info.height == height {
+            return Ok(());
+        }
+        let texture = try!(self.canvas.factory.create_texture_rgba8(width, height));
+        self.batch.param.color.0 = texture;
+        self.view.set_frame_size(width, height);
+        Ok(())
+    }
+
+    /// Show VPx frame decoded by worker.
+    fn show_frame(&mut self, frame: Arc<worker::Frame>) {
+        let shown = frame.image.is_some();
+        let size = frame.image.as_ref().map(|image| {
+            (image.get_display_width(), image.get_display_height())
+        });
+        if let Some((width, height)) = size {
+            if let Err(err) = self.resize_texture(width, height) {
+                printerr!("Cannot resize texture to {}x{}: {}", width, height, err);
+                return;
+            }
+        }
+        self.block_stats = match frame.vp9_header {
+            Some(vp9::FrameHeader {blocks: Some(ref blocks), ..}) => Some(blocks.get_stats()),
+            _ => None,
+        };
+        self.frame = Some(frame);
+        // Reader of the viewer indexes frames lazily too, errors are
+        // reported by the worker.
+        if let Some(pos) = self.get_frame_pos() {
+            let _ = self.reader.index_until(pos);
+        }
+        if shown {
+            self.update_texture();
+        } else {
+            printerr!("Frame is not shown, displaying previous image");
+        }
+        self.update_title();
+        self.update_overlay();
+    }
+
+    /// Image of the current VPx frame.
+    fn get_image(&self) -> Option<&vpx::Image> {
+        self.frame.as_ref().and_then(|frame| frame.image.as_ref()).map(|image| &**image)
+    }
+
+    /// Headers of the current VPx frame.
+    fn get_vp9_header(&self) -> Option<&vp9::FrameHeader> {
+        self.frame.as_ref().and_then(|frame| frame.vp9_header.as_ref())
+    }
+
+    /// Rebuild lines drawn over the frame from the current VP9 frame's mode
+    /// info.
+    fn update_overlay(&mut self) {
+        self.overlay = None;
+        let frame = match self.frame {
+            Some(ref frame) => frame.clone(),
+            None => return,
+        };
+        let (hdr, blocks) = match frame.vp9_header {
+            Some(vp9::FrameHeader {uncompressed: ref hdr, blocks: Some(ref blocks), ..}) => {
+                (hdr, blocks)
             },
-            Err(err) => {
-                printerr!("Cannot decode IVF frame: {}", err);
+            _ => return,
+        };
+        let mut lines = Vec::new();
+        if self.show_partitions {
+            lines.extend(overlay::partition_lines(blocks, hdr.width, hdr.height));
+        }
+        if self.show_mvs {
+            lines.extend(overlay::mv_lines(blocks));
+        }
+        if lines.is_empty() {
+            return;
+        }
+        let (width, height) = (hdr.width as f32, hdr.height as f32);
+        let mut vertices = Vec::with_capacity(lines.len() * 2);
+        for line in &lines {
+            for point in &[line.from, line.to] {
+                vertices.push(LineVertex {
+                    pos: [point[0] / width, point[1] / height],
+                    color: line.color,
+                });
+            }
+        }
+        let mesh = self.canvas.factory.create_mesh(&vertices);
+        let slice = mesh.to_slice(gfx::PrimitiveType::Line);
+        let param = LineParams {scale: [1.0, 1.0], offset: [0.0, 0.0], _r: PhantomData};
+        match OwnedBatch::new(mesh, self.line_program.clone(), param) {
+            Ok(mut batch) => {
+                batch.slice = slice;
+                self.overlay = Some(batch);
             },
+            Err(err) => printerr!("Cannot create overlay: {:?}", err),
+        }
+    }
+
+    /// Range signalled by the stream, only VP9 has one.
+    fn get_stream_range(&self) -> Option<bool> {
+        self.get_vp9_header().map(|frame| frame.uncompressed.color.color_range)
+    }
+
+    /// YUV to RGB conversion of the image: user's choice, then the stream's
+    /// one, then a guess.
+    fn get_converter(&self, image: &vpx::Image) -> Converter {
+        let matrix = self.matrix_override.or(image.get_matrix()).unwrap_or_else(|| {
+            Matrix::guess(image.get_display_width(), image.get_display_height())
+        });
+        let full_range = self.range_override.or(self.get_stream_range()).unwrap_or(false);
+        Converter::new(matrix, full_range, image.get_bit_depth())
+    }
+
+    /// Transfer function signalled by the container.
+    fn get_stream_transfer(&self) -> Option<Transfer> {
+        self.reader.get_transfer_characteristics().map(Transfer::from_code)
+    }
+
+    fn get_transfer(&self) -> Transfer {
+        self.transfer_override.or(self.get_stream_transfer()).unwrap_or(Transfer::Sdr)
+    }
+
+    /// HDR to SDR conversion, if the image is HDR.
+    fn get_tone_mapper(&self) -> Option<ToneMapper> {
+        match self.get_transfer() {
+            Transfer::Sdr => None,
+            transfer => Some(ToneMapper::new(transfer, self.tone_map, self.source_nits,
+                                             self.display_nits, self.exposure,
+                                             self.highlight_gamut)),
+        }
+    }
+
+    /// Render the current image into the texture.
+    fn update_texture(&mut self) {
+        // Keep the frame alive while texture is updated.
+        let frame = match self.frame {
+            Some(ref frame) => frame.clone(),
+            None => return,
         };
+        let image = match frame.image {
+            Some(ref image) => image,
+            None => return,
+        };
+        let conv = self.get_converter(image);
+        let tone_mapper = self.get_tone_mapper();
+        let pixels = try_print!(image.render(self.display_mode, &conv, tone_mapper.as_ref()),
+                                "Cannot render image: {}");
+        let texture = &self.batch.param.color.0;
+        let update_result = self.canvas.factory.update_texture_raw(
+            texture,
+            &texture.get_info().to_image_info(),
+            &pixels,
+            None);
+        if let Err(err) = update_result {
+            printerr!("Error occured while updating texture: {:?}", err);
+        }
+    }
+
+    /// Position of the current container frame.
+    fn get_frame_pos(&self) -> Option<usize> {
+        self.frame.as_ref().map(|frame| frame.pos)
+    }
+
+    /// Human-readable (i.e. 1-based) position of the current frame.
+    fn get_frame_number(&self) -> String {
+        self.get_frame_pos().map_or_else(|| "-".to_string(), |pos| (pos + 1).to_string())
+    }
+
+    fn get_subframe_number(&self) -> String {
+        self.frame.as_ref().map_or_else(|| "-".to_string(), |f| (f.subframe_pos + 1).to_string())
+    }
+
+    /// Presentation time of the current frame.
+    fn get_frame_time(&self) -> String {
+        let time = self.get_frame_pos().and_then(|pos| self.reader.get_frame_time(pos));
+        time.map_or_else(|| "?".to_string(), |t| format!("{:.3}s", t))
+    }
+
+    fn get_frame_pts(&self) -> String {
+        let pts = self.get_frame_pos().and_then(|pos| self.reader.get_frame_pts(pos));
+        pts.map_or_else(|| "?".to_string(), |pts| pts.to_string())
     }
 
+    /// Number of the indexed frames, "+" marks that file may have more.
     fn get_frame_count(&self) -> String {
-        self.reader.get_frame_count().map_or_else(|| "?".to_string(), |n| n.to_string())
+        let more = if self.reader.is_indexed() { "" } else { "+" };
+        format!("{}{}", self.reader.get_frame_count(), more)
     }
 
     fn update_title(&self) {
-        let title = format!("vpxview - {} - {}/{}",
+        let title = format!("vpxview - {} - {}/{} - {}",
                             self.reader.get_filename(),
-                            self.reader.get_frame_pos(),
-                            self.get_frame_count());
+                            self.get_frame_number(),
+                            self.get_frame_count(),
+                            self.get_frame_time());
         self.canvas.output.window.set_title(&title);
     }
 
+    /// Details of the current VP9 frame header.
+    fn get_vp9_lines(&self) -> Vec<String> {
+        let hdr = match self.get_vp9_header() {
+            Some(frame) => &frame.uncompressed,
+            None => return Vec::new(),
+        };
+        if hdr.show_existing_frame {
+            return vec![format!("VP9: profile {}, show existing frame from slot {}",
+                                hdr.profile, hdr.frame_to_show_map_idx)];
+        }
+        let yes_no = |flag| if flag { "yes" } else { "no" };
+        let subsampling = match (hdr.color.subsampling_x, hdr.color.subsampling_y) {
+            (true, true) => "4:2:0",
+            (true, false) => "4:2:2",
+            (false, true) => "4:4:0",
+            (false, false) => "4:4:4",
+        };
+        let lf = &hdr.loop_filter;
+        let quant = &hdr.quantization;
+        let seg = &hdr.segmentation;
+        let mut lines = vec![
+            format!("VP9: profile {}, {:?}, shown: {}, intra-only: {}, error resilient: {}",
+                    hdr.profile, hdr.frame_type, yes_no(hdr.show_frame),
+                    yes_no(hdr.intra_only), yes_no(hdr.error_resilient_mode)),
+            format!("Size: {}x{}{} (render {}x{}), {}-bit {:?} {}, {} range",
+                    hdr.width, hdr.height,
+                    hdr.size_from_ref.map_or_else(String::new, |i| format!(" from slot {}", i)),
+                    hdr.render_width, hdr.render_height,
+                    hdr.color.bit_depth, hdr.color.color_space, subsampling,
+                    if hdr.color.color_range { "full" } else { "limited" }),
+        ];
+        if !hdr.is_intra() {
+            lines.push(format!("Refs: slots {:?}, sign bias {:?}, high precision mv: {}, \
+                                interp filter: {:?}",
+                               hdr.ref_frame_idx, &hdr.ref_frame_sign_bias[1..],
+                               yes_no(hdr.allow_high_precision_mv), hdr.interp_filter));
+        }
+        lines.extend(vec![
+            format!("Refresh slots: {:08b}, context: idx {}, reset {}, refresh {}, parallel {}",
+                    hdr.refresh_frame_flags, hdr.frame_context_idx, hdr.reset_frame_context,
+                    yes_no(hdr.refresh_frame_context), yes_no(hdr.frame_parallel_decoding_mode)),
+            format!("Loop filter: level {}, sharpness {}, deltas: {} (ref {:?}, mode {:?})",
+                    lf.level, lf.sharpness, yes_no(lf.delta_enabled),
+                    lf.ref_deltas, lf.mode_deltas),
+            format!("Quantizer: base {}, delta y_dc {}, uv_dc {}, uv_ac {}, lossless: {}",
+                    quant.base_q_idx, quant.delta_q_y_dc, quant.delta_q_uv_dc,
+                    quant.delta_q_uv_ac, yes_no(quant.lossless)),
+            format!("Segmentation: {}, update map: {}, temporal: {}, update data: {}",
+                    yes_no(seg.enabled), yes_no(seg.update_map),
+                    yes_no(seg.temporal_update), yes_no(seg.update_data)),
+            format!("Tiles: {}x{}, header sizes: {} + {} bytes",
+                    1 << hdr.tile_cols_log2, 1 << hdr.tile_rows_log2,
+                    hdr.size, hdr.header_size_in_bytes),
+        ]);
+        lines.extend(self.get_compressed_lines());
+        lines.extend(self.get_block_lines());
+        lines
+    }
+
+    /// Summary of the current VP9 frame's mode info.
+    fn get_block_lines(&self) -> Vec<String> {
+        let stats = match self.block_stats {
+            Some(ref stats) => stats,
+            None => return Vec::new(),
+        };
+        let mut lines = Vec::new();
+        lines.push(format!("Blocks: {} (intra {}, compound {}, skip {}, sub8x8 {}), \
+                            partitions: {}",
+                           stats.blocks, stats.intra, stats.compound, stats.skip, stats.sub8x8,
+                           stats.partitions.iter().sum::<usize>()));
+        if self.show_partitions {
+            let counts = stats.partitions;
+            lines.push(format!("Partitions (B): none {}, horz {}, vert {}, split {}; \
+                                outlines from red (4x4) to blue (64x64)",
+                               counts[vp9::Partition::None as usize],
+                               counts[vp9::Partition::Horz as usize],
+                               counts[vp9::Partition::Vert as usize],
+                               counts[vp9::Partition::Split as usize]));
+        }
+        if self.show_mvs {
+            let legend: Vec<String> = (0..3).map(|i| {
+                format!("{} {} ({})", overlay::REF_NAMES[i], overlay::REF_COLOR_NAMES[i],
+                        stats.refs[i])
+            }).collect();
+            lines.push(format!("Motion vectors (M): {}", legend.join(", ")));
+        }
+        lines
+    }
+
+    /// Summary of the current VP9 compressed header.
+    fn get_compressed_lines(&self) -> Vec<String> {
+        let chdr = match self.get_vp9_header() {
+            Some(&vp9::FrameHeader {compressed: Some(ref chdr), ..}) => chdr,
+            _ => return Vec::new(),
+        };
+        let mut lines = vec![
+            format!("TX mode: {:?}, reference mode: {:?}, probability updates: {} (P to list)",
+                    chdr.tx_mode, chdr.reference_mode, chdr.updates.len()),
+        ];
+        if self.show_prob_updates {
+            let max_updates = PROB_UPDATES_PER_LINE * MAX_PROB_UPDATE_LINES;
+            let updates = &chdr.updates[..cmp::min(chdr.updates.len(), max_updates)];
+            for chunk in updates.chunks(PROB_UPDATES_PER_LINE) {
+                let items: Vec<String> = chunk.iter().map(|u| u.to_string()).collect();
+                lines.push(format!("  {}", items.join(", ")));
+            }
+            if chdr.updates.len() > updates.len() {
+                lines.push(format!("  ... and {} more", chdr.updates.len() - updates.len()));
+            }
+            return lines;
+        }
+        let counts = chdr.count_updates();
+        for chunk in counts.chunks(4) {
+            let items: Vec<String> = chunk.iter()
+                                          .map(|&(table, n)| format!("{}: {}", table, n))
+                                          .collect();
+            lines.push(format!("  {}", items.join(", ")));
+        }
+        lines
+    }
+
+    fn get_cache_line(&self) -> String {
+        let stats = &self.cache_stats;
+        format!("Cache: {} frames, {}/{} MiB",
+                stats.frames, stats.size >> 20, stats.budget >> 20)
+    }
+
+    fn get_view_line(&self) -> String {
+        if self.view.fit {
+            return "View: fit to window (F for 1:1)".to_string();
+        }
+        let frame_size = self.view.get_frame_size();
+        format!("View: zoom {}x, center at ({:.0}, {:.0}) (F to fit)",
+                self.view.zoom, self.view.center[0] * frame_size[0],
+                self.view.center[1] * frame_size[1])
+    }
+
+    /// Image format and conversion in use with where it comes from.
+    fn get_image_line(&self) -> Option<String> {
+        let image = match self.get_image() {
+            Some(image) => image,
+            None => return None,
+        };
+        let conv = self.get_converter(image);
+        let matrix_source = if self.matrix_override.is_some() {
+            "override"
+        } else if image.get_matrix().is_some() {
+            "stream"
+        } else {
+            "guessed"
+        };
+        let range_source = if self.range_override.is_some() {
+            "override"
+        } else if self.get_stream_range().is_some() {
+            "stream"
+        } else {
+            "default"
+        };
+        Some(format!("Image: {} {}-bit, {} ({}, C to change), {} range ({}, R to change)",
+                     image.get_format_name(), image.get_bit_depth(),
+                     conv.matrix, matrix_source,
+                     if conv.full_range { "full" } else { "limited" }, range_source))
+    }
+
+    fn get_hdr_line(&self) -> String {
+        let transfer = self.get_transfer();
+        let source = if self.transfer_override.is_some() {
+            "override"
+        } else if self.get_stream_transfer().is_some() {
+            "container"
+        } else {
+            "default"
+        };
+        if transfer == Transfer::Sdr {
+            return format!("Transfer: SDR ({}, H to change)", source);
+        }
+        format!("Transfer: {} ({}, H to change), tone mapping: {} (T), source peak: {:.0} \
+                 nits (; '), display: {:.0} nits (, .), exposure: {:+.1} ([ ]), \
+                 gamut highlight: {} (G)",
+                transfer, source, self.tone_map, self.source_nits, self.display_nits,
+                self.exposure, if self.highlight_gamut { "yes" } else { "no" })
+    }
+
+    /// Samples and position of the frame pixel under the cursor.
+    fn get_pixel_lines(&self) -> Vec<String> {
+        let image = match self.get_image() {
+            Some(image) => image,
+            None => return Vec::new(),
+        };
+        let width = image.get_display_width();
+        let height = image.get_display_height();
+        let pos = self.view.to_texture(self.cursor);
+        let x = (pos[0] * width as f32).floor();
+        let y = (pos[1] * height as f32).floor();
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+            return Vec::new();
+        }
+        let (x, y) = (x as u16, y as u16);
+        let pixel = match image.get_pixel(x, y, &self.get_converter(image)) {
+            Ok(pixel) => pixel,
+            Err(err) => return vec![format!("Pixel: ({}, {}), {}", x, y, err)],
+        };
+        let mut lines = vec![
+            format!("Pixel: ({}, {}), Y {}, U {}, V {} ({}-bit), RGB ({}, {}, {})",
+                    x, y, pixel.y, pixel.u, pixel.v, image.get_bit_depth(),
+                    pixel.rgb[0], pixel.rgb[1], pixel.rgb[2]),
+            format!("8x8 block: ({}, {}) at ({}, {}), superblock: ({}, {}) at ({}, {})",
+                    x / 8, y / 8, x / 8 * 8, y / 8 * 8,
+                    x / 64, y / 64, x / 64 * 64, y / 64 * 64),
+        ];
+        if let Some(&vp9::FrameHeader {blocks: Some(ref blocks), ..}) = self.get_vp9_header() {
+            if let Some(b) = blocks.get(y as u32 / 8, x as u32 / 8) {
+                lines.push(format!("Block: {} {} at ({}, {}), segment {}, skip: {}",
+                                   b.size, b.y_mode, b.mi_col * 8, b.mi_row * 8,
+                                   b.segment_id, if b.skip { "yes" } else { "no" }));
+            }
+        }
+        lines
+    }
+
     /// Draw given lines sequentially from top to bottom.
     fn draw_lines(&mut self, start_pos: [i32; 2], lines: &[String]) {
         let (x, mut y) = (start_pos[0], start_pos[1]);
@@ -256,10 +906,37 @@ impl Gui {
 
     /// Render some VPx frame details on canvas.
     fn render_hud(&mut self) {
-        let lines = [
+        let timebase = self.reader.get_timebase();
+        let subframe = self.frame.as_ref()
+            .and_then(|frame| frame.subframes.get(frame.subframe_pos).cloned())
+            .unwrap_or(SubFrame {offset: 0, size: 0});
+        let mut lines = vec![
             format!("Filename: {}", self.reader.get_filename()),
-            format!("Frame: {}/{}", self.reader.get_frame_pos(), self.get_frame_count()),
+            format!("Container: {}, codec: {}",
+                    self.reader.get_format_name(),
+                    common::fourcc_to_string(self.reader.get_fourcc())),
+            format!("Frame: {}/{} (declared: {})",
+                    self.get_frame_number(),
+                    self.get_frame_count(),
+                    self.reader.get_declared_frame_count()
+                        .map_or_else(|| "-".to_string(), |n| n.to_string())),
+            format!("Sub-frame: {}/{} (offset: {}, size: {}){}",
+                    self.get_subframe_number(),
+                    self.frame.as_ref().map_or(0, |frame| frame.subframes.len()),
+                    subframe.offset,
+                    subframe.size,
+                    if self.get_image().is_some() { "" } else { ", not shown" }),
+            format!("Time: {} (pts: {})", self.get_frame_time(), self.get_frame_pts()),
+            format!("Timebase: {}/{}", timebase.0, timebase.1),
+            self.get_view_line(),
+            self.get_cache_line(),
+            format!("Display: {} (V to cycle)", self.display_mode),
         ];
+        lines.extend(self.get_image_line());
+        lines.push(self.get_hdr_line());
+        lines.extend(self.get_pixel_lines());
+        lines.extend(self.reader.get_codec_details());
+        lines.extend(self.get_vp9_lines());
         self.draw_lines([10, 10], &lines);
         let draw_result = self.text.draw_end(&mut self.canvas);
         try_print!(draw_result, "Error occured why drawing the text: {:?}");
@@ -0,0 +1,193 @@
+//! MD5 checksums of the decoded frames, compatible with the `.md5` files of
+//! libvpx test vectors.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+use ::cli::{self, Options};
+use ::common;
+use ::md5;
+use ::vpx;
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(io::Error),
+    /// Line of the MD5 file which has no checksum, 1-based.
+    InvalidLine(usize),
+    /// Number of the image, position of the container frame, expected and
+    /// actual checksums.
+    Mismatch(usize, usize, String, String),
+    /// Number of the decoded images and checksums in the MD5 file.
+    FrameCount(usize, usize),
+    /// Checksums are numbered from the first frame.
+    OffsetRange(cli::Range),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::IoError(err) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IoError(ref err) => write!(f, "Cannot read MD5 file: {}", err),
+            Error::InvalidLine(line) => write!(f, "No checksum at line {} of MD5 file", line),
+            Error::Mismatch(number, pos, ref expected, ref actual) => {
+                write!(f, "Frame {} (container frame {}) mismatch: expected {}, got {}",
+                       number, pos + 1, expected, actual)
+            },
+            Error::FrameCount(images, expected) => {
+                write!(f, "Decoded {} frames while MD5 file has {}", images, expected)
+            },
+            Error::OffsetRange(range) => {
+                write!(f, "Cannot verify range {}, it should start from the first frame", range)
+            },
+        }
+    }
+}
+
+/// Checksum of the Y, U and V planes without row padding, the same as
+/// libvpx `MD5::Add` computes. High bit depth samples take 2 little-endian
+/// bytes.
+fn get_digest(image: &vpx::Image) -> Result<md5::Digest, common::Error> {
+    let mut md5 = md5::Context::new();
+    try!(image.for_each_le_row::<_, common::Error>(|row| {
+        md5.consume(row);
+        Ok(())
+    }));
+    Ok(md5.compute())
+}
+
+/// Name of the frame in libvpx MD5 files, e.g.
+/// "vp90-2-00-quantizer-00-352x288-0001.i420".
+fn get_frame_name(stem: &str, image: &vpx::Image, number: usize) -> String {
+    format_frame_name(stem, image.get_display_width(), image.get_display_height(),
+                      image.get_chroma_shift(), number)
+}
+
+fn format_frame_name(stem: &str,
+                     width: u16,
+                     height: u16,
+                     chroma_shift: (usize, usize),
+                     number: usize) -> String {
+    let subsampling = match chroma_shift {
+        (1, 0) => "i422",
+        (0, 1) => "i440",
+        (0, 0) => "i444",
+        _ => "i420",
+    };
+    format!("{}-{}x{}-{:04}.{}", stem, width, height, number, subsampling)
+}
+
+fn read_md5_file(filename: &str) -> Result<Vec<String>, Error> {
+    let mut data = String::new();
+    try!(try!(File::open(filename)).read_to_string(&mut data));
+    parse_md5(&data)
+}
+
+/// Checksums of the MD5 file in order, one per line followed by the frame
+/// name. Empty lines are skipped.
+fn parse_md5(data: &str) -> Result<Vec<String>, Error> {
+    let mut digests = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let digest = match line.split_whitespace().next() {
+            Some(digest) => digest,
+            None => continue,
+        };
+        if digest.len() != 32 || !digest.chars().all(|c| c.is_digit(16)) {
+            return Err(Error::InvalidLine(i + 1));
+        }
+        digests.push(digest.to_lowercase());
+    }
+    Ok(digests)
+}
+
+/// Decode frames of the range and print checksum of every image, or check
+/// them against the MD5 file and stop at the first mismatch.
+pub fn run(opts: &Options) -> Result<(), common::Error> {
+    let expected = match opts.verify {
+        Some(ref filename) => Some(try!(read_md5_file(filename))),
+        None => None,
+    };
+    // NOTE(Kagami): Hidden frames don't produce images so number of the
+    // first image is unknown without decoding all preceding frames.
+    if expected.is_some() && opts.range.first > 0 {
+        return Err(From::from(Error::OffsetRange(opts.range)));
+    }
+    let mut reader = try!(opts.open_reader());
+    let mut decoder = try!(opts.init_decoder(&*reader));
+    let stem = Path::new(&opts.filename).file_stem()
+                                        .and_then(|stem| stem.to_str())
+                                        .unwrap_or("frame")
+                                        .to_string();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut number = 0;
+    let images = try!(cli::decode_range(&mut *reader, &mut decoder, opts.range,
+                                        |pos, image, _| {
+        number += 1;
+        let digest = try!(get_digest(image)).to_string();
+        match expected {
+            Some(ref expected) => match expected.get(number - 1) {
+                Some(digest_expected) if *digest_expected == digest => {},
+                Some(digest_expected) => {
+                    let err = Error::Mismatch(number, pos, digest_expected.clone(), digest);
+                    return Err(From::from(err));
+                },
+                None => return Err(From::from(Error::FrameCount(number, expected.len()))),
+            },
+            None => try!(writeln!(out, "{}  {}", digest, get_frame_name(&stem, image, number))),
+        }
+        Ok(())
+    }));
+    if let Some(ref expected) = expected {
+        // Shorter range is checked only partially.
+        if opts.range.last.is_none() && images != expected.len() {
+            return Err(From::from(Error::FrameCount(images, expected.len())));
+        }
+        printerr!("{} frames match {}", images, opts.verify.as_ref().unwrap());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_names() {
+        let stem = "vp90-2-00-quantizer-00";
+        assert_eq!(format_frame_name(stem, 352, 288, (1, 1), 1),
+                   "vp90-2-00-quantizer-00-352x288-0001.i420");
+        assert_eq!(format_frame_name("a", 8, 2, (1, 0), 12345), "a-8x2-12345.i422");
+        assert_eq!(format_frame_name("a", 8, 2, (0, 1), 2), "a-8x2-0002.i440");
+        assert_eq!(format_frame_name("a", 8, 2, (0, 0), 3), "a-8x2-0003.i444");
+    }
+
+    #[test]
+    fn libvpx_lines() {
+        let digest = "8d3ed8f5ab9e4b6aba0c2b5de1bd4ca7";
+        let line = format!("{}  {}\n", digest,
+                           format_frame_name("vp90-2-00-quantizer-00", 352, 288, (1, 1), 1));
+        assert_eq!(parse_md5(&line).unwrap(), [digest]);
+        let data = "\n8D3ED8F5AB9E4B6ABA0C2B5DE1BD4CA7  a.i420\r\n\n  \n\
+                    d41d8cd98f00b204e9800998ecf8427e\n";
+        assert_eq!(parse_md5(data).unwrap(),
+                   [digest, "d41d8cd98f00b204e9800998ecf8427e"]);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let check = |data: &str, line| match parse_md5(data) {
+            Err(Error::InvalidLine(n)) => assert_eq!(n, line, "data {:?}", data),
+            res => panic!("data {:?}: {:?}", data, res),
+        };
+        check("frame-0001.i420", 1);
+        check("d41d8cd98f00b204e9800998ecf8427e\n\nd41d8cd98f00b204  a.i420", 3);
+        check("d41d8cd98f00b204e9800998ecf8427g  a.i420", 1);
+        check("d41d8cd98f00b204e9800998ecf8427e0  a.i420", 1);
+        assert!(parse_md5("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+//! HDR to SDR conversion: PQ and HLG transfer functions, BT.2020 to BT.709
+//! gamut mapping and tone mapping.
+
+use std::fmt;
+use ::colorspace::pack_rgba;
+
+/// PQ (SMPTE ST 2084) constants.
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+const PQ_MAX_NITS: f32 = 10000.0;
+
+/// HLG (ARIB STD-B67) constants.
+const HLG_A: f32 = 0.17883277;
+const HLG_B: f32 = 0.28466892;
+const HLG_C: f32 = 0.5599107;
+/// Nominal peak of HLG display, system gamma is 1.2 for it.
+const HLG_PEAK_NITS: f32 = 1000.0;
+const HLG_GAMMA: f32 = 1.2;
+
+// NOTE(Kagami): Mastering metadata is not parsed, assume typical peak of
+// the HDR content unless user gives the actual one.
+pub const DEFAULT_SOURCE_NITS: f32 = 1000.0;
+
+/// Linear BT.2020 to linear BT.709 primaries.
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+const BT2020_LUMA: [f32; 3] = [0.2627, 0.6780, 0.0593];
+const BT709_LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Resolution of the transfer function tables.
+const LUT_SIZE: usize = 4096;
+const OUT_OF_GAMUT_COLOR: u32 = pack_rgba(255, 0, 255);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    Sdr,
+    Pq,
+    Hlg,
+}
+
+impl Transfer {
+    /// Transfer from the ISO/IEC 23001-8 code.
+    pub fn from_code(code: u8) -> Transfer {
+        match code {
+            16 => Transfer::Pq,
+            18 => Transfer::Hlg,
+            _ => Transfer::Sdr,
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Transfer::Sdr => "SDR",
+            Transfer::Pq => "PQ",
+            Transfer::Hlg => "HLG",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    Clip,
+    Reinhard,
+    Hable,
+    Bt2390,
+}
+
+impl ToneMap {
+    pub fn next(&self) -> ToneMap {
+        match *self {
+            ToneMap::Clip => ToneMap::Reinhard,
+            ToneMap::Reinhard => ToneMap::Hable,
+            ToneMap::Hable => ToneMap::Bt2390,
+            ToneMap::Bt2390 => ToneMap::Clip,
+        }
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ToneMap::Clip => "clip",
+            ToneMap::Reinhard => "Reinhard",
+            ToneMap::Hable => "Hable",
+            ToneMap::Bt2390 => "BT.2390",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Nonlinear PQ value to nits.
+fn pq_eotf(val: f32) -> f32 {
+    let p = val.max(0.0).powf(1.0 / PQ_M2);
+    let l = (p - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * p);
+    l.powf(1.0 / PQ_M1) * PQ_MAX_NITS
+}
+
+/// Nits to nonlinear PQ value.
+fn pq_inverse_eotf(nits: f32) -> f32 {
+    let l = (nits / PQ_MAX_NITS).max(0.0).powf(PQ_M1);
+    ((PQ_C1 + PQ_C2 * l) / (1.0 + PQ_C3 * l)).powf(PQ_M2)
+}
+
+/// Nonlinear HLG value to relative scene light.
+fn hlg_inverse_oetf(val: f32) -> f32 {
+    let val = val.max(0.0);
+    if val <= 0.5 {
+        val * val / 3.0
+    } else {
+        (((val - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+    }
+}
+
+fn hable_curve(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+/// sRGB encoding of the linear value.
+fn srgb_oetf(val: f32) -> f32 {
+    if val <= 0.0031308 {
+        val * 12.92
+    } else {
+        1.055 * val.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn dot(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Conversion of nonlinear BT.2020 RGB to SDR BT.709 RGBA8.
+pub struct ToneMapper {
+    transfer: Transfer,
+    tone_map: ToneMap,
+    /// Peak luminance of the content, tone mapping curves end there.
+    source_nits: f32,
+    /// Luminance of the SDR white.
+    display_nits: f32,
+    /// Nits to the value relative to the SDR white, with exposure.
+    scale: f32,
+    /// Paint colors outside of BT.709 gamut.
+    highlight_gamut: bool,
+    /// Nonlinear value to nits.
+    eotf_lut: Vec<f32>,
+    /// Linear value in [0, 1] to the display code value.
+    oetf_lut: Vec<u8>,
+}
+
+impl ToneMapper {
+    /// `transfer` should be PQ or HLG, SDR doesn't need tone mapping.
+    /// `exposure` is adjustment in stops applied before tone mapping.
+    pub fn new(transfer: Transfer, tone_map: ToneMap, source_nits: f32, display_nits: f32,
+               exposure: f32, highlight_gamut: bool) -> ToneMapper {
+        assert!(transfer != Transfer::Sdr);
+        let eotf_lut = (0..LUT_SIZE).map(|i| {
+            let val = i as f32 / (LUT_SIZE - 1) as f32;
+            if transfer == Transfer::Pq {
+                pq_eotf(val)
+            } else {
+                // Scene light, system gamma is applied later on luminance.
+                hlg_inverse_oetf(val)
+            }
+        }).collect();
+        let oetf_lut = (0..LUT_SIZE).map(|i| {
+            let val = i as f32 / (LUT_SIZE - 1) as f32;
+            (srgb_oetf(val) * 255.0 + 0.5) as u8
+        }).collect();
+        ToneMapper {
+            transfer: transfer,
+            tone_map: tone_map,
+            source_nits: source_nits,
+            display_nits: display_nits,
+            scale: 2f32.powf(exposure) / display_nits,
+            highlight_gamut: highlight_gamut,
+            eotf_lut: eotf_lut,
+            oetf_lut: oetf_lut,
+        }
+    }
+
+    fn eotf(&self, val: f32) -> f32 {
+        let idx = (val * (LUT_SIZE - 1) as f32 + 0.5).max(0.0).min((LUT_SIZE - 1) as f32);
+        self.eotf_lut[idx as usize]
+    }
+
+    fn oetf(&self, val: f32) -> u32 {
+        let idx = (val * (LUT_SIZE - 1) as f32 + 0.5).max(0.0).min((LUT_SIZE - 1) as f32);
+        self.oetf_lut[idx as usize] as u32
+    }
+
+    /// Map luminance relative to the SDR white into [0, 1].
+    fn tone_map(&self, val: f32) -> f32 {
+        let peak = self.source_nits / self.display_nits;
+        if peak <= 1.0 {
+            return val.min(1.0);
+        }
+        match self.tone_map {
+            ToneMap::Clip => val.min(1.0),
+            // Extended variant with the white point at the source peak.
+            ToneMap::Reinhard => val * (1.0 + val / (peak * peak)) / (1.0 + val),
+            ToneMap::Hable => hable_curve(val.min(peak)) / hable_curve(peak),
+            ToneMap::Bt2390 => {
+                // Knee in PQ space normalized to the source range.
+                let src_max = pq_inverse_eotf(self.source_nits);
+                let e1 = pq_inverse_eotf(val * self.display_nits) / src_max;
+                let max_lum = pq_inverse_eotf(self.display_nits) / src_max;
+                let ks = 1.5 * max_lum - 0.5;
+                let e2 = if e1 < ks {
+                    e1
+                } else {
+                    let t = ((e1 - ks) / (1.0 - ks)).min(1.0);
+                    let (t2, t3) = (t * t, t * t * t);
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * ks + (t3 - 2.0 * t2 + t) * (1.0 - ks) +
+                    (-2.0 * t3 + 3.0 * t2) * max_lum
+                };
+                (pq_eotf(e2 * src_max) / self.display_nits).min(1.0)
+            },
+        }
+    }
+
+    /// Convert nonlinear BT.2020 RGB in [0, 1] to RGBA8.
+    pub fn convert(&self, rgb: [f32; 3]) -> u32 {
+        let mut light = [self.eotf(rgb[0]), self.eotf(rgb[1]), self.eotf(rgb[2])];
+        if self.transfer == Transfer::Hlg {
+            // OOTF, scene light to display nits.
+            let luma = dot(&BT2020_LUMA, &light);
+            let gain = HLG_PEAK_NITS * luma.max(1e-6).powf(HLG_GAMMA - 1.0);
+            for c in light.iter_mut() {
+                *c *= gain;
+            }
+        }
+        let mut out = [0.0; 3];
+        let mut out_of_gamut = false;
+        for i in 0..3 {
+            out[i] = dot(&BT2020_TO_BT709[i], &light) * self.scale;
+            if out[i] < 0.0 {
+                out_of_gamut = true;
+                out[i] = 0.0;
+            }
+        }
+        if out_of_gamut && self.highlight_gamut {
+            return OUT_OF_GAMUT_COLOR;
+        }
+        // Scale all components by the luminance ratio to keep hue.
+        let luma = dot(&BT709_LUMA, &out);
+        if luma > 0.0 {
+            let ratio = self.tone_map(luma) / luma;
+            for c in out.iter_mut() {
+                *c *= ratio;
+            }
+        }
+        pack_rgba(self.oetf(out[0]), self.oetf(out[1]), self.oetf(out[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE_MAPS: [ToneMap; 4] = [ToneMap::Clip, ToneMap::Reinhard, ToneMap::Hable,
+                                     ToneMap::Bt2390];
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn pq() {
+        assert_close(pq_eotf(0.0), 0.0, 1e-6);
+        assert_close(pq_eotf(1.0), 10000.0, 0.5);
+        for &nits in &[0.1, 1.0, 100.0, 203.0, 1000.0, 4000.0, 10000.0] {
+            assert_close(pq_eotf(pq_inverse_eotf(nits)), nits, nits * 1e-3);
+        }
+    }
+
+    #[test]
+    fn hlg() {
+        assert_close(hlg_inverse_oetf(0.0), 0.0, 1e-6);
+        assert_close(hlg_inverse_oetf(0.5), 1.0 / 12.0, 1e-6);
+        // Log segment continues the square root one.
+        assert_close(hlg_inverse_oetf(0.5 + 1e-4), hlg_inverse_oetf(0.5), 1e-4);
+        assert_close(hlg_inverse_oetf(1.0), 1.0, 1e-4);
+    }
+
+    #[test]
+    fn tone_maps() {
+        let (source_nits, display_nits) = (1000.0, 200.0);
+        let peak = source_nits / display_nits;
+        for &tone_map in &TONE_MAPS {
+            let mapper = ToneMapper::new(Transfer::Pq, tone_map, source_nits, display_nits,
+                                         0.0, false);
+            assert_close(mapper.tone_map(peak), 1.0, 1e-3);
+            let mut prev = 0.0;
+            for i in 0..101 {
+                let val = mapper.tone_map(peak * i as f32 / 100.0);
+                assert!(val >= prev - 1e-6, "{} isn't monotonic at step {}", tone_map, i);
+                prev = val;
+            }
+        }
+    }
+
+    #[test]
+    fn gamut_highlight() {
+        let green = [0.0, 0.6, 0.0];
+        let mapper = ToneMapper::new(Transfer::Pq, ToneMap::Bt2390, 1000.0, 203.0, 0.0, true);
+        assert_eq!(mapper.convert(green), OUT_OF_GAMUT_COLOR);
+        let mapper = ToneMapper::new(Transfer::Pq, ToneMap::Bt2390, 1000.0, 203.0, 0.0, false);
+        let pixel = mapper.convert(green);
+        assert!(pixel != OUT_OF_GAMUT_COLOR);
+        // Clipped to the nearest BT.709 color.
+        assert_eq!(pixel & 0xff00ff, 0);
+        // Gray is inside of any gamut.
+        let mapper = ToneMapper::new(Transfer::Hlg, ToneMap::Clip, 1000.0, 203.0, 0.0, true);
+        assert!(mapper.convert([0.5, 0.5, 0.5]) != OUT_OF_GAMUT_COLOR);
+    }
+}
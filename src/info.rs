@@ -0,0 +1,324 @@
+//! Container and stream summary, decodes the whole file or the frame range
+//! to collect image formats and check that every frame is decodable.
+
+use std::io::{self, Write};
+use ::cli::{self, Options};
+use ::common;
+use ::json;
+
+/// Window of the peak bitrate, in seconds.
+const PEAK_WINDOW: f64 = 1.0;
+
+/// Distinct values in order of appearance with number of frames of each.
+struct Tally {
+    items: Vec<(String, usize)>,
+}
+
+impl Tally {
+    fn new() -> Tally {
+        Tally {items: Vec::new()}
+    }
+
+    fn add(&mut self, name: String) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.0 == name) {
+            item.1 += 1;
+            return;
+        }
+        self.items.push((name, 1));
+    }
+
+    fn to_text(&self) -> String {
+        if self.items.is_empty() {
+            return "-".to_string();
+        }
+        let items: Vec<String> = self.items.iter()
+                                           .map(|&(ref name, n)| format!("{} ({})", name, n))
+                                           .collect();
+        items.join(", ")
+    }
+
+    fn to_json(&self, key: &str) -> json::Value {
+        let items: Vec<json::Value> = self.items.iter().map(|&(ref name, n)| {
+            let mut item = json::Object::new();
+            item.add(key, &name[..]);
+            item.add("frames", n);
+            json::Value::from(item)
+        }).collect();
+        json::Value::Array(items)
+    }
+}
+
+struct Summary {
+    filename: String,
+    container: &'static str,
+    fourcc: String,
+    width: u16,
+    height: u16,
+    timebase: (u32, u32),
+    declared_frames: Option<u32>,
+    /// Frames the summary is collected from, the whole file by default.
+    range: cli::Range,
+    frames: usize,
+    /// Number of images output by decoder.
+    shown_frames: usize,
+    /// Size of all frames in bytes.
+    size: u64,
+    /// In seconds, estimated by timestamps.
+    duration: Option<f64>,
+    /// In bits per second.
+    average_bitrate: Option<f64>,
+    peak_bitrate: Option<f64>,
+    /// Positions of the keyframes.
+    keyframes: Vec<usize>,
+    /// Dimensions of the decoded images.
+    sizes: Tally,
+    formats: Tally,
+    color_spaces: Tally,
+    transfer: Option<u8>,
+    codec_details: Vec<String>,
+    /// Positions of the frames decoder failed on with the reasons.
+    errors: Vec<(usize, String)>,
+}
+
+/// Duration, including the last frame, and peak bitrate by the frame times
+/// and sizes in bytes.
+fn get_timing(mut frames: Vec<(f64, u32)>) -> (Option<f64>, Option<f64>) {
+    // Broken timestamps don't tell anything about timing.
+    frames.retain(|frame| frame.0.is_finite());
+    if frames.len() < 2 {
+        return (None, None);
+    }
+    frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let span = frames[frames.len() - 1].0 - frames[0].0;
+    if span <= 0.0 {
+        return (None, None);
+    }
+    let duration = span + span / (frames.len() - 1) as f64;
+    let mut peak = 0;
+    let mut window = 0;
+    let mut end = 0;
+    for start in 0..frames.len() {
+        while end < frames.len() && frames[end].0 < frames[start].0 + PEAK_WINDOW {
+            window += frames[end].1 as u64;
+            end += 1;
+        }
+        if window > peak {
+            peak = window;
+        }
+        window -= frames[start].1 as u64;
+    }
+    (Some(duration), Some(peak as f64 * 8.0 / PEAK_WINDOW))
+}
+
+fn collect(opts: &Options) -> Result<Summary, common::Error> {
+    let mut reader = try!(opts.open_reader());
+    let mut decoder = try!(opts.init_decoder(&*reader));
+    let count = reader.get_frame_count();
+    let positions = opts.range.positions(count);
+    // Empty file still has the summary.
+    if positions.start >= positions.end && !opts.range.is_all() {
+        return Err(From::from(cli::Error::EmptyRange(opts.range, count)));
+    }
+    let mut summary = Summary {
+        filename: reader.get_filename().to_string(),
+        container: reader.get_format_name(),
+        fourcc: common::fourcc_to_string(reader.get_fourcc()),
+        width: reader.get_width(),
+        height: reader.get_height(),
+        timebase: reader.get_timebase(),
+        declared_frames: reader.get_declared_frame_count(),
+        range: opts.range,
+        frames: positions.len(),
+        shown_frames: 0,
+        size: 0,
+        duration: None,
+        average_bitrate: None,
+        peak_bitrate: None,
+        keyframes: Vec::new(),
+        sizes: Tally::new(),
+        formats: Tally::new(),
+        color_spaces: Tally::new(),
+        transfer: reader.get_transfer_characteristics(),
+        codec_details: reader.get_codec_details(),
+        errors: Vec::new(),
+    };
+    let mut times = Vec::with_capacity(positions.len());
+    let start = if positions.start < positions.end {
+        try!(cli::find_keyframe(&mut *reader, &decoder, positions.start))
+    } else {
+        positions.start
+    };
+    for pos in start..positions.end {
+        let data = try!(reader.read_frame(pos));
+        // Frames preceding the range are only needed as references.
+        if pos < positions.start {
+            if !data.is_empty() {
+                let _ = decoder.decode(&data);
+            }
+            continue;
+        }
+        summary.size += data.len() as u64;
+        if let Some(time) = reader.get_frame_time(pos) {
+            times.push((time, data.len() as u32));
+        }
+        if data.is_empty() {
+            continue;
+        }
+        let keyframe = match reader.is_keyframe(pos) {
+            Some(keyframe) => keyframe,
+            None => decoder.is_keyframe(&data).unwrap_or(false),
+        };
+        if keyframe {
+            summary.keyframes.push(pos);
+        }
+        let images = match decoder.decode(&data) {
+            Ok(images) => images,
+            Err(err) => {
+                summary.errors.push((pos, err.to_string()));
+                continue;
+            },
+        };
+        for image in images {
+            summary.shown_frames += 1;
+            summary.sizes.add(format!("{}x{}", image.get_display_width(),
+                                      image.get_display_height()));
+            summary.formats.add(format!("{} {}-bit", image.get_format_name(),
+                                        image.get_bit_depth()));
+            let color_space = image.get_matrix().map_or_else(|| "unspecified".to_string(),
+                                                             |matrix| matrix.to_string());
+            summary.color_spaces.add(color_space);
+        }
+    }
+    let (duration, peak_bitrate) = get_timing(times);
+    summary.duration = duration;
+    summary.average_bitrate = duration.map(|duration| summary.size as f64 * 8.0 / duration);
+    summary.peak_bitrate = peak_bitrate;
+    Ok(summary)
+}
+
+fn print_text(out: &mut Write, summary: &Summary) -> io::Result<()> {
+    let or_dash = |val: Option<String>| val.unwrap_or_else(|| "-".to_string());
+    let kbps = |bitrate: Option<f64>| {
+        or_dash(bitrate.map(|bitrate| format!("{:.1} kbit/s", bitrate / 1000.0)))
+    };
+    try!(writeln!(out, "File: {}", summary.filename));
+    try!(writeln!(out, "Container: {}, codec: {}", summary.container, summary.fourcc));
+    try!(writeln!(out, "Size: {}x{} (decoded: {})",
+                  summary.width, summary.height, summary.sizes.to_text()));
+    try!(writeln!(out, "Time base: {}/{}", summary.timebase.0, summary.timebase.1));
+    if !summary.range.is_all() {
+        try!(writeln!(out, "Range: {}", summary.range));
+    }
+    try!(writeln!(out, "Frames: {} (declared: {}, shown: {})",
+                  summary.frames,
+                  or_dash(summary.declared_frames.map(|n| n.to_string())),
+                  summary.shown_frames));
+    let duration = summary.duration.map(|d| format!("{:.3} s", d));
+    try!(writeln!(out, "Duration: {}", or_dash(duration)));
+    try!(writeln!(out, "Bitrate: {} average, {} peak ({} bytes total)",
+                  kbps(summary.average_bitrate), kbps(summary.peak_bitrate), summary.size));
+    let positions: Vec<String> = summary.keyframes.iter()
+                                                  .map(|pos| (pos + 1).to_string())
+                                                  .collect();
+    if positions.is_empty() {
+        try!(writeln!(out, "Keyframes: 0"));
+    } else {
+        try!(writeln!(out, "Keyframes: {} at {}", positions.len(), positions.join(", ")));
+    }
+    try!(writeln!(out, "Formats: {}", summary.formats.to_text()));
+    try!(writeln!(out, "Color spaces: {}", summary.color_spaces.to_text()));
+    if let Some(transfer) = summary.transfer {
+        try!(writeln!(out, "Transfer characteristics: {}", transfer));
+    }
+    for line in &summary.codec_details {
+        try!(writeln!(out, "{}", line));
+    }
+    try!(writeln!(out, "Decode errors: {}", summary.errors.len()));
+    for &(pos, ref err) in &summary.errors {
+        try!(writeln!(out, "  frame {}: {}", pos + 1, err));
+    }
+    Ok(())
+}
+
+fn to_json(summary: &Summary) -> json::Object {
+    let mut root = json::Object::new();
+    root.add("filename", &summary.filename[..]);
+    root.add("container", summary.container);
+    root.add("fourcc", &summary.fourcc[..]);
+    root.add("width", summary.width);
+    root.add("height", summary.height);
+    root.add("timebase", vec![summary.timebase.0, summary.timebase.1]);
+    root.add("declared_frames", summary.declared_frames);
+    root.add("range", if summary.range.is_all() {
+        None
+    } else {
+        Some(summary.range.to_string())
+    });
+    root.add("frames", summary.frames);
+    root.add("shown_frames", summary.shown_frames);
+    root.add("size", summary.size);
+    root.add("duration", summary.duration);
+    root.add("average_bitrate", summary.average_bitrate);
+    root.add("peak_bitrate", summary.peak_bitrate);
+    root.add("keyframes", summary.keyframes.iter().map(|pos| pos + 1).collect::<Vec<_>>());
+    root.add("decoded_sizes", summary.sizes.to_json("size"));
+    root.add("formats", summary.formats.to_json("format"));
+    root.add("color_spaces", summary.color_spaces.to_json("color_space"));
+    root.add("transfer_characteristics", summary.transfer);
+    root.add("codec_details", summary.codec_details.clone());
+    let errors: Vec<json::Value> = summary.errors.iter().map(|&(pos, ref err)| {
+        let mut error = json::Object::new();
+        error.add("frame", pos + 1);
+        error.add("error", &err[..]);
+        json::Value::from(error)
+    }).collect();
+    root.add("decode_errors", errors);
+    root
+}
+
+pub fn run(opts: &Options) -> Result<(), common::Error> {
+    let summary = try!(collect(opts));
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if opts.json {
+        try!(writeln!(out, "{}", to_json(&summary)));
+    } else {
+        try!(print_text(&mut out, &summary));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64;
+
+    #[test]
+    fn timing() {
+        // Duration includes the average frame duration after the last one.
+        let (duration, peak) = get_timing(vec![(1.0, 100), (0.0, 100), (0.5, 100)]);
+        assert_eq!(duration, Some(1.5));
+        // Window ends before the frame at 1.0.
+        assert_eq!(peak, Some(1600.0));
+        let (duration, _) = get_timing(vec![(0.0, 1), (f64::NAN, 1), (1.0, 1),
+                                            (f64::INFINITY, 1)]);
+        assert_eq!(duration, Some(2.0));
+    }
+
+    #[test]
+    fn peak_window() {
+        let sizes = [10, 10, 10, 10, 100, 100, 100, 100, 10];
+        let frames = sizes.iter().enumerate().map(|(i, &size)| (i as f64 * 0.25, size));
+        let (duration, peak) = get_timing(frames.collect());
+        assert_eq!(duration, Some(2.25));
+        assert_eq!(peak, Some(400.0 * 8.0));
+    }
+
+    #[test]
+    fn no_timing() {
+        assert_eq!(get_timing(Vec::new()), (None, None));
+        assert_eq!(get_timing(vec![(1.0, 100)]), (None, None));
+        assert_eq!(get_timing(vec![(2.0, 100), (2.0, 100)]), (None, None));
+        assert_eq!(get_timing(vec![(f64::NAN, 100), (2.0, 100)]), (None, None));
+    }
+}
@@ -3,18 +3,22 @@
 
 use std::fmt;
 use std::io;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::fs::File;
 use ::common;
+use ::demux::{Demuxer, FrameEntry, Source};
 
-const DKIF: [u8; 4] = [68, 75, 73, 70];
-const VP9_FOURCC: u32 = 0x30395056;
+/// "DKIF" signature.
+pub const SIGNATURE: [u8; 4] = [68, 75, 73, 70];
+const HEADER_SIZE: u64 = 32;
+const FRAME_HEADER_SIZE: u64 = 12;
 
 #[derive(Debug)]
 pub enum Error {
     IoError(io::Error),
     // TODO(Kagami): Better granularity of parse errors.
     ParseError,
+    FrameOutOfRange(usize),
 }
 
 impl From<io::Error> for Error {
@@ -26,14 +30,14 @@ impl fmt::Display for Error {
         let descr = match *self {
             Error::IoError(ref err) => format!("IO error: {}", err),
             Error::ParseError => format!("Parse error"),
+            Error::FrameOutOfRange(pos) => format!("No frame #{} in file", pos),
         };
         f.write_str(&descr)
     }
 }
 
 // TODO(Kagami): Better BufReader.
-pub fn read_bytes(breader: &mut io::BufReader<File>,
-                  count: usize) -> Result<Box<[u8]>, Error> {
+pub fn read_bytes<R: Read>(breader: &mut R, count: usize) -> Result<Box<[u8]>, Error> {
     let mut buf = common::alloc(count);
     let mut collected = 0;
     while collected < count {
@@ -47,40 +51,38 @@ pub fn read_bytes(breader: &mut io::BufReader<File>,
 }
 
 pub struct Reader {
-    breader: io::BufReader<File>,
+    breader: io::BufReader<Box<Source>>,
     filename: String,
-    #[allow(dead_code)]
     fourcc: u32,
     width: u16,
     height: u16,
-    /// Frame position we are currently viewing file at.
-    /// Set to 0 after file header was read.
-    frame_pos: usize,
-    frame_count: Option<usize>,
+    /// Time base as numerator/denominator pair, i.e. duration of one tick in
+    /// seconds.
+    timebase: (u32, u32),
+    /// Number of frames as written in file header, may be bogus.
+    declared_frame_count: u32,
+    /// Table of all complete frames in file.
+    frames: Vec<FrameEntry>,
 }
 
 impl Reader {
-    // It's a shame Rust doesn't have const struct fields...
-    pub fn get_filename(&self) -> &str { &self.filename }
-    #[allow(dead_code)]
-    pub fn get_fourcc(&self) -> u32 { self.fourcc }
-    pub fn get_width(&self) -> u16 { self.width }
-    pub fn get_height(&self) -> u16 { self.height }
-    pub fn get_frame_pos(&self) -> usize { self.frame_pos }
-    pub fn get_frame_count(&self) -> Option<usize> { self.frame_count }
-
     pub fn open(filename: &str) -> Result<Reader, Error> {
         let fh = try!(File::open(&filename));
-        let mut breader = io::BufReader::new(fh);
-        let header = try!(read_bytes(&mut breader, 32));
+        let file_size = try!(fh.metadata()).len();
+        Reader::from_source(filename, Box::new(fh), file_size)
+    }
+
+    fn from_source(filename: &str, source: Box<Source>,
+                   file_size: u64) -> Result<Reader, Error> {
+        let mut breader = io::BufReader::new(source);
+        let header = try!(read_bytes(&mut breader, HEADER_SIZE as usize));
         // Parse and check only few header fields. It's better if we can view a
         // quite corruped files too.
-        if &header[..4] != DKIF {
+        if &header[..4] != SIGNATURE {
             return Err(Error::ParseError);
         }
         let fourcc = common::get_le32(&header[8..]);
-        // TODO(Kagami): Support for VP8.
-        if fourcc != VP9_FOURCC {
+        if fourcc != common::VP8_FOURCC && fourcc != common::VP9_FOURCC {
             return Err(Error::ParseError);
         }
         let width = common::get_le16(&header[12..]);
@@ -88,53 +90,136 @@ impl Reader {
         if width == 0 || height == 0 {
             return Err(Error::ParseError);
         }
+        // NOTE(Kagami): Spec names them as rate and scale, rate goes first.
+        let timebase_den = common::get_le32(&header[16..]);
+        let timebase_num = common::get_le32(&header[20..]);
+        let declared_frame_count = common::get_le32(&header[24..]);
+        let frames = try!(Self::index_frames(&mut breader, file_size));
         Ok(Reader {
             breader: breader,
             filename: filename.to_string(),
             fourcc: fourcc,
             width: width,
             height: height,
-            frame_pos: 0,
-            frame_count: None,
+            timebase: (timebase_num, timebase_den),
+            declared_frame_count: declared_frame_count,
+            frames: frames,
         })
     }
+
+    /// Walk through all frame headers and remember where the frames are.
+    fn index_frames(breader: &mut io::BufReader<Box<Source>>,
+                    file_size: u64) -> Result<Vec<FrameEntry>, Error> {
+        let mut offset = try!(breader.seek(SeekFrom::Start(HEADER_SIZE)));
+        let mut frames = Vec::new();
+        // NOTE(Kagami): IVF header has *number of frames in file* property per
+        // spec, but ffmpeg sets 0 to that field for some reason. So we just
+        // read the file until we reached the end.
+        while offset + FRAME_HEADER_SIZE <= file_size {
+            let fheader = try!(read_bytes(breader, FRAME_HEADER_SIZE as usize));
+            let size = common::get_le32(&fheader[..]);
+            let data_offset = offset + FRAME_HEADER_SIZE;
+            if data_offset + size as u64 > file_size {
+                // Truncated frame at the end of file, skip it.
+                break;
+            }
+            frames.push(FrameEntry {
+                offset: data_offset,
+                size: size,
+                pts: common::get_le64(&fheader[4..]),
+                keyframe: None,
+            });
+            offset = try!(breader.seek(SeekFrom::Current(size as i64)));
+        }
+        Ok(frames)
+    }
+
+    fn read_frame_data(&mut self, pos: usize) -> Result<Box<[u8]>, Error> {
+        let entry = match self.frames.get(pos) {
+            Some(entry) => *entry,
+            None => return Err(Error::FrameOutOfRange(pos)),
+        };
+        try!(self.breader.seek(SeekFrom::Start(entry.offset)));
+        read_bytes(&mut self.breader, entry.size as usize)
+    }
 }
 
-impl Iterator for Reader {
-    type Item = Box<[u8]>;
+impl Demuxer for Reader {
+    // It's a shame Rust doesn't have const struct fields...
+    fn get_filename(&self) -> &str { &self.filename }
+    fn get_format_name(&self) -> &'static str { "IVF" }
+    fn get_fourcc(&self) -> u32 { self.fourcc }
+    fn get_width(&self) -> u16 { self.width }
+    fn get_height(&self) -> u16 { self.height }
+    fn get_timebase(&self) -> (u32, u32) { self.timebase }
+    fn get_declared_frame_count(&self) -> Option<u32> { Some(self.declared_frame_count) }
+    fn get_frames(&self) -> &[FrameEntry] { &self.frames }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.frame_count {
-            Some(count) if self.frame_pos >= count => return None,
-            _ => {},
-        }
-        match read_bytes(&mut self.breader, 12) {
-            Ok(fheader) => {
-                let fsize = common::get_le32(&fheader[..]) as usize;
-                match read_bytes(&mut self.breader, fsize) {
-                    Ok(frame) => {
-                        self.frame_pos += 1;
-                        Some(frame)
-                    },
-                    Err(_) => {
-                        // No more frames.
-                        // TODO: Panic on non-EOF errors.
-                        self.frame_count = Some(self.frame_pos);
-                        None
-                    },
-                }
-            },
-            Err(_) => {
-                // No more frames.
-                // NOTE(Kagami): IVF header has *number of frames in file*
-                // property per spec, but ffmpeg sets 0 to that field for some
-                // reason. So we just read the file until we reached the end.
-                // TODO: Panic on non-EOF errors.
-                self.frame_count = Some(self.frame_pos);
-                None
-            },
-        }
+    fn read_frame(&mut self, pos: usize) -> Result<Box<[u8]>, common::Error> {
+        self.read_frame_data(pos).map_err(From::from)
     }
 }
 
-// TODO(Kagami): prev().
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::*;
+
+    /// IVF file with 30000/1001 timebase and frames of the given sizes.
+    fn ivf_data(declared_frame_count: u32, sizes: &[u32]) -> Vec<u8> {
+        let mut data = b"DKIF\x00\x00\x20\x00VP90".to_vec();
+        data.extend_from_slice(&[0x40, 0x01, 0xf0, 0x00]);
+        data.extend_from_slice(&[0x30, 0x75, 0x00, 0x00, 0xe9, 0x03, 0x00, 0x00]);
+        data.extend((0..4).map(|i| (declared_frame_count >> (i * 8)) as u8));
+        data.extend_from_slice(&[0; 4]);
+        for (i, &size) in sizes.iter().enumerate() {
+            data.extend((0..4).map(|j| (size >> (j * 8)) as u8));
+            data.extend((0..8).map(|j| (i as u64 * 2 >> (j * 8)) as u8));
+            data.extend((0..size).map(|_| i as u8));
+        }
+        data
+    }
+
+    fn open(data: Vec<u8>) -> Result<Reader, Error> {
+        let size = data.len() as u64;
+        Reader::from_source("test.ivf", Box::new(io::Cursor::new(data)), size)
+    }
+
+    #[test]
+    fn header() {
+        let r = open(ivf_data(3, &[])).unwrap();
+        assert_eq!(r.get_fourcc(), common::VP9_FOURCC);
+        assert_eq!((r.get_width(), r.get_height()), (320, 240));
+        assert_eq!(r.get_timebase(), (1001, 30000));
+        assert_eq!(r.get_declared_frame_count(), Some(3));
+        assert_eq!(r.get_frame_count(), 0);
+        let mut data = ivf_data(0, &[]);
+        data[8..12].copy_from_slice(b"H264");
+        assert!(open(data).is_err());
+        assert!(open(b"DKIF".to_vec()).is_err());
+    }
+
+    #[test]
+    fn frames() {
+        let mut r = open(ivf_data(0, &[3, 0, 5])).unwrap();
+        assert_eq!(r.get_frame_count(), 3);
+        let entries: Vec<_> = r.get_frames().iter().map(|e| (e.offset, e.size, e.pts)).collect();
+        assert_eq!(entries, [(44, 3, 0), (59, 0, 2), (71, 5, 4)]);
+        assert_eq!(r.get_frame_time(2), Some(4.0 * 1001.0 / 30000.0));
+        assert_eq!(&*r.read_frame(2).unwrap(), [2; 5]);
+        assert_eq!(&*r.read_frame(0).unwrap(), [0; 3]);
+        assert!(r.read_frame(3).is_err());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut data = ivf_data(2, &[3, 5]);
+        data.pop();
+        let r = open(data).unwrap();
+        assert_eq!(r.get_frame_count(), 1);
+        // Incomplete frame header.
+        let mut data = ivf_data(2, &[3]);
+        data.extend_from_slice(&[5, 0, 0, 0, 2]);
+        assert_eq!(open(data).unwrap().get_frame_count(), 1);
+    }
+}
@@ -0,0 +1,109 @@
+//! Minimal JSON output for the machine-readable reports.
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Object),
+}
+
+/// Object with the keys in insertion order.
+#[derive(Debug, Clone)]
+pub struct Object {
+    fields: Vec<(String, Value)>,
+}
+
+impl Object {
+    pub fn new() -> Object {
+        Object {fields: Vec::new()}
+    }
+
+    pub fn add<T: Into<Value>>(&mut self, key: &str, value: T) {
+        self.fields.push((key.to_string(), value.into()));
+    }
+}
+
+impl From<bool> for Value { fn from(val: bool) -> Value { Value::Bool(val) } }
+impl From<u8> for Value { fn from(val: u8) -> Value { Value::Int(val as i64) } }
+impl From<u16> for Value { fn from(val: u16) -> Value { Value::Int(val as i64) } }
+impl From<u32> for Value { fn from(val: u32) -> Value { Value::Int(val as i64) } }
+impl From<u64> for Value { fn from(val: u64) -> Value { Value::Int(val as i64) } }
+impl From<usize> for Value { fn from(val: usize) -> Value { Value::Int(val as i64) } }
+impl From<i64> for Value { fn from(val: i64) -> Value { Value::Int(val) } }
+impl From<f64> for Value { fn from(val: f64) -> Value { Value::Float(val) } }
+impl<'a> From<&'a str> for Value { fn from(val: &'a str) -> Value { Value::String(val.into()) } }
+impl From<String> for Value { fn from(val: String) -> Value { Value::String(val) } }
+impl From<Object> for Value { fn from(val: Object) -> Value { Value::Object(val) } }
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(val: Option<T>) -> Value {
+        val.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(val: Vec<T>) -> Value {
+        Value::Array(val.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, val: &str) -> fmt::Result {
+    try!(f.write_str("\""));
+    for c in val.chars() {
+        match c {
+            '"' => try!(f.write_str("\\\"")),
+            '\\' => try!(f.write_str("\\\\")),
+            '\n' => try!(f.write_str("\\n")),
+            '\r' => try!(f.write_str("\\r")),
+            '\t' => try!(f.write_str("\\t")),
+            c if (c as u32) < 0x20 => try!(write!(f, "\\u{:04x}", c as u32)),
+            c => try!(write!(f, "{}", c)),
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(val) => write!(f, "{}", val),
+            Value::Int(val) => write!(f, "{}", val),
+            // NOTE(Kagami): JSON has no NaN and infinities.
+            Value::Float(val) if !val.is_finite() => f.write_str("null"),
+            Value::Float(val) => write!(f, "{}", val),
+            Value::String(ref val) => write_string(f, val),
+            Value::Array(ref items) => {
+                try!(f.write_str("["));
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        try!(f.write_str(","));
+                    }
+                    try!(write!(f, "{}", item));
+                }
+                f.write_str("]")
+            },
+            Value::Object(ref object) => write!(f, "{}", object),
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str("{"));
+        for (i, &(ref key, ref value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                try!(f.write_str(","));
+            }
+            try!(write_string(f, key));
+            try!(write!(f, ":{}", value));
+        }
+        f.write_str("}")
+    }
+}
@@ -6,28 +6,79 @@ extern crate gfx_window_glutin;
 extern crate glutin;
 extern crate gfx_text;
 
+use std::cmp;
 use std::env;
+use std::io;
 use std::process::exit;
 #[macro_use]
 mod common;
+mod demux;
 mod ivf;
+mod webm;
+mod mp4;
+mod superframe;
+mod vp9;
+mod overlay;
+mod view;
 mod gui;
 mod vpx;
+mod colorspace;
+mod hdr;
+mod simd;
+mod worker;
+mod cli;
+mod md5;
+mod json;
+mod png;
+mod info;
+mod frames;
+mod export;
+mod hash;
 
-fn run(filename: &str) -> Result<(), common::Error> {
-    let decoder = try!(vpx::Decoder::init());
-    let reader = try!(ivf::Reader::open(filename));
-    try!(gui::init(reader, decoder)).run();
+use cli::{Command, Options};
+
+fn view(opts: &Options) -> Result<(), common::Error> {
+    let mut reader = try!(opts.open_lazy_reader());
+    try!(reader.index_until(opts.range.first));
+    if reader.get_frame_count() == 0 {
+        return Err(From::from(demux::Error::NoFrames));
+    }
+    // Worker reads frames with its own reader.
+    let worker_reader = try!(opts.open_lazy_reader());
+    let decoder = try!(opts.init_decoder(&*worker_reader));
+    let worker = worker::Worker::spawn(worker_reader, decoder, opts.worker);
+    let start = cmp::min(opts.range.first, reader.get_frame_count().saturating_sub(1));
+    try!(gui::init(reader, worker, opts.peak_nits)).run(start);
     Ok(())
 }
 
+fn run(opts: &Options) -> Result<(), common::Error> {
+    match opts.command {
+        Command::View => view(opts),
+        Command::Info => info::run(opts),
+        Command::Frames => frames::run(opts),
+        Command::Export => export::run(opts),
+        Command::Hash => hash::run(opts),
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        printerr!("Usage: {} file.ivf", args[0]);
-        exit(1);
+    if args.len() < 2 || args.iter().skip(1).any(|arg| arg == "-h" || arg == "--help") {
+        cli::print_usage(&args[0]);
+        exit(if args.len() < 2 { 1 } else { 0 });
     }
-    match run(&args[1]) {
+    let opts = match cli::parse(&args) {
+        Ok(opts) => opts,
+        Err(err) => {
+            printerr!("{}", err);
+            cli::print_usage(&args[0]);
+            exit(1);
+        },
+    };
+    match run(&opts) {
+        // Reader of the output quit early, e.g. `vpxview frames file.webm | head`.
+        Err(common::Error::OutputError(ref err)) if err.kind() == io::ErrorKind::BrokenPipe => {},
         Err(err) => {
             printerr!("Cannot proceed due to {}", err);
             exit(1);
@@ -0,0 +1,175 @@
+//! MD5 digest (RFC 1321), used to compare decoded frames with libvpx.
+
+use std::fmt;
+
+/// Per-round shift amounts.
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// Integer part of 2^32 * abs(sin(i + 1)).
+const SINES: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee,
+    0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be,
+    0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa,
+    0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+    0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c,
+    0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05,
+    0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039,
+    0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1,
+    0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// Incremental MD5 computation.
+#[derive(Clone)]
+pub struct Context {
+    state: [u32; 4],
+    buffer: [u8; 64],
+    /// Total number of bytes consumed.
+    length: u64,
+}
+
+/// Resulting 16-byte digest, displayed as lowercase hex.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Digest(pub [u8; 16]);
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            try!(write!(f, "{:02x}", byte));
+        }
+        Ok(())
+    }
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            buffer: [0; 64],
+            length: 0,
+        }
+    }
+
+    pub fn consume(&mut self, mut data: &[u8]) {
+        let used = (self.length % 64) as usize;
+        self.length += data.len() as u64;
+        if used > 0 {
+            let n = if data.len() < 64 - used { data.len() } else { 64 - used };
+            self.buffer[used..used + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            if used + n < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.transform(&block);
+        }
+        while data.len() >= 64 {
+            self.transform(&data[..64]);
+            data = &data[64..];
+        }
+        self.buffer[..data.len()].copy_from_slice(data);
+    }
+
+    pub fn compute(mut self) -> Digest {
+        let bits = self.length.wrapping_mul(8);
+        let used = (self.length % 64) as usize;
+        let padding = if used < 56 { 56 - used } else { 120 - used };
+        let mut tail = [0; 72];
+        tail[0] = 0x80;
+        for i in 0..8 {
+            tail[padding + i] = (bits >> (i * 8)) as u8;
+        }
+        self.consume(&tail[..padding + 8]);
+        let mut digest = [0; 16];
+        for (i, word) in self.state.iter().enumerate() {
+            for j in 0..4 {
+                digest[i * 4 + j] = (word >> (j * 8)) as u8;
+            }
+        }
+        Digest(digest)
+    }
+
+    fn transform(&mut self, block: &[u8]) {
+        let mut words = [0u32; 16];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = (block[i * 4] as u32) | (block[i * 4 + 1] as u32) << 8 |
+                    (block[i * 4 + 2] as u32) << 16 | (block[i * 4 + 3] as u32) << 24;
+        }
+        let (mut a, mut b, mut c, mut d) = (self.state[0], self.state[1], self.state[2],
+                                            self.state[3]);
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a.wrapping_add(f)
+                           .wrapping_add(SINES[i])
+                           .wrapping_add(words[g])
+                           .rotate_left(SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        self.state[0] = self.state[0].wrapping_add(a);
+        self.state[1] = self.state[1].wrapping_add(b);
+        self.state[2] = self.state[2].wrapping_add(c);
+        self.state[3] = self.state[3].wrapping_add(d);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_hex(data: &[u8]) -> String {
+        let mut md5 = Context::new();
+        md5.consume(data);
+        md5.compute().to_string()
+    }
+
+    /// Test suite of RFC 1321 appendix A.5.
+    #[test]
+    fn rfc_vectors() {
+        let digits = "1234567890".repeat(8);
+        let vectors = [
+            ("", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("a", "0cc175b9c0f1b6a831c399e269772661"),
+            ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            ("abcdefghijklmnopqrstuvwxyz", "c3fcd3d76192e4007dfb496cca67e13b"),
+            ("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+             "d174ab98d277d9f5a5611c2c9f419d9f"),
+            (&digits, "57edf4a22be3c955ac49da2e2107b67a"),
+        ];
+        for &(input, expected) in &vectors {
+            assert_eq!(get_hex(input.as_bytes()), expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn chunked_input() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let expected = get_hex(&data);
+        for &chunk in &[1, 3, 63, 64, 65, 200] {
+            let mut md5 = Context::new();
+            for part in data.chunks(chunk) {
+                md5.consume(part);
+            }
+            assert_eq!(md5.compute().to_string(), expected, "chunk {}", chunk);
+        }
+    }
+}
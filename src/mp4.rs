@@ -0,0 +1,962 @@
+//! ISO Base Media File Format (MP4) container parser, both plain and
+//! fragmented. DASH media segments are read either concatenated after the
+//! init segment or with the init segment given separately.
+//! Reference: ISO/IEC 14496-12, <http://www.webmproject.org/vp9/mp4/>.
+
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use ::common;
+use ::demux::{Demuxer, FrameEntry};
+
+/// Types of boxes which may start the file, including the DASH media
+/// segment.
+const LEADING_BOXES: [&'static [u8; 4]; 7] = [
+    b"ftyp", b"styp", b"moov", b"free", b"skip", b"sidx", b"moof",
+];
+
+/// Sample flags bit meaning the sample is not a sync sample.
+const SAMPLE_IS_NON_SYNC: u32 = 0x10000;
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(io::Error),
+    // TODO(Kagami): Better granularity of parse errors.
+    ParseError,
+    NoVideoTrack,
+    UnsupportedCodec(String),
+    FrameOutOfRange(usize),
+    /// Movie fragment goes before the movie header, e.g. in DASH media
+    /// segment.
+    MissingInitSegment,
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::IoError(err) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descr = match *self {
+            Error::IoError(ref err) => format!("IO error: {}", err),
+            Error::ParseError => format!("Parse error"),
+            Error::NoVideoTrack => format!("No video track in file"),
+            Error::UnsupportedCodec(ref codec) => format!("Unsupported codec {}", codec),
+            Error::FrameOutOfRange(pos) => format!("No frame #{} in file", pos),
+            Error::MissingInitSegment => {
+                format!("No movie header before fragments, give init segment with --init-segment")
+            },
+        };
+        f.write_str(&descr)
+    }
+}
+
+/// Check whether file with the given first 8 bytes looks like MP4.
+pub fn is_mp4(signature: &[u8]) -> bool {
+    signature.len() >= 8 && LEADING_BOXES.iter().any(|&kind| &signature[4..8] == kind)
+}
+
+/// Contents of the VP codec configuration box.
+#[derive(Debug, Clone, Copy)]
+pub struct VpcConfig {
+    pub version: u8,
+    pub profile: u8,
+    pub level: u8,
+    pub bit_depth: u8,
+    pub chroma_subsampling: u8,
+    pub full_range: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+}
+
+/// Big-endian reader over in-memory box data.
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader {data: data, pos: 0}
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        if self.pos + count > self.data.len() {
+            return Err(Error::ParseError);
+        }
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), Error> {
+        self.bytes(count).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        self.bytes(2).map(common::get_be16)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.bytes(4).map(common::get_be32)
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        self.bytes(8).map(common::get_be64)
+    }
+
+    /// Read number of the table entries and check they fit into the rest
+    /// of the data.
+    fn count(&mut self, entry_size: usize) -> Result<u32, Error> {
+        let count = try!(self.u32());
+        if count as u64 * entry_size as u64 > (self.data.len() - self.pos) as u64 {
+            return Err(Error::ParseError);
+        }
+        Ok(count)
+    }
+
+    /// Read version and flags of the full box.
+    fn full_box(&mut self) -> Result<(u8, u32), Error> {
+        let val = try!(self.u32());
+        Ok(((val >> 24) as u8, val & 0xffffff))
+    }
+}
+
+/// Split box data into child boxes.
+fn children(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>, Error> {
+    let mut boxes = Vec::new();
+    let mut r = ByteReader::new(data);
+    while r.pos + 8 <= data.len() {
+        let start = r.pos;
+        let size = try!(r.u32()) as u64;
+        let mut kind = [0; 4];
+        kind.clone_from_slice(try!(r.bytes(4)));
+        let size = match size {
+            0 => (data.len() - start) as u64,
+            1 => try!(r.u64()),
+            _ => size,
+        };
+        let header_size = (r.pos - start) as u64;
+        let end = try!((start as u64).checked_add(size).ok_or(Error::ParseError));
+        if size < header_size || end > data.len() as u64 {
+            return Err(Error::ParseError);
+        }
+        boxes.push((kind, &data[r.pos..start + size as usize]));
+        r.pos = start + size as usize;
+    }
+    Ok(boxes)
+}
+
+/// Offsets, sizes and timestamps are read from file, so their sums can be
+/// arbitrarily large.
+fn checked_add(a: u64, b: u64) -> Result<u64, Error> {
+    a.checked_add(b).ok_or(Error::ParseError)
+}
+
+fn checked_add_signed(a: u64, b: i32) -> Result<u64, Error> {
+    if b < 0 {
+        a.checked_sub(-(b as i64) as u64).ok_or(Error::ParseError)
+    } else {
+        checked_add(a, b as u64)
+    }
+}
+
+/// Presentation time out of the decode time and composition offset, clamped
+/// to the valid range.
+fn add_offset(dts: u64, cts_offset: i32) -> u64 {
+    if cts_offset < 0 {
+        dts.saturating_sub(-(cts_offset as i64) as u64)
+    } else {
+        dts.saturating_add(cts_offset as u64)
+    }
+}
+
+fn find_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<Option<&'a [u8]>, Error> {
+    let boxes = try!(children(data));
+    Ok(boxes.into_iter().find(|&(k, _)| &k == kind).map(|(_, d)| d))
+}
+
+/// Defaults for fragmented tracks.
+#[derive(Clone, Copy, Default)]
+struct TrackDefaults {
+    sample_duration: u32,
+    sample_size: u32,
+    sample_flags: u32,
+}
+
+struct Track {
+    id: u32,
+    handler: [u8; 4],
+    timescale: u32,
+    codec: [u8; 4],
+    width: u16,
+    height: u16,
+    vpcc: Option<VpcConfig>,
+    defaults: TrackDefaults,
+    samples: Vec<FrameEntry>,
+    /// Decode time of the next sample.
+    next_dts: u64,
+}
+
+fn parse_vpcc(data: &[u8]) -> Result<VpcConfig, Error> {
+    let mut r = ByteReader::new(data);
+    let (version, _) = try!(r.full_box());
+    let profile = try!(r.u8());
+    let level = try!(r.u8());
+    let b = try!(r.u8());
+    if version == 0 {
+        // Early draft of the spec, color info was packed differently.
+        let b2 = try!(r.u8());
+        let b3 = try!(r.u8());
+        Ok(VpcConfig {
+            version: version,
+            profile: profile,
+            level: level,
+            bit_depth: b >> 4,
+            chroma_subsampling: b2 >> 4,
+            full_range: b3 & 0x80 != 0,
+            colour_primaries: 2,
+            transfer_characteristics: b2 & 0xf,
+            // Color space code of the draft is not a matrix coefficients
+            // value, leave it unspecified.
+            matrix_coefficients: 2,
+        })
+    } else {
+        Ok(VpcConfig {
+            version: version,
+            profile: profile,
+            level: level,
+            bit_depth: b >> 4,
+            chroma_subsampling: (b >> 1) & 7,
+            full_range: b & 1 != 0,
+            colour_primaries: try!(r.u8()),
+            transfer_characteristics: try!(r.u8()),
+            matrix_coefficients: try!(r.u8()),
+        })
+    }
+}
+
+fn parse_stsd(data: &[u8], track: &mut Track) -> Result<(), Error> {
+    let mut r = ByteReader::new(data);
+    try!(r.full_box());
+    let count = try!(r.u32());
+    if count == 0 {
+        return Err(Error::ParseError);
+    }
+    // Only the first sample description is used.
+    let entries = try!(children(&data[r.pos..]));
+    let &(kind, entry) = try!(entries.first().ok_or(Error::ParseError));
+    track.codec = kind;
+    if &track.handler != b"vide" {
+        return Ok(());
+    }
+    // Visual sample entry fields.
+    let mut r = ByteReader::new(entry);
+    try!(r.skip(24));
+    track.width = try!(r.u16());
+    track.height = try!(r.u16());
+    try!(r.skip(50));
+    if let Some(vpcc) = try!(find_child(&entry[r.pos..], b"vpcC")) {
+        track.vpcc = Some(try!(parse_vpcc(vpcc)));
+    }
+    Ok(())
+}
+
+/// Build sample list out of the sample table boxes. Samples are bounded by
+/// the file size.
+fn parse_stbl(data: &[u8], track: &mut Track, file_size: u64) -> Result<(), Error> {
+    // Either the constant size of all samples or the table of sizes.
+    let mut sample_size = 0;
+    let mut sample_count = 0;
+    let mut sizes = Vec::new();
+    let mut chunk_offsets = Vec::new();
+    let mut chunk_runs = Vec::new();
+    let mut durations = Vec::new();
+    let mut ctts = Vec::new();
+    let mut sync_samples = None;
+    for (kind, data) in try!(children(data)) {
+        let mut r = ByteReader::new(data);
+        match &kind {
+            b"stsd" => try!(parse_stsd(data, track)),
+            b"stsz" => {
+                try!(r.full_box());
+                sample_size = try!(r.u32());
+                if sample_size != 0 {
+                    // Samples of the constant size have no table, they are
+                    // bounded by the file size instead.
+                    sample_count = try!(r.u32());
+                    if sample_count as u64 * sample_size as u64 > file_size {
+                        return Err(Error::ParseError);
+                    }
+                } else {
+                    sample_count = try!(r.count(4));
+                    for _ in 0..sample_count {
+                        sizes.push(try!(r.u32()));
+                    }
+                }
+            },
+            b"stz2" => {
+                try!(r.full_box());
+                try!(r.skip(3));
+                let field_size = try!(r.u8());
+                let count = try!(r.u32());
+                if count as u64 * field_size as u64 > (data.len() - r.pos) as u64 * 8 {
+                    return Err(Error::ParseError);
+                }
+                sample_size = 0;
+                sample_count = count;
+                let mut pair = 0;
+                for i in 0..count {
+                    sizes.push(match field_size {
+                        // Two sizes are packed into one byte.
+                        4 if i % 2 == 0 => {
+                            pair = try!(r.u8());
+                            (pair >> 4) as u32
+                        },
+                        4 => (pair & 0xf) as u32,
+                        8 => try!(r.u8()) as u32,
+                        16 => try!(r.u16()) as u32,
+                        _ => return Err(Error::ParseError),
+                    });
+                }
+            },
+            b"stco" | b"co64" => {
+                try!(r.full_box());
+                let count = try!(r.count(if &kind == b"co64" { 8 } else { 4 }));
+                for _ in 0..count {
+                    chunk_offsets.push(if &kind == b"co64" {
+                        try!(r.u64())
+                    } else {
+                        try!(r.u32()) as u64
+                    });
+                }
+            },
+            b"stsc" => {
+                try!(r.full_box());
+                let count = try!(r.count(12));
+                for _ in 0..count {
+                    let first_chunk = try!(r.u32());
+                    let samples_per_chunk = try!(r.u32());
+                    try!(r.skip(4));
+                    chunk_runs.push((first_chunk, samples_per_chunk));
+                }
+            },
+            b"stts" => {
+                try!(r.full_box());
+                let count = try!(r.count(8));
+                for _ in 0..count {
+                    let sample_count = try!(r.u32());
+                    let delta = try!(r.u32());
+                    durations.push((sample_count, delta));
+                }
+            },
+            b"ctts" => {
+                try!(r.full_box());
+                let count = try!(r.count(8));
+                for _ in 0..count {
+                    let sample_count = try!(r.u32());
+                    // Version 0 offsets are unsigned per spec, but signed
+                    // values are written in practice.
+                    let offset = try!(r.u32()) as i32;
+                    ctts.push((sample_count, offset));
+                }
+            },
+            b"stss" => {
+                try!(r.full_box());
+                let count = try!(r.count(4));
+                let mut numbers = Vec::new();
+                for _ in 0..count {
+                    numbers.push(try!(r.u32()));
+                }
+                sync_samples = Some(numbers);
+            },
+            _ => {},
+        }
+    }
+
+    let get_size = |i: usize| if sample_size != 0 { sample_size } else { sizes[i] };
+    let sample_count = sample_count as usize;
+
+    // Sample offsets. Count of the constant size samples isn't backed by
+    // the table, so the list grows only as chunks are walked.
+    let mut offsets = Vec::new();
+    for (i, &(first_chunk, samples_per_chunk)) in chunk_runs.iter().enumerate() {
+        let last_chunk = match chunk_runs.get(i + 1) {
+            Some(&(next_first_chunk, _)) => next_first_chunk,
+            None => chunk_offsets.len() as u32 + 1,
+        };
+        if first_chunk == 0 || last_chunk < first_chunk {
+            return Err(Error::ParseError);
+        }
+        for chunk in first_chunk..last_chunk {
+            let mut offset = try!(chunk_offsets.get(chunk as usize - 1).cloned()
+                                               .ok_or(Error::ParseError));
+            for _ in 0..samples_per_chunk {
+                if offsets.len() == sample_count {
+                    break;
+                }
+                let size = get_size(offsets.len());
+                offsets.push(offset);
+                offset = try!(checked_add(offset, size as u64));
+            }
+        }
+    }
+    if offsets.len() != sample_count {
+        return Err(Error::ParseError);
+    }
+
+    // Sample timestamps. Run lengths are not trusted, tables are expanded
+    // only up to the number of samples.
+    let mut dts = Vec::with_capacity(sample_count);
+    for &(count, delta) in &durations {
+        for _ in 0..cmp::min(count as usize, sample_count - dts.len()) {
+            dts.push(track.next_dts);
+            track.next_dts = try!(checked_add(track.next_dts, delta as u64));
+        }
+    }
+    let mut cts_offsets = Vec::with_capacity(sample_count);
+    for &(count, offset) in &ctts {
+        for _ in 0..cmp::min(count as usize, sample_count - cts_offsets.len()) {
+            cts_offsets.push(offset);
+        }
+    }
+
+    for (i, &offset) in offsets.iter().enumerate() {
+        let dts = dts.get(i).cloned().unwrap_or(track.next_dts);
+        let pts = add_offset(dts, cts_offsets.get(i).cloned().unwrap_or(0));
+        let keyframe = match sync_samples {
+            Some(ref numbers) => numbers.binary_search(&(i as u32 + 1)).is_ok(),
+            // Every sample is a sync sample if there is no table.
+            None => true,
+        };
+        track.samples.push(FrameEntry {
+            offset: offset,
+            size: get_size(i),
+            pts: pts,
+            keyframe: Some(keyframe),
+        });
+    }
+    Ok(())
+}
+
+fn parse_trak(data: &[u8], file_size: u64) -> Result<Track, Error> {
+    let mut track = Track {
+        id: 0,
+        handler: [0; 4],
+        timescale: 0,
+        codec: [0; 4],
+        width: 0,
+        height: 0,
+        vpcc: None,
+        defaults: Default::default(),
+        samples: Vec::new(),
+        next_dts: 0,
+    };
+    let tkhd = try!(try!(find_child(data, b"tkhd")).ok_or(Error::ParseError));
+    let mut r = ByteReader::new(tkhd);
+    let (version, _) = try!(r.full_box());
+    try!(r.skip(if version == 1 { 16 } else { 8 }));
+    track.id = try!(r.u32());
+    let mdia = try!(try!(find_child(data, b"mdia")).ok_or(Error::ParseError));
+    let mdhd = try!(try!(find_child(mdia, b"mdhd")).ok_or(Error::ParseError));
+    let mut r = ByteReader::new(mdhd);
+    let (version, _) = try!(r.full_box());
+    try!(r.skip(if version == 1 { 16 } else { 8 }));
+    track.timescale = try!(r.u32());
+    if let Some(hdlr) = try!(find_child(mdia, b"hdlr")) {
+        let mut r = ByteReader::new(hdlr);
+        try!(r.skip(8));
+        track.handler.clone_from_slice(try!(r.bytes(4)));
+    }
+    let minf = try!(try!(find_child(mdia, b"minf")).ok_or(Error::ParseError));
+    let stbl = try!(try!(find_child(minf, b"stbl")).ok_or(Error::ParseError));
+    try!(parse_stbl(stbl, &mut track, file_size));
+    Ok(track)
+}
+
+fn parse_moov(data: &[u8], file_size: u64) -> Result<Vec<Track>, Error> {
+    let mut tracks = Vec::new();
+    for (kind, data) in try!(children(data)) {
+        match &kind {
+            b"trak" => tracks.push(try!(parse_trak(data, file_size))),
+            b"mvex" => for (kind, data) in try!(children(data)) {
+                if &kind != b"trex" {
+                    continue;
+                }
+                let mut r = ByteReader::new(data);
+                try!(r.full_box());
+                let id = try!(r.u32());
+                try!(r.skip(4));
+                let defaults = TrackDefaults {
+                    sample_duration: try!(r.u32()),
+                    sample_size: try!(r.u32()),
+                    sample_flags: try!(r.u32()),
+                };
+                for track in tracks.iter_mut().filter(|t| t.id == id) {
+                    track.defaults = defaults;
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(tracks)
+}
+
+/// Append samples of the movie fragment to tracks. Samples are bounded by
+/// the file size.
+fn parse_moof(data: &[u8], moof_offset: u64, tracks: &mut [Track],
+              file_size: u64) -> Result<(), Error> {
+    // Data of the next track fragment starts after the previous one if base
+    // offset is not given.
+    let mut next_base = moof_offset;
+    for (kind, traf) in try!(children(data)) {
+        if &kind != b"traf" {
+            continue;
+        }
+        let tfhd = try!(try!(find_child(traf, b"tfhd")).ok_or(Error::ParseError));
+        let mut r = ByteReader::new(tfhd);
+        let (_, flags) = try!(r.full_box());
+        let id = try!(r.u32());
+        let track = match tracks.iter_mut().find(|t| t.id == id) {
+            Some(track) => track,
+            None => continue,
+        };
+        let mut defaults = track.defaults;
+        let mut base = next_base;
+        if flags & 0x1 != 0 {
+            base = try!(r.u64());
+        } else if flags & 0x20000 != 0 {
+            base = moof_offset;
+        }
+        if flags & 0x2 != 0 { try!(r.skip(4)); }
+        if flags & 0x8 != 0 { defaults.sample_duration = try!(r.u32()); }
+        if flags & 0x10 != 0 { defaults.sample_size = try!(r.u32()); }
+        if flags & 0x20 != 0 { defaults.sample_flags = try!(r.u32()); }
+
+        if let Some(tfdt) = try!(find_child(traf, b"tfdt")) {
+            let mut r = ByteReader::new(tfdt);
+            let (version, _) = try!(r.full_box());
+            track.next_dts = if version == 1 { try!(r.u64()) } else { try!(r.u32()) as u64 };
+        }
+
+        let mut offset = base;
+        for (kind, trun) in try!(children(traf)) {
+            if &kind != b"trun" {
+                continue;
+            }
+            let mut r = ByteReader::new(trun);
+            let (_, flags) = try!(r.full_box());
+            let count = try!(r.u32());
+            // Every sample takes either some bytes of the box or at least a
+            // byte of the file.
+            let sample_fields = [0x100, 0x200, 0x400, 0x800].iter()
+                                                            .filter(|&&flag| flags & flag != 0)
+                                                            .count();
+            let max_count = if sample_fields > 0 {
+                (trun.len() / (sample_fields * 4)) as u64
+            } else {
+                file_size / cmp::max(defaults.sample_size, 1) as u64
+            };
+            if count as u64 > max_count {
+                return Err(Error::ParseError);
+            }
+            if flags & 0x1 != 0 {
+                let data_offset = try!(r.u32()) as i32;
+                offset = try!(checked_add_signed(base, data_offset));
+            }
+            let first_flags = if flags & 0x4 != 0 { Some(try!(r.u32())) } else { None };
+            for i in 0..count {
+                let duration = if flags & 0x100 != 0 { try!(r.u32()) } else { defaults.sample_duration };
+                let size = if flags & 0x200 != 0 { try!(r.u32()) } else { defaults.sample_size };
+                let mut sample_flags = if flags & 0x400 != 0 { try!(r.u32()) } else { defaults.sample_flags };
+                if i == 0 {
+                    sample_flags = first_flags.unwrap_or(sample_flags);
+                }
+                let cts_offset = if flags & 0x800 != 0 { try!(r.u32()) as i32 } else { 0 };
+                track.samples.push(FrameEntry {
+                    offset: offset,
+                    size: size,
+                    pts: add_offset(track.next_dts, cts_offset),
+                    keyframe: Some(sample_flags & SAMPLE_IS_NON_SYNC == 0),
+                });
+                track.next_dts = try!(checked_add(track.next_dts, duration as u64));
+                offset = try!(checked_add(offset, size as u64));
+            }
+        }
+        next_base = offset;
+    }
+    Ok(())
+}
+
+fn codec_to_fourcc(codec: &[u8; 4]) -> Option<u32> {
+    match codec {
+        b"vp08" => Some(common::VP8_FOURCC),
+        b"vp09" => Some(common::VP9_FOURCC),
+        _ => None,
+    }
+}
+
+pub struct Reader {
+    breader: io::BufReader<File>,
+    filename: String,
+    fourcc: u32,
+    width: u16,
+    height: u16,
+    timebase: (u32, u32),
+    vpcc: Option<VpcConfig>,
+    frames: Vec<FrameEntry>,
+}
+
+/// Read box header at the current position. Return box type, offset of the
+/// data and offset of the box end, which is past the file size if file is
+/// truncated.
+fn read_box_header(breader: &mut io::BufReader<File>,
+                   offset: u64,
+                   file_size: u64) -> Result<([u8; 4], u64, u64), Error> {
+    let header = try!(read_bytes(breader, 8));
+    let mut kind = [0; 4];
+    kind.clone_from_slice(&header[4..]);
+    let (size, header_size) = match common::get_be32(&header[..]) {
+        0 => (file_size - offset, 8),
+        1 => (common::get_be64(&try!(read_bytes(breader, 8))), 16),
+        size => (size as u64, 8),
+    };
+    if size < header_size {
+        return Err(Error::ParseError);
+    }
+    let end = try!(offset.checked_add(size).ok_or(Error::ParseError));
+    Ok((kind, offset + header_size, end))
+}
+
+fn read_bytes(breader: &mut io::BufReader<File>, count: usize) -> Result<Box<[u8]>, Error> {
+    let mut buf = common::alloc(count);
+    let mut collected = 0;
+    while collected < count {
+        let chunk_size = try!(breader.read(&mut buf[collected..]));
+        if chunk_size == 0 {
+            return Err(Error::ParseError);
+        }
+        collected += chunk_size;
+    }
+    Ok(buf)
+}
+
+/// Walk through the top-level boxes, parse movie header and append samples
+/// of the fragments to its tracks.
+fn parse_boxes(breader: &mut io::BufReader<File>, file_size: u64,
+               tracks: &mut Vec<Track>, fragments: bool) -> Result<(), Error> {
+    let mut offset = 0;
+    while offset + 8 <= file_size {
+        try!(breader.seek(SeekFrom::Start(offset)));
+        let (kind, data_offset, end) = try!(read_box_header(breader, offset, file_size));
+        if end > file_size {
+            // Truncated file, use what we already have.
+            break;
+        }
+        match &kind {
+            b"moov" => {
+                let data = try!(read_bytes(breader, (end - data_offset) as usize));
+                *tracks = try!(parse_moov(&data, file_size));
+            },
+            b"moof" if fragments => {
+                if tracks.is_empty() {
+                    return Err(Error::MissingInitSegment);
+                }
+                let data = try!(read_bytes(breader, (end - data_offset) as usize));
+                try!(parse_moof(&data, offset, tracks, file_size));
+            },
+            _ => {},
+        }
+        offset = end;
+    }
+    Ok(())
+}
+
+impl Reader {
+    pub fn open(filename: &str) -> Result<Reader, Error> {
+        Self::open_with_init(filename, None)
+    }
+
+    /// Open file with the movie header taken from the separate init
+    /// segment, if given. Fragments of the init segment are ignored.
+    pub fn open_with_init(filename: &str, init: Option<&str>) -> Result<Reader, Error> {
+        let mut tracks = Vec::new();
+        if let Some(init) = init {
+            let fh = try!(File::open(init));
+            let file_size = try!(fh.metadata()).len();
+            try!(parse_boxes(&mut io::BufReader::new(fh), file_size, &mut tracks, false));
+        }
+        let fh = try!(File::open(&filename));
+        let file_size = try!(fh.metadata()).len();
+        let mut breader = io::BufReader::new(fh);
+        try!(parse_boxes(&mut breader, file_size, &mut tracks, true));
+
+        let mut unsupported = None;
+        let mut video = None;
+        for track in tracks.into_iter().filter(|t| &t.handler == b"vide") {
+            match codec_to_fourcc(&track.codec) {
+                Some(fourcc) => {
+                    video = Some((track, fourcc));
+                    break;
+                },
+                None => if unsupported.is_none() {
+                    unsupported = Some(String::from_utf8_lossy(&track.codec).into_owned());
+                },
+            }
+        }
+        let (track, fourcc) = match video {
+            Some(video) => video,
+            None => return Err(match unsupported {
+                Some(codec) => Error::UnsupportedCodec(codec),
+                None => Error::NoVideoTrack,
+            }),
+        };
+        if track.width == 0 || track.height == 0 || track.timescale == 0 {
+            return Err(Error::ParseError);
+        }
+        // Skip samples which point outside of the truncated file.
+        let frames = track.samples.into_iter()
+            .take_while(|s| {
+                s.offset.checked_add(s.size as u64).map_or(false, |end| end <= file_size)
+            })
+            .collect();
+        Ok(Reader {
+            breader: breader,
+            filename: filename.to_string(),
+            fourcc: fourcc,
+            width: track.width,
+            height: track.height,
+            timebase: (1, track.timescale),
+            vpcc: track.vpcc,
+            frames: frames,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn get_vpcc(&self) -> Option<&VpcConfig> { self.vpcc.as_ref() }
+
+    fn read_frame_data(&mut self, pos: usize) -> Result<Box<[u8]>, Error> {
+        let entry = match self.frames.get(pos) {
+            Some(entry) => *entry,
+            None => return Err(Error::FrameOutOfRange(pos)),
+        };
+        try!(self.breader.seek(SeekFrom::Start(entry.offset)));
+        read_bytes(&mut self.breader, entry.size as usize)
+    }
+}
+
+impl Demuxer for Reader {
+    fn get_filename(&self) -> &str { &self.filename }
+    fn get_format_name(&self) -> &'static str { "MP4" }
+    fn get_fourcc(&self) -> u32 { self.fourcc }
+    fn get_width(&self) -> u16 { self.width }
+    fn get_height(&self) -> u16 { self.height }
+    fn get_timebase(&self) -> (u32, u32) { self.timebase }
+    fn get_frames(&self) -> &[FrameEntry] { &self.frames }
+
+    fn read_frame(&mut self, pos: usize) -> Result<Box<[u8]>, common::Error> {
+        self.read_frame_data(pos).map_err(From::from)
+    }
+
+    fn get_codec_details(&self) -> Vec<String> {
+        match self.vpcc {
+            Some(ref vpcc) => vec![
+                format!("vpcC: profile {}, level {}.{}, {}-bit, chroma subsampling {}",
+                        vpcc.profile, vpcc.level / 10, vpcc.level % 10,
+                        vpcc.bit_depth, vpcc.chroma_subsampling),
+                format!("vpcC: primaries {}, transfer {}, matrix {}, {} range",
+                        vpcc.colour_primaries, vpcc.transfer_characteristics,
+                        vpcc.matrix_coefficients,
+                        if vpcc.full_range { "full" } else { "limited" }),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    fn get_transfer_characteristics(&self) -> Option<u8> {
+        match self.vpcc {
+            // Draft version of the box stores some other value there.
+            Some(ref vpcc) if vpcc.version >= 1 => Some(vpcc.transfer_characteristics),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let size = data.len() as u32 + 8;
+        let mut buf = vec![(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8];
+        buf.extend_from_slice(kind);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    fn full_box(kind: &[u8; 4], version: u8, flags: u32, fields: &[u32]) -> Vec<u8> {
+        let mut data = vec![version, (flags >> 16) as u8, (flags >> 8) as u8, flags as u8];
+        for &field in fields {
+            data.extend_from_slice(&[(field >> 24) as u8, (field >> 16) as u8,
+                                     (field >> 8) as u8, field as u8]);
+        }
+        mp4_box(kind, &data)
+    }
+
+    fn track(id: u32) -> Track {
+        Track {
+            id: id,
+            handler: *b"vide",
+            timescale: 1000,
+            codec: *b"vp09",
+            width: 64,
+            height: 64,
+            vpcc: None,
+            defaults: Default::default(),
+            samples: Vec::new(),
+            next_dts: 0,
+        }
+    }
+
+    fn get_samples(track: &Track) -> Vec<(u64, u32, u64, Option<bool>)> {
+        track.samples.iter().map(|s| (s.offset, s.size, s.pts, s.keyframe)).collect()
+    }
+
+    fn assert_parse_error<T>(res: Result<T, Error>) {
+        match res {
+            Err(Error::ParseError) => {},
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("no error"),
+        }
+    }
+
+    #[test]
+    fn boxes() {
+        let mut data = mp4_box(b"ftyp", b"isom");
+        data.extend(mp4_box(b"free", &[]));
+        // Large size.
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 18, 0xaa, 0xbb]);
+        // Zero size lasts until the end.
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"skip");
+        data.extend_from_slice(&[1, 2, 3]);
+        let boxes = children(&data).unwrap();
+        let kinds: Vec<_> = boxes.iter().map(|&(kind, data)| (kind, data.len())).collect();
+        assert_eq!(kinds, [(*b"ftyp", 4), (*b"free", 0), (*b"mdat", 2), (*b"skip", 3)]);
+        assert_eq!(boxes[2].1, [0xaa, 0xbb]);
+        assert_eq!(find_child(&data, b"skip").unwrap().unwrap(), [1, 2, 3]);
+        assert!(find_child(&data, b"moov").unwrap().is_none());
+    }
+
+    #[test]
+    fn broken_boxes() {
+        // Size is less than the header.
+        assert_parse_error(children(&[0, 0, 0, 7, b'f', b'r', b'e', b'e']));
+        // Box exceeds the data.
+        assert_parse_error(children(&mp4_box(b"free", &[0; 4])[..11]));
+        // Large size overflows.
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[0xff; 8]);
+        assert_parse_error(children(&data));
+    }
+
+    #[test]
+    fn sample_table() {
+        let mut stbl = full_box(b"stsz", 0, 0, &[0, 3, 10, 20, 30]);
+        stbl.extend(full_box(b"stco", 0, 0, &[2, 100, 500]));
+        // Two samples in the first chunk, one in the rest.
+        stbl.extend(full_box(b"stsc", 0, 0, &[2, 1, 2, 1, 2, 1, 1]));
+        stbl.extend(full_box(b"stts", 0, 0, &[1, 3, 10]));
+        stbl.extend(full_box(b"ctts", 0, 0, &[3, 1, 0, 1, 20, 1, -10i32 as u32]));
+        stbl.extend(full_box(b"stss", 0, 0, &[2, 1, 3]));
+        let mut t = track(1);
+        parse_stbl(&stbl, &mut t, 1000).unwrap();
+        assert_eq!(get_samples(&t), [(100, 10, 0, Some(true)),
+                                     (110, 20, 30, Some(false)),
+                                     (500, 30, 10, Some(true))]);
+        assert_eq!(t.next_dts, 30);
+    }
+
+    #[test]
+    fn constant_sample_size() {
+        let mut stbl = full_box(b"stsz", 0, 0, &[5, 3]);
+        stbl.extend(full_box(b"stco", 0, 0, &[1, 1000]));
+        stbl.extend(full_box(b"stsc", 0, 0, &[1, 1, 3, 1]));
+        let mut t = track(1);
+        parse_stbl(&stbl, &mut t, 2000).unwrap();
+        let offsets: Vec<_> = t.samples.iter().map(|s| (s.offset, s.size)).collect();
+        assert_eq!(offsets, [(1000, 5), (1005, 5), (1010, 5)]);
+        // Samples don't fit into the file.
+        assert_parse_error(parse_stbl(&stbl, &mut track(1), 14));
+        // Count isn't backed by the chunks.
+        let mut stbl = full_box(b"stsz", 0, 0, &[5, 10]);
+        stbl.extend(full_box(b"stco", 0, 0, &[1, 1000]));
+        stbl.extend(full_box(b"stsc", 0, 0, &[1, 1, 5, 1]));
+        assert_parse_error(parse_stbl(&stbl, &mut track(1), 2000));
+    }
+
+    #[test]
+    fn broken_sample_tables() {
+        // Table is longer than the box.
+        let stbl = full_box(b"stsz", 0, 0, &[0, 1000, 10]);
+        assert_parse_error(parse_stbl(&stbl, &mut track(1), 1000));
+        // Three 16-bit sizes in 4 bytes.
+        let stbl = full_box(b"stz2", 0, 0, &[16, 3, 0x00010002]);
+        assert_parse_error(parse_stbl(&stbl, &mut track(1), 1000));
+        // Offset of the second sample overflows.
+        let mut stbl = full_box(b"stsz", 0, 0, &[0, 3, 10, 10, 10]);
+        stbl.extend(full_box(b"co64", 0, 0, &[1, 0xffffffff, 0xfffffff8]));
+        stbl.extend(full_box(b"stsc", 0, 0, &[1, 1, 3, 1]));
+        assert_parse_error(parse_stbl(&stbl, &mut track(1), 1000));
+    }
+
+    /// Movie fragment of the single track fragment.
+    fn moof(tfhd: &[u32], tfhd_flags: u32, trun: &[u32], trun_flags: u32) -> Vec<u8> {
+        let mut traf = full_box(b"tfhd", 0, tfhd_flags, tfhd);
+        traf.extend(full_box(b"tfdt", 0, 0, &[1000]));
+        traf.extend(full_box(b"trun", 0, trun_flags, trun));
+        mp4_box(b"traf", &traf)
+    }
+
+    #[test]
+    fn track_run() {
+        // Base offset is the moof, default duration is 100, data offset,
+        // first sample flags, sample sizes and composition offsets are set.
+        let data = moof(&[1, 100], 0x20008, &[2, 8, 0, 40, 0, 50, 100], 0xa05);
+        let mut tracks = [track(1), track(2)];
+        tracks[0].defaults.sample_flags = SAMPLE_IS_NON_SYNC;
+        parse_moof(&data, 200, &mut tracks, 1000).unwrap();
+        assert_eq!(get_samples(&tracks[0]), [(208, 40, 1000, Some(true)),
+                                             (248, 50, 1200, Some(false))]);
+        assert_eq!(tracks[0].next_dts, 1200);
+        assert!(tracks[1].samples.is_empty());
+    }
+
+    #[test]
+    fn broken_track_runs() {
+        // Explicit base offset plus data offset overflows.
+        let data = moof(&[1, 0xffffffff, 0xfffffff0], 0x1, &[1, 100, 10], 0x201);
+        assert_parse_error(parse_moof(&data, 0, &mut [track(1)], 1000));
+        // Negative data offset goes before the file start.
+        let data = moof(&[1], 0x20000, &[1, -100i32 as u32, 10], 0x201);
+        assert_parse_error(parse_moof(&data, 0, &mut [track(1)], 1000));
+        // Sample fields don't fit into the box.
+        let data = moof(&[1], 0x20000, &[3, 10, 10], 0x200);
+        assert_parse_error(parse_moof(&data, 0, &mut [track(1)], 1000));
+        // Samples without fields don't fit into the file.
+        let data = moof(&[1, 10], 0x20010, &[101], 0);
+        assert_parse_error(parse_moof(&data, 0, &mut [track(1)], 1000));
+        let data = moof(&[1, 10], 0x20010, &[100], 0);
+        parse_moof(&data, 0, &mut [track(1)], 1000).unwrap();
+    }
+}
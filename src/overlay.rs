@@ -0,0 +1,217 @@
+//! Line overlays drawn on top of the frame texture. Coordinates are in
+//! frame pixels, conversion to the screen space is done by GUI.
+
+use std::cmp;
+use std::f32::consts::PI;
+use ::vp9::{BlockMap, BlockInfo, BlockSize};
+
+pub type Color = [f32; 3];
+
+/// Colors of LAST, GOLDEN and ALTREF references.
+pub const REF_COLORS: [Color; 3] = [
+    [1.0, 0.25, 0.25],
+    [0.25, 1.0, 0.25],
+    [0.3, 0.55, 1.0],
+];
+pub const REF_NAMES: [&'static str; 3] = ["LAST", "GOLDEN", "ALTREF"];
+pub const REF_COLOR_NAMES: [&'static str; 3] = ["red", "green", "blue"];
+
+const ARROW_HEAD_LENGTH: f32 = 3.0;
+const ARROW_HEAD_ANGLE: f32 = PI / 6.0;
+
+/// Outline colors go from red for 4x4 blocks to blue for 64x64 ones.
+const SMALLEST_HUE: f32 = 0.0;
+const LARGEST_HUE: f32 = 240.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Line {
+    pub from: [f32; 2],
+    pub to: [f32; 2],
+    pub color: Color,
+}
+
+fn add_arrow(lines: &mut Vec<Line>, from: [f32; 2], to: [f32; 2], color: Color) {
+    let dx = to[0] - from[0];
+    let dy = to[1] - from[1];
+    let length = (dx * dx + dy * dy).sqrt();
+    // Zero vectors are the most common ones, don't clutter the picture.
+    if length < 0.5 {
+        return;
+    }
+    lines.push(Line {from: from, to: to, color: color});
+    let head = ARROW_HEAD_LENGTH.min(length / 2.0);
+    let angle = dy.atan2(dx) + PI;
+    for &side in &[-1.0, 1.0] {
+        let a = angle + side * ARROW_HEAD_ANGLE;
+        let end = [to[0] + head * a.cos(), to[1] + head * a.sin()];
+        lines.push(Line {from: to, to: end, color: color});
+    }
+}
+
+/// Rectangles of the block in pixels as (x, y, width, height). Blocks
+/// smaller than 8x8 share single mode info of the 8x8 area so they give
+/// several rectangles.
+fn block_rects(b: &BlockInfo) -> Vec<(u32, u32, u32, u32)> {
+    let (x, y) = (b.mi_col * 8, b.mi_row * 8);
+    let (w, h) = (b.size.width(), b.size.height());
+    if b.size >= BlockSize::Block8x8 {
+        return vec![(x, y, w, h)];
+    }
+    let mut rects = Vec::new();
+    for row in 0..8 / h {
+        for col in 0..8 / w {
+            rects.push((x + col * w, y + row * h, w, h));
+        }
+    }
+    rects
+}
+
+/// Arrows of the block, one per reference and sub-block.
+fn add_block_mvs(lines: &mut Vec<Line>, b: &BlockInfo) {
+    let refs = if b.is_compound() { 2 } else { 1 };
+    for (x, y, w, h) in block_rects(b) {
+        // Vectors are stored per 4x4 sub-block of the 8x8 area, larger
+        // blocks have the same vector in all of them.
+        let idx = ((y - b.mi_row * 8) / 4 * 2 + (x - b.mi_col * 8) / 4) as usize;
+        let center = [(x + w / 2) as f32, (y + h / 2) as f32];
+        for i in 0..refs {
+            let mv = b.sub_mvs[idx][i];
+            let to = [center[0] + mv.col as f32 / 8.0, center[1] + mv.row as f32 / 8.0];
+            add_arrow(lines, center, to, REF_COLORS[b.ref_frame[i] as usize - 1]);
+        }
+    }
+}
+
+/// Motion vectors of inter blocks as arrows pointing from the block center
+/// to the position of its prediction in the reference frame, colored by
+/// reference. Compound blocks get two arrows.
+pub fn mv_lines(blocks: &BlockMap) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for b in blocks.blocks.iter().filter(|b| b.is_inter()) {
+        add_block_mvs(&mut lines, b);
+    }
+    lines
+}
+
+fn hue_to_rgb(hue: f32) -> Color {
+    let h = hue / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    match h as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+/// Color of the block outline depending on its area.
+pub fn size_color(size: BlockSize) -> Color {
+    // Area is between 2^4 and 2^12.
+    let area_log2 = (size.width() * size.height()).trailing_zeros();
+    let t = (area_log2 - 4) as f32 / 8.0;
+    hue_to_rgb(SMALLEST_HUE + t * (LARGEST_HUE - SMALLEST_HUE))
+}
+
+/// Outlines of all blocks, i.e. leaves of the partition trees, clipped to
+/// the frame of the given size.
+pub fn partition_lines(blocks: &BlockMap, width: u32, height: u32) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for b in &blocks.blocks {
+        let color = size_color(b.size);
+        for (x, y, w, h) in block_rects(b) {
+            if x >= width || y >= height {
+                continue;
+            }
+            let x0 = x as f32;
+            let y0 = y as f32;
+            let x1 = cmp::min(x + w, width) as f32;
+            let y1 = cmp::min(y + h, height) as f32;
+            let corners = [[x0, y0], [x1, y0], [x1, y1], [x0, y1]];
+            for i in 0..4 {
+                lines.push(Line {from: corners[i], to: corners[(i + 1) % 4], color: color});
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::vp9::block::Mv;
+    use ::vp9::block::tests::block;
+
+    fn get_ends(lines: &[Line]) -> Vec<([f32; 2], [f32; 2])> {
+        lines.iter().map(|line| (line.from, line.to)).collect()
+    }
+
+    #[test]
+    fn block_mvs() {
+        let mut map = BlockMap::new(4, 4);
+        map.push(block(0, 0, BlockSize::Block16x16, [1, 0], Mv {row: 16, col: -8}));
+        // Intra and zero vectors aren't drawn.
+        map.push(block(0, 2, BlockSize::Block16x16, [0, 0], Mv {row: 16, col: 16}));
+        map.push(block(2, 0, BlockSize::Block16x16, [2, 0], Mv::default()));
+        let lines = mv_lines(&map);
+        // Arrow goes from the center 1 pixel left and 2 down, then the head.
+        assert_eq!(lines.len(), 3);
+        assert_eq!((lines[0].from, lines[0].to), ([8.0, 8.0], [7.0, 10.0]));
+        assert!(lines.iter().all(|line| line.color == REF_COLORS[0]));
+        assert!(lines[1..].iter().all(|line| line.from == [7.0, 10.0]));
+    }
+
+    #[test]
+    fn compound_mvs() {
+        let mut map = BlockMap::new(1, 1);
+        let mut b = block(0, 0, BlockSize::Block8x8, [1, 3], Mv {row: 0, col: 16});
+        for mvs in b.sub_mvs.iter_mut() {
+            mvs[1] = Mv {row: -16, col: 0};
+        }
+        map.push(b);
+        let lines = mv_lines(&map);
+        assert_eq!(lines.len(), 6);
+        assert_eq!((lines[0].to, lines[0].color), ([6.0, 4.0], REF_COLORS[0]));
+        assert_eq!((lines[3].to, lines[3].color), ([4.0, 2.0], REF_COLORS[2]));
+    }
+
+    #[test]
+    fn sub8x8_mvs() {
+        let mut map = BlockMap::new(1, 1);
+        let mut b = block(0, 0, BlockSize::Block4x8, [1, 0], Mv::default());
+        b.sub_mvs[0][0] = Mv {row: 8, col: 0};
+        b.sub_mvs[1][0] = Mv {row: 0, col: 8};
+        map.push(b);
+        // Vectors of the left and right halves.
+        let shafts: Vec<_> = get_ends(&mv_lines(&map)).into_iter().step_by(3).collect();
+        assert_eq!(shafts, [([2.0, 4.0], [2.0, 5.0]), ([6.0, 4.0], [7.0, 4.0])]);
+    }
+
+    #[test]
+    fn size_colors() {
+        assert_eq!(size_color(BlockSize::Block4x4), [1.0, 0.0, 0.0]);
+        assert_eq!(size_color(BlockSize::Block8x8), [1.0, 1.0, 0.0]);
+        assert_eq!(size_color(BlockSize::Block16x16), [0.0, 1.0, 0.0]);
+        assert_eq!(size_color(BlockSize::Block64x64), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn outlines() {
+        let mut map = BlockMap::new(3, 3);
+        map.push(block(0, 0, BlockSize::Block16x16, [0, 0], Mv::default()));
+        map.push(block(0, 2, BlockSize::Block16x16, [0, 0], Mv::default()));
+        map.push(block(2, 0, BlockSize::Block4x4, [0, 0], Mv::default()));
+        let lines = partition_lines(&map, 20, 20);
+        let ends = get_ends(&lines);
+        assert_eq!(&ends[..4], [([0.0, 0.0], [16.0, 0.0]), ([16.0, 0.0], [16.0, 16.0]),
+                                ([16.0, 16.0], [0.0, 16.0]), ([0.0, 16.0], [0.0, 0.0])]);
+        // Clipped by the right edge.
+        assert_eq!(ends[5], ([20.0, 0.0], [20.0, 16.0]));
+        // Only the top row of 4x4 blocks is inside.
+        assert_eq!(lines.len(), 4 + 4 + 2 * 4);
+        assert_eq!(ends[12], ([4.0, 16.0], [8.0, 16.0]));
+        assert_eq!(lines[0].color, size_color(BlockSize::Block16x16));
+        assert_eq!(lines[15].color, size_color(BlockSize::Block4x4));
+    }
+}
@@ -0,0 +1,150 @@
+//! Minimal PNG encoder of 8-bit RGB images.
+//! Reference: PNG Specification, <https://www.w3.org/TR/PNG/>.
+
+use std::io;
+use std::io::Write;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Maximum size of the stored deflate block.
+const MAX_BLOCK_SIZE: usize = 65535;
+
+fn get_crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    for n in 0..256 {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[n] = c;
+    }
+    table
+}
+
+fn crc32(table: &[u32; 256], parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffffffff;
+    for part in parts {
+        for &byte in part.iter() {
+            crc = table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+    }
+    crc ^ 0xffffffff
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // NOTE(Kagami): Sums can't overflow in this many bytes before modulo.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+fn put_be32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+}
+
+fn write_chunk(out: &mut Write, table: &[u32; 256], kind: &[u8],
+               data: &[u8]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(8);
+    put_be32(&mut header, data.len() as u32);
+    header.extend_from_slice(kind);
+    let mut crc = Vec::with_capacity(4);
+    put_be32(&mut crc, crc32(table, &[kind, data]));
+    try!(out.write_all(&header));
+    try!(out.write_all(data));
+    out.write_all(&crc)
+}
+
+/// Wrap data into zlib stream of stored deflate blocks.
+// TODO(Kagami): Actually compress the data.
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let blocks = (data.len() + MAX_BLOCK_SIZE - 1) / MAX_BLOCK_SIZE;
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // Deflate with 32K window, no preset dictionary, fastest compression.
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut chunks = data.chunks(MAX_BLOCK_SIZE).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(chunk);
+    }
+    put_be32(&mut out, adler32(data));
+    out
+}
+
+/// Write RGBA8 pixels as PNG image. Alpha is dropped since decoded images
+/// are opaque anyway.
+pub fn write_rgba8(out: &mut Write, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    assert_eq!(rgba.len(), width as usize * height as usize * 4);
+    let table = get_crc_table();
+    let mut ihdr = Vec::with_capacity(13);
+    put_be32(&mut ihdr, width);
+    put_be32(&mut ihdr, height);
+    // 8-bit RGB, deflate compression, adaptive filtering, no interlace.
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut scanlines = Vec::with_capacity(height as usize * (width as usize * 3 + 1));
+    for row in rgba.chunks(width as usize * 4) {
+        // Filter type none.
+        scanlines.push(0);
+        for pixel in row.chunks(4) {
+            scanlines.extend_from_slice(&pixel[..3]);
+        }
+    }
+    try!(out.write_all(&SIGNATURE));
+    try!(write_chunk(out, &table, b"IHDR", &ihdr));
+    try!(write_chunk(out, &table, b"IDAT", &zlib_store(&scanlines)));
+    write_chunk(out, &table, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // Sums are reduced before they overflow.
+        assert_eq!(adler32(&vec![0xff; 100000]), 0x149a302c);
+        let table = get_crc_table();
+        assert_eq!(crc32(&table, &[b"IEND"]), 0xae426082);
+        assert_eq!(crc32(&table, &[b"IE", b"", b"ND"]), 0xae426082);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        assert_eq!(zlib_store(&[]), [0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+        assert_eq!(zlib_store(b"ab"),
+                   [0x78, 0x01, 1, 2, 0, 0xfd, 0xff, b'a', b'b', 0x01, 0x26, 0, 0xc4]);
+        let data = vec![7; MAX_BLOCK_SIZE + 1];
+        let out = zlib_store(&data);
+        assert_eq!(out.len(), 2 + 5 + MAX_BLOCK_SIZE + 5 + 1 + 4);
+        assert_eq!(out[2..7], [0, 0xff, 0xff, 0, 0]);
+        assert_eq!(out[7 + MAX_BLOCK_SIZE..12 + MAX_BLOCK_SIZE], [1, 1, 0, 0xfe, 0xff]);
+    }
+
+    #[test]
+    fn image() {
+        let mut out = Vec::new();
+        write_rgba8(&mut out, 1, 1, &[10, 20, 30, 255]).unwrap();
+        assert_eq!(out[..8], SIGNATURE);
+        assert_eq!(out[8..33], [0, 0, 0, 13, b'I', b'H', b'D', b'R',
+                                0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0,
+                                0x90, 0x77, 0x53, 0xde]);
+        // Filter byte and the pixel without alpha.
+        assert_eq!(out[33..41], [0, 0, 0, 15, b'I', b'D', b'A', b'T']);
+        assert_eq!(out[48..52], [0, 10, 20, 30]);
+        assert_eq!(out[out.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D',
+                                           0xae, 0x42, 0x60, 0x82]);
+    }
+}
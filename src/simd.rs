@@ -0,0 +1,421 @@
+//! Vectorized YUV to RGBA8 conversion of the image rows. Kernels compute
+//! exactly the same fixed point expressions as `Converter::convert` so the
+//! output is bit-exact with it.
+
+use std::fmt;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+use ::colorspace::PRECISION;
+
+/// Instruction set used for the conversion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Scalar,
+    Sse2,
+    Avx2,
+    Neon,
+}
+
+impl Level {
+    /// The best level supported by the CPU.
+    pub fn detect() -> Level {
+        Level::available().pop().unwrap_or(Level::Scalar)
+    }
+
+    /// All levels supported by the CPU, from the slowest to the fastest.
+    pub fn available() -> Vec<Level> {
+        let mut levels = vec![Level::Scalar];
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                levels.push(Level::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                levels.push(Level::Avx2);
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if ::std::arch::is_aarch64_feature_detected!("neon") {
+                levels.push(Level::Neon);
+            }
+        }
+        levels
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Level::Scalar => "scalar",
+            Level::Sse2 => "SSE2",
+            Level::Avx2 => "AVX2",
+            Level::Neon => "NEON",
+        };
+        f.pad(name)
+    }
+}
+
+/// Fixed point coefficients of the YUV to RGB conversion, see `Converter`.
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+    pub y_offset: i32,
+    pub c_offset: i32,
+    pub y_mul: i32,
+    pub vr: i32,
+    pub ug: i32,
+    pub vg: i32,
+    pub ub: i32,
+}
+
+/// Type of the stored sample: 8-bit or high bit depth.
+pub trait Sample: Copy + Send + Sync {
+    fn widen(self) -> u16;
+
+    /// Load 8 samples zero-extended to 16 bits.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    unsafe fn load8(ptr: *const Self) -> __m128i;
+    /// Load 4 samples zero-extended to 16 bits into the low half.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    unsafe fn load4(ptr: *const Self) -> __m128i;
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn load8(ptr: *const Self) -> uint16x8_t;
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn load4(ptr: *const Self) -> uint16x4_t;
+}
+
+impl Sample for u8 {
+    #[inline]
+    fn widen(self) -> u16 { self as u16 }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[inline]
+    unsafe fn load8(ptr: *const u8) -> __m128i {
+        _mm_unpacklo_epi8(_mm_loadl_epi64(ptr as *const __m128i), _mm_setzero_si128())
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[inline]
+    unsafe fn load4(ptr: *const u8) -> __m128i {
+        let val = (ptr as *const i32).read_unaligned();
+        _mm_unpacklo_epi8(_mm_cvtsi32_si128(val), _mm_setzero_si128())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    unsafe fn load8(ptr: *const u8) -> uint16x8_t {
+        vmovl_u8(vld1_u8(ptr))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    unsafe fn load4(ptr: *const u8) -> uint16x4_t {
+        let val = (ptr as *const u32).read_unaligned();
+        vget_low_u16(vmovl_u8(vreinterpret_u8_u32(vdup_n_u32(val))))
+    }
+}
+
+impl Sample for u16 {
+    #[inline]
+    fn widen(self) -> u16 { self }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[inline]
+    unsafe fn load8(ptr: *const u16) -> __m128i {
+        _mm_loadu_si128(ptr as *const __m128i)
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[inline]
+    unsafe fn load4(ptr: *const u16) -> __m128i {
+        _mm_loadl_epi64(ptr as *const __m128i)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    unsafe fn load8(ptr: *const u16) -> uint16x8_t {
+        vld1q_u16(ptr)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    unsafe fn load4(ptr: *const u16) -> uint16x4_t {
+        vld1_u16(ptr)
+    }
+}
+
+/// Convert the leading pixels of the row which are multiple of the vector
+/// width and return their number, the rest is left to the caller. Chroma
+/// rows are horizontally subsampled by `x_shift` (0 or 1).
+pub fn convert_row<T: Sample>(level: Level, params: &Params,
+                              y: &[T], u: &[T], v: &[T], x_shift: usize,
+                              out: &mut [u32]) -> usize {
+    let n = out.len() / 8 * 8;
+    if n == 0 || x_shift > 1 {
+        return 0;
+    }
+    assert!(y.len() >= n);
+    assert!(u.len() >= n >> x_shift && v.len() >= n >> x_shift);
+    unsafe {
+        match level {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Level::Sse2 => convert_row_sse2(params, y, u, v, x_shift, &mut out[..n]),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Level::Avx2 => convert_row_avx2(params, y, u, v, x_shift, &mut out[..n]),
+            #[cfg(target_arch = "aarch64")]
+            Level::Neon => convert_row_neon(params, y, u, v, x_shift, &mut out[..n]),
+            _ => return 0,
+        }
+    }
+    n
+}
+
+// SSE2 doesn't have 32-bit multiplication, make it from two 64-bit ones.
+// Low 32 bits of the product are the same for signed values.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn mullo_epi32_sse2(a: __m128i, b: __m128i) -> __m128i {
+    let even = _mm_mul_epu32(a, b);
+    let odd = _mm_mul_epu32(_mm_srli_epi64(a, 32), _mm_srli_epi64(b, 32));
+    _mm_unpacklo_epi32(_mm_shuffle_epi32(even, 0b00_00_10_00),
+                       _mm_shuffle_epi32(odd, 0b00_00_10_00))
+}
+
+// The same branchless clamp as the scalar code uses.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn clamp_sse2(val: __m128i) -> __m128i {
+    let zero = _mm_setzero_si128();
+    let max = _mm_set1_epi32(255);
+    let val = _mm_and_si128(_mm_srai_epi32(_mm_sub_epi32(zero, val), 31), val);
+    _mm_and_si128(_mm_or_si128(_mm_srai_epi32(_mm_sub_epi32(max, val), 31), val), max)
+}
+
+/// Convert 4 pixels of 32-bit samples.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn yuv_to_rgba_sse2(params: &Params, y: __m128i, u: __m128i, v: __m128i) -> __m128i {
+    let y = _mm_sub_epi32(y, _mm_set1_epi32(params.y_offset));
+    let y1 = _mm_add_epi32(mullo_epi32_sse2(y, _mm_set1_epi32(params.y_mul)),
+                           _mm_set1_epi32(1 << (PRECISION - 1)));
+    let d = _mm_sub_epi32(u, _mm_set1_epi32(params.c_offset));
+    let e = _mm_sub_epi32(v, _mm_set1_epi32(params.c_offset));
+    let r = _mm_add_epi32(y1, mullo_epi32_sse2(e, _mm_set1_epi32(params.vr)));
+    let g = _mm_sub_epi32(_mm_sub_epi32(y1, mullo_epi32_sse2(d, _mm_set1_epi32(params.ug))),
+                          mullo_epi32_sse2(e, _mm_set1_epi32(params.vg)));
+    let b = _mm_add_epi32(y1, mullo_epi32_sse2(d, _mm_set1_epi32(params.ub)));
+    let r = clamp_sse2(_mm_srai_epi32(r, PRECISION as i32));
+    let g = clamp_sse2(_mm_srai_epi32(g, PRECISION as i32));
+    let b = clamp_sse2(_mm_srai_epi32(b, PRECISION as i32));
+    _mm_or_si128(_mm_or_si128(_mm_set1_epi32(255 << 24), _mm_slli_epi32(b, 16)),
+                 _mm_or_si128(_mm_slli_epi32(g, 8), r))
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn convert_row_sse2<T: Sample>(params: &Params, y: &[T], u: &[T], v: &[T],
+                                      x_shift: usize, out: &mut [u32]) {
+    let zero = _mm_setzero_si128();
+    let mut j = 0;
+    while j < out.len() {
+        let ys = T::load8(y.as_ptr().offset(j as isize));
+        let (us, vs) = if x_shift == 0 {
+            (T::load8(u.as_ptr().offset(j as isize)), T::load8(v.as_ptr().offset(j as isize)))
+        } else {
+            let us = T::load4(u.as_ptr().offset((j >> 1) as isize));
+            let vs = T::load4(v.as_ptr().offset((j >> 1) as isize));
+            (_mm_unpacklo_epi16(us, us), _mm_unpacklo_epi16(vs, vs))
+        };
+        let lo = yuv_to_rgba_sse2(params,
+                                  _mm_unpacklo_epi16(ys, zero),
+                                  _mm_unpacklo_epi16(us, zero),
+                                  _mm_unpacklo_epi16(vs, zero));
+        let hi = yuv_to_rgba_sse2(params,
+                                  _mm_unpackhi_epi16(ys, zero),
+                                  _mm_unpackhi_epi16(us, zero),
+                                  _mm_unpackhi_epi16(vs, zero));
+        let dst = out.as_mut_ptr().offset(j as isize) as *mut __m128i;
+        _mm_storeu_si128(dst, lo);
+        _mm_storeu_si128(dst.offset(1), hi);
+        j += 8;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn clamp_avx2(val: __m256i, min: __m256i, max: __m256i) -> __m256i {
+    _mm256_min_epi32(_mm256_max_epi32(val, min), max)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn convert_row_avx2<T: Sample>(params: &Params, y: &[T], u: &[T], v: &[T],
+                                      x_shift: usize, out: &mut [u32]) {
+    let y_offset = _mm256_set1_epi32(params.y_offset);
+    let c_offset = _mm256_set1_epi32(params.c_offset);
+    let y_mul = _mm256_set1_epi32(params.y_mul);
+    let round = _mm256_set1_epi32(1 << (PRECISION - 1));
+    let vr = _mm256_set1_epi32(params.vr);
+    let ug = _mm256_set1_epi32(params.ug);
+    let vg = _mm256_set1_epi32(params.vg);
+    let ub = _mm256_set1_epi32(params.ub);
+    let zero = _mm256_setzero_si256();
+    let max = _mm256_set1_epi32(255);
+    let alpha = _mm256_set1_epi32(255 << 24);
+    let mut j = 0;
+    while j < out.len() {
+        let ys = T::load8(y.as_ptr().offset(j as isize));
+        let (us, vs) = if x_shift == 0 {
+            (T::load8(u.as_ptr().offset(j as isize)), T::load8(v.as_ptr().offset(j as isize)))
+        } else {
+            let us = T::load4(u.as_ptr().offset((j >> 1) as isize));
+            let vs = T::load4(v.as_ptr().offset((j >> 1) as isize));
+            (_mm_unpacklo_epi16(us, us), _mm_unpacklo_epi16(vs, vs))
+        };
+        let ys = _mm256_sub_epi32(_mm256_cvtepu16_epi32(ys), y_offset);
+        let y1 = _mm256_add_epi32(_mm256_mullo_epi32(ys, y_mul), round);
+        let d = _mm256_sub_epi32(_mm256_cvtepu16_epi32(us), c_offset);
+        let e = _mm256_sub_epi32(_mm256_cvtepu16_epi32(vs), c_offset);
+        let r = _mm256_add_epi32(y1, _mm256_mullo_epi32(e, vr));
+        let g = _mm256_sub_epi32(_mm256_sub_epi32(y1, _mm256_mullo_epi32(d, ug)),
+                                 _mm256_mullo_epi32(e, vg));
+        let b = _mm256_add_epi32(y1, _mm256_mullo_epi32(d, ub));
+        let r = clamp_avx2(_mm256_srai_epi32(r, PRECISION as i32), zero, max);
+        let g = clamp_avx2(_mm256_srai_epi32(g, PRECISION as i32), zero, max);
+        let b = clamp_avx2(_mm256_srai_epi32(b, PRECISION as i32), zero, max);
+        let rgba = _mm256_or_si256(_mm256_or_si256(alpha, _mm256_slli_epi32(b, 16)),
+                                   _mm256_or_si256(_mm256_slli_epi32(g, 8), r));
+        _mm256_storeu_si256(out.as_mut_ptr().offset(j as isize) as *mut __m256i, rgba);
+        j += 8;
+    }
+}
+
+/// Convert 4 pixels of 32-bit samples.
+#[cfg(target_arch = "aarch64")]
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn yuv_to_rgba_neon(params: &Params, y: uint32x4_t, u: uint32x4_t,
+                           v: uint32x4_t) -> uint32x4_t {
+    let y = vsubq_s32(vreinterpretq_s32_u32(y), vdupq_n_s32(params.y_offset));
+    let y1 = vaddq_s32(vmulq_s32(y, vdupq_n_s32(params.y_mul)),
+                       vdupq_n_s32(1 << (PRECISION - 1)));
+    let d = vsubq_s32(vreinterpretq_s32_u32(u), vdupq_n_s32(params.c_offset));
+    let e = vsubq_s32(vreinterpretq_s32_u32(v), vdupq_n_s32(params.c_offset));
+    let r = vaddq_s32(y1, vmulq_s32(e, vdupq_n_s32(params.vr)));
+    let g = vsubq_s32(vsubq_s32(y1, vmulq_s32(d, vdupq_n_s32(params.ug))),
+                      vmulq_s32(e, vdupq_n_s32(params.vg)));
+    let b = vaddq_s32(y1, vmulq_s32(d, vdupq_n_s32(params.ub)));
+    let zero = vdupq_n_s32(0);
+    let max = vdupq_n_s32(255);
+    let r = vminq_s32(vmaxq_s32(vshrq_n_s32::<{ PRECISION as i32 }>(r), zero), max);
+    let g = vminq_s32(vmaxq_s32(vshrq_n_s32::<{ PRECISION as i32 }>(g), zero), max);
+    let b = vminq_s32(vmaxq_s32(vshrq_n_s32::<{ PRECISION as i32 }>(b), zero), max);
+    let (r, g, b) = (vreinterpretq_u32_s32(r), vreinterpretq_u32_s32(g), vreinterpretq_u32_s32(b));
+    vorrq_u32(vorrq_u32(vdupq_n_u32(255 << 24), vshlq_n_u32::<16>(b)),
+              vorrq_u32(vshlq_n_u32::<8>(g), r))
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn convert_row_neon<T: Sample>(params: &Params, y: &[T], u: &[T], v: &[T],
+                                      x_shift: usize, out: &mut [u32]) {
+    let mut j = 0;
+    while j < out.len() {
+        let ys = T::load8(y.as_ptr().offset(j as isize));
+        let (us, vs) = if x_shift == 0 {
+            (T::load8(u.as_ptr().offset(j as isize)), T::load8(v.as_ptr().offset(j as isize)))
+        } else {
+            let us = T::load4(u.as_ptr().offset((j >> 1) as isize));
+            let vs = T::load4(v.as_ptr().offset((j >> 1) as isize));
+            let (us, vs) = (vcombine_u16(us, us), vcombine_u16(vs, vs));
+            (vzip1q_u16(us, us), vzip1q_u16(vs, vs))
+        };
+        let lo = yuv_to_rgba_neon(params,
+                                  vmovl_u16(vget_low_u16(ys)),
+                                  vmovl_u16(vget_low_u16(us)),
+                                  vmovl_u16(vget_low_u16(vs)));
+        let hi = yuv_to_rgba_neon(params,
+                                  vmovl_u16(vget_high_u16(ys)),
+                                  vmovl_u16(vget_high_u16(us)),
+                                  vmovl_u16(vget_high_u16(vs)));
+        let dst = out.as_mut_ptr().offset(j as isize);
+        vst1q_u32(dst, lo);
+        vst1q_u32(dst.offset(4), hi);
+        j += 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::colorspace::{Matrix, Converter};
+
+    /// Pseudo-random samples of the given bit depth, extremes included.
+    fn get_samples(count: usize, bit_depth: u32, seed: u32) -> Vec<u16> {
+        let max = (1 << bit_depth) - 1;
+        let mut state = seed;
+        (0..count).map(|i| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            match i % 7 {
+                0 => 0,
+                1 => max,
+                _ => ((state >> 8) & max as u32) as u16,
+            }
+        }).collect()
+    }
+
+    fn check<T: Sample>(conv: &Converter, y: &[T], u: &[T], v: &[T], x_shift: usize) {
+        let width = y.len();
+        let mut expected = vec![0; width];
+        let mut scalar = *conv;
+        scalar.level = Level::Scalar;
+        scalar.convert_row(y, u, v, x_shift, &mut expected);
+        for level in Level::available() {
+            let mut vectorized = *conv;
+            vectorized.level = level;
+            let mut out = vec![0; width];
+            vectorized.convert_row(y, u, v, x_shift, &mut out);
+            assert!(out == expected, "{} differs from scalar: {:?}, width {}, {}-bit, \
+                                      x_shift {}", level, conv.matrix, width,
+                    conv.bit_depth, x_shift);
+        }
+    }
+
+    #[test]
+    fn bit_exact() {
+        let widths = [1, 7, 9, 15, 17, 31, 33, 63, 65, 127];
+        let matrices = [Matrix::Bt601, Matrix::Bt709, Matrix::Smpte240, Matrix::Bt2020];
+        for &bit_depth in &[8, 10, 12] {
+            for &matrix in &matrices {
+                for &full_range in &[false, true] {
+                    let conv = Converter::new(matrix, full_range, bit_depth);
+                    for &width in &widths {
+                        for x_shift in 0..2 {
+                            let c_width = (width + (1 << x_shift) - 1) >> x_shift;
+                            let y = get_samples(width, bit_depth, 1);
+                            let u = get_samples(c_width, bit_depth, 2);
+                            let v = get_samples(c_width, bit_depth, 3);
+                            if bit_depth == 8 {
+                                let narrow = |samples: &[u16]| -> Vec<u8> {
+                                    samples.iter().map(|&s| s as u8).collect()
+                                };
+                                check(&conv, &narrow(&y), &narrow(&u), &narrow(&v), x_shift);
+                            } else {
+                                check(&conv, &y, &u, &v, x_shift);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
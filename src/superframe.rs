@@ -0,0 +1,111 @@
+//! VP9 superframe index parser.
+//! Reference: VP9 Bitstream & Decoding Process Specification, Annex B.
+
+/// Location of the VPx frame inside the container frame.
+#[derive(Debug, Clone, Copy)]
+pub struct SubFrame {
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Split container frame into VPx frames according to the superframe index
+/// at the end of data. Data without valid index is a single frame.
+pub fn split(data: &[u8]) -> Vec<SubFrame> {
+    let whole = vec![SubFrame {offset: 0, size: data.len()}];
+    let marker = match data.last() {
+        Some(&marker) if marker & 0xe0 == 0xc0 => marker,
+        _ => return whole,
+    };
+    let frame_count = (marker & 0x7) as usize + 1;
+    let mag = ((marker >> 3) & 0x3) as usize + 1;
+    let index_size = 2 + mag * frame_count;
+    // Index is enclosed in marker bytes.
+    if data.len() < index_size || data[data.len() - index_size] != marker {
+        return whole;
+    }
+    let frames_end = data.len() - index_size;
+    let mut pos = frames_end + 1;
+    let mut offset = 0;
+    let mut subframes = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        let mut size = 0;
+        for i in 0..mag {
+            size |= (data[pos + i] as usize) << (i * 8);
+        }
+        pos += mag;
+        if offset + size > frames_end {
+            return whole;
+        }
+        subframes.push(SubFrame {offset: offset, size: size});
+        offset += size;
+    }
+    subframes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames followed by the index with 2-byte sizes.
+    fn build(frames: &[&[u8]], sizes: &[usize]) -> Vec<u8> {
+        let marker = 0xc0 | (1 << 3) | (sizes.len() as u8 - 1);
+        let mut data: Vec<u8> = frames.iter().flat_map(|frame| frame.iter().cloned()).collect();
+        data.push(marker);
+        for &size in sizes {
+            data.push(size as u8);
+            data.push((size >> 8) as u8);
+        }
+        data.push(marker);
+        data
+    }
+
+    fn get_sizes(subframes: &[SubFrame]) -> Vec<(usize, usize)> {
+        subframes.iter().map(|subframe| (subframe.offset, subframe.size)).collect()
+    }
+
+    #[test]
+    fn two_frames() {
+        let data = build(&[&[1, 2, 3], &[4, 5]], &[3, 2]);
+        assert_eq!(get_sizes(&split(&data)), [(0, 3), (3, 2)]);
+    }
+
+    #[test]
+    fn single_frame() {
+        let data = [0x82, 0x49, 0x83, 0x42, 0x00];
+        assert_eq!(get_sizes(&split(&data)), [(0, 5)]);
+        assert_eq!(get_sizes(&split(&[])), [(0, 0)]);
+    }
+
+    #[test]
+    fn marker_mismatch() {
+        let mut data = build(&[&[1, 2, 3], &[4, 5]], &[3, 2]);
+        let first_marker = data.len() - 6;
+        data[first_marker] ^= 1;
+        assert_eq!(get_sizes(&split(&data)), [(0, data.len())]);
+    }
+
+    #[test]
+    fn truncated_index() {
+        let data = build(&[&[1, 2, 3], &[4, 5]], &[3, 2]);
+        // Only the last marker and part of the index is left.
+        let data = &data[data.len() - 4..];
+        assert_eq!(get_sizes(&split(data)), [(0, 4)]);
+    }
+
+    #[test]
+    fn sizes_exceed_frames() {
+        let data = build(&[&[1, 2, 3], &[4, 5]], &[3, 3]);
+        assert_eq!(get_sizes(&split(&data)), [(0, data.len())]);
+        let data = build(&[&[1, 2, 3], &[4, 5]], &[6, 0]);
+        assert_eq!(get_sizes(&split(&data)), [(0, data.len())]);
+    }
+
+    #[test]
+    fn zero_size_frames() {
+        // Empty frames are kept, callers skip them.
+        let data = build(&[&[1, 2, 3], &[4, 5]], &[0, 3, 2]);
+        assert_eq!(get_sizes(&split(&data)), [(0, 0), (0, 3), (3, 2)]);
+        let data = build(&[&[1, 2, 3]], &[3, 0]);
+        assert_eq!(get_sizes(&split(&data)), [(0, 3), (3, 0)]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Placement of the frame texture in the window: zoom and pan.
+
+const MIN_ZOOM: f32 = 0.125;
+const MAX_ZOOM: f32 = 32.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    /// Stretch the frame over the whole window, zoom and pan are ignored.
+    pub fit: bool,
+    /// Window pixels per frame pixel.
+    pub zoom: f32,
+    /// Frame point shown at the window center, in texture coordinates.
+    pub center: [f32; 2],
+    window_size: [f32; 2],
+    frame_size: [f32; 2],
+}
+
+impl View {
+    pub fn new(width: u16, height: u16) -> View {
+        View {
+            fit: true,
+            zoom: 1.0,
+            center: [0.5, 0.5],
+            window_size: [width as f32, height as f32],
+            frame_size: [width as f32, height as f32],
+        }
+    }
+
+    /// Window got resized, zoom and the frame point at the window center are
+    /// kept.
+    pub fn set_window_size(&mut self, width: u32, height: u32) {
+        self.window_size = [width as f32, height as f32];
+    }
+
+    /// Frame size changed mid-stream, relative center is kept.
+    pub fn set_frame_size(&mut self, width: u16, height: u16) {
+        self.frame_size = [width as f32, height as f32];
+    }
+
+    pub fn get_window_size(&self) -> [f32; 2] { self.window_size }
+    pub fn get_frame_size(&self) -> [f32; 2] { self.frame_size }
+
+    /// Scale and offset to apply to the full-window quad in clip space.
+    pub fn transform(&self) -> ([f32; 2], [f32; 2]) {
+        if self.fit {
+            return ([1.0, 1.0], [0.0, 0.0]);
+        }
+        let scale = [self.zoom * self.frame_size[0] / self.window_size[0],
+                     self.zoom * self.frame_size[1] / self.window_size[1]];
+        let offset = [(1.0 - 2.0 * self.center[0]) * scale[0],
+                      (2.0 * self.center[1] - 1.0) * scale[1]];
+        (scale, offset)
+    }
+
+    /// Whether texels are large enough to be shown as squares.
+    pub fn is_magnified(&self) -> bool {
+        !self.fit && self.zoom > 1.0
+    }
+
+    /// Texture coordinates of the window point.
+    pub fn to_texture(&self, pos: [f32; 2]) -> [f32; 2] {
+        let (scale, offset) = self.transform();
+        let clip_x = 2.0 * pos[0] / self.window_size[0] - 1.0;
+        let clip_y = 1.0 - 2.0 * pos[1] / self.window_size[1];
+        let quad_x = (clip_x - offset[0]) / scale[0];
+        let quad_y = (clip_y - offset[1]) / scale[1];
+        [(quad_x + 1.0) / 2.0, (1.0 - quad_y) / 2.0]
+    }
+
+    /// Switch between stretched frame and 1:1 zoom, keeping the center.
+    pub fn toggle_fit(&mut self) {
+        if !self.fit {
+            self.fit = true;
+        } else {
+            self.fit = false;
+            self.zoom = 1.0;
+        }
+    }
+
+    /// Zoom in (positive steps) or out by powers of two, keeping the frame
+    /// point under the given window position in place.
+    pub fn zoom_at(&mut self, steps: i32, pos: [f32; 2]) {
+        let point = self.to_texture(pos);
+        if self.fit {
+            // Start from the zoom fit mode effectively had.
+            self.fit = false;
+            self.zoom = self.window_size[0] / self.frame_size[0];
+        }
+        let zoom = self.zoom * 2f32.powi(steps);
+        self.zoom = zoom.max(MIN_ZOOM).min(MAX_ZOOM);
+        for i in 0..2 {
+            let from_center = pos[i] - self.window_size[i] / 2.0;
+            self.center[i] = point[i] - from_center / (self.zoom * self.frame_size[i]);
+        }
+    }
+
+    /// Move the frame by the given number of window pixels.
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        if self.fit {
+            return;
+        }
+        self.center[0] -= dx / (self.zoom * self.frame_size[0]);
+        self.center[1] -= dy / (self.zoom * self.frame_size[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!((a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5,
+                "{:?} != {:?}", a, b);
+    }
+
+    /// 1:1 view of 320x240 frame in 640x480 window.
+    fn get_view() -> View {
+        let mut view = View::new(320, 240);
+        view.set_window_size(640, 480);
+        view.toggle_fit();
+        view
+    }
+
+    #[test]
+    fn transform() {
+        let mut view = get_view();
+        assert_eq!(view.transform(), ([0.5, 0.5], [0.0, 0.0]));
+        // Frame moves left and up (clip space Y goes up) to show its lower
+        // right part at the center.
+        view.center = [0.75, 0.75];
+        assert_eq!(view.transform(), ([0.5, 0.5], [-0.25, 0.25]));
+        view.toggle_fit();
+        assert_eq!(view.transform(), ([1.0, 1.0], [0.0, 0.0]));
+    }
+
+    #[test]
+    fn to_texture() {
+        let mut view = get_view();
+        view.zoom = 2.0;
+        view.center = [0.3, 0.6];
+        assert_close(view.to_texture([320.0, 240.0]), [0.3, 0.6]);
+        // Window pixel is half of the frame pixel.
+        assert_close(view.to_texture([320.0 + 64.0, 240.0 - 48.0]), [0.4, 0.5]);
+        view.toggle_fit();
+        assert_close(view.to_texture([0.0, 0.0]), [0.0, 0.0]);
+        assert_close(view.to_texture([640.0, 480.0]), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn zoom_at() {
+        let mut view = get_view();
+        let pos = [100.0, 400.0];
+        let point = view.to_texture(pos);
+        view.zoom_at(1, pos);
+        assert_eq!(view.zoom, 2.0);
+        assert_close(view.to_texture(pos), point);
+        view.zoom_at(-3, pos);
+        assert_eq!(view.zoom, 0.25);
+        assert_close(view.to_texture(pos), point);
+        // Fit mode starts from the zoom it had.
+        let mut view = get_view();
+        view.toggle_fit();
+        let point = view.to_texture(pos);
+        view.zoom_at(1, pos);
+        assert!(!view.fit);
+        assert_eq!(view.zoom, 4.0);
+        assert_close(view.to_texture(pos), point);
+    }
+
+    #[test]
+    fn zoom_limits() {
+        let mut view = get_view();
+        view.zoom_at(100, [0.0, 0.0]);
+        assert_eq!(view.zoom, MAX_ZOOM);
+        view.zoom_at(1, [0.0, 0.0]);
+        assert_eq!(view.zoom, MAX_ZOOM);
+        view.zoom_at(-100, [0.0, 0.0]);
+        assert_eq!(view.zoom, MIN_ZOOM);
+    }
+
+    #[test]
+    fn pan() {
+        let mut view = get_view();
+        view.pan(32.0, -24.0);
+        assert_close(view.center, [0.4, 0.6]);
+        view.toggle_fit();
+        view.pan(32.0, -24.0);
+        assert_close(view.center, [0.4, 0.6]);
+    }
+
+    #[test]
+    fn toggle_fit() {
+        let mut view = View::new(320, 240);
+        assert!(view.fit);
+        view.zoom_at(2, [0.0, 0.0]);
+        view.toggle_fit();
+        assert!(view.fit);
+        view.toggle_fit();
+        assert!(!view.fit);
+        assert_eq!(view.zoom, 1.0);
+        view.toggle_fit();
+        assert!(view.fit);
+    }
+}
@@ -0,0 +1,239 @@
+//! Symbol counts and backward adaptation of probabilities, section 8.4 of
+//! the spec.
+
+use super::probs::*;
+use super::tile::{INTRA_MODE_TREE, INTER_MODE_TREE, PARTITION_TREE, INTERP_FILTER_TREE,
+                  MV_JOINT_TREE, MV_CLASS_TREE, MV_FR_TREE};
+
+const COEF_COUNT_SAT: u32 = 24;
+const COEF_MAX_UPDATE_FACTOR: u32 = 112;
+const COEF_MAX_UPDATE_FACTOR_AFTER_KEY: u32 = 128;
+const MODE_MV_COUNT_SAT: u32 = 20;
+const MODE_MV_MAX_UPDATE_FACTOR: u32 = 128;
+
+/// Counts of ZERO, ONE, larger tokens and end of block per context.
+pub type CoefCounts = [[[[[[u32; UNCONSTRAINED_NODES + 1]; PREV_COEF_CONTEXTS]; COEF_BANDS];
+                         REF_TYPES]; PLANE_TYPES]; TX_SIZES];
+pub type EobCounts = [[[[[u32; PREV_COEF_CONTEXTS]; COEF_BANDS]; REF_TYPES]; PLANE_TYPES];
+                      TX_SIZES];
+
+#[derive(Default, Clone, Copy)]
+pub struct MvComponentCounts {
+    pub sign: [u32; 2],
+    pub classes: [u32; MV_CLASSES],
+    pub class0_bit: [u32; CLASS0_SIZE],
+    pub bits: [[u32; 2]; MV_OFFSET_BITS],
+    pub class0_fr: [[u32; MV_FR_SIZE]; CLASS0_SIZE],
+    pub fr: [u32; MV_FR_SIZE],
+    pub class0_hp: [u32; 2],
+    pub hp: [u32; 2],
+}
+
+/// How many times each symbol was decoded in the frame, layout follows
+/// `FrameContext`.
+#[derive(Default)]
+pub struct Counts {
+    pub tx8x8: [[u32; 2]; TX_SIZE_CONTEXTS],
+    pub tx16x16: [[u32; 3]; TX_SIZE_CONTEXTS],
+    pub tx32x32: [[u32; 4]; TX_SIZE_CONTEXTS],
+    pub coef: CoefCounts,
+    /// Number of times the "more coefficients" bit was read.
+    pub eob_branch: EobCounts,
+    pub skip: [[u32; 2]; SKIP_CONTEXTS],
+    /// Indexed by the mode offset from NEARESTMV.
+    pub inter_mode: [[u32; 4]; INTER_MODE_CONTEXTS],
+    pub interp_filter: [[u32; 3]; INTERP_FILTER_CONTEXTS],
+    pub is_inter: [[u32; 2]; IS_INTER_CONTEXTS],
+    pub comp_mode: [[u32; 2]; COMP_MODE_CONTEXTS],
+    pub single_ref: [[[u32; 2]; 2]; REF_CONTEXTS],
+    pub comp_ref: [[u32; 2]; REF_CONTEXTS],
+    pub y_mode: [[u32; INTRA_MODES]; BLOCK_SIZE_GROUPS],
+    pub uv_mode: [[u32; INTRA_MODES]; INTRA_MODES],
+    pub partition: [[u32; 4]; PARTITION_CONTEXTS],
+    pub mv_joint: [u32; MV_JOINTS],
+    pub mv_comp: [MvComponentCounts; 2],
+}
+
+fn get_prob(num: u32, den: u32) -> u8 {
+    let p = (num as u64 * 256 + (den >> 1) as u64) / den as u64;
+    if p > 255 { 255 } else if p < 1 { 1 } else { p as u8 }
+}
+
+fn merge_prob(pre_prob: u8, ct: [u32; 2], count_sat: u32, max_update_factor: u32) -> u8 {
+    let den = ct[0] + ct[1];
+    let prob = if den == 0 { 128 } else { get_prob(ct[0], den) as u32 };
+    let count = if den < count_sat { den } else { count_sat };
+    let factor = max_update_factor * count / count_sat;
+    ((pre_prob as u32 * (256 - factor) + prob * factor + 128) >> 8) as u8
+}
+
+fn merge_mode_prob(pre_prob: u8, ct: [u32; 2]) -> u8 {
+    merge_prob(pre_prob, ct, MODE_MV_COUNT_SAT, MODE_MV_MAX_UPDATE_FACTOR)
+}
+
+/// Adapt probabilities of the tree nodes, returns total count of the
+/// subtree.
+fn merge_tree_probs(tree: &[i8], i: usize, pre_probs: &[u8], counts: &[u32],
+                    probs: &mut [u8]) -> u32 {
+    let left = match tree[i] {
+        node if node <= 0 => counts[-node as usize],
+        node => merge_tree_probs(tree, node as usize, pre_probs, counts, probs),
+    };
+    let right = match tree[i + 1] {
+        node if node <= 0 => counts[-node as usize],
+        node => merge_tree_probs(tree, node as usize, pre_probs, counts, probs),
+    };
+    probs[i >> 1] = merge_mode_prob(pre_probs[i >> 1], [left, right]);
+    left + right
+}
+
+fn merge_tree(tree: &[i8], pre_probs: &[u8], counts: &[u32], probs: &mut [u8]) {
+    merge_tree_probs(tree, 0, pre_probs, counts, probs);
+}
+
+/// Adapt coefficient probabilities of the current frame context `fc`
+/// based on the saved context `pre`.
+pub fn adapt_coef_probs(fc: &mut FrameContext, pre: &FrameContext, counts: &Counts,
+                        after_key: bool) {
+    let update_factor = if after_key {
+        COEF_MAX_UPDATE_FACTOR_AFTER_KEY
+    } else {
+        COEF_MAX_UPDATE_FACTOR
+    };
+    for t in 0..TX_SIZES {
+        for i in 0..PLANE_TYPES {
+            for j in 0..REF_TYPES {
+                for k in 0..COEF_BANDS {
+                    let contexts = if k == 0 { 3 } else { PREV_COEF_CONTEXTS };
+                    for l in 0..contexts {
+                        let c = &counts.coef[t][i][j][k][l];
+                        let eob = counts.eob_branch[t][i][j][k][l];
+                        let branch_ct = [[c[3], eob - c[3]], [c[0], c[1] + c[2]], [c[1], c[2]]];
+                        for m in 0..UNCONSTRAINED_NODES {
+                            fc.coef[t][i][j][k][l][m] = merge_prob(
+                                pre.coef[t][i][j][k][l][m], branch_ct[m],
+                                COEF_COUNT_SAT, update_factor);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Adapt mode and motion vector probabilities, done only for inter
+/// frames.
+pub fn adapt_noncoef_probs(fc: &mut FrameContext, pre: &FrameContext, counts: &Counts,
+                           interp_switchable: bool, tx_select: bool, allow_hp: bool) {
+    for i in 0..IS_INTER_CONTEXTS {
+        fc.is_inter[i] = merge_mode_prob(pre.is_inter[i], counts.is_inter[i]);
+    }
+    for i in 0..COMP_MODE_CONTEXTS {
+        fc.comp_mode[i] = merge_mode_prob(pre.comp_mode[i], counts.comp_mode[i]);
+    }
+    for i in 0..REF_CONTEXTS {
+        fc.comp_ref[i] = merge_mode_prob(pre.comp_ref[i], counts.comp_ref[i]);
+        for j in 0..2 {
+            fc.single_ref[i][j] = merge_mode_prob(pre.single_ref[i][j],
+                                                  counts.single_ref[i][j]);
+        }
+    }
+    for i in 0..INTER_MODE_CONTEXTS {
+        merge_tree(&INTER_MODE_TREE, &pre.inter_mode[i], &counts.inter_mode[i],
+                   &mut fc.inter_mode[i]);
+    }
+    for i in 0..BLOCK_SIZE_GROUPS {
+        merge_tree(&INTRA_MODE_TREE, &pre.y_mode[i], &counts.y_mode[i], &mut fc.y_mode[i]);
+    }
+    for i in 0..INTRA_MODES {
+        merge_tree(&INTRA_MODE_TREE, &pre.uv_mode[i], &counts.uv_mode[i], &mut fc.uv_mode[i]);
+    }
+    for i in 0..PARTITION_CONTEXTS {
+        merge_tree(&PARTITION_TREE, &pre.partition[i], &counts.partition[i],
+                   &mut fc.partition[i]);
+    }
+    if interp_switchable {
+        for i in 0..INTERP_FILTER_CONTEXTS {
+            merge_tree(&INTERP_FILTER_TREE, &pre.interp_filter[i], &counts.interp_filter[i],
+                       &mut fc.interp_filter[i]);
+        }
+    }
+    if tx_select {
+        for i in 0..TX_SIZE_CONTEXTS {
+            let c = &counts.tx8x8[i];
+            fc.tx8x8[i][0] = merge_mode_prob(pre.tx8x8[i][0], [c[0], c[1]]);
+            let c = &counts.tx16x16[i];
+            let branch_ct = [[c[0], c[1] + c[2]], [c[1], c[2]]];
+            for j in 0..2 {
+                fc.tx16x16[i][j] = merge_mode_prob(pre.tx16x16[i][j], branch_ct[j]);
+            }
+            let c = &counts.tx32x32[i];
+            let branch_ct = [[c[0], c[1] + c[2] + c[3]], [c[1], c[2] + c[3]], [c[2], c[3]]];
+            for j in 0..3 {
+                fc.tx32x32[i][j] = merge_mode_prob(pre.tx32x32[i][j], branch_ct[j]);
+            }
+        }
+    }
+    for i in 0..SKIP_CONTEXTS {
+        fc.skip[i] = merge_mode_prob(pre.skip[i], counts.skip[i]);
+    }
+
+    merge_tree(&MV_JOINT_TREE, &pre.mv_joint, &counts.mv_joint, &mut fc.mv_joint);
+    for i in 0..2 {
+        let pre = &pre.mv_comp[i];
+        let c = &counts.mv_comp[i];
+        let comp = &mut fc.mv_comp[i];
+        comp.sign = merge_mode_prob(pre.sign, c.sign);
+        merge_tree(&MV_CLASS_TREE, &pre.classes, &c.classes, &mut comp.classes);
+        comp.class0_bit = merge_mode_prob(pre.class0_bit, c.class0_bit);
+        for j in 0..MV_OFFSET_BITS {
+            comp.bits[j] = merge_mode_prob(pre.bits[j], c.bits[j]);
+        }
+        for j in 0..CLASS0_SIZE {
+            merge_tree(&MV_FR_TREE, &pre.class0_fr[j], &c.class0_fr[j], &mut comp.class0_fr[j]);
+        }
+        merge_tree(&MV_FR_TREE, &pre.fr, &c.fr, &mut comp.fr);
+        if allow_hp {
+            comp.class0_hp = merge_mode_prob(pre.class0_hp, c.class0_hp);
+            comp.hp = merge_mode_prob(pre.hp, c.hp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        // No counts, the probability is kept.
+        assert_eq!(merge_mode_prob(77, [0, 0]), 77);
+        // Saturated counts move it half-way with the largest factor.
+        assert_eq!(merge_mode_prob(128, [20, 0]), 192);
+        assert_eq!(merge_mode_prob(128, [0, 40]), 65);
+        // Half of the saturation count, factor is halved.
+        assert_eq!(merge_mode_prob(100, [5, 5]), 107);
+        assert_eq!(merge_prob(100, [5, 5], COEF_COUNT_SAT, COEF_MAX_UPDATE_FACTOR), 105);
+    }
+
+    #[test]
+    fn tree() {
+        // Only PARTITION_NONE and PARTITION_SPLIT were used.
+        let mut probs = [0; 3];
+        merge_tree(&PARTITION_TREE, &[128; 3], &[10, 0, 0, 10], &mut probs);
+        assert_eq!(probs, [128, 96, 96]);
+    }
+
+    #[test]
+    fn coef_probs() {
+        let pre = FrameContext::default();
+        let mut fc = pre;
+        let mut counts = Counts::default();
+        // Block ended right away 24 times of 48.
+        counts.eob_branch[0][0][0][0][0] = 48;
+        counts.coef[0][0][0][0][0] = [12, 6, 6, 24];
+        adapt_coef_probs(&mut fc, &pre, &counts, false);
+        assert_eq!(fc.coef[0][0][0][0][0], [166, 72, 171]);
+        assert_eq!(fc.coef[0][0][0][0][1], pre.coef[0][0][0][0][1]);
+    }
+}
@@ -0,0 +1,45 @@
+//! MSB-first bit reader for the uncompressed header.
+
+use super::Error;
+
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {data: data, pos: 0}
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, Error> {
+        let byte = match self.data.get(self.pos >> 3) {
+            Some(&byte) => byte,
+            None => return Err(Error::NotEnoughData),
+        };
+        let bit = (byte >> (7 - (self.pos & 7))) & 1;
+        self.pos += 1;
+        Ok(bit != 0)
+    }
+
+    /// Read unsigned n-bit number, f(n) in spec.
+    pub fn read_bits(&mut self, n: usize) -> Result<u32, Error> {
+        let mut val = 0;
+        for _ in 0..n {
+            val = val << 1 | try!(self.read_bit()) as u32;
+        }
+        Ok(val)
+    }
+
+    /// Read n-bit magnitude followed by sign bit, su(n) in spec.
+    pub fn read_signed(&mut self, n: usize) -> Result<i32, Error> {
+        let val = try!(self.read_bits(n)) as i32;
+        Ok(if try!(self.read_bit()) { -val } else { val })
+    }
+
+    /// Number of bytes consumed so far, including the partial one.
+    pub fn byte_pos(&self) -> usize {
+        (self.pos + 7) >> 3
+    }
+}
@@ -0,0 +1,407 @@
+//! Per-block mode info produced by the tile decoder.
+
+use std::cmp;
+use std::fmt;
+use std::mem;
+use super::header::InterpFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlockSize {
+    Block4x4,
+    Block4x8,
+    Block8x4,
+    Block8x8,
+    Block8x16,
+    Block16x8,
+    Block16x16,
+    Block16x32,
+    Block32x16,
+    Block32x32,
+    Block32x64,
+    Block64x32,
+    Block64x64,
+}
+
+impl BlockSize {
+    /// Width in pixels.
+    pub fn width(&self) -> u32 {
+        match *self {
+            BlockSize::Block4x4 | BlockSize::Block4x8 => 4,
+            BlockSize::Block8x4 | BlockSize::Block8x8 | BlockSize::Block8x16 => 8,
+            BlockSize::Block16x8 | BlockSize::Block16x16 | BlockSize::Block16x32 => 16,
+            BlockSize::Block32x16 | BlockSize::Block32x32 | BlockSize::Block32x64 => 32,
+            BlockSize::Block64x32 | BlockSize::Block64x64 => 64,
+        }
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> u32 {
+        match *self {
+            BlockSize::Block4x4 | BlockSize::Block8x4 => 4,
+            BlockSize::Block4x8 | BlockSize::Block8x8 | BlockSize::Block16x8 => 8,
+            BlockSize::Block8x16 | BlockSize::Block16x16 | BlockSize::Block32x16 => 16,
+            BlockSize::Block16x32 | BlockSize::Block32x32 | BlockSize::Block64x32 => 32,
+            BlockSize::Block32x64 | BlockSize::Block64x64 => 64,
+        }
+    }
+
+    /// Width in 8x8 units, blocks smaller than 8x8 still take one unit.
+    pub fn mi_width(&self) -> u32 {
+        cmp::max(self.width() >> 3, 1)
+    }
+
+    pub fn mi_height(&self) -> u32 {
+        cmp::max(self.height() >> 3, 1)
+    }
+
+    /// Largest transform size which fits the block, 0 is 4x4.
+    pub fn max_tx_size(&self) -> usize {
+        let min = cmp::min(self.width(), self.height());
+        cmp::min(min.trailing_zeros() as usize - 2, 3)
+    }
+
+    /// Square block split in the given way.
+    pub fn subsize(&self, partition: Partition) -> BlockSize {
+        use self::BlockSize::*;
+        match (partition, *self) {
+            (Partition::None, size) => size,
+            (Partition::Horz, Block8x8) => Block8x4,
+            (Partition::Horz, Block16x16) => Block16x8,
+            (Partition::Horz, Block32x32) => Block32x16,
+            (Partition::Horz, _) => Block64x32,
+            (Partition::Vert, Block8x8) => Block4x8,
+            (Partition::Vert, Block16x16) => Block8x16,
+            (Partition::Vert, Block32x32) => Block16x32,
+            (Partition::Vert, _) => Block32x64,
+            (Partition::Split, Block8x8) => Block4x4,
+            (Partition::Split, Block16x16) => Block8x8,
+            (Partition::Split, Block32x32) => Block16x16,
+            (Partition::Split, _) => Block32x32,
+        }
+    }
+}
+
+impl fmt::Display for BlockSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width(), self.height())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    None,
+    Horz,
+    Vert,
+    Split,
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Partition::None => "PARTITION_NONE",
+            Partition::Horz => "PARTITION_HORZ",
+            Partition::Vert => "PARTITION_VERT",
+            Partition::Split => "PARTITION_SPLIT",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Intra and inter prediction modes, numbered as in the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PredictionMode {
+    DcPred,
+    VPred,
+    HPred,
+    D45Pred,
+    D135Pred,
+    D117Pred,
+    D153Pred,
+    D207Pred,
+    D63Pred,
+    TmPred,
+    NearestMv,
+    NearMv,
+    ZeroMv,
+    NewMv,
+}
+
+impl PredictionMode {
+    pub fn from_index(index: u8) -> PredictionMode {
+        use self::PredictionMode::*;
+        match index {
+            0 => DcPred,
+            1 => VPred,
+            2 => HPred,
+            3 => D45Pred,
+            4 => D135Pred,
+            5 => D117Pred,
+            6 => D153Pred,
+            7 => D207Pred,
+            8 => D63Pred,
+            9 => TmPred,
+            10 => NearestMv,
+            11 => NearMv,
+            12 => ZeroMv,
+            _ => NewMv,
+        }
+    }
+}
+
+impl fmt::Display for PredictionMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            PredictionMode::DcPred => "DC_PRED",
+            PredictionMode::VPred => "V_PRED",
+            PredictionMode::HPred => "H_PRED",
+            PredictionMode::D45Pred => "D45_PRED",
+            PredictionMode::D135Pred => "D135_PRED",
+            PredictionMode::D117Pred => "D117_PRED",
+            PredictionMode::D153Pred => "D153_PRED",
+            PredictionMode::D207Pred => "D207_PRED",
+            PredictionMode::D63Pred => "D63_PRED",
+            PredictionMode::TmPred => "TM_PRED",
+            PredictionMode::NearestMv => "NEARESTMV",
+            PredictionMode::NearMv => "NEARMV",
+            PredictionMode::ZeroMv => "ZEROMV",
+            PredictionMode::NewMv => "NEWMV",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Motion vector in 1/8 pel units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Mv {
+    pub row: i16,
+    pub col: i16,
+}
+
+impl fmt::Display for Mv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {})", self.col, self.row)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockInfo {
+    /// Position of the top-left corner in 8x8 units.
+    pub mi_row: u32,
+    pub mi_col: u32,
+    pub size: BlockSize,
+    pub segment_id: u8,
+    /// Segment id was taken from the previous frame's map.
+    pub seg_id_predicted: bool,
+    pub skip: bool,
+    /// Transform size, 0 is 4x4.
+    pub tx_size: usize,
+    /// Luma mode, equal to the mode of the last sub-block for blocks
+    /// smaller than 8x8.
+    pub y_mode: PredictionMode,
+    /// Modes of the 4x4 sub-blocks in raster order, same as `y_mode` for
+    /// 8x8 and larger blocks.
+    pub sub_modes: [PredictionMode; 4],
+    /// Chroma mode, only meaningful for intra blocks.
+    pub uv_mode: PredictionMode,
+    /// 1 is LAST, 2 is GOLDEN and 3 is ALTREF. First reference is 0 for
+    /// intra blocks, second is 0 unless prediction is compound.
+    pub ref_frame: [u8; 2],
+    /// Only meaningful for inter blocks.
+    pub interp_filter: InterpFilter,
+    /// Motion vectors of the 4x4 sub-blocks in raster order, per
+    /// reference.
+    pub sub_mvs: [[Mv; 2]; 4],
+    /// Sum of end of block positions over all transform blocks, 0 means
+    /// there are no coded coefficients.
+    pub eob_total: u32,
+}
+
+impl BlockInfo {
+    pub fn is_inter(&self) -> bool {
+        self.ref_frame[0] > 0
+    }
+
+    pub fn is_compound(&self) -> bool {
+        self.ref_frame[1] > 0
+    }
+
+    /// Motion vector of the whole block, the last sub-block's one for
+    /// blocks smaller than 8x8.
+    pub fn mv(&self, ref_idx: usize) -> Mv {
+        self.sub_mvs[3][ref_idx]
+    }
+}
+
+/// Single partition decision of the square block.
+#[derive(Debug, Clone, Copy)]
+pub struct PartitionInfo {
+    pub mi_row: u32,
+    pub mi_col: u32,
+    pub size: BlockSize,
+    pub partition: Partition,
+}
+
+const NO_BLOCK: u32 = !0;
+
+/// Number of blocks and partition decisions of the frame by their kind.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockStats {
+    pub blocks: usize,
+    pub intra: usize,
+    pub compound: usize,
+    pub skip: usize,
+    pub sub8x8: usize,
+    /// Blocks predicted from LAST, GOLDEN and ALTREF frames.
+    pub refs: [usize; 3],
+    /// Partition decisions in `Partition` order.
+    pub partitions: [usize; 4],
+}
+
+/// Mode info of the whole frame.
+#[derive(Debug, Clone)]
+pub struct BlockMap {
+    /// Frame dimensions in 8x8 units.
+    pub mi_cols: u32,
+    pub mi_rows: u32,
+    /// Blocks in decoding order.
+    pub blocks: Vec<BlockInfo>,
+    /// Partition decisions in decoding order, from 64x64 down to 8x8.
+    pub partitions: Vec<PartitionInfo>,
+    /// Index of the block covering each 8x8 unit, in raster order.
+    grid: Vec<u32>,
+}
+
+impl BlockMap {
+    pub fn new(mi_cols: u32, mi_rows: u32) -> BlockMap {
+        BlockMap {
+            mi_cols: mi_cols,
+            mi_rows: mi_rows,
+            blocks: Vec::new(),
+            partitions: Vec::new(),
+            grid: vec![NO_BLOCK; (mi_cols * mi_rows) as usize],
+        }
+    }
+
+    /// Add block covering the not yet decoded area.
+    pub fn push(&mut self, block: BlockInfo) {
+        let index = self.blocks.len() as u32;
+        let row_end = cmp::min(block.mi_row + block.size.mi_height(), self.mi_rows);
+        let col_end = cmp::min(block.mi_col + block.size.mi_width(), self.mi_cols);
+        for row in block.mi_row..row_end {
+            for col in block.mi_col..col_end {
+                self.grid[(row * self.mi_cols + col) as usize] = index;
+            }
+        }
+        self.blocks.push(block);
+    }
+
+    /// Block covering given 8x8 unit.
+    pub fn get(&self, mi_row: u32, mi_col: u32) -> Option<&BlockInfo> {
+        if mi_row >= self.mi_rows || mi_col >= self.mi_cols {
+            return None;
+        }
+        match self.grid[(mi_row * self.mi_cols + mi_col) as usize] {
+            NO_BLOCK => None,
+            index => Some(&self.blocks[index as usize]),
+        }
+    }
+    pub fn get_stats(&self) -> BlockStats {
+        let mut stats = BlockStats {blocks: self.blocks.len(), ..Default::default()};
+        for block in &self.blocks {
+            stats.intra += !block.is_inter() as usize;
+            stats.compound += block.is_compound() as usize;
+            stats.skip += block.skip as usize;
+            stats.sub8x8 += (block.size < BlockSize::Block8x8) as usize;
+            for &ref_frame in block.ref_frame.iter().filter(|&&ref_frame| ref_frame > 0) {
+                stats.refs[ref_frame as usize - 1] += 1;
+            }
+        }
+        for info in &self.partitions {
+            stats.partitions[info.partition as usize] += 1;
+        }
+        stats
+    }
+
+    /// Approximate number of bytes taken by the map.
+    pub fn get_memory_size(&self) -> usize {
+        self.blocks.capacity() * mem::size_of::<BlockInfo>() +
+        self.partitions.capacity() * mem::size_of::<PartitionInfo>() +
+        self.grid.capacity() * mem::size_of::<u32>()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Block predicted from the given references with the same vector.
+    pub fn block(mi_row: u32, mi_col: u32, size: BlockSize, ref_frame: [u8; 2], mv: Mv)
+                 -> BlockInfo {
+        BlockInfo {
+            mi_row: mi_row,
+            mi_col: mi_col,
+            size: size,
+            segment_id: 0,
+            seg_id_predicted: false,
+            skip: false,
+            tx_size: 0,
+            y_mode: if ref_frame[0] > 0 { PredictionMode::NewMv } else { PredictionMode::DcPred },
+            sub_modes: [PredictionMode::DcPred; 4],
+            uv_mode: PredictionMode::DcPred,
+            ref_frame: ref_frame,
+            interp_filter: InterpFilter::EightTap,
+            sub_mvs: [[mv; 2]; 4],
+            eob_total: 0,
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(BlockSize::Block16x16.subsize(Partition::Vert), BlockSize::Block8x16);
+        assert_eq!(BlockSize::Block8x8.subsize(Partition::Split), BlockSize::Block4x4);
+        assert_eq!(BlockSize::Block64x64.subsize(Partition::Horz), BlockSize::Block64x32);
+        assert_eq!((BlockSize::Block4x8.mi_width(), BlockSize::Block4x8.mi_height()), (1, 1));
+        assert_eq!(BlockSize::Block8x16.max_tx_size(), 1);
+        assert_eq!(BlockSize::Block64x64.max_tx_size(), 3);
+    }
+
+    #[test]
+    fn block_map() {
+        let mut map = BlockMap::new(3, 2);
+        map.push(block(0, 0, BlockSize::Block16x16, [0, 0], Mv::default()));
+        // Clipped by the frame edges.
+        map.push(block(0, 2, BlockSize::Block16x16, [1, 0], Mv {row: 2, col: -4}));
+        assert_eq!(map.get(1, 1).map(|b| b.mi_col), Some(0));
+        assert_eq!(map.get(1, 2).map(|b| b.mv(0)), Some(Mv {row: 2, col: -4}));
+        assert!(map.get(2, 0).is_none());
+        assert!(map.get(0, 3).is_none());
+        let mut map = BlockMap::new(3, 2);
+        map.push(block(0, 0, BlockSize::Block8x8, [0, 0], Mv::default()));
+        assert!(map.get(0, 1).is_none());
+    }
+
+    #[test]
+    fn stats() {
+        let mut map = BlockMap::new(4, 4);
+        map.push(block(0, 0, BlockSize::Block16x16, [0, 0], Mv::default()));
+        let mut b = block(0, 2, BlockSize::Block16x16, [1, 3], Mv::default());
+        b.skip = true;
+        map.push(b);
+        map.push(block(2, 0, BlockSize::Block4x8, [2, 0], Mv::default()));
+        map.partitions.push(PartitionInfo {
+            mi_row: 0,
+            mi_col: 0,
+            size: BlockSize::Block32x32,
+            partition: Partition::Split,
+        });
+        assert_eq!(map.get_stats(), BlockStats {
+            blocks: 3,
+            intra: 1,
+            compound: 1,
+            skip: 1,
+            sub8x8: 1,
+            refs: [1, 1, 1],
+            partitions: [0, 0, 0, 1],
+        });
+    }
+}
@@ -0,0 +1,236 @@
+//! Boolean (arithmetic) decoder, section 9.2 of the spec.
+
+use super::Error;
+
+/// Size of the bit window.
+const VALUE_BITS: i32 = 64;
+/// Bits count meaning "data is over", padding zeroes are shifted in instead.
+const LOTS_OF_BITS: i32 = 0x4000_0000;
+
+pub struct BoolDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Not yet consumed bits, MSB-aligned.
+    value: u64,
+    /// Number of valid bits in the window besides the top byte.
+    count: i32,
+    range: u32,
+}
+
+impl<'a> BoolDecoder<'a> {
+    /// Start decoding of the given data, init_bool() in spec.
+    pub fn new(data: &'a [u8]) -> Result<BoolDecoder<'a>, Error> {
+        if data.is_empty() {
+            return Err(Error::NotEnoughData);
+        }
+        let mut bd = BoolDecoder {
+            data: data,
+            pos: 0,
+            value: 0,
+            count: -8,
+            range: 255,
+        };
+        bd.fill();
+        if bd.read_bool(128) {
+            return Err(Error::InvalidMarkerBit);
+        }
+        Ok(bd)
+    }
+
+    fn fill(&mut self) {
+        let mut shift = VALUE_BITS - 16 - self.count;
+        while shift >= 0 {
+            match self.data.get(self.pos) {
+                Some(&byte) => {
+                    self.value |= (byte as u64) << shift;
+                    self.pos += 1;
+                    self.count += 8;
+                    shift -= 8;
+                },
+                None => {
+                    self.count += LOTS_OF_BITS;
+                    break;
+                },
+            }
+        }
+    }
+
+    /// Read one bool with given probability of zero (out of 256), B(p) in
+    /// spec.
+    pub fn read_bool(&mut self, prob: u8) -> bool {
+        let split = (self.range * prob as u32 + (256 - prob as u32)) >> 8;
+        if self.count < 0 {
+            self.fill();
+        }
+        let bigsplit = (split as u64) << (VALUE_BITS - 8);
+        let bit = if self.value >= bigsplit {
+            self.range -= split;
+            self.value -= bigsplit;
+            true
+        } else {
+            self.range = split;
+            false
+        };
+        // Renormalize so range is in [128, 255] again.
+        let shift = (self.range as u8).leading_zeros();
+        self.range <<= shift;
+        self.value <<= shift;
+        self.count -= shift as i32;
+        bit
+    }
+
+    /// Read unsigned n-bit number, L(n) in spec.
+    pub fn read_literal(&mut self, n: usize) -> u32 {
+        let mut val = 0;
+        for _ in 0..n {
+            val = val << 1 | self.read_bool(128) as u32;
+        }
+        val
+    }
+
+    /// Read tree-coded value, T in spec. Tree is given in the libvpx form:
+    /// positive entries point to the next node pair and the rest are
+    /// negated leaf values.
+    pub fn read_tree(&mut self, tree: &[i8], probs: &[u8]) -> u8 {
+        let mut i = 0;
+        loop {
+            let node = tree[i + self.read_bool(probs[i >> 1]) as usize];
+            if node <= 0 {
+                return -node as u8;
+            }
+            i = node as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::cmp;
+    use super::*;
+
+    /// Bool encoder of libvpx (vpx_dsp/bitwriter.c), used to build the test
+    /// streams.
+    pub struct BoolEncoder {
+        data: Vec<u8>,
+        low: u32,
+        range: u32,
+        count: i32,
+    }
+
+    impl BoolEncoder {
+        /// Start the stream with marker bit.
+        pub fn new() -> BoolEncoder {
+            let mut e = BoolEncoder {data: Vec::new(), low: 0, range: 255, count: -24};
+            e.write_bool(false, 128);
+            e
+        }
+
+        pub fn write_bool(&mut self, bit: bool, prob: u8) {
+            let split = 1 + (((self.range - 1) * prob as u32) >> 8);
+            let mut range = split;
+            if bit {
+                self.low += split;
+                range = self.range - split;
+            }
+            let mut shift = (range as u8).leading_zeros() as i32;
+            self.range = range << shift;
+            self.count += shift;
+            if self.count >= 0 {
+                let offset = shift - self.count;
+                if (self.low << (offset - 1)) & 0x8000_0000 != 0 {
+                    // Propagate the carry.
+                    let mut pos = self.data.len();
+                    while self.data[pos - 1] == 0xff {
+                        self.data[pos - 1] = 0;
+                        pos -= 1;
+                    }
+                    self.data[pos - 1] += 1;
+                }
+                self.data.push((self.low >> (24 - offset)) as u8);
+                self.low = (self.low << offset) & 0xff_ffff;
+                shift = self.count;
+                self.count -= 8;
+            }
+            self.low <<= shift;
+        }
+
+        pub fn write_literal(&mut self, value: u32, n: usize) {
+            for i in (0..n).rev() {
+                self.write_bool((value >> i) & 1 != 0, 128);
+            }
+        }
+
+        pub fn finish(mut self) -> Vec<u8> {
+            for _ in 0..32 {
+                self.write_bool(false, 128);
+            }
+            self.data
+        }
+    }
+
+    #[test]
+    fn init() {
+        assert!(BoolDecoder::new(&[0x00]).is_ok());
+        match BoolDecoder::new(&[0x80]) {
+            Err(Error::InvalidMarkerBit) => {},
+            _ => panic!("marker bit is not checked"),
+        }
+        match BoolDecoder::new(&[]) {
+            Err(Error::NotEnoughData) => {},
+            _ => panic!("empty data is accepted"),
+        }
+    }
+
+    #[test]
+    fn even_probability() {
+        // With probability of 1/2 the decoder reads bits as is.
+        let mut bd = BoolDecoder::new(&[0x5a, 0xc3]).unwrap();
+        assert_eq!(bd.read_literal(7), 0x5a);
+        assert_eq!(bd.read_literal(8), 0xc3);
+        // Zeroes after the end of data.
+        assert_eq!(bd.read_literal(16), 0);
+    }
+
+    #[test]
+    fn skewed_probability() {
+        // Value is 0x7ffe. After the marker range is 128 and split of p=1
+        // is 1 so value is above. Range becomes 127 << 1, split of p=255 is
+        // 253 and value 0xfdfc is above again. Remaining range of 1 is
+        // shifted by 7 to 128 and value 0x7e is below split 127 of p=255.
+        let mut bd = BoolDecoder::new(&[0x7f, 0xfe]).unwrap();
+        assert!(bd.read_bool(1));
+        assert!(bd.read_bool(255));
+        assert!(!bd.read_bool(255));
+    }
+
+    #[test]
+    fn tree() {
+        let tree = [0, 2, -1, 4, -2, -3];
+        // 0, 10, 110 and 111 after the marker bit.
+        let mut bd = BoolDecoder::new(&[0x2d, 0xc0]).unwrap();
+        let values: Vec<u8> = (0..4).map(|_| bd.read_tree(&tree, &[128; 3])).collect();
+        assert_eq!(values, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn encoder_roundtrip() {
+        let mut seed = 1u32;
+        let mut symbols = Vec::new();
+        for _ in 0..10000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let prob = cmp::max(1, (seed >> 16) as u8);
+            // Follow the probability so long runs of likely values occur.
+            let bit = ((seed >> 8) & 0xff) as u8 >= prob;
+            symbols.push((bit, prob));
+        }
+        let mut e = BoolEncoder::new();
+        for &(bit, prob) in &symbols {
+            e.write_bool(bit, prob);
+        }
+        let data = e.finish();
+        let mut bd = BoolDecoder::new(&data).unwrap();
+        for (i, &(bit, prob)) in symbols.iter().enumerate() {
+            assert_eq!(bd.read_bool(prob), bit, "symbol {}", i);
+        }
+    }
+}
@@ -0,0 +1,587 @@
+//! Compressed header syntax, section 6.3 of the spec.
+
+use std::fmt;
+use super::Error;
+use super::booldec::BoolDecoder;
+use super::header::{UncompressedHeader, InterpFilter};
+use super::probs::*;
+
+const DIFF_UPDATE_PROB: u8 = 252;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxMode {
+    Only4x4,
+    Allow8x8,
+    Allow16x16,
+    Allow32x32,
+    TxModeSelect,
+}
+
+impl TxMode {
+    /// Largest transform size allowed, 0 is 4x4.
+    pub fn max_tx_size(&self) -> usize {
+        match *self {
+            TxMode::Only4x4 => 0,
+            TxMode::Allow8x8 => 1,
+            TxMode::Allow16x16 => 2,
+            TxMode::Allow32x32 | TxMode::TxModeSelect => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceMode {
+    Single,
+    Compound,
+    Select,
+}
+
+/// Probability table which can be updated by the compressed header. Names
+/// follow the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbTable {
+    Tx8x8,
+    Tx16x16,
+    Tx32x32,
+    Coef,
+    Skip,
+    InterMode,
+    InterpFilter,
+    IsInter,
+    CompMode,
+    SingleRef,
+    CompRef,
+    YMode,
+    Partition,
+    MvJoint,
+    MvSign,
+    MvClass,
+    MvClass0Bit,
+    MvBits,
+    MvClass0Fr,
+    MvFr,
+    MvClass0Hp,
+    MvHp,
+}
+
+impl fmt::Display for ProbTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ProbTable::Tx8x8 => "tx_probs_8x8",
+            ProbTable::Tx16x16 => "tx_probs_16x16",
+            ProbTable::Tx32x32 => "tx_probs_32x32",
+            ProbTable::Coef => "coef_probs",
+            ProbTable::Skip => "skip_prob",
+            ProbTable::InterMode => "inter_mode_probs",
+            ProbTable::InterpFilter => "interp_filter_probs",
+            ProbTable::IsInter => "is_inter_prob",
+            ProbTable::CompMode => "comp_mode_prob",
+            ProbTable::SingleRef => "single_ref_prob",
+            ProbTable::CompRef => "comp_ref_prob",
+            ProbTable::YMode => "y_mode_probs",
+            ProbTable::Partition => "partition_probs",
+            ProbTable::MvJoint => "mv_joint_probs",
+            ProbTable::MvSign => "mv_sign_prob",
+            ProbTable::MvClass => "mv_class_probs",
+            ProbTable::MvClass0Bit => "mv_class0_bit_prob",
+            ProbTable::MvBits => "mv_bits_prob",
+            ProbTable::MvClass0Fr => "mv_class0_fr_probs",
+            ProbTable::MvFr => "mv_fr_probs",
+            ProbTable::MvClass0Hp => "mv_class0_hp_prob",
+            ProbTable::MvHp => "mv_hp_prob",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Single probability changed by the frame.
+#[derive(Debug, Clone)]
+pub struct ProbUpdate {
+    pub table: ProbTable,
+    /// Position in the table, outermost index goes first. Coefficient
+    /// probabilities are indexed by transform size first.
+    pub index: Vec<u8>,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl fmt::Display for ProbUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.table));
+        for i in &self.index {
+            try!(write!(f, "[{}]", i));
+        }
+        write!(f, ": {} -> {}", self.old, self.new)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressedHeader {
+    pub tx_mode: TxMode,
+    pub reference_mode: ReferenceMode,
+    /// References of compound prediction, 1 is LAST, 2 is GOLDEN and 3 is
+    /// ALTREF.
+    pub comp_fixed_ref: u8,
+    pub comp_var_ref: [u8; 2],
+    /// Coded updates in bitstream order.
+    pub updates: Vec<ProbUpdate>,
+}
+
+impl CompressedHeader {
+    /// Number of updates per table in order of appearance.
+    pub fn count_updates(&self) -> Vec<(ProbTable, usize)> {
+        let mut counts: Vec<(ProbTable, usize)> = Vec::new();
+        for update in &self.updates {
+            match counts.iter().position(|&(table, _)| table == update.table) {
+                Some(i) => counts[i].1 += 1,
+                None => counts.push((update.table, 1)),
+            }
+        }
+        counts
+    }
+}
+
+fn inv_recenter_nonneg(v: u32, m: u32) -> u32 {
+    if v > 2 * m {
+        v
+    } else if v & 1 != 0 {
+        m - ((v + 1) >> 1)
+    } else {
+        m + (v >> 1)
+    }
+}
+
+fn inv_remap_prob(delta: u8, prob: u8) -> u8 {
+    let v = INV_MAP_TABLE[delta as usize] as u32;
+    let m = prob as u32 - 1;
+    if m << 1 <= 255 {
+        (1 + inv_recenter_nonneg(v, m)) as u8
+    } else {
+        (255 - inv_recenter_nonneg(v, 255 - 1 - m)) as u8
+    }
+}
+
+struct Reader<'a> {
+    bd: BoolDecoder<'a>,
+    updates: Vec<ProbUpdate>,
+}
+
+impl<'a> Reader<'a> {
+    fn decode_term_subexp(&mut self) -> u8 {
+        if self.bd.read_literal(1) == 0 {
+            return self.bd.read_literal(4) as u8;
+        }
+        if self.bd.read_literal(1) == 0 {
+            return self.bd.read_literal(4) as u8 + 16;
+        }
+        if self.bd.read_literal(1) == 0 {
+            return self.bd.read_literal(5) as u8 + 32;
+        }
+        let v = self.bd.read_literal(7) as u8;
+        if v < 65 {
+            return v + 64;
+        }
+        let bit = self.bd.read_literal(1) as u8;
+        (v << 1) - 1 + bit
+    }
+
+    fn set(&mut self, prob: &mut u8, new: u8, table: ProbTable, index: &[usize]) {
+        self.updates.push(ProbUpdate {
+            table: table,
+            index: index.iter().map(|&i| i as u8).collect(),
+            old: *prob,
+            new: new,
+        });
+        *prob = new;
+    }
+
+    /// diff_update_prob() in spec.
+    fn diff_update(&mut self, prob: &mut u8, table: ProbTable, index: &[usize]) {
+        if self.bd.read_bool(DIFF_UPDATE_PROB) {
+            let delta = self.decode_term_subexp();
+            let new = inv_remap_prob(delta, *prob);
+            self.set(prob, new, table, index);
+        }
+    }
+
+    /// update_mv_prob() in spec.
+    fn mv_update(&mut self, prob: &mut u8, table: ProbTable, index: &[usize]) {
+        if self.bd.read_bool(DIFF_UPDATE_PROB) {
+            let new = (self.bd.read_literal(7) << 1 | 1) as u8;
+            self.set(prob, new, table, index);
+        }
+    }
+
+    fn read_tx_mode(&mut self, hdr: &UncompressedHeader) -> TxMode {
+        if hdr.quantization.lossless {
+            return TxMode::Only4x4;
+        }
+        match self.bd.read_literal(2) {
+            0 => TxMode::Only4x4,
+            1 => TxMode::Allow8x8,
+            2 => TxMode::Allow16x16,
+            _ if self.bd.read_literal(1) == 0 => TxMode::Allow32x32,
+            _ => TxMode::TxModeSelect,
+        }
+    }
+
+    fn read_tx_mode_probs(&mut self, fc: &mut FrameContext) {
+        for i in 0..TX_SIZE_CONTEXTS {
+            self.diff_update(&mut fc.tx8x8[i][0], ProbTable::Tx8x8, &[i, 0]);
+        }
+        for i in 0..TX_SIZE_CONTEXTS {
+            for j in 0..2 {
+                self.diff_update(&mut fc.tx16x16[i][j], ProbTable::Tx16x16, &[i, j]);
+            }
+        }
+        for i in 0..TX_SIZE_CONTEXTS {
+            for j in 0..3 {
+                self.diff_update(&mut fc.tx32x32[i][j], ProbTable::Tx32x32, &[i, j]);
+            }
+        }
+    }
+
+    fn read_coef_probs(&mut self, fc: &mut FrameContext, tx_mode: TxMode) {
+        for tx_size in 0..tx_mode.max_tx_size() + 1 {
+            if self.bd.read_literal(1) == 0 {
+                continue;
+            }
+            for i in 0..PLANE_TYPES {
+                for j in 0..REF_TYPES {
+                    for k in 0..COEF_BANDS {
+                        let max_l = if k == 0 { 3 } else { PREV_COEF_CONTEXTS };
+                        for l in 0..max_l {
+                            for m in 0..UNCONSTRAINED_NODES {
+                                self.diff_update(&mut fc.coef[tx_size][i][j][k][l][m],
+                                                 ProbTable::Coef,
+                                                 &[tx_size, i, j, k, l, m]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_frame_reference_mode(&mut self, hdr: &UncompressedHeader) -> ReferenceMode {
+        let sign_bias = &hdr.ref_frame_sign_bias;
+        let compound_allowed = sign_bias[2] != sign_bias[1] || sign_bias[3] != sign_bias[1];
+        if !compound_allowed || self.bd.read_literal(1) == 0 {
+            ReferenceMode::Single
+        } else if self.bd.read_literal(1) == 0 {
+            ReferenceMode::Compound
+        } else {
+            ReferenceMode::Select
+        }
+    }
+
+    fn read_frame_reference_mode_probs(&mut self, fc: &mut FrameContext, mode: ReferenceMode) {
+        if mode == ReferenceMode::Select {
+            for i in 0..COMP_MODE_CONTEXTS {
+                self.diff_update(&mut fc.comp_mode[i], ProbTable::CompMode, &[i]);
+            }
+        }
+        if mode != ReferenceMode::Compound {
+            for i in 0..REF_CONTEXTS {
+                for j in 0..2 {
+                    self.diff_update(&mut fc.single_ref[i][j], ProbTable::SingleRef, &[i, j]);
+                }
+            }
+        }
+        if mode != ReferenceMode::Single {
+            for i in 0..REF_CONTEXTS {
+                self.diff_update(&mut fc.comp_ref[i], ProbTable::CompRef, &[i]);
+            }
+        }
+    }
+
+    fn read_mv_probs(&mut self, fc: &mut FrameContext, hdr: &UncompressedHeader) {
+        for j in 0..MV_JOINTS - 1 {
+            self.mv_update(&mut fc.mv_joint[j], ProbTable::MvJoint, &[j]);
+        }
+        for i in 0..2 {
+            let comp = &mut fc.mv_comp[i];
+            self.mv_update(&mut comp.sign, ProbTable::MvSign, &[i]);
+            for j in 0..MV_CLASSES - 1 {
+                self.mv_update(&mut comp.classes[j], ProbTable::MvClass, &[i, j]);
+            }
+            self.mv_update(&mut comp.class0_bit, ProbTable::MvClass0Bit, &[i]);
+            for j in 0..MV_OFFSET_BITS {
+                self.mv_update(&mut comp.bits[j], ProbTable::MvBits, &[i, j]);
+            }
+        }
+        for i in 0..2 {
+            let comp = &mut fc.mv_comp[i];
+            for j in 0..CLASS0_SIZE {
+                for k in 0..MV_FR_SIZE - 1 {
+                    self.mv_update(&mut comp.class0_fr[j][k], ProbTable::MvClass0Fr, &[i, j, k]);
+                }
+            }
+            for k in 0..MV_FR_SIZE - 1 {
+                self.mv_update(&mut comp.fr[k], ProbTable::MvFr, &[i, k]);
+            }
+        }
+        if hdr.allow_high_precision_mv {
+            for i in 0..2 {
+                let comp = &mut fc.mv_comp[i];
+                self.mv_update(&mut comp.class0_hp, ProbTable::MvClass0Hp, &[i]);
+                self.mv_update(&mut comp.hp, ProbTable::MvHp, &[i]);
+            }
+        }
+    }
+}
+
+/// Compound references for the given sign bias, setup_compound_reference_mode()
+/// in spec.
+fn setup_compound_reference_mode(sign_bias: &[bool; 4]) -> (u8, [u8; 2]) {
+    if sign_bias[1] == sign_bias[2] {
+        (3, [1, 2])
+    } else if sign_bias[1] == sign_bias[3] {
+        (2, [1, 3])
+    } else {
+        (1, [2, 3])
+    }
+}
+
+/// Parse compressed header and apply coded updates to the frame context.
+pub fn parse(data: &[u8],
+             hdr: &UncompressedHeader,
+             fc: &mut FrameContext) -> Result<CompressedHeader, Error> {
+    let mut r = Reader {
+        bd: try!(BoolDecoder::new(data)),
+        updates: Vec::new(),
+    };
+    let tx_mode = r.read_tx_mode(hdr);
+    if tx_mode == TxMode::TxModeSelect {
+        r.read_tx_mode_probs(fc);
+    }
+    r.read_coef_probs(fc, tx_mode);
+    for i in 0..SKIP_CONTEXTS {
+        r.diff_update(&mut fc.skip[i], ProbTable::Skip, &[i]);
+    }
+    let mut reference_mode = ReferenceMode::Single;
+    let (mut comp_fixed_ref, mut comp_var_ref) = (0, [0; 2]);
+    if !hdr.is_intra() {
+        for i in 0..INTER_MODE_CONTEXTS {
+            for j in 0..3 {
+                r.diff_update(&mut fc.inter_mode[i][j], ProbTable::InterMode, &[i, j]);
+            }
+        }
+        if hdr.interp_filter == InterpFilter::Switchable {
+            for j in 0..INTERP_FILTER_CONTEXTS {
+                for i in 0..2 {
+                    r.diff_update(&mut fc.interp_filter[j][i], ProbTable::InterpFilter, &[j, i]);
+                }
+            }
+        }
+        for i in 0..IS_INTER_CONTEXTS {
+            r.diff_update(&mut fc.is_inter[i], ProbTable::IsInter, &[i]);
+        }
+        reference_mode = r.read_frame_reference_mode(hdr);
+        if reference_mode != ReferenceMode::Single {
+            let refs = setup_compound_reference_mode(&hdr.ref_frame_sign_bias);
+            comp_fixed_ref = refs.0;
+            comp_var_ref = refs.1;
+        }
+        r.read_frame_reference_mode_probs(fc, reference_mode);
+        for i in 0..BLOCK_SIZE_GROUPS {
+            for j in 0..INTRA_MODES - 1 {
+                r.diff_update(&mut fc.y_mode[i][j], ProbTable::YMode, &[i, j]);
+            }
+        }
+        for i in 0..PARTITION_CONTEXTS {
+            for j in 0..3 {
+                r.diff_update(&mut fc.partition[i][j], ProbTable::Partition, &[i, j]);
+            }
+        }
+        r.read_mv_probs(fc, hdr);
+    }
+    Ok(CompressedHeader {
+        tx_mode: tx_mode,
+        reference_mode: reference_mode,
+        comp_fixed_ref: comp_fixed_ref,
+        comp_var_ref: comp_var_ref,
+        updates: r.updates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::booldec::tests::BoolEncoder;
+    use super::super::header::tests::parse_frames;
+
+    /// Coefficient probabilities updated per transform size.
+    const COEF_UPDATES: usize = PLANE_TYPES * REF_TYPES * (3 + (COEF_BANDS - 1) * 6) *
+                                UNCONSTRAINED_NODES;
+
+    fn skip_updates(e: &mut BoolEncoder, count: usize) {
+        for _ in 0..count {
+            e.write_bool(false, DIFF_UPDATE_PROB);
+        }
+    }
+
+    /// Inverse of decode_term_subexp().
+    fn write_update(e: &mut BoolEncoder, delta: u8) {
+        e.write_bool(true, DIFF_UPDATE_PROB);
+        if delta < 16 {
+            e.write_literal(0, 1);
+            e.write_literal(delta as u32, 4);
+        } else if delta < 32 {
+            e.write_literal(2, 2);
+            e.write_literal(delta as u32 - 16, 4);
+        } else if delta < 64 {
+            e.write_literal(6, 3);
+            e.write_literal(delta as u32 - 32, 5);
+        } else if delta < 129 {
+            e.write_literal(7, 3);
+            e.write_literal(delta as u32 - 64, 7);
+        } else {
+            e.write_literal(7, 3);
+            e.write_literal((delta as u32 + 1) >> 1, 7);
+            e.write_literal((delta as u32 + 1) & 1, 1);
+        }
+    }
+
+    fn get_updates(chdr: &CompressedHeader) -> Vec<(ProbTable, Vec<u8>, u8, u8)> {
+        chdr.updates.iter().map(|u| (u.table, u.index.clone(), u.old, u.new)).collect()
+    }
+
+    #[test]
+    fn keyframe() {
+        let (mut hdr, _) = parse_frames();
+        hdr.quantization.base_q_idx = 60;
+        hdr.quantization.lossless = false;
+        let mut e = BoolEncoder::new();
+        // TX_MODE_SELECT.
+        e.write_literal(3, 2);
+        e.write_literal(1, 1);
+        skip_updates(&mut e, 1);
+        write_update(&mut e, 0);
+        skip_updates(&mut e, TX_SIZE_CONTEXTS * (2 + 3));
+        // Only coefficients of 4x4 transform are updated.
+        e.write_literal(1, 1);
+        write_update(&mut e, 21);
+        skip_updates(&mut e, COEF_UPDATES - 1);
+        e.write_literal(0, 3);
+        skip_updates(&mut e, 2);
+        write_update(&mut e, 74);
+        let data = e.finish();
+
+        let mut fc = FrameContext::default();
+        let chdr = parse(&data, &hdr, &mut fc).unwrap();
+        assert_eq!(chdr.tx_mode, TxMode::TxModeSelect);
+        assert_eq!(chdr.tx_mode.max_tx_size(), 3);
+        assert_eq!(chdr.reference_mode, ReferenceMode::Single);
+        assert_eq!(get_updates(&chdr), [
+            (ProbTable::Tx8x8, vec![1, 0], 66, 62),
+            (ProbTable::Coef, vec![0, 0, 0, 0, 0, 0], 195, 194),
+            (ProbTable::Skip, vec![2], 64, 94),
+        ]);
+        assert_eq!(chdr.count_updates(), [(ProbTable::Tx8x8, 1), (ProbTable::Coef, 1),
+                                          (ProbTable::Skip, 1)]);
+        assert_eq!(fc.tx8x8, [[100], [62]]);
+        assert_eq!(fc.coef[0][0][0][0][0], [194, 29, 183]);
+        assert_eq!(fc.skip, [192, 128, 94]);
+    }
+
+    #[test]
+    fn lossless_keyframe() {
+        let (hdr, _) = parse_frames();
+        // Transform mode isn't coded, only 4x4 coefficients may be updated.
+        let mut e = BoolEncoder::new();
+        e.write_literal(0, 1);
+        skip_updates(&mut e, SKIP_CONTEXTS);
+        let data = e.finish();
+        let mut fc = FrameContext::default();
+        let chdr = parse(&data, &hdr, &mut fc).unwrap();
+        assert_eq!(chdr.tx_mode, TxMode::Only4x4);
+        assert!(chdr.updates.is_empty());
+    }
+
+    #[test]
+    fn inter_frame() {
+        let (_, mut hdr) = parse_frames();
+        hdr.quantization.lossless = false;
+        let mut e = BoolEncoder::new();
+        // ALLOW_8X8, no coefficient updates.
+        e.write_literal(1, 2);
+        e.write_literal(0, 2);
+        skip_updates(&mut e, SKIP_CONTEXTS + INTER_MODE_CONTEXTS * 3 + 1);
+        // Filter is switchable.
+        write_update(&mut e, 200);
+        skip_updates(&mut e, INTERP_FILTER_CONTEXTS * 2 - 2 + IS_INTER_CONTEXTS);
+        // No compound prediction with the same sign bias of references.
+        skip_updates(&mut e, REF_CONTEXTS * 2 + BLOCK_SIZE_GROUPS * (INTRA_MODES - 1) +
+                             PARTITION_CONTEXTS * 3);
+        e.write_bool(true, DIFF_UPDATE_PROB);
+        e.write_literal(0x40, 7);
+        // Rest of the motion vector probabilities including high precision
+        // ones.
+        skip_updates(&mut e, MV_JOINTS - 2 + 2 * (2 + MV_CLASSES - 1 + MV_OFFSET_BITS) +
+                             2 * (CLASS0_SIZE + 1) * (MV_FR_SIZE - 1) + 2 * 2 - 1);
+        e.write_bool(true, DIFF_UPDATE_PROB);
+        e.write_literal(0x7f, 7);
+        let data = e.finish();
+
+        let mut fc = FrameContext::default();
+        let chdr = parse(&data, &hdr, &mut fc).unwrap();
+        assert_eq!(chdr.tx_mode, TxMode::Allow8x8);
+        assert_eq!(chdr.reference_mode, ReferenceMode::Single);
+        assert_eq!(get_updates(&chdr), [
+            (ProbTable::InterpFilter, vec![0, 1], 162, 59),
+            (ProbTable::MvJoint, vec![0], 32, 129),
+            (ProbTable::MvHp, vec![1], 128, 255),
+        ]);
+        assert_eq!(fc.interp_filter[0], [235, 59]);
+        assert_eq!(fc.mv_comp[1].hp, 255);
+    }
+
+    #[test]
+    fn compound_reference() {
+        let (_, mut hdr) = parse_frames();
+        hdr.quantization.lossless = false;
+        hdr.ref_frame_sign_bias[3] = true;
+        let mut e = BoolEncoder::new();
+        e.write_literal(0, 2);
+        e.write_literal(0, 1);
+        skip_updates(&mut e, SKIP_CONTEXTS + INTER_MODE_CONTEXTS * 3 +
+                             INTERP_FILTER_CONTEXTS * 2 + IS_INTER_CONTEXTS);
+        // REFERENCE_MODE_SELECT.
+        e.write_literal(3, 2);
+        let data = e.finish();
+        let mut fc = FrameContext::default();
+        let chdr = parse(&data, &hdr, &mut fc).unwrap();
+        assert_eq!(chdr.tx_mode, TxMode::Only4x4);
+        assert_eq!(chdr.reference_mode, ReferenceMode::Select);
+        assert_eq!((chdr.comp_fixed_ref, chdr.comp_var_ref), (3, [1, 2]));
+        assert!(chdr.updates.is_empty());
+    }
+
+    #[test]
+    fn prob_remapping() {
+        // Small deltas move the probability by one towards the middle or
+        // away from it.
+        assert_eq!(inv_remap_prob(20, 100), 99);
+        assert_eq!(inv_remap_prob(21, 100), 101);
+        assert_eq!(inv_remap_prob(20, 200), 201);
+        assert_eq!(inv_remap_prob(21, 200), 199);
+        // Large ones jump to the other end.
+        assert_eq!(inv_remap_prob(254, 2), 254);
+        assert_eq!(inv_remap_prob(254, 254), 2);
+    }
+
+    #[test]
+    fn broken_data() {
+        let (hdr, _) = parse_frames();
+        let mut fc = FrameContext::default();
+        match parse(&[], &hdr, &mut fc) {
+            Err(Error::NotEnoughData) => {},
+            res => panic!("unexpected result {:?}", res.map(|chdr| chdr.tx_mode)),
+        }
+        match parse(&[0xff], &hdr, &mut fc) {
+            Err(Error::InvalidMarkerBit) => {},
+            res => panic!("unexpected result {:?}", res.map(|chdr| chdr.tx_mode)),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Contexts of the reference frame and filter symbols, derived from the
+//! above and left blocks. Mirrors vp9_pred_common.c of libvpx.
+
+use super::block::BlockInfo;
+
+const LAST_FRAME: u8 = 1;
+const GOLDEN_FRAME: u8 = 2;
+const ALTREF_FRAME: u8 = 3;
+const SWITCHABLE_FILTERS: usize = 3;
+
+type Edge<'a> = Option<&'a BlockInfo>;
+
+fn has_ref(b: &BlockInfo, ref_frame: u8) -> bool {
+    b.ref_frame[0] == ref_frame || b.ref_frame[1] == ref_frame
+}
+
+pub fn interp_filter_ctx(above: Edge, left: Edge) -> usize {
+    let filter_type = |edge: Edge| match edge {
+        Some(b) if b.is_inter() => b.interp_filter as usize,
+        _ => SWITCHABLE_FILTERS,
+    };
+    let left_type = filter_type(left);
+    let above_type = filter_type(above);
+    if left_type == above_type {
+        left_type
+    } else if left_type == SWITCHABLE_FILTERS {
+        above_type
+    } else if above_type == SWITCHABLE_FILTERS {
+        left_type
+    } else {
+        SWITCHABLE_FILTERS
+    }
+}
+
+pub fn comp_mode_ctx(above: Edge, left: Edge, fixed_ref: u8) -> usize {
+    match (above, left) {
+        (Some(a), Some(l)) => {
+            if !a.is_compound() && !l.is_compound() {
+                ((a.ref_frame[0] == fixed_ref) ^ (l.ref_frame[0] == fixed_ref)) as usize
+            } else if !a.is_compound() {
+                2 + (a.ref_frame[0] == fixed_ref || !a.is_inter()) as usize
+            } else if !l.is_compound() {
+                2 + (l.ref_frame[0] == fixed_ref || !l.is_inter()) as usize
+            } else {
+                4
+            }
+        },
+        (Some(edge), None) | (None, Some(edge)) => {
+            if edge.is_compound() {
+                3
+            } else {
+                (edge.ref_frame[0] == fixed_ref) as usize
+            }
+        },
+        (None, None) => 1,
+    }
+}
+
+pub fn comp_ref_ctx(above: Edge, left: Edge, fixed_ref: u8, var_ref: [u8; 2],
+                    var_ref_idx: usize) -> usize {
+    match (above, left) {
+        (Some(a), Some(l)) => {
+            let above_intra = !a.is_inter();
+            let left_intra = !l.is_inter();
+            if above_intra && left_intra {
+                2
+            } else if above_intra || left_intra {
+                let edge = if above_intra { l } else { a };
+                if !edge.is_compound() {
+                    1 + 2 * (edge.ref_frame[0] != var_ref[1]) as usize
+                } else {
+                    1 + 2 * (edge.ref_frame[var_ref_idx] != var_ref[1]) as usize
+                }
+            } else {
+                let l_sg = !l.is_compound();
+                let a_sg = !a.is_compound();
+                let vrfa = if a_sg { a.ref_frame[0] } else { a.ref_frame[var_ref_idx] };
+                let vrfl = if l_sg { l.ref_frame[0] } else { l.ref_frame[var_ref_idx] };
+                if vrfa == vrfl && var_ref[1] == vrfa {
+                    0
+                } else if l_sg && a_sg {
+                    if (vrfa == fixed_ref && vrfl == var_ref[0]) ||
+                       (vrfl == fixed_ref && vrfa == var_ref[0]) {
+                        4
+                    } else if vrfa == vrfl {
+                        3
+                    } else {
+                        1
+                    }
+                } else if l_sg || a_sg {
+                    let vrfc = if l_sg { vrfa } else { vrfl };
+                    let rfs = if a_sg { vrfa } else { vrfl };
+                    if vrfc == var_ref[1] && rfs != var_ref[1] {
+                        1
+                    } else if rfs == var_ref[1] && vrfc != var_ref[1] {
+                        2
+                    } else {
+                        4
+                    }
+                } else if vrfa == vrfl {
+                    4
+                } else {
+                    2
+                }
+            }
+        },
+        (Some(edge), None) | (None, Some(edge)) => {
+            if !edge.is_inter() {
+                2
+            } else if edge.is_compound() {
+                4 * (edge.ref_frame[var_ref_idx] != var_ref[1]) as usize
+            } else {
+                3 * (edge.ref_frame[0] != var_ref[1]) as usize
+            }
+        },
+        (None, None) => 2,
+    }
+}
+
+/// Context of the LAST versus GOLDEN or ALTREF choice.
+pub fn single_ref_p1_ctx(above: Edge, left: Edge) -> usize {
+    match (above, left) {
+        (Some(a), Some(l)) => {
+            let above_intra = !a.is_inter();
+            let left_intra = !l.is_inter();
+            if above_intra && left_intra {
+                2
+            } else if above_intra || left_intra {
+                let edge = if above_intra { l } else { a };
+                if !edge.is_compound() {
+                    4 * (edge.ref_frame[0] == LAST_FRAME) as usize
+                } else {
+                    1 + has_ref(edge, LAST_FRAME) as usize
+                }
+            } else if a.is_compound() && l.is_compound() {
+                1 + (has_ref(a, LAST_FRAME) || has_ref(l, LAST_FRAME)) as usize
+            } else if a.is_compound() || l.is_compound() {
+                let (single, comp) = if a.is_compound() { (l, a) } else { (a, l) };
+                if single.ref_frame[0] == LAST_FRAME {
+                    3 + has_ref(comp, LAST_FRAME) as usize
+                } else {
+                    has_ref(comp, LAST_FRAME) as usize
+                }
+            } else {
+                2 * (a.ref_frame[0] == LAST_FRAME) as usize +
+                2 * (l.ref_frame[0] == LAST_FRAME) as usize
+            }
+        },
+        (Some(edge), None) | (None, Some(edge)) => {
+            if !edge.is_inter() {
+                2
+            } else if !edge.is_compound() {
+                4 * (edge.ref_frame[0] == LAST_FRAME) as usize
+            } else {
+                1 + has_ref(edge, LAST_FRAME) as usize
+            }
+        },
+        (None, None) => 2,
+    }
+}
+
+/// Context of the GOLDEN versus ALTREF choice.
+pub fn single_ref_p2_ctx(above: Edge, left: Edge) -> usize {
+    match (above, left) {
+        (Some(a), Some(l)) => {
+            let above_intra = !a.is_inter();
+            let left_intra = !l.is_inter();
+            if above_intra && left_intra {
+                2
+            } else if above_intra || left_intra {
+                let edge = if above_intra { l } else { a };
+                if !edge.is_compound() {
+                    if edge.ref_frame[0] == LAST_FRAME {
+                        3
+                    } else {
+                        4 * (edge.ref_frame[0] == GOLDEN_FRAME) as usize
+                    }
+                } else {
+                    1 + 2 * has_ref(edge, GOLDEN_FRAME) as usize
+                }
+            } else if a.is_compound() && l.is_compound() {
+                if a.ref_frame == l.ref_frame {
+                    3 * (has_ref(a, GOLDEN_FRAME) || has_ref(l, GOLDEN_FRAME)) as usize
+                } else {
+                    2
+                }
+            } else if a.is_compound() || l.is_compound() {
+                let (single, comp) = if a.is_compound() { (l, a) } else { (a, l) };
+                if single.ref_frame[0] == GOLDEN_FRAME {
+                    3 + has_ref(comp, GOLDEN_FRAME) as usize
+                } else if single.ref_frame[0] == ALTREF_FRAME {
+                    has_ref(comp, GOLDEN_FRAME) as usize
+                } else {
+                    1 + 2 * has_ref(comp, GOLDEN_FRAME) as usize
+                }
+            } else {
+                let above0 = a.ref_frame[0];
+                let left0 = l.ref_frame[0];
+                if above0 == LAST_FRAME && left0 == LAST_FRAME {
+                    3
+                } else if above0 == LAST_FRAME || left0 == LAST_FRAME {
+                    let edge0 = if above0 == LAST_FRAME { left0 } else { above0 };
+                    4 * (edge0 == GOLDEN_FRAME) as usize
+                } else {
+                    2 * (above0 == GOLDEN_FRAME) as usize + 2 * (left0 == GOLDEN_FRAME) as usize
+                }
+            }
+        },
+        (Some(edge), None) | (None, Some(edge)) => {
+            if !edge.is_inter() || (edge.ref_frame[0] == LAST_FRAME && !edge.is_compound()) {
+                2
+            } else if !edge.is_compound() {
+                4 * (edge.ref_frame[0] == GOLDEN_FRAME) as usize
+            } else {
+                3 * has_ref(edge, GOLDEN_FRAME) as usize
+            }
+        },
+        (None, None) => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::block::{BlockSize, Mv};
+    use super::super::block::tests::block;
+    use super::super::header::InterpFilter;
+
+    fn edge(ref_frame: [u8; 2]) -> BlockInfo {
+        block(0, 0, BlockSize::Block8x8, ref_frame, Mv::default())
+    }
+
+    #[test]
+    fn interp_filter() {
+        let mut sharp = edge([1, 0]);
+        sharp.interp_filter = InterpFilter::EightTapSharp;
+        let intra = edge([0, 0]);
+        assert_eq!(interp_filter_ctx(None, None), SWITCHABLE_FILTERS);
+        assert_eq!(interp_filter_ctx(Some(&sharp), Some(&intra)), 2);
+        assert_eq!(interp_filter_ctx(Some(&sharp), Some(&sharp)), 2);
+        assert_eq!(interp_filter_ctx(Some(&sharp), Some(&edge([1, 0]))), SWITCHABLE_FILTERS);
+    }
+
+    #[test]
+    fn comp_mode() {
+        let last = edge([LAST_FRAME, 0]);
+        let altref = edge([ALTREF_FRAME, 0]);
+        let comp = edge([LAST_FRAME, ALTREF_FRAME]);
+        assert_eq!(comp_mode_ctx(None, None, ALTREF_FRAME), 1);
+        assert_eq!(comp_mode_ctx(Some(&comp), None, ALTREF_FRAME), 3);
+        assert_eq!(comp_mode_ctx(None, Some(&altref), ALTREF_FRAME), 1);
+        assert_eq!(comp_mode_ctx(Some(&last), Some(&altref), ALTREF_FRAME), 1);
+        assert_eq!(comp_mode_ctx(Some(&last), Some(&last), ALTREF_FRAME), 0);
+        assert_eq!(comp_mode_ctx(Some(&altref), Some(&comp), ALTREF_FRAME), 3);
+        assert_eq!(comp_mode_ctx(Some(&comp), Some(&comp), ALTREF_FRAME), 4);
+    }
+
+    #[test]
+    fn comp_ref() {
+        let var_ref = [LAST_FRAME, GOLDEN_FRAME];
+        let comp_ctx = |above: Edge, left: Edge| comp_ref_ctx(above, left, ALTREF_FRAME,
+                                                              var_ref, 0);
+        let intra = edge([0, 0]);
+        let last = edge([LAST_FRAME, 0]);
+        assert_eq!(comp_ctx(None, None), 2);
+        assert_eq!(comp_ctx(Some(&last), None), 3);
+        assert_eq!(comp_ctx(None, Some(&edge([GOLDEN_FRAME, ALTREF_FRAME]))), 0);
+        assert_eq!(comp_ctx(Some(&edge([LAST_FRAME, ALTREF_FRAME])), None), 4);
+        assert_eq!(comp_ctx(Some(&intra), Some(&intra)), 2);
+        assert_eq!(comp_ctx(Some(&intra), Some(&last)), 3);
+        assert_eq!(comp_ctx(Some(&edge([GOLDEN_FRAME, 0])), Some(&edge([GOLDEN_FRAME, 0]))), 0);
+        assert_eq!(comp_ctx(Some(&last), Some(&last)), 3);
+        assert_eq!(comp_ctx(Some(&edge([ALTREF_FRAME, 0])), Some(&last)), 4);
+    }
+
+    #[test]
+    fn single_ref() {
+        let intra = edge([0, 0]);
+        let last = edge([LAST_FRAME, 0]);
+        let golden = edge([GOLDEN_FRAME, 0]);
+        let altref = edge([ALTREF_FRAME, 0]);
+        let comp = edge([LAST_FRAME, GOLDEN_FRAME]);
+        assert_eq!(single_ref_p1_ctx(None, None), 2);
+        assert_eq!(single_ref_p1_ctx(Some(&last), None), 4);
+        assert_eq!(single_ref_p1_ctx(None, Some(&comp)), 2);
+        assert_eq!(single_ref_p1_ctx(Some(&intra), Some(&intra)), 2);
+        assert_eq!(single_ref_p1_ctx(Some(&intra), Some(&golden)), 0);
+        assert_eq!(single_ref_p1_ctx(Some(&last), Some(&last)), 4);
+        assert_eq!(single_ref_p1_ctx(Some(&last), Some(&altref)), 2);
+        assert_eq!(single_ref_p1_ctx(Some(&last), Some(&comp)), 4);
+
+        assert_eq!(single_ref_p2_ctx(None, None), 2);
+        assert_eq!(single_ref_p2_ctx(Some(&last), None), 2);
+        assert_eq!(single_ref_p2_ctx(None, Some(&golden)), 4);
+        assert_eq!(single_ref_p2_ctx(None, Some(&altref)), 0);
+        assert_eq!(single_ref_p2_ctx(Some(&intra), Some(&last)), 3);
+        assert_eq!(single_ref_p2_ctx(Some(&last), Some(&last)), 3);
+        assert_eq!(single_ref_p2_ctx(Some(&last), Some(&golden)), 4);
+        assert_eq!(single_ref_p2_ctx(Some(&golden), Some(&altref)), 2);
+        assert_eq!(single_ref_p2_ctx(Some(&altref), Some(&comp)), 1);
+    }
+}
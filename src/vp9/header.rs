@@ -0,0 +1,646 @@
+//! Uncompressed header syntax, section 6.2 of the spec.
+
+use super::{Error, NUM_REF_FRAMES, MAX_SEGMENTS, SEG_LVL_MAX};
+use super::bitreader::BitReader;
+
+const FRAME_MARKER: u32 = 2;
+const SYNC_CODE: [u32; 3] = [0x49, 0x83, 0x42];
+const MIN_TILE_WIDTH_B64: u32 = 4;
+const MAX_TILE_WIDTH_B64: u32 = 64;
+const SEGMENTATION_FEATURE_BITS: [usize; SEG_LVL_MAX] = [8, 6, 2, 0];
+const SEGMENTATION_FEATURE_SIGNED: [bool; SEG_LVL_MAX] = [true, true, false, false];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameType {
+    KeyFrame,
+    NonKeyFrame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Unknown,
+    Bt601,
+    Bt709,
+    Smpte170,
+    Smpte240,
+    Bt2020,
+    Reserved,
+    Srgb,
+}
+
+impl ColorSpace {
+    fn from_bits(bits: u32) -> ColorSpace {
+        match bits {
+            1 => ColorSpace::Bt601,
+            2 => ColorSpace::Bt709,
+            3 => ColorSpace::Smpte170,
+            4 => ColorSpace::Smpte240,
+            5 => ColorSpace::Bt2020,
+            6 => ColorSpace::Reserved,
+            7 => ColorSpace::Srgb,
+            _ => ColorSpace::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterpFilter {
+    EightTap,
+    EightTapSmooth,
+    EightTapSharp,
+    Bilinear,
+    Switchable,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ColorConfig {
+    pub bit_depth: u8,
+    pub color_space: ColorSpace,
+    /// Full (studio swing otherwise) range.
+    pub color_range: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+}
+
+impl Default for ColorConfig {
+    /// Implied config of profile 0 intra-only frames.
+    fn default() -> ColorConfig {
+        ColorConfig {
+            bit_depth: 8,
+            color_space: ColorSpace::Bt601,
+            color_range: false,
+            subsampling_x: true,
+            subsampling_y: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoopFilterParams {
+    pub level: u8,
+    pub sharpness: u8,
+    pub delta_enabled: bool,
+    pub delta_update: bool,
+    /// Indexed by reference frame, intra goes first.
+    pub ref_deltas: [i8; 4],
+    pub mode_deltas: [i8; 2],
+}
+
+impl Default for LoopFilterParams {
+    /// Values set by `setup_past_independence`.
+    fn default() -> LoopFilterParams {
+        LoopFilterParams {
+            level: 0,
+            sharpness: 0,
+            delta_enabled: true,
+            delta_update: false,
+            ref_deltas: [1, 0, -1, -1],
+            mode_deltas: [0, 0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuantizationParams {
+    pub base_q_idx: u8,
+    pub delta_q_y_dc: i8,
+    pub delta_q_uv_dc: i8,
+    pub delta_q_uv_ac: i8,
+    pub lossless: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SegmentationParams {
+    pub enabled: bool,
+    pub update_map: bool,
+    pub tree_probs: [u8; MAX_SEGMENTS - 1],
+    pub pred_probs: [u8; 3],
+    pub temporal_update: bool,
+    pub update_data: bool,
+    pub abs_or_delta_update: bool,
+    pub feature_enabled: [[bool; SEG_LVL_MAX]; MAX_SEGMENTS],
+    pub feature_data: [[i16; SEG_LVL_MAX]; MAX_SEGMENTS],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UncompressedHeader {
+    pub profile: u8,
+    pub show_existing_frame: bool,
+    pub frame_to_show_map_idx: u8,
+    pub frame_type: FrameType,
+    pub show_frame: bool,
+    pub error_resilient_mode: bool,
+    pub intra_only: bool,
+    pub reset_frame_context: u8,
+    pub refresh_frame_flags: u8,
+    /// Slots used as LAST, GOLDEN and ALTREF references.
+    pub ref_frame_idx: [u8; 3],
+    /// Indexed by reference frame, intra goes first.
+    pub ref_frame_sign_bias: [bool; 4],
+    pub color: ColorConfig,
+    pub width: u32,
+    pub height: u32,
+    pub render_width: u32,
+    pub render_height: u32,
+    /// Whether frame size was copied from the reference frame.
+    pub size_from_ref: Option<u8>,
+    pub allow_high_precision_mv: bool,
+    pub interp_filter: InterpFilter,
+    pub refresh_frame_context: bool,
+    pub frame_parallel_decoding_mode: bool,
+    /// As coded, intra and error resilient frames actually use context 0.
+    pub frame_context_idx: u8,
+    pub loop_filter: LoopFilterParams,
+    pub quantization: QuantizationParams,
+    pub segmentation: SegmentationParams,
+    pub tile_cols_log2: u8,
+    pub tile_rows_log2: u8,
+    /// Size of the compressed header.
+    pub header_size_in_bytes: u16,
+    /// Size of the uncompressed header itself.
+    pub size: usize,
+}
+
+impl UncompressedHeader {
+    pub fn is_intra(&self) -> bool {
+        self.frame_type == FrameType::KeyFrame || self.intra_only
+    }
+
+    /// Width in 8x8 mode info units.
+    pub fn mi_cols(&self) -> u32 {
+        (self.width + 7) >> 3
+    }
+
+    /// Height in 8x8 mode info units.
+    pub fn mi_rows(&self) -> u32 {
+        (self.height + 7) >> 3
+    }
+
+    pub fn sb64_cols(&self) -> u32 {
+        (self.mi_cols() + 7) >> 3
+    }
+
+    fn new(profile: u8) -> UncompressedHeader {
+        UncompressedHeader {
+            profile: profile,
+            show_existing_frame: false,
+            frame_to_show_map_idx: 0,
+            frame_type: FrameType::KeyFrame,
+            show_frame: true,
+            error_resilient_mode: false,
+            intra_only: false,
+            reset_frame_context: 0,
+            refresh_frame_flags: 0,
+            ref_frame_idx: [0; 3],
+            ref_frame_sign_bias: [false; 4],
+            color: Default::default(),
+            width: 0,
+            height: 0,
+            render_width: 0,
+            render_height: 0,
+            size_from_ref: None,
+            allow_high_precision_mv: false,
+            interp_filter: InterpFilter::EightTap,
+            refresh_frame_context: false,
+            frame_parallel_decoding_mode: true,
+            frame_context_idx: 0,
+            loop_filter: Default::default(),
+            quantization: Default::default(),
+            segmentation: Default::default(),
+            tile_cols_log2: 0,
+            tile_rows_log2: 0,
+            header_size_in_bytes: 0,
+            size: 0,
+        }
+    }
+}
+
+fn read_sync_code(r: &mut BitReader) -> Result<(), Error> {
+    for &code in &SYNC_CODE {
+        if try!(r.read_bits(8)) != code {
+            return Err(Error::InvalidSyncCode);
+        }
+    }
+    Ok(())
+}
+
+fn read_color_config(r: &mut BitReader, profile: u8) -> Result<ColorConfig, Error> {
+    let bit_depth = if profile >= 2 {
+        if try!(r.read_bit()) { 12 } else { 10 }
+    } else {
+        8
+    };
+    let color_space = ColorSpace::from_bits(try!(r.read_bits(3)));
+    let mut color = ColorConfig {
+        bit_depth: bit_depth,
+        color_space: color_space,
+        color_range: true,
+        subsampling_x: true,
+        subsampling_y: true,
+    };
+    if color_space != ColorSpace::Srgb {
+        color.color_range = try!(r.read_bit());
+        if profile == 1 || profile == 3 {
+            color.subsampling_x = try!(r.read_bit());
+            color.subsampling_y = try!(r.read_bit());
+            if try!(r.read_bit()) {
+                return Err(Error::ReservedBitSet);
+            }
+        }
+    } else {
+        if profile == 1 || profile == 3 {
+            color.subsampling_x = false;
+            color.subsampling_y = false;
+            if try!(r.read_bit()) {
+                return Err(Error::ReservedBitSet);
+            }
+        } else {
+            // RGB is not allowed in profiles 0 and 2.
+            return Err(Error::UnsupportedColorSpace);
+        }
+    }
+    Ok(color)
+}
+
+fn read_frame_size(r: &mut BitReader, hdr: &mut UncompressedHeader) -> Result<(), Error> {
+    hdr.width = try!(r.read_bits(16)) + 1;
+    hdr.height = try!(r.read_bits(16)) + 1;
+    Ok(())
+}
+
+fn read_render_size(r: &mut BitReader, hdr: &mut UncompressedHeader) -> Result<(), Error> {
+    if try!(r.read_bit()) {
+        hdr.render_width = try!(r.read_bits(16)) + 1;
+        hdr.render_height = try!(r.read_bits(16)) + 1;
+    } else {
+        hdr.render_width = hdr.width;
+        hdr.render_height = hdr.height;
+    }
+    Ok(())
+}
+
+fn read_frame_size_with_refs(r: &mut BitReader,
+                             hdr: &mut UncompressedHeader,
+                             ref_sizes: &[(u32, u32); NUM_REF_FRAMES]) -> Result<(), Error> {
+    for i in 0..3 {
+        if try!(r.read_bit()) {
+            let idx = hdr.ref_frame_idx[i];
+            let (width, height) = ref_sizes[idx as usize];
+            if width == 0 || height == 0 {
+                return Err(Error::MissingReference);
+            }
+            hdr.width = width;
+            hdr.height = height;
+            hdr.size_from_ref = Some(idx);
+            break;
+        }
+    }
+    if hdr.size_from_ref.is_none() {
+        try!(read_frame_size(r, hdr));
+    }
+    read_render_size(r, hdr)
+}
+
+fn read_interp_filter(r: &mut BitReader) -> Result<InterpFilter, Error> {
+    if try!(r.read_bit()) {
+        return Ok(InterpFilter::Switchable);
+    }
+    Ok(match try!(r.read_bits(2)) {
+        0 => InterpFilter::EightTapSmooth,
+        1 => InterpFilter::EightTap,
+        2 => InterpFilter::EightTapSharp,
+        _ => InterpFilter::Bilinear,
+    })
+}
+
+fn read_loop_filter_params(r: &mut BitReader, lf: &mut LoopFilterParams) -> Result<(), Error> {
+    lf.level = try!(r.read_bits(6)) as u8;
+    lf.sharpness = try!(r.read_bits(3)) as u8;
+    lf.delta_enabled = try!(r.read_bit());
+    lf.delta_update = false;
+    if lf.delta_enabled {
+        lf.delta_update = try!(r.read_bit());
+        if lf.delta_update {
+            for delta in lf.ref_deltas.iter_mut() {
+                if try!(r.read_bit()) {
+                    *delta = try!(r.read_signed(6)) as i8;
+                }
+            }
+            for delta in lf.mode_deltas.iter_mut() {
+                if try!(r.read_bit()) {
+                    *delta = try!(r.read_signed(6)) as i8;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_delta_q(r: &mut BitReader) -> Result<i8, Error> {
+    if try!(r.read_bit()) {
+        Ok(try!(r.read_signed(4)) as i8)
+    } else {
+        Ok(0)
+    }
+}
+
+fn read_quantization_params(r: &mut BitReader) -> Result<QuantizationParams, Error> {
+    let mut quant = QuantizationParams {
+        base_q_idx: try!(r.read_bits(8)) as u8,
+        delta_q_y_dc: try!(read_delta_q(r)),
+        delta_q_uv_dc: try!(read_delta_q(r)),
+        delta_q_uv_ac: try!(read_delta_q(r)),
+        lossless: false,
+    };
+    quant.lossless = quant.base_q_idx == 0 && quant.delta_q_y_dc == 0 &&
+                     quant.delta_q_uv_dc == 0 && quant.delta_q_uv_ac == 0;
+    Ok(quant)
+}
+
+fn read_prob(r: &mut BitReader) -> Result<u8, Error> {
+    if try!(r.read_bit()) {
+        Ok(try!(r.read_bits(8)) as u8)
+    } else {
+        Ok(255)
+    }
+}
+
+fn read_segmentation_params(r: &mut BitReader, seg: &mut SegmentationParams) -> Result<(), Error> {
+    seg.enabled = try!(r.read_bit());
+    seg.update_map = false;
+    seg.update_data = false;
+    if !seg.enabled {
+        return Ok(());
+    }
+    seg.update_map = try!(r.read_bit());
+    if seg.update_map {
+        for prob in seg.tree_probs.iter_mut() {
+            *prob = try!(read_prob(r));
+        }
+        seg.temporal_update = try!(r.read_bit());
+        for prob in seg.pred_probs.iter_mut() {
+            *prob = if seg.temporal_update { try!(read_prob(r)) } else { 255 };
+        }
+    }
+    seg.update_data = try!(r.read_bit());
+    if seg.update_data {
+        seg.abs_or_delta_update = try!(r.read_bit());
+        for i in 0..MAX_SEGMENTS {
+            for j in 0..SEG_LVL_MAX {
+                let mut value = 0;
+                let enabled = try!(r.read_bit());
+                if enabled {
+                    value = try!(r.read_bits(SEGMENTATION_FEATURE_BITS[j])) as i16;
+                    if SEGMENTATION_FEATURE_SIGNED[j] && try!(r.read_bit()) {
+                        value = -value;
+                    }
+                }
+                seg.feature_enabled[i][j] = enabled;
+                seg.feature_data[i][j] = value;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_tile_info(r: &mut BitReader, hdr: &mut UncompressedHeader) -> Result<(), Error> {
+    let sb64_cols = hdr.sb64_cols();
+    let mut min_log2 = 0;
+    while (MAX_TILE_WIDTH_B64 << min_log2) < sb64_cols {
+        min_log2 += 1;
+    }
+    let mut max_log2 = 1;
+    while (sb64_cols >> max_log2) >= MIN_TILE_WIDTH_B64 {
+        max_log2 += 1;
+    }
+    max_log2 -= 1;
+    hdr.tile_cols_log2 = min_log2;
+    while hdr.tile_cols_log2 < max_log2 {
+        if !try!(r.read_bit()) {
+            break;
+        }
+        hdr.tile_cols_log2 += 1;
+    }
+    hdr.tile_rows_log2 = try!(r.read_bit()) as u8;
+    if hdr.tile_rows_log2 != 0 {
+        hdr.tile_rows_log2 += try!(r.read_bit()) as u8;
+    }
+    Ok(())
+}
+
+/// Parse uncompressed header of the frame. Some values are inherited from
+/// the previously parsed frame and reference slots.
+pub fn parse(data: &[u8],
+             ref_sizes: &[(u32, u32); NUM_REF_FRAMES],
+             prev: Option<&UncompressedHeader>) -> Result<UncompressedHeader, Error> {
+    let mut r = BitReader::new(data);
+    if try!(r.read_bits(2)) != FRAME_MARKER {
+        return Err(Error::InvalidFrameMarker);
+    }
+    let profile_low = try!(r.read_bit()) as u8;
+    let profile_high = try!(r.read_bit()) as u8;
+    let profile = profile_high << 1 | profile_low;
+    if profile == 3 && try!(r.read_bit()) {
+        return Err(Error::ReservedBitSet);
+    }
+    let mut hdr = UncompressedHeader::new(profile);
+    if let Some(prev) = prev {
+        hdr.color = prev.color;
+        hdr.width = prev.width;
+        hdr.height = prev.height;
+        hdr.loop_filter = prev.loop_filter;
+        hdr.segmentation = prev.segmentation;
+    }
+
+    hdr.show_existing_frame = try!(r.read_bit());
+    if hdr.show_existing_frame {
+        hdr.frame_to_show_map_idx = try!(r.read_bits(3)) as u8;
+        hdr.loop_filter.level = 0;
+        hdr.size = r.byte_pos();
+        return Ok(hdr);
+    }
+    hdr.frame_type = if try!(r.read_bit()) { FrameType::NonKeyFrame } else { FrameType::KeyFrame };
+    hdr.show_frame = try!(r.read_bit());
+    hdr.error_resilient_mode = try!(r.read_bit());
+    if hdr.frame_type == FrameType::KeyFrame {
+        try!(read_sync_code(&mut r));
+        hdr.color = try!(read_color_config(&mut r, profile));
+        try!(read_frame_size(&mut r, &mut hdr));
+        try!(read_render_size(&mut r, &mut hdr));
+        hdr.refresh_frame_flags = 0xff;
+    } else {
+        if !hdr.show_frame {
+            hdr.intra_only = try!(r.read_bit());
+        }
+        if !hdr.error_resilient_mode {
+            hdr.reset_frame_context = try!(r.read_bits(2)) as u8;
+        }
+        if hdr.intra_only {
+            try!(read_sync_code(&mut r));
+            hdr.color = if profile > 0 {
+                try!(read_color_config(&mut r, profile))
+            } else {
+                Default::default()
+            };
+            hdr.refresh_frame_flags = try!(r.read_bits(8)) as u8;
+            try!(read_frame_size(&mut r, &mut hdr));
+            try!(read_render_size(&mut r, &mut hdr));
+        } else {
+            if prev.is_none() {
+                return Err(Error::MissingReference);
+            }
+            hdr.refresh_frame_flags = try!(r.read_bits(8)) as u8;
+            for i in 0..3 {
+                hdr.ref_frame_idx[i] = try!(r.read_bits(3)) as u8;
+                hdr.ref_frame_sign_bias[i + 1] = try!(r.read_bit());
+            }
+            try!(read_frame_size_with_refs(&mut r, &mut hdr, ref_sizes));
+            hdr.allow_high_precision_mv = try!(r.read_bit());
+            hdr.interp_filter = try!(read_interp_filter(&mut r));
+        }
+    }
+
+    if !hdr.error_resilient_mode {
+        hdr.refresh_frame_context = try!(r.read_bit());
+        hdr.frame_parallel_decoding_mode = try!(r.read_bit());
+    }
+    hdr.frame_context_idx = try!(r.read_bits(2)) as u8;
+    if hdr.is_intra() || hdr.error_resilient_mode {
+        // setup_past_independence
+        hdr.loop_filter = Default::default();
+        hdr.segmentation.abs_or_delta_update = false;
+        hdr.segmentation.feature_enabled = Default::default();
+        hdr.segmentation.feature_data = Default::default();
+    }
+    try!(read_loop_filter_params(&mut r, &mut hdr.loop_filter));
+    hdr.quantization = try!(read_quantization_params(&mut r));
+    try!(read_segmentation_params(&mut r, &mut hdr.segmentation));
+    try!(read_tile_info(&mut r, &mut hdr));
+    hdr.header_size_in_bytes = try!(r.read_bits(16)) as u16;
+    if hdr.header_size_in_bytes == 0 {
+        return Err(Error::InvalidHeaderSize);
+    }
+    hdr.size = r.byte_pos();
+    Ok(hdr)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::fmt;
+    use std::mem;
+    use super::*;
+
+    /// 352x288 lossless keyframe: BT.601 limited range, loop filter level 0
+    /// with reference deltas of 1, 0, -1, -1, compressed header of 112
+    /// bytes.
+    pub const KEYFRAME: [u8; 18] = [0x82, 0x49, 0x83, 0x42, 0x20, 0x15, 0xf0, 0x11, 0xf4, 0x00,
+                                0x38, 0x24, 0x1c, 0x18, 0x00, 0x00, 0x07, 0x00];
+    /// Following inter frame: refreshes slot 0, LAST/GOLDEN/ALTREF are slots
+    /// 0, 1 and 2, size is taken from LAST, high precision motion vectors
+    /// and switchable filter, compressed header of 80 bytes.
+    pub const INTER_FRAME: [u8; 10] = [0x86, 0x00, 0x40, 0x92, 0xe0, 0x01, 0x00, 0x00, 0x01, 0x40];
+
+    pub const NO_REFS: [(u32, u32); NUM_REF_FRAMES] = [(0, 0); NUM_REF_FRAMES];
+
+    /// Headers of the keyframe and inter frame following it.
+    pub fn parse_frames() -> (UncompressedHeader, UncompressedHeader) {
+        let key = parse(&KEYFRAME, &NO_REFS, None).unwrap();
+        let ref_sizes = [(key.width, key.height); NUM_REF_FRAMES];
+        let inter = parse(&INTER_FRAME, &ref_sizes, Some(&key)).unwrap();
+        (key, inter)
+    }
+
+    fn assert_error<T: fmt::Debug>(res: Result<T, Error>, expected: Error) {
+        match res {
+            Err(ref err) if mem::discriminant(err) == mem::discriminant(&expected) => {},
+            res => panic!("expected {:?}, got {:?}", expected, res),
+        }
+    }
+
+    #[test]
+    fn keyframe() {
+        let hdr = parse(&KEYFRAME, &NO_REFS, None).unwrap();
+        assert_eq!(hdr.profile, 0);
+        assert_eq!(hdr.frame_type, FrameType::KeyFrame);
+        assert!(hdr.show_frame && !hdr.show_existing_frame && !hdr.error_resilient_mode);
+        assert!(hdr.is_intra());
+        assert_eq!((hdr.width, hdr.height), (352, 288));
+        assert_eq!((hdr.render_width, hdr.render_height), (352, 288));
+        assert_eq!((hdr.mi_cols(), hdr.mi_rows()), (44, 36));
+        assert_eq!(hdr.sb64_cols(), 6);
+        assert_eq!(hdr.color.bit_depth, 8);
+        assert_eq!(hdr.color.color_space, ColorSpace::Bt601);
+        assert!(!hdr.color.color_range);
+        assert!(hdr.color.subsampling_x && hdr.color.subsampling_y);
+        assert_eq!(hdr.refresh_frame_flags, 0xff);
+        assert!(hdr.refresh_frame_context && !hdr.frame_parallel_decoding_mode);
+        assert_eq!(hdr.frame_context_idx, 0);
+        assert_eq!((hdr.loop_filter.level, hdr.loop_filter.sharpness), (0, 0));
+        assert!(hdr.loop_filter.delta_enabled && hdr.loop_filter.delta_update);
+        assert_eq!(hdr.loop_filter.ref_deltas, [1, 0, -1, -1]);
+        assert_eq!(hdr.loop_filter.mode_deltas, [0, 0]);
+        assert_eq!(hdr.quantization.base_q_idx, 0);
+        assert!(hdr.quantization.lossless);
+        assert!(!hdr.segmentation.enabled);
+        assert_eq!((hdr.tile_cols_log2, hdr.tile_rows_log2), (0, 0));
+        assert_eq!(hdr.header_size_in_bytes, 112);
+        assert_eq!(hdr.size, KEYFRAME.len());
+    }
+
+    #[test]
+    fn inter_frame() {
+        let (_, hdr) = parse_frames();
+        assert_eq!(hdr.frame_type, FrameType::NonKeyFrame);
+        assert!(hdr.show_frame && !hdr.intra_only && !hdr.is_intra());
+        assert_eq!(hdr.reset_frame_context, 0);
+        assert_eq!(hdr.refresh_frame_flags, 0x01);
+        assert_eq!(hdr.ref_frame_idx, [0, 1, 2]);
+        assert_eq!(hdr.ref_frame_sign_bias, [false; 4]);
+        assert_eq!(hdr.size_from_ref, Some(0));
+        assert_eq!((hdr.width, hdr.height), (352, 288));
+        assert_eq!((hdr.render_width, hdr.render_height), (352, 288));
+        assert_eq!(hdr.color.color_space, ColorSpace::Bt601);
+        assert!(hdr.allow_high_precision_mv);
+        assert_eq!(hdr.interp_filter, InterpFilter::Switchable);
+        assert!(hdr.refresh_frame_context && !hdr.frame_parallel_decoding_mode);
+        assert_eq!(hdr.loop_filter.level, 0);
+        // Deltas are kept from the keyframe.
+        assert!(hdr.loop_filter.delta_enabled && !hdr.loop_filter.delta_update);
+        assert_eq!(hdr.loop_filter.ref_deltas, [1, 0, -1, -1]);
+        assert!(hdr.quantization.lossless);
+        assert_eq!(hdr.header_size_in_bytes, 80);
+        assert_eq!(hdr.size, INTER_FRAME.len());
+    }
+
+    #[test]
+    fn inter_frame_without_reference() {
+        let key = parse(&KEYFRAME, &NO_REFS, None).unwrap();
+        assert_error(parse(&INTER_FRAME, &NO_REFS, None), Error::MissingReference);
+        // Referenced slot is empty.
+        assert_error(parse(&INTER_FRAME, &NO_REFS, Some(&key)), Error::MissingReference);
+    }
+
+    #[test]
+    fn show_existing_frame() {
+        let hdr = parse(&[0x8b], &NO_REFS, None).unwrap();
+        assert!(hdr.show_existing_frame);
+        assert_eq!(hdr.frame_to_show_map_idx, 3);
+        assert_eq!(hdr.size, 1);
+    }
+
+    #[test]
+    fn broken_headers() {
+        assert_error(parse(&[0x42], &NO_REFS, None), Error::InvalidFrameMarker);
+        let mut data = KEYFRAME;
+        data[2] = 0x84;
+        assert_error(parse(&data, &NO_REFS, None), Error::InvalidSyncCode);
+        // Zero size of the compressed header.
+        let mut data = KEYFRAME;
+        data[16] = 0;
+        data[17] = 0;
+        assert_error(parse(&data, &NO_REFS, None), Error::InvalidHeaderSize);
+        for len in 0..KEYFRAME.len() {
+            assert_error(parse(&KEYFRAME[..len], &NO_REFS, None), Error::NotEnoughData);
+        }
+    }
+}
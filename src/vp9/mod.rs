@@ -0,0 +1,212 @@
+//! VP9 bitstream parser.
+//! Reference: VP9 Bitstream & Decoding Process Specification v0.6,
+//! <http://www.webmproject.org/vp9/>.
+
+use std::fmt;
+
+mod bitreader;
+mod booldec;
+pub mod header;
+pub mod compressed;
+pub mod probs;
+mod adapt;
+pub mod block;
+mod context;
+mod mvref;
+mod residual;
+mod scan;
+mod tile;
+
+pub use self::header::{UncompressedHeader, FrameType, InterpFilter};
+pub use self::compressed::{CompressedHeader, TxMode};
+pub use self::block::{BlockSize, Partition, BlockInfo, BlockMap, BlockStats};
+use self::probs::FrameContext;
+
+pub const NUM_REF_FRAMES: usize = 8;
+pub const MAX_SEGMENTS: usize = 8;
+pub const SEG_LVL_MAX: usize = 4;
+pub const FRAME_CONTEXTS: usize = 4;
+
+#[derive(Debug)]
+pub enum Error {
+    NotEnoughData,
+    InvalidFrameMarker,
+    InvalidSyncCode,
+    InvalidHeaderSize,
+    ReservedBitSet,
+    InvalidMarkerBit,
+    UnsupportedColorSpace,
+    /// Frame refers to the state we don't have, e.g. when parsing didn't
+    /// start from keyframe.
+    MissingReference,
+    /// Decoded motion vector is out of the allowed range.
+    InvalidMotionVector,
+    /// Skip segment feature is set on block smaller than 8x8.
+    InvalidSegmentSkip,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descr = match *self {
+            Error::NotEnoughData => "Not enough data",
+            Error::InvalidFrameMarker => "Invalid frame marker",
+            Error::InvalidSyncCode => "Invalid sync code",
+            Error::InvalidHeaderSize => "Invalid header size",
+            Error::ReservedBitSet => "Reserved bit set",
+            Error::InvalidMarkerBit => "Invalid bool decoder marker bit",
+            Error::UnsupportedColorSpace => "Unsupported color space",
+            Error::MissingReference => "Missing reference frame",
+            Error::InvalidMotionVector => "Invalid motion vector",
+            Error::InvalidSegmentSkip => "Invalid usage of segment skip on small block",
+        };
+        write!(f, "VP9 parse error: {}", descr)
+    }
+}
+
+/// Headers of the single VPx frame.
+#[derive(Debug, Clone)]
+pub struct FrameHeader {
+    pub uncompressed: UncompressedHeader,
+    /// Missing for frames which only show existing one.
+    pub compressed: Option<CompressedHeader>,
+    /// Mode info of all blocks, missing the same way as compressed header.
+    pub blocks: Option<BlockMap>,
+}
+
+/// Parser of the uncompressed headers only, much faster than the full one
+/// when block info is not needed. Frames should be fed the same way.
+pub struct HeaderParser {
+    /// Frame dimensions of the reference slots.
+    ref_sizes: [(u32, u32); NUM_REF_FRAMES],
+    /// Some values are inherited from the previous frame.
+    prev: Option<UncompressedHeader>,
+}
+
+impl HeaderParser {
+    pub fn new() -> HeaderParser {
+        HeaderParser {
+            ref_sizes: [(0, 0); NUM_REF_FRAMES],
+            prev: None,
+        }
+    }
+
+    /// Parse uncompressed header of the single VPx frame.
+    pub fn parse(&mut self, data: &[u8]) -> Result<UncompressedHeader, Error> {
+        let hdr = try!(header::parse(data, &self.ref_sizes, self.prev.as_ref()));
+        if hdr.show_existing_frame {
+            return Ok(hdr);
+        }
+        for i in 0..NUM_REF_FRAMES {
+            if hdr.refresh_frame_flags & (1 << i) != 0 {
+                self.ref_sizes[i] = (hdr.width, hdr.height);
+            }
+        }
+        self.prev = Some(hdr);
+        Ok(hdr)
+    }
+
+    /// Header of the last parsed frame which doesn't show existing one.
+    pub fn get_last(&self) -> Option<&UncompressedHeader> {
+        self.prev.as_ref()
+    }
+}
+
+/// Stateful parser, frames should be fed in decoding order starting from
+/// keyframe, the same way as to the decoder.
+pub struct Parser {
+    headers: HeaderParser,
+    /// Saved probabilities.
+    frame_contexts: [FrameContext; FRAME_CONTEXTS],
+    /// Mode info of the previous decoded frame, source of motion vector
+    /// candidates.
+    prev_blocks: Option<BlockMap>,
+    /// Segmentation map of the previous frame which used segmentation.
+    prev_segment_ids: Vec<u8>,
+    last_show_frame: bool,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            headers: HeaderParser::new(),
+            frame_contexts: [Default::default(); FRAME_CONTEXTS],
+            prev_blocks: None,
+            prev_segment_ids: Vec::new(),
+            last_show_frame: false,
+        }
+    }
+
+    /// Parse single VPx frame (i.e. not superframe).
+    pub fn parse_frame(&mut self, data: &[u8]) -> Result<FrameHeader, Error> {
+        let last = self.headers.prev;
+        let hdr = try!(self.headers.parse(data));
+        if hdr.show_existing_frame {
+            // Doesn't affect decoding state besides of the motion vectors
+            // usage.
+            self.last_show_frame = true;
+            return Ok(FrameHeader {uncompressed: hdr, compressed: None, blocks: None});
+        }
+
+        let end = hdr.size + hdr.header_size_in_bytes as usize;
+        if end > data.len() {
+            return Err(Error::NotEnoughData);
+        }
+        let ctx = self.setup_frame_context(&hdr);
+        let mut fc = self.frame_contexts[ctx];
+        let compressed = try!(compressed::parse(&data[hdr.size..end], &hdr, &mut fc));
+
+        let same_size = last.map_or(false, |l| l.width == hdr.width && l.height == hdr.height);
+        if hdr.is_intra() || hdr.error_resilient_mode || !same_size {
+            self.prev_segment_ids.clear();
+        }
+        let use_prev_mvs = !hdr.error_resilient_mode && same_size && self.last_show_frame &&
+                           !last.map_or(true, |l| l.intra_only);
+        let tiles = {
+            let prev_blocks = if use_prev_mvs { self.prev_blocks.as_ref() } else { None };
+            try!(tile::decode(&data[end..], &hdr, &compressed, &fc, prev_blocks,
+                              &self.prev_segment_ids))
+        };
+
+        if !hdr.error_resilient_mode && !hdr.frame_parallel_decoding_mode {
+            let pre = self.frame_contexts[ctx];
+            let after_key = last.map_or(false, |l| l.frame_type == FrameType::KeyFrame);
+            adapt::adapt_coef_probs(&mut fc, &pre, &tiles.counts, !hdr.is_intra() && after_key);
+            if !hdr.is_intra() {
+                adapt::adapt_noncoef_probs(&mut fc, &pre, &tiles.counts,
+                                           hdr.interp_filter == InterpFilter::Switchable,
+                                           compressed.tx_mode == TxMode::TxModeSelect,
+                                           hdr.allow_high_precision_mv);
+            }
+        }
+        if hdr.refresh_frame_context {
+            self.frame_contexts[ctx] = fc;
+        }
+        if hdr.segmentation.enabled {
+            self.prev_segment_ids = tiles.segment_ids;
+        }
+        self.prev_blocks = Some(tiles.blocks.clone());
+        self.last_show_frame = hdr.show_frame;
+        Ok(FrameHeader {
+            uncompressed: hdr,
+            compressed: Some(compressed),
+            blocks: Some(tiles.blocks),
+        })
+    }
+
+    /// Reset saved probabilities if frame asks for it and return index of
+    /// the context frame actually uses.
+    fn setup_frame_context(&mut self, hdr: &UncompressedHeader) -> usize {
+        if !hdr.is_intra() && !hdr.error_resilient_mode {
+            return hdr.frame_context_idx as usize;
+        }
+        // Part of setup_past_independence().
+        if hdr.frame_type == FrameType::KeyFrame ||
+           hdr.error_resilient_mode ||
+           hdr.reset_frame_context == 3 {
+            self.frame_contexts = [Default::default(); FRAME_CONTEXTS];
+        } else if hdr.reset_frame_context == 2 {
+            self.frame_contexts[hdr.frame_context_idx as usize] = Default::default();
+        }
+        0
+    }
+}
@@ -0,0 +1,313 @@
+//! Motion vector prediction, section 6.4.22 of the spec. Candidate search
+//! follows vp9_mvref_common.c of libvpx.
+
+use std::cmp;
+use super::block::{BlockSize, BlockInfo, BlockMap, Mv};
+
+/// Allow vectors to point 16 pixels outside of the frame.
+const MV_BORDER: i32 = 16 << 3;
+/// Border of the reference frame minus filter taps, in 1/8 pel.
+const REF_BORDER: i32 = (160 - 4) << 3;
+const COMPANDED_MVREF_THRESH: i32 = 8;
+
+/// Positions of the candidate neighbours as (row, col) offsets in 8x8
+/// units, per block size.
+const MV_REF_BLOCKS: [[(i32, i32); 8]; 13] = [
+    [(-1, 0), (0, -1), (-1, -1), (-2, 0), (0, -2), (-2, -1), (-1, -2), (-2, -2)],
+    [(-1, 0), (0, -1), (-1, -1), (-2, 0), (0, -2), (-2, -1), (-1, -2), (-2, -2)],
+    [(-1, 0), (0, -1), (-1, -1), (-2, 0), (0, -2), (-2, -1), (-1, -2), (-2, -2)],
+    [(-1, 0), (0, -1), (-1, -1), (-2, 0), (0, -2), (-2, -1), (-1, -2), (-2, -2)],
+    [(0, -1), (-1, 0), (1, -1), (-1, -1), (0, -2), (-2, 0), (-2, -1), (-1, -2)],
+    [(-1, 0), (0, -1), (-1, 1), (-1, -1), (-2, 0), (0, -2), (-1, -2), (-2, -1)],
+    [(-1, 0), (0, -1), (-1, 1), (1, -1), (-1, -1), (-3, 0), (0, -3), (-3, -3)],
+    [(0, -1), (-1, 0), (2, -1), (-1, -1), (-1, 1), (0, -3), (-3, 0), (-3, -3)],
+    [(-1, 0), (0, -1), (-1, 2), (-1, -1), (1, -1), (-3, 0), (0, -3), (-3, -3)],
+    [(-1, 1), (1, -1), (-1, 2), (2, -1), (-1, -1), (-3, 0), (0, -3), (-3, -3)],
+    [(0, -1), (-1, 0), (4, -1), (-1, 2), (-1, -1), (0, -3), (-3, 0), (2, -1)],
+    [(-1, 0), (0, -1), (-1, 4), (2, -1), (-1, -1), (-3, 0), (0, -3), (-1, 2)],
+    [(-1, 3), (3, -1), (-1, 4), (4, -1), (-1, -1), (-1, 0), (0, -1), (-1, 6)],
+];
+
+/// Weight of the neighbour's mode in the inter mode context, indexed by
+/// prediction mode.
+const MODE_2_COUNTER: [usize; 14] = [9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 0, 0, 3, 1];
+
+/// Unreachable sums map to 0.
+const COUNTER_TO_CONTEXT: [usize; 19] = [2, 3, 4, 1, 3, 0, 0, 0, 0, 5, 5, 0, 5, 0, 0, 0, 0, 0, 6];
+
+/// Sub-block of the sub8x8 neighbour to take the vector from, indexed by
+/// the current sub-block and whether neighbour is above.
+const IDX_N_COLUMN_TO_SUBBLOCK: [[usize; 2]; 4] = [[1, 2], [1, 3], [3, 2], [3, 3]];
+
+/// Whether vector is short enough to use 1/8 pel precision.
+pub fn use_mv_hp(mv: Mv) -> bool {
+    (mv.row as i32).abs() >> 3 < COMPANDED_MVREF_THRESH &&
+    (mv.col as i32).abs() >> 3 < COMPANDED_MVREF_THRESH
+}
+
+fn lower_mv_precision(mv: &mut Mv, allow_hp: bool) {
+    if !(allow_hp && use_mv_hp(*mv)) {
+        if mv.row & 1 != 0 {
+            mv.row += if mv.row > 0 { -1 } else { 1 };
+        }
+        if mv.col & 1 != 0 {
+            mv.col += if mv.col > 0 { -1 } else { 1 };
+        }
+    }
+}
+
+fn clamp(val: i16, min: i32, max: i32) -> i16 {
+    cmp::max(min, cmp::min(max, val as i32)) as i16
+}
+
+/// Add vector to the candidate list, returns true when the list is full.
+fn add_mv_ref(list: &mut [Mv; 2], count: &mut usize, mv: Mv) -> bool {
+    if *count == 0 {
+        list[0] = mv;
+        *count = 1;
+        false
+    } else if mv != list[0] {
+        list[1] = mv;
+        true
+    } else {
+        false
+    }
+}
+
+/// Everything candidate search needs to know about the frame.
+pub struct MvRefSearch<'a> {
+    /// Already decoded blocks of the current frame.
+    pub blocks: &'a BlockMap,
+    /// Blocks of the previous frame if its vectors can be used.
+    pub prev_blocks: Option<&'a BlockMap>,
+    pub sign_bias: [bool; 4],
+    pub allow_hp: bool,
+    pub mi_col_start: u32,
+    pub mi_col_end: u32,
+}
+
+impl<'a> MvRefSearch<'a> {
+    fn candidate(&self, mi_row: u32, mi_col: u32, pos: (i32, i32)) -> Option<&'a BlockInfo> {
+        let row = mi_row as i32 + pos.0;
+        let col = mi_col as i32 + pos.1;
+        if row < 0 || row >= self.blocks.mi_rows as i32 ||
+           col < self.mi_col_start as i32 || col >= self.mi_col_end as i32 {
+            return None;
+        }
+        self.blocks.get(row as u32, col as u32)
+    }
+
+    fn scale_mv(&self, cand: &BlockInfo, which: usize, ref_frame: u8) -> Mv {
+        let mv = cand.mv(which);
+        let cand_ref = cand.ref_frame[which] as usize;
+        if self.sign_bias[cand_ref] != self.sign_bias[ref_frame as usize] {
+            Mv {row: -mv.row, col: -mv.col}
+        } else {
+            mv
+        }
+    }
+
+    /// Distance to the frame edges in 1/8 pel as (left, right, top,
+    /// bottom).
+    fn edges(&self, mi_row: u32, mi_col: u32, size: BlockSize) -> (i32, i32, i32, i32) {
+        let mi_cols = self.blocks.mi_cols as i32;
+        let mi_rows = self.blocks.mi_rows as i32;
+        (-(mi_col as i32 * 64),
+         (mi_cols - size.mi_width() as i32 - mi_col as i32) * 64,
+         -(mi_row as i32 * 64),
+         (mi_rows - size.mi_height() as i32 - mi_row as i32) * 64)
+    }
+
+    fn clamp_mv(&self, mv: Mv, mi_row: u32, mi_col: u32, size: BlockSize, border: i32) -> Mv {
+        let (left, right, top, bottom) = self.edges(mi_row, mi_col, size);
+        Mv {
+            row: clamp(mv.row, top - border, bottom + border),
+            col: clamp(mv.col, left - border, right + border),
+        }
+    }
+
+    /// Find up to two candidate vectors for the given reference, returns
+    /// them along with the inter mode context. `block` is the sub-block
+    /// index when searching for sub8x8 vectors.
+    pub fn find_mv_refs(&self, mi_row: u32, mi_col: u32, size: BlockSize, ref_frame: u8,
+                        block: Option<usize>) -> ([Mv; 2], usize) {
+        let (mut list, counter) = self.search_mv_refs(mi_row, mi_col, size, ref_frame, block);
+        for mv in list.iter_mut() {
+            *mv = self.clamp_mv(*mv, mi_row, mi_col, size, MV_BORDER);
+        }
+        (list, COUNTER_TO_CONTEXT[counter])
+    }
+
+    /// Fill the candidate list, stops as soon as it's full. Mode counter
+    /// only depends on the first two neighbours which are always visited.
+    fn search_mv_refs(&self, mi_row: u32, mi_col: u32, size: BlockSize, ref_frame: u8,
+                      block: Option<usize>) -> ([Mv; 2], usize) {
+        let mut list = [Mv::default(); 2];
+        let mut count = 0;
+        let mut counter = 0;
+        let mut different_ref_found = false;
+        let search = &MV_REF_BLOCKS[size as usize];
+        let prev = match self.prev_blocks {
+            Some(blocks) => blocks.get(mi_row, mi_col),
+            None => None,
+        };
+
+        for (i, &pos) in search.iter().enumerate() {
+            let cand = match self.candidate(mi_row, mi_col, pos) {
+                Some(cand) => cand,
+                None => continue,
+            };
+            different_ref_found = true;
+            if i < 2 {
+                counter += MODE_2_COUNTER[cand.y_mode as usize];
+            }
+            let which = if cand.ref_frame[0] == ref_frame {
+                0
+            } else if cand.ref_frame[1] == ref_frame {
+                1
+            } else {
+                continue;
+            };
+            // The nearest 2 blocks may give vectors of the sub-blocks.
+            let mv = match block {
+                Some(b) if i < 2 && cand.size < BlockSize::Block8x8 => {
+                    cand.sub_mvs[IDX_N_COLUMN_TO_SUBBLOCK[b][(pos.1 == 0) as usize]][which]
+                },
+                _ => cand.mv(which),
+            };
+            if add_mv_ref(&mut list, &mut count, mv) {
+                return (list, counter);
+            }
+        }
+
+        // Check the last frame's block at the same position.
+        if let Some(prev) = prev {
+            let which = if prev.ref_frame[0] == ref_frame {
+                Some(0)
+            } else if prev.ref_frame[1] == ref_frame {
+                Some(1)
+            } else {
+                None
+            };
+            if let Some(which) = which {
+                if add_mv_ref(&mut list, &mut count, prev.mv(which)) {
+                    return (list, counter);
+                }
+            }
+        }
+
+        if different_ref_found {
+            for &pos in search.iter() {
+                let cand = match self.candidate(mi_row, mi_col, pos) {
+                    Some(cand) if cand.is_inter() => cand,
+                    _ => continue,
+                };
+                if cand.ref_frame[0] != ref_frame &&
+                   add_mv_ref(&mut list, &mut count, self.scale_mv(cand, 0, ref_frame)) {
+                    return (list, counter);
+                }
+                if cand.is_compound() && cand.ref_frame[1] != ref_frame &&
+                   cand.mv(1) != cand.mv(0) &&
+                   add_mv_ref(&mut list, &mut count, self.scale_mv(cand, 1, ref_frame)) {
+                    return (list, counter);
+                }
+            }
+        }
+
+        if let Some(prev) = prev {
+            if prev.is_inter() && prev.ref_frame[0] != ref_frame &&
+               add_mv_ref(&mut list, &mut count, self.scale_mv(prev, 0, ref_frame)) {
+                return (list, counter);
+            }
+            if prev.is_compound() && prev.ref_frame[1] != ref_frame &&
+               prev.mv(1) != prev.mv(0) {
+                add_mv_ref(&mut list, &mut count, self.scale_mv(prev, 1, ref_frame));
+            }
+        }
+        (list, counter)
+    }
+
+    /// Turn candidates into the NEARESTMV and NEARMV vectors.
+    pub fn find_best_ref_mvs(&self, mut list: [Mv; 2], mi_row: u32, mi_col: u32,
+                             size: BlockSize) -> (Mv, Mv) {
+        for mv in list.iter_mut() {
+            lower_mv_precision(mv, self.allow_hp);
+            *mv = self.clamp_mv(*mv, mi_row, mi_col, size, REF_BORDER);
+        }
+        (list[0], list[1])
+    }
+
+    /// NEARESTMV and NEARMV vectors of the sub-block, taking already
+    /// decoded sub-blocks of the current block into account.
+    pub fn append_sub8x8_mvs(&self, b: &BlockInfo, ref_idx: usize, block: usize) -> (Mv, Mv) {
+        let (list, _) = self.find_mv_refs(b.mi_row, b.mi_col, b.size, b.ref_frame[ref_idx],
+                                          Some(block));
+        let sub_mvs = &b.sub_mvs;
+        let (nearest, candidates) = match block {
+            0 => return (list[0], list[1]),
+            1 | 2 => (sub_mvs[0][ref_idx], vec![list[0], list[1]]),
+            _ => (sub_mvs[2][ref_idx],
+                  vec![sub_mvs[1][ref_idx], sub_mvs[0][ref_idx], list[0], list[1]]),
+        };
+        let near = candidates.into_iter().find(|&mv| mv != nearest).unwrap_or(Mv::default());
+        (nearest, near)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::block::tests::block;
+
+    fn search<'a>(map: &'a BlockMap, sign_bias: [bool; 4]) -> MvRefSearch<'a> {
+        MvRefSearch {
+            blocks: map,
+            prev_blocks: None,
+            sign_bias: sign_bias,
+            allow_hp: false,
+            mi_col_start: 0,
+            mi_col_end: map.mi_cols,
+        }
+    }
+
+    #[test]
+    fn high_precision() {
+        assert!(use_mv_hp(Mv {row: 63, col: -63}));
+        assert!(!use_mv_hp(Mv {row: 64, col: 0}));
+        assert!(!use_mv_hp(Mv {row: 0, col: -64}));
+    }
+
+    #[test]
+    fn above_candidate() {
+        let mut map = BlockMap::new(2, 2);
+        map.push(block(0, 0, BlockSize::Block8x8, [1, 0], Mv {row: 4, col: -8}));
+        let s = search(&map, [false; 4]);
+        // NEWMV neighbour gives counter of 1.
+        assert_eq!(s.find_mv_refs(1, 0, BlockSize::Block8x8, 1, None),
+                   ([Mv {row: 4, col: -8}, Mv::default()], 3));
+        // Vector of the different reference is used only if sign bias is
+        // the same.
+        assert_eq!(s.find_mv_refs(1, 0, BlockSize::Block8x8, 2, None),
+                   ([Mv {row: 4, col: -8}, Mv::default()], 3));
+        let s = search(&map, [false, false, true, false]);
+        assert_eq!(s.find_mv_refs(1, 0, BlockSize::Block8x8, 2, None),
+                   ([Mv {row: -4, col: 8}, Mv::default()], 3));
+        // No neighbours.
+        assert_eq!(s.find_mv_refs(0, 0, BlockSize::Block8x8, 1, None),
+                   ([Mv::default(); 2], 2));
+    }
+
+    #[test]
+    fn clamping() {
+        let mut map = BlockMap::new(2, 2);
+        map.push(block(0, 0, BlockSize::Block8x8, [1, 0], Mv {row: 1001, col: -1001}));
+        let s = search(&map, [false; 4]);
+        // Block may point 16 pixels out of the frame.
+        let (list, _) = s.find_mv_refs(1, 0, BlockSize::Block8x8, 1, None);
+        assert_eq!(list[0], Mv {row: 128, col: -128});
+        // Without high precision odd components are rounded towards zero.
+        let (nearest, near) = s.find_best_ref_mvs([Mv {row: 3, col: -5}, Mv {row: 2, col: 1}],
+                                                  1, 0, BlockSize::Block8x8);
+        assert_eq!((nearest, near), (Mv {row: 2, col: -4}, Mv {row: 2, col: 0}));
+    }
+}
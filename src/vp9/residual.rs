@@ -0,0 +1,242 @@
+//! Residual tokens, section 6.4.24 of the spec. Coefficient values aren't
+//! kept, only the end of block positions needed for contexts.
+
+use super::booldec::BoolDecoder;
+use super::block::PredictionMode;
+use super::probs::{FrameContext, PARETO8_FULL};
+use super::adapt::Counts;
+use super::scan::*;
+
+const COEF_CON_TREE: [i8; 16] = [2, 6, -2, 4, -3, -4, 8, 10, -5, -6, 12, 14, -7, -8, -9, -10];
+
+const ONE_TOKEN: usize = 1;
+const CAT1_TOKEN: usize = 5;
+const CAT6_TOKEN: usize = 10;
+
+const CAT1_PROBS: [u8; 1] = [159];
+const CAT2_PROBS: [u8; 2] = [165, 145];
+const CAT3_PROBS: [u8; 3] = [173, 148, 140];
+const CAT4_PROBS: [u8; 4] = [176, 155, 140, 135];
+const CAT5_PROBS: [u8; 5] = [180, 157, 141, 134, 130];
+const EXTRA_BITS_PROBS: [&'static [u8]; 5] = [&CAT1_PROBS, &CAT2_PROBS, &CAT3_PROBS,
+                                               &CAT4_PROBS, &CAT5_PROBS];
+/// Probabilities for 12-bit streams, lower bit depths skip the first ones.
+const CAT6_PROBS: [u8; 18] = [255, 255, 255, 255, 254, 254, 254, 252, 249, 243, 230, 196, 177,
+                              153, 140, 133, 130, 129];
+
+/// Energy class of the token, used as context of the following ones.
+const ENERGY_CLASS: [u8; 11] = [0, 1, 2, 3, 3, 4, 4, 5, 5, 5, 5];
+
+const COEFBAND_4X4: [usize; 16] = [0, 1, 1, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 5, 5, 5];
+const COEFBAND_8X8PLUS: [usize; 15] = [0, 1, 1, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanKind {
+    Default,
+    /// Used with vertical ADST.
+    Row,
+    /// Used with horizontal ADST.
+    Col,
+}
+
+impl ScanKind {
+    /// Scan order of the luma transform block of intra block predicted with
+    /// the given mode.
+    pub fn from_mode(mode: PredictionMode) -> ScanKind {
+        match mode {
+            PredictionMode::VPred | PredictionMode::D117Pred | PredictionMode::D63Pred => {
+                ScanKind::Row
+            },
+            PredictionMode::HPred | PredictionMode::D153Pred | PredictionMode::D207Pred => {
+                ScanKind::Col
+            },
+            _ => ScanKind::Default,
+        }
+    }
+
+    fn scan(&self, tx_size: usize) -> &'static [u16] {
+        match (tx_size, *self) {
+            (0, ScanKind::Default) => &DEFAULT_SCAN_4X4,
+            (0, ScanKind::Row) => &ROW_SCAN_4X4,
+            (0, ScanKind::Col) => &COL_SCAN_4X4,
+            (1, ScanKind::Default) => &DEFAULT_SCAN_8X8,
+            (1, ScanKind::Row) => &ROW_SCAN_8X8,
+            (1, ScanKind::Col) => &COL_SCAN_8X8,
+            (2, ScanKind::Default) => &DEFAULT_SCAN_16X16,
+            (2, ScanKind::Row) => &ROW_SCAN_16X16,
+            (2, ScanKind::Col) => &COL_SCAN_16X16,
+            _ => &DEFAULT_SCAN_32X32,
+        }
+    }
+}
+
+/// Single transform block to read tokens of.
+pub struct TxBlock {
+    pub tx_size: usize,
+    /// 0 for luma, 1 for chroma.
+    pub plane_type: usize,
+    pub is_inter: bool,
+    pub scan: ScanKind,
+    /// Number of neighbouring blocks with coded coefficients.
+    pub ctx: usize,
+}
+
+/// Context of the position from the energy of already decoded neighbours.
+fn coef_ctx(token_cache: &[u8], scan: ScanKind, size: usize, pos: usize) -> usize {
+    let i = pos / size;
+    let j = pos % size;
+    let above = pos.wrapping_sub(size);
+    let left = pos.wrapping_sub(1);
+    let (a, b) = if i > 0 && j > 0 {
+        match scan {
+            ScanKind::Col => (above, above),
+            ScanKind::Row => (left, left),
+            ScanKind::Default => (above, left),
+        }
+    } else if i > 0 {
+        (above, above)
+    } else {
+        (left, left)
+    };
+    (1 + token_cache[a] as usize + token_cache[b] as usize) >> 1
+}
+
+fn read_extra_bits(bd: &mut BoolDecoder, probs: &[u8]) {
+    for &prob in probs {
+        bd.read_bool(prob);
+    }
+}
+
+/// Read tokens of the transform block, returns end of block position.
+pub fn read_coefs(bd: &mut BoolDecoder, fc: &FrameContext, counts: &mut Counts,
+                  tx: &TxBlock, bit_depth: u8) -> u32 {
+    let probs = &fc.coef[tx.tx_size][tx.plane_type][tx.is_inter as usize];
+    let coef_counts = &mut counts.coef[tx.tx_size][tx.plane_type][tx.is_inter as usize];
+    let eob_counts = &mut counts.eob_branch[tx.tx_size][tx.plane_type][tx.is_inter as usize];
+    let scan = tx.scan.scan(tx.tx_size);
+    let size = 4 << tx.tx_size;
+    let max_eob = scan.len();
+    let band = |c: usize| if tx.tx_size == 0 {
+        COEFBAND_4X4[c]
+    } else if c < COEFBAND_8X8PLUS.len() {
+        COEFBAND_8X8PLUS[c]
+    } else {
+        5
+    };
+    let mut token_cache = [0u8; 1024];
+    let mut ctx = tx.ctx;
+    let mut c = 0;
+    while c < max_eob {
+        let mut b = band(c);
+        eob_counts[b][ctx] += 1;
+        if !bd.read_bool(probs[b][ctx][0]) {
+            coef_counts[b][ctx][3] += 1;
+            break;
+        }
+        while !bd.read_bool(probs[b][ctx][1]) {
+            coef_counts[b][ctx][0] += 1;
+            token_cache[scan[c] as usize] = 0;
+            c += 1;
+            if c >= max_eob {
+                return c as u32;
+            }
+            ctx = coef_ctx(&token_cache, tx.scan, size, scan[c] as usize);
+            b = band(c);
+        }
+        let token = if !bd.read_bool(probs[b][ctx][2]) {
+            coef_counts[b][ctx][1] += 1;
+            ONE_TOKEN
+        } else {
+            coef_counts[b][ctx][2] += 1;
+            let pareto = &PARETO8_FULL[probs[b][ctx][2] as usize - 1];
+            bd.read_tree(&COEF_CON_TREE, pareto) as usize
+        };
+        if token == CAT6_TOKEN {
+            read_extra_bits(bd, &CAT6_PROBS[12 - bit_depth as usize..]);
+        } else if token >= CAT1_TOKEN {
+            read_extra_bits(bd, EXTRA_BITS_PROBS[token - CAT1_TOKEN]);
+        }
+        // Sign.
+        bd.read_bool(128);
+        token_cache[scan[c] as usize] = ENERGY_CLASS[token];
+        c += 1;
+        if c < max_eob {
+            ctx = coef_ctx(&token_cache, tx.scan, size, scan[c] as usize);
+        }
+    }
+    c as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::booldec::tests::BoolEncoder;
+
+    fn read(data: &[u8], fc: &FrameContext, counts: &mut Counts) -> u32 {
+        let tx = TxBlock {
+            tx_size: 0,
+            plane_type: 0,
+            is_inter: false,
+            scan: ScanKind::Default,
+            ctx: 0,
+        };
+        let mut bd = BoolDecoder::new(data).unwrap();
+        read_coefs(&mut bd, fc, counts, &tx, 8)
+    }
+
+    #[test]
+    fn one_token() {
+        let fc = FrameContext::default();
+        let probs = &fc.coef[0][0][0];
+        let mut e = BoolEncoder::new();
+        e.write_bool(true, probs[0][0][0]);
+        e.write_bool(true, probs[0][0][1]);
+        e.write_bool(false, probs[0][0][2]);
+        e.write_bool(true, 128);
+        // Next position is below the first one in band 1, energy of ONE
+        // gives context 1.
+        e.write_bool(false, probs[1][1][0]);
+        let data = e.finish();
+        let mut counts = Counts::default();
+        assert_eq!(read(&data, &fc, &mut counts), 1);
+        let coef_counts = &counts.coef[0][0][0];
+        assert_eq!(coef_counts[0][0], [0, 1, 0, 0]);
+        assert_eq!(coef_counts[1][1], [0, 0, 0, 1]);
+        assert_eq!(counts.eob_branch[0][0][0][0][0], 1);
+        assert_eq!(counts.eob_branch[0][0][0][1][1], 1);
+    }
+
+    #[test]
+    fn zero_and_two_tokens() {
+        let fc = FrameContext::default();
+        let probs = &fc.coef[0][0][0];
+        let mut e = BoolEncoder::new();
+        // ZERO token, end of block isn't checked after it.
+        e.write_bool(true, probs[0][0][0]);
+        e.write_bool(false, probs[0][0][1]);
+        // TWO token in band 1 and context 0.
+        e.write_bool(true, probs[1][0][1]);
+        e.write_bool(true, probs[1][0][2]);
+        let pareto = &PARETO8_FULL[probs[1][0][2] as usize - 1];
+        e.write_bool(false, pareto[0]);
+        e.write_bool(false, pareto[1]);
+        e.write_bool(false, 128);
+        // Third position is right of the first one with ZERO.
+        e.write_bool(false, probs[1][0][0]);
+        let data = e.finish();
+        let mut counts = Counts::default();
+        assert_eq!(read(&data, &fc, &mut counts), 2);
+        let coef_counts = &counts.coef[0][0][0];
+        assert_eq!(coef_counts[0][0], [1, 0, 0, 0]);
+        assert_eq!(coef_counts[1][0], [0, 0, 1, 1]);
+        assert_eq!(counts.eob_branch[0][0][0][0][0], 1);
+        assert_eq!(counts.eob_branch[0][0][0][1][0], 1);
+    }
+
+    #[test]
+    fn scan_kinds() {
+        assert_eq!(ScanKind::from_mode(PredictionMode::VPred), ScanKind::Row);
+        assert_eq!(ScanKind::from_mode(PredictionMode::HPred), ScanKind::Col);
+        assert_eq!(ScanKind::from_mode(PredictionMode::TmPred), ScanKind::Default);
+    }
+}
@@ -0,0 +1,939 @@
+//! Tile data syntax, section 6.4 of the spec. Mode info of every block is
+//! kept, residual tokens are only parsed to stay in sync with the bool
+//! decoder. Follows vp9_decodeframe.c and vp9_decodemv.c of libvpx where
+//! the spec leaves freedom.
+
+use std::cmp;
+use ::common;
+use super::Error;
+use super::booldec::BoolDecoder;
+use super::header::{UncompressedHeader, InterpFilter};
+use super::compressed::{CompressedHeader, TxMode, ReferenceMode};
+use super::probs::*;
+use super::adapt::Counts;
+use super::block::{BlockSize, Partition, PredictionMode, Mv, BlockInfo, PartitionInfo, BlockMap};
+use super::context::*;
+use super::mvref::{self, MvRefSearch};
+use super::residual::{self, ScanKind, TxBlock};
+
+pub const INTRA_MODE_TREE: [i8; 18] = [0, 2, -9, 4, -1, 6, 8, 12, -2, 10, -4, -5, -3, 14, -8,
+                                       16, -6, -7];
+/// Leaves are offsets from NEARESTMV.
+pub const INTER_MODE_TREE: [i8; 6] = [-2, 2, 0, 4, -1, -3];
+pub const PARTITION_TREE: [i8; 6] = [0, 2, -1, 4, -2, -3];
+pub const INTERP_FILTER_TREE: [i8; 4] = [0, 2, -1, -2];
+pub const MV_JOINT_TREE: [i8; 6] = [0, 2, -1, 4, -2, -3];
+pub const MV_CLASS_TREE: [i8; 20] = [0, 2, -1, 4, 6, 8, -2, -3, 10, 12, -4, -5, -6, 14, 16, 18,
+                                     -7, -8, -9, -10];
+pub const MV_FR_TREE: [i8; 6] = [0, 2, -1, 4, -2, -3];
+const SEGMENT_TREE: [i8; 14] = [2, 4, 6, 8, 10, 12, 0, -1, -2, -3, -4, -5, -6, -7];
+
+/// Group of the luma mode probabilities, per block size.
+const SIZE_GROUP: [usize; 13] = [0, 0, 0, 1, 1, 1, 2, 2, 2, 3, 3, 3, 3];
+
+const SEG_LVL_REF_FRAME: usize = 2;
+const SEG_LVL_SKIP: usize = 3;
+
+/// Motion vector components must stay inside of (-MV_UPP, MV_UPP).
+const MV_UPP: i32 = 1 << 14;
+
+/// Result of the tile data parsing.
+pub struct TileData {
+    pub blocks: BlockMap,
+    /// Symbol counts for backward adaptation.
+    pub counts: Counts,
+    /// Segment id of every 8x8 unit.
+    pub segment_ids: Vec<u8>,
+}
+
+/// Skip flag as seen by the following blocks. libvpx also sets it for inter
+/// blocks without coded coefficients.
+fn ctx_skip(b: &BlockInfo) -> bool {
+    b.skip || (b.is_inter() && b.size >= BlockSize::Block8x8 && b.eob_total == 0)
+}
+
+/// Mode of the sub-block above of the given one.
+fn above_block_mode(b: &BlockInfo, above: Option<&BlockInfo>, block: usize) -> PredictionMode {
+    if block >= 2 {
+        return b.sub_modes[block - 2];
+    }
+    match above {
+        Some(a) if !a.is_inter() => a.sub_modes[block + 2],
+        _ => PredictionMode::DcPred,
+    }
+}
+
+fn left_block_mode(b: &BlockInfo, left: Option<&BlockInfo>, block: usize) -> PredictionMode {
+    if block & 1 != 0 {
+        return b.sub_modes[block - 1];
+    }
+    match left {
+        Some(l) if !l.is_inter() => l.sub_modes[block + 1],
+        _ => PredictionMode::DcPred,
+    }
+}
+
+/// First 8x8 unit of the tile, get_tile_offset() in libvpx.
+fn tile_offset(i: u32, mis: u32, log2: u8) -> u32 {
+    let sbs = (mis + 7) >> 3;
+    cmp::min(((i * sbs) >> log2) << 3, mis)
+}
+
+struct TileDecoder<'a> {
+    hdr: &'a UncompressedHeader,
+    chdr: &'a CompressedHeader,
+    fc: &'a FrameContext,
+    prev_blocks: Option<&'a BlockMap>,
+    prev_segment_ids: &'a [u8],
+    counts: Counts,
+    map: BlockMap,
+    segment_ids: Vec<u8>,
+    /// Bounds of the current tile column.
+    mi_col_start: u32,
+    mi_col_end: u32,
+    above_partition: Vec<u8>,
+    left_partition: [u8; 8],
+    /// Whether 4x4 columns/rows of each plane have coded coefficients.
+    above_nonzero: [Vec<u8>; 3],
+    left_nonzero: [[u8; 16]; 3],
+}
+
+impl<'a> TileDecoder<'a> {
+    fn mv_ref_search(&self) -> MvRefSearch {
+        MvRefSearch {
+            blocks: &self.map,
+            prev_blocks: self.prev_blocks,
+            sign_bias: self.hdr.ref_frame_sign_bias,
+            allow_hp: self.hdr.allow_high_precision_mv,
+            mi_col_start: self.mi_col_start,
+            mi_col_end: self.mi_col_end,
+        }
+    }
+
+    fn seg_feature_active(&self, segment_id: u8, feature: usize) -> bool {
+        let seg = &self.hdr.segmentation;
+        seg.enabled && seg.feature_enabled[segment_id as usize][feature]
+    }
+
+    /// Number of 8x8 units block covers inside of the frame, as (cols,
+    /// rows).
+    fn visible_mis(&self, b: &BlockInfo) -> (u32, u32) {
+        (cmp::min(b.size.mi_width(), self.map.mi_cols - b.mi_col),
+         cmp::min(b.size.mi_height(), self.map.mi_rows - b.mi_row))
+    }
+
+    /// Lowest segment id of the covered units in the previous frame.
+    fn predicted_segment_id(&self, b: &BlockInfo) -> u8 {
+        if self.prev_segment_ids.is_empty() {
+            return 0;
+        }
+        let (x_mis, y_mis) = self.visible_mis(b);
+        let mut segment_id = (super::MAX_SEGMENTS - 1) as u8;
+        for y in 0..y_mis {
+            for x in 0..x_mis {
+                let offset = (b.mi_row + y) * self.map.mi_cols + b.mi_col + x;
+                segment_id = cmp::min(segment_id, self.prev_segment_ids[offset as usize]);
+            }
+        }
+        segment_id
+    }
+
+    /// Store segment id of the block, or keep the previous frame's ones if
+    /// `segment_id` is missing.
+    fn set_segment_ids(&mut self, b: &BlockInfo, segment_id: Option<u8>) {
+        let (x_mis, y_mis) = self.visible_mis(b);
+        for y in 0..y_mis {
+            for x in 0..x_mis {
+                let offset = ((b.mi_row + y) * self.map.mi_cols + b.mi_col + x) as usize;
+                self.segment_ids[offset] = match segment_id {
+                    Some(id) => id,
+                    None => self.prev_segment_ids.get(offset).cloned().unwrap_or(0),
+                };
+            }
+        }
+    }
+
+    fn read_intra_segment_id(&mut self, bd: &mut BoolDecoder, b: &BlockInfo) -> u8 {
+        let seg = self.hdr.segmentation;
+        if !seg.enabled {
+            return 0;
+        }
+        if !seg.update_map {
+            self.set_segment_ids(b, None);
+            return 0;
+        }
+        let segment_id = bd.read_tree(&SEGMENT_TREE, &seg.tree_probs);
+        self.set_segment_ids(b, Some(segment_id));
+        segment_id
+    }
+
+    fn read_inter_segment_id(&mut self, bd: &mut BoolDecoder, b: &mut BlockInfo,
+                             above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> u8 {
+        let seg = self.hdr.segmentation;
+        if !seg.enabled {
+            return 0;
+        }
+        let predicted = self.predicted_segment_id(b);
+        if !seg.update_map {
+            self.set_segment_ids(b, None);
+            return predicted;
+        }
+        let segment_id = if seg.temporal_update {
+            let ctx = above.map_or(0, |a| a.seg_id_predicted as usize) +
+                      left.map_or(0, |l| l.seg_id_predicted as usize);
+            b.seg_id_predicted = bd.read_bool(seg.pred_probs[ctx]);
+            if b.seg_id_predicted {
+                predicted
+            } else {
+                bd.read_tree(&SEGMENT_TREE, &seg.tree_probs)
+            }
+        } else {
+            bd.read_tree(&SEGMENT_TREE, &seg.tree_probs)
+        };
+        self.set_segment_ids(b, Some(segment_id));
+        segment_id
+    }
+
+    fn read_skip(&mut self, bd: &mut BoolDecoder, segment_id: u8,
+                 above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> bool {
+        if self.seg_feature_active(segment_id, SEG_LVL_SKIP) {
+            return true;
+        }
+        let ctx = above.map_or(0, |a| ctx_skip(a) as usize) +
+                  left.map_or(0, |l| ctx_skip(l) as usize);
+        let skip = bd.read_bool(self.fc.skip[ctx]);
+        self.counts.skip[ctx][skip as usize] += 1;
+        skip
+    }
+
+    fn read_tx_size(&mut self, bd: &mut BoolDecoder, size: BlockSize, allow_select: bool,
+                    above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> usize {
+        let max_tx_size = size.max_tx_size();
+        let tx_mode = self.chdr.tx_mode;
+        if !allow_select || tx_mode != TxMode::TxModeSelect || size < BlockSize::Block8x8 {
+            return cmp::min(max_tx_size, tx_mode.max_tx_size());
+        }
+        let tx_ctx = |edge: Option<&BlockInfo>| match edge {
+            Some(b) if !ctx_skip(b) => b.tx_size,
+            _ => max_tx_size,
+        };
+        let mut above_ctx = tx_ctx(above);
+        let mut left_ctx = tx_ctx(left);
+        if left.is_none() {
+            left_ctx = above_ctx;
+        }
+        if above.is_none() {
+            above_ctx = left_ctx;
+        }
+        let ctx = (above_ctx + left_ctx > max_tx_size) as usize;
+        let probs: &[u8] = match max_tx_size {
+            1 => &self.fc.tx8x8[ctx],
+            2 => &self.fc.tx16x16[ctx],
+            _ => &self.fc.tx32x32[ctx],
+        };
+        let mut tx_size = 0;
+        while tx_size < max_tx_size && bd.read_bool(probs[tx_size]) {
+            tx_size += 1;
+        }
+        match max_tx_size {
+            1 => self.counts.tx8x8[ctx][tx_size] += 1,
+            2 => self.counts.tx16x16[ctx][tx_size] += 1,
+            _ => self.counts.tx32x32[ctx][tx_size] += 1,
+        }
+        tx_size
+    }
+
+    fn read_intra_y_mode(&mut self, bd: &mut BoolDecoder, b: &BlockInfo, block: usize,
+                         above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> PredictionMode {
+        let mode = if self.hdr.is_intra() {
+            let a = above_block_mode(b, above, block) as usize;
+            let l = left_block_mode(b, left, block) as usize;
+            bd.read_tree(&INTRA_MODE_TREE, &KF_Y_MODE_PROBS[a][l])
+        } else {
+            let group = SIZE_GROUP[b.size as usize];
+            let mode = bd.read_tree(&INTRA_MODE_TREE, &self.fc.y_mode[group]);
+            self.counts.y_mode[group][mode as usize] += 1;
+            mode
+        };
+        PredictionMode::from_index(mode)
+    }
+
+    fn read_intra_modes(&mut self, bd: &mut BoolDecoder, b: &mut BlockInfo,
+                        above: Option<&BlockInfo>, left: Option<&BlockInfo>) {
+        if b.size >= BlockSize::Block8x8 {
+            let mode = self.read_intra_y_mode(bd, b, 0, above, left);
+            b.sub_modes = [mode; 4];
+        } else {
+            let w4 = b.size.width() as usize / 4;
+            let h4 = b.size.height() as usize / 4;
+            let mut idy = 0;
+            while idy < 2 {
+                let mut idx = 0;
+                while idx < 2 {
+                    let j = idy * 2 + idx;
+                    let mode = self.read_intra_y_mode(bd, b, j, above, left);
+                    b.sub_modes[j] = mode;
+                    if h4 == 2 {
+                        b.sub_modes[j + 2] = mode;
+                    }
+                    if w4 == 2 {
+                        b.sub_modes[j + 1] = mode;
+                    }
+                    idx += w4;
+                }
+                idy += h4;
+            }
+        }
+        b.y_mode = b.sub_modes[3];
+        let y = b.y_mode as usize;
+        let uv_mode = if self.hdr.is_intra() {
+            bd.read_tree(&INTRA_MODE_TREE, &KF_UV_MODE_PROBS[y])
+        } else {
+            let mode = bd.read_tree(&INTRA_MODE_TREE, &self.fc.uv_mode[y]);
+            self.counts.uv_mode[y][mode as usize] += 1;
+            mode
+        };
+        b.uv_mode = PredictionMode::from_index(uv_mode);
+    }
+
+    fn read_is_inter(&mut self, bd: &mut BoolDecoder, segment_id: u8,
+                     above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> bool {
+        if self.seg_feature_active(segment_id, SEG_LVL_REF_FRAME) {
+            return self.hdr.segmentation.feature_data[segment_id as usize][SEG_LVL_REF_FRAME] != 0;
+        }
+        let ctx = match (above, left) {
+            (Some(a), Some(l)) => {
+                if !a.is_inter() && !l.is_inter() {
+                    3
+                } else {
+                    (!a.is_inter() || !l.is_inter()) as usize
+                }
+            },
+            (Some(edge), None) | (None, Some(edge)) => 2 * !edge.is_inter() as usize,
+            (None, None) => 0,
+        };
+        let is_inter = bd.read_bool(self.fc.is_inter[ctx]);
+        self.counts.is_inter[ctx][is_inter as usize] += 1;
+        is_inter
+    }
+
+    fn read_ref_frames(&mut self, bd: &mut BoolDecoder, b: &mut BlockInfo,
+                       above: Option<&BlockInfo>, left: Option<&BlockInfo>) {
+        if self.seg_feature_active(b.segment_id, SEG_LVL_REF_FRAME) {
+            let data = self.hdr.segmentation.feature_data[b.segment_id as usize][SEG_LVL_REF_FRAME];
+            b.ref_frame = [data as u8, 0];
+            return;
+        }
+        let chdr = self.chdr;
+        let compound = match chdr.reference_mode {
+            ReferenceMode::Single => false,
+            ReferenceMode::Compound => true,
+            ReferenceMode::Select => {
+                let ctx = comp_mode_ctx(above, left, chdr.comp_fixed_ref);
+                let compound = bd.read_bool(self.fc.comp_mode[ctx]);
+                self.counts.comp_mode[ctx][compound as usize] += 1;
+                compound
+            },
+        };
+        if compound {
+            let fix_idx = self.hdr.ref_frame_sign_bias[chdr.comp_fixed_ref as usize] as usize;
+            let ctx = comp_ref_ctx(above, left, chdr.comp_fixed_ref, chdr.comp_var_ref,
+                                   1 - fix_idx);
+            let bit = bd.read_bool(self.fc.comp_ref[ctx]);
+            self.counts.comp_ref[ctx][bit as usize] += 1;
+            b.ref_frame[fix_idx] = chdr.comp_fixed_ref;
+            b.ref_frame[1 - fix_idx] = chdr.comp_var_ref[bit as usize];
+        } else {
+            let ctx = single_ref_p1_ctx(above, left);
+            let bit = bd.read_bool(self.fc.single_ref[ctx][0]);
+            self.counts.single_ref[ctx][0][bit as usize] += 1;
+            b.ref_frame[0] = if bit {
+                let ctx = single_ref_p2_ctx(above, left);
+                let bit = bd.read_bool(self.fc.single_ref[ctx][1]);
+                self.counts.single_ref[ctx][1][bit as usize] += 1;
+                if bit { 3 } else { 2 }
+            } else {
+                1
+            };
+        }
+    }
+
+    fn read_inter_mode(&mut self, bd: &mut BoolDecoder, ctx: usize) -> PredictionMode {
+        let offset = bd.read_tree(&INTER_MODE_TREE, &self.fc.inter_mode[ctx]);
+        self.counts.inter_mode[ctx][offset as usize] += 1;
+        PredictionMode::from_index(PredictionMode::NearestMv as u8 + offset)
+    }
+
+    fn read_interp_filter(&mut self, bd: &mut BoolDecoder,
+                          above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> InterpFilter {
+        if self.hdr.interp_filter != InterpFilter::Switchable {
+            return self.hdr.interp_filter;
+        }
+        let ctx = interp_filter_ctx(above, left);
+        let filter = bd.read_tree(&INTERP_FILTER_TREE, &self.fc.interp_filter[ctx]);
+        self.counts.interp_filter[ctx][filter as usize] += 1;
+        match filter {
+            0 => InterpFilter::EightTap,
+            1 => InterpFilter::EightTapSmooth,
+            _ => InterpFilter::EightTapSharp,
+        }
+    }
+
+    fn read_mv_component(&mut self, bd: &mut BoolDecoder, comp: usize, use_hp: bool) -> i32 {
+        let probs = self.fc.mv_comp[comp];
+        let counts = &mut self.counts.mv_comp[comp];
+        let sign = bd.read_bool(probs.sign);
+        counts.sign[sign as usize] += 1;
+        let class = bd.read_tree(&MV_CLASS_TREE, &probs.classes) as usize;
+        counts.classes[class] += 1;
+        let (d, fr_probs, mut mag) = if class == 0 {
+            let d = bd.read_bool(probs.class0_bit) as usize;
+            counts.class0_bit[d] += 1;
+            (d, probs.class0_fr[d], 0)
+        } else {
+            let mut d = 0;
+            for i in 0..class {
+                let bit = bd.read_bool(probs.bits[i]) as usize;
+                counts.bits[i][bit] += 1;
+                d |= bit << i;
+            }
+            (d, probs.fr, (CLASS0_SIZE as i32) << (class + 2))
+        };
+        let fr = bd.read_tree(&MV_FR_TREE, &fr_probs) as usize;
+        // High precision bit defaults to 1 when it's not coded.
+        let hp = if !use_hp {
+            1
+        } else if class == 0 {
+            bd.read_bool(probs.class0_hp) as usize
+        } else {
+            bd.read_bool(probs.hp) as usize
+        };
+        if class == 0 {
+            counts.class0_fr[d][fr] += 1;
+            counts.class0_hp[hp] += 1;
+        } else {
+            counts.fr[fr] += 1;
+            counts.hp[hp] += 1;
+        }
+        mag += ((d << 3 | fr << 1 | hp) + 1) as i32;
+        if sign { -mag } else { mag }
+    }
+
+    fn read_mv(&mut self, bd: &mut BoolDecoder, best: Mv) -> Result<Mv, Error> {
+        let joint = bd.read_tree(&MV_JOINT_TREE, &self.fc.mv_joint);
+        self.counts.mv_joint[joint as usize] += 1;
+        let use_hp = self.hdr.allow_high_precision_mv && mvref::use_mv_hp(best);
+        let diff_row = if joint == 2 || joint == 3 {
+            self.read_mv_component(bd, 0, use_hp)
+        } else {
+            0
+        };
+        let diff_col = if joint == 1 || joint == 3 {
+            self.read_mv_component(bd, 1, use_hp)
+        } else {
+            0
+        };
+        let row = best.row as i32 + diff_row;
+        let col = best.col as i32 + diff_col;
+        if row.abs() >= MV_UPP || col.abs() >= MV_UPP {
+            return Err(Error::InvalidMotionVector);
+        }
+        Ok(Mv {row: row as i16, col: col as i16})
+    }
+
+    fn assign_mv(&mut self, bd: &mut BoolDecoder, mode: PredictionMode, refs: usize,
+                 best: &[Mv; 2], nearest: &[Mv; 2], near: &[Mv; 2]) -> Result<[Mv; 2], Error> {
+        let mut mvs = [Mv::default(); 2];
+        for i in 0..refs {
+            mvs[i] = match mode {
+                PredictionMode::NewMv => try!(self.read_mv(bd, best[i])),
+                PredictionMode::NearestMv => nearest[i],
+                PredictionMode::NearMv => near[i],
+                _ => Mv::default(),
+            };
+        }
+        Ok(mvs)
+    }
+
+    fn read_inter_block(&mut self, bd: &mut BoolDecoder, b: &mut BlockInfo,
+                        above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> Result<(), Error> {
+        self.read_ref_frames(bd, b, above, left);
+        let refs = 1 + b.is_compound() as usize;
+        let mut ref_mvs = [[Mv::default(); 2]; 2];
+        let mut mode_ctx = 0;
+        for i in 0..refs {
+            let (list, ctx) = self.mv_ref_search().find_mv_refs(
+                b.mi_row, b.mi_col, b.size, b.ref_frame[i], None);
+            ref_mvs[i] = list;
+            if i == 0 {
+                mode_ctx = ctx;
+            }
+        }
+
+        let mut mode = PredictionMode::ZeroMv;
+        if self.seg_feature_active(b.segment_id, SEG_LVL_SKIP) {
+            if b.size < BlockSize::Block8x8 {
+                return Err(Error::InvalidSegmentSkip);
+            }
+        } else if b.size >= BlockSize::Block8x8 {
+            mode = self.read_inter_mode(bd, mode_ctx);
+        }
+
+        let mut nearest = [Mv::default(); 2];
+        let mut near = [Mv::default(); 2];
+        if b.size < BlockSize::Block8x8 || mode != PredictionMode::ZeroMv {
+            for i in 0..refs {
+                let (nearest_mv, near_mv) = self.mv_ref_search().find_best_ref_mvs(
+                    ref_mvs[i], b.mi_row, b.mi_col, b.size);
+                nearest[i] = nearest_mv;
+                near[i] = near_mv;
+            }
+        }
+
+        b.interp_filter = self.read_interp_filter(bd, above, left);
+
+        if b.size >= BlockSize::Block8x8 {
+            let mvs = try!(self.assign_mv(bd, mode, refs, &nearest, &nearest, &near));
+            b.y_mode = mode;
+            b.sub_modes = [mode; 4];
+            b.sub_mvs = [mvs; 4];
+            return Ok(());
+        }
+        let w4 = b.size.width() as usize / 4;
+        let h4 = b.size.height() as usize / 4;
+        let mut idy = 0;
+        while idy < 2 {
+            let mut idx = 0;
+            while idx < 2 {
+                let j = idy * 2 + idx;
+                let b_mode = self.read_inter_mode(bd, mode_ctx);
+                let mut sub_nearest = [Mv::default(); 2];
+                let mut sub_near = [Mv::default(); 2];
+                if b_mode == PredictionMode::NearestMv || b_mode == PredictionMode::NearMv {
+                    for i in 0..refs {
+                        let (nearest_mv, near_mv) = self.mv_ref_search().append_sub8x8_mvs(b, i, j);
+                        sub_nearest[i] = nearest_mv;
+                        sub_near[i] = near_mv;
+                    }
+                }
+                let mvs = try!(self.assign_mv(bd, b_mode, refs, &nearest, &sub_nearest, &sub_near));
+                b.sub_modes[j] = b_mode;
+                b.sub_mvs[j] = mvs;
+                if h4 == 2 {
+                    b.sub_modes[j + 2] = b_mode;
+                    b.sub_mvs[j + 2] = mvs;
+                }
+                if w4 == 2 {
+                    b.sub_modes[j + 1] = b_mode;
+                    b.sub_mvs[j + 1] = mvs;
+                }
+                idx += w4;
+            }
+            idy += h4;
+        }
+        b.y_mode = b.sub_modes[3];
+        Ok(())
+    }
+
+    fn read_mode_info(&mut self, bd: &mut BoolDecoder, b: &mut BlockInfo,
+                      above: Option<&BlockInfo>, left: Option<&BlockInfo>) -> Result<(), Error> {
+        if self.hdr.is_intra() {
+            b.segment_id = self.read_intra_segment_id(bd, b);
+            b.skip = self.read_skip(bd, b.segment_id, above, left);
+            b.tx_size = self.read_tx_size(bd, b.size, true, above, left);
+            self.read_intra_modes(bd, b, above, left);
+            return Ok(());
+        }
+        b.segment_id = self.read_inter_segment_id(bd, b, above, left);
+        b.skip = self.read_skip(bd, b.segment_id, above, left);
+        let is_inter = self.read_is_inter(bd, b.segment_id, above, left);
+        b.tx_size = self.read_tx_size(bd, b.size, !b.skip || !is_inter, above, left);
+        if is_inter {
+            self.read_inter_block(bd, b, above, left)
+        } else {
+            self.read_intra_modes(bd, b, above, left);
+            Ok(())
+        }
+    }
+
+    /// Parse tokens of all transform blocks and update the nonzero
+    /// contexts.
+    fn read_residual(&mut self, bd: &mut BoolDecoder, b: &mut BlockInfo) {
+        let mi_w = b.size.mi_width();
+        let mi_h = b.size.mi_height();
+        let right_edge = self.map.mi_cols as i32 - (b.mi_col + mi_w) as i32;
+        let bottom_edge = self.map.mi_rows as i32 - (b.mi_row + mi_h) as i32;
+        for plane in 0..3 {
+            let (ss_x, ss_y) = if plane == 0 {
+                (0, 0)
+            } else {
+                (self.hdr.color.subsampling_x as u32, self.hdr.color.subsampling_y as u32)
+            };
+            let n4_w = (mi_w * 2) >> ss_x;
+            let n4_h = (mi_h * 2) >> ss_y;
+            let above_base = ((b.mi_col * 2) >> ss_x) as usize;
+            let left_base = (((b.mi_row & 7) * 2) >> ss_y) as usize;
+            if b.skip {
+                for i in 0..n4_w as usize {
+                    self.above_nonzero[plane][above_base + i] = 0;
+                }
+                for i in 0..n4_h as usize {
+                    self.left_nonzero[plane][left_base + i] = 0;
+                }
+                continue;
+            }
+            let tx_size = if plane == 0 {
+                b.tx_size
+            } else if b.size < BlockSize::Block8x8 {
+                0
+            } else {
+                cmp::min(b.tx_size, cmp::min(n4_w, n4_h).trailing_zeros() as usize)
+            };
+            let step = 1 << tx_size;
+            // Parts of the block outside of the frame aren't coded.
+            let max_w = n4_w as i32 + cmp::min(0, (right_edge * 2) >> ss_x);
+            let max_h = n4_h as i32 + cmp::min(0, (bottom_edge * 2) >> ss_y);
+            let mut row = 0;
+            while row < max_h as usize {
+                let mut col = 0;
+                while col < max_w as usize {
+                    let above = &mut self.above_nonzero[plane][above_base + col..][..step];
+                    let left = &mut self.left_nonzero[plane][left_base + row..][..step];
+                    let ctx = above.iter().any(|&nz| nz != 0) as usize +
+                              left.iter().any(|&nz| nz != 0) as usize;
+                    let scan = if plane > 0 || b.is_inter() || self.hdr.quantization.lossless {
+                        ScanKind::Default
+                    } else if b.size < BlockSize::Block8x8 {
+                        ScanKind::from_mode(b.sub_modes[(row << 1) + col])
+                    } else {
+                        ScanKind::from_mode(b.y_mode)
+                    };
+                    let tx = TxBlock {
+                        tx_size: tx_size,
+                        plane_type: (plane > 0) as usize,
+                        is_inter: b.is_inter(),
+                        scan: scan,
+                        ctx: ctx,
+                    };
+                    let eob = residual::read_coefs(bd, self.fc, &mut self.counts, &tx,
+                                                   self.hdr.color.bit_depth);
+                    for i in 0..step {
+                        above[i] = (eob > 0 && col + i < max_w as usize) as u8;
+                        left[i] = (eob > 0 && row + i < max_h as usize) as u8;
+                    }
+                    b.eob_total += eob;
+                    col += step;
+                }
+                row += step;
+            }
+        }
+    }
+
+    fn decode_block(&mut self, bd: &mut BoolDecoder, mi_row: u32, mi_col: u32,
+                    size: BlockSize) -> Result<(), Error> {
+        let above = if mi_row > 0 {
+            self.map.get(mi_row - 1, mi_col).cloned()
+        } else {
+            None
+        };
+        let left = if mi_col > self.mi_col_start {
+            self.map.get(mi_row, mi_col - 1).cloned()
+        } else {
+            None
+        };
+        let mut b = BlockInfo {
+            mi_row: mi_row,
+            mi_col: mi_col,
+            size: size,
+            segment_id: 0,
+            seg_id_predicted: false,
+            skip: false,
+            tx_size: 0,
+            y_mode: PredictionMode::DcPred,
+            sub_modes: [PredictionMode::DcPred; 4],
+            uv_mode: PredictionMode::DcPred,
+            ref_frame: [0, 0],
+            interp_filter: InterpFilter::EightTap,
+            sub_mvs: [[Mv::default(); 2]; 4],
+            eob_total: 0,
+        };
+        try!(self.read_mode_info(bd, &mut b, above.as_ref(), left.as_ref()));
+        self.read_residual(bd, &mut b);
+        self.map.push(b);
+        Ok(())
+    }
+
+    fn decode_partition(&mut self, bd: &mut BoolDecoder, mi_row: u32, mi_col: u32,
+                        size: BlockSize) -> Result<(), Error> {
+        if mi_row >= self.map.mi_rows || mi_col >= self.map.mi_cols {
+            return Ok(());
+        }
+        let num8x8 = size.mi_width();
+        let hbs = num8x8 / 2;
+        let has_rows = mi_row + hbs < self.map.mi_rows;
+        let has_cols = mi_col + hbs < self.map.mi_cols;
+        let bsl = num8x8.trailing_zeros() as usize;
+        let above = (self.above_partition[mi_col as usize] >> bsl) & 1;
+        let left = (self.left_partition[(mi_row & 7) as usize] >> bsl) & 1;
+        let ctx = bsl * 4 + (left * 2 + above) as usize;
+        let probs = if self.hdr.is_intra() {
+            KF_PARTITION_PROBS[ctx]
+        } else {
+            self.fc.partition[ctx]
+        };
+        let partition = match (has_rows, has_cols) {
+            (true, true) => match bd.read_tree(&PARTITION_TREE, &probs) {
+                0 => Partition::None,
+                1 => Partition::Horz,
+                2 => Partition::Vert,
+                _ => Partition::Split,
+            },
+            (false, true) => if bd.read_bool(probs[1]) { Partition::Split } else { Partition::Horz },
+            (true, false) => if bd.read_bool(probs[2]) { Partition::Split } else { Partition::Vert },
+            (false, false) => Partition::Split,
+        };
+        self.counts.partition[ctx][partition as usize] += 1;
+        self.map.partitions.push(PartitionInfo {
+            mi_row: mi_row,
+            mi_col: mi_col,
+            size: size,
+            partition: partition,
+        });
+
+        let subsize = size.subsize(partition);
+        if hbs == 0 {
+            try!(self.decode_block(bd, mi_row, mi_col, subsize));
+        } else {
+            match partition {
+                Partition::None => {
+                    try!(self.decode_block(bd, mi_row, mi_col, subsize));
+                },
+                Partition::Horz => {
+                    try!(self.decode_block(bd, mi_row, mi_col, subsize));
+                    if has_rows {
+                        try!(self.decode_block(bd, mi_row + hbs, mi_col, subsize));
+                    }
+                },
+                Partition::Vert => {
+                    try!(self.decode_block(bd, mi_row, mi_col, subsize));
+                    if has_cols {
+                        try!(self.decode_block(bd, mi_row, mi_col + hbs, subsize));
+                    }
+                },
+                Partition::Split => {
+                    try!(self.decode_partition(bd, mi_row, mi_col, subsize));
+                    try!(self.decode_partition(bd, mi_row, mi_col + hbs, subsize));
+                    try!(self.decode_partition(bd, mi_row + hbs, mi_col, subsize));
+                    try!(self.decode_partition(bd, mi_row + hbs, mi_col + hbs, subsize));
+                },
+            }
+        }
+
+        // Bits of the sizes larger than the decoded one are set.
+        if size == BlockSize::Block8x8 || partition != Partition::Split {
+            let above = (15 << (subsize.width() / 4).trailing_zeros()) & 15;
+            let left = (15 << (subsize.height() / 4).trailing_zeros()) & 15;
+            for i in 0..num8x8 {
+                self.above_partition[(mi_col + i) as usize] = above;
+                self.left_partition[((mi_row + i) & 7) as usize] = left;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split tile data into per-tile buffers in raster order.
+fn split_tiles(data: &[u8], count: usize) -> Result<Vec<&[u8]>, Error> {
+    let mut tiles = Vec::with_capacity(count);
+    let mut pos = 0;
+    for i in 0..count {
+        // Every tile except the last one is prefixed with its size.
+        let size = if i == count - 1 {
+            data.len() - pos
+        } else {
+            if pos + 4 > data.len() {
+                return Err(Error::NotEnoughData);
+            }
+            let size = common::get_be32(&data[pos..]) as usize;
+            pos += 4;
+            if size > data.len() - pos {
+                return Err(Error::NotEnoughData);
+            }
+            size
+        };
+        tiles.push(&data[pos..pos + size]);
+        pos += size;
+    }
+    Ok(tiles)
+}
+
+/// Parse all tiles of the frame. `prev_blocks` should be given only if
+/// motion vectors of the previous frame can be used for prediction.
+pub fn decode(data: &[u8],
+              hdr: &UncompressedHeader,
+              chdr: &CompressedHeader,
+              fc: &FrameContext,
+              prev_blocks: Option<&BlockMap>,
+              prev_segment_ids: &[u8]) -> Result<TileData, Error> {
+    let mi_cols = hdr.mi_cols();
+    let mi_rows = hdr.mi_rows();
+    let aligned_cols = (hdr.sb64_cols() * 8) as usize;
+    let ss_x = hdr.color.subsampling_x as usize;
+    let tile_cols = 1 << hdr.tile_cols_log2;
+    let tile_rows = 1 << hdr.tile_rows_log2;
+    let tiles = try!(split_tiles(data, (tile_cols * tile_rows) as usize));
+    let mut dec = TileDecoder {
+        hdr: hdr,
+        chdr: chdr,
+        fc: fc,
+        prev_blocks: prev_blocks,
+        prev_segment_ids: prev_segment_ids,
+        counts: Default::default(),
+        map: BlockMap::new(mi_cols, mi_rows),
+        segment_ids: vec![0; (mi_cols * mi_rows) as usize],
+        mi_col_start: 0,
+        mi_col_end: 0,
+        above_partition: vec![0; aligned_cols],
+        left_partition: [0; 8],
+        above_nonzero: [vec![0; aligned_cols * 2],
+                        vec![0; (aligned_cols * 2) >> ss_x],
+                        vec![0; (aligned_cols * 2) >> ss_x]],
+        left_nonzero: [[0; 16]; 3],
+    };
+    for tile_row in 0..tile_rows {
+        let mi_row_start = tile_offset(tile_row, mi_rows, hdr.tile_rows_log2);
+        let mi_row_end = tile_offset(tile_row + 1, mi_rows, hdr.tile_rows_log2);
+        let mut bds = Vec::with_capacity(tile_cols as usize);
+        for tile_col in 0..tile_cols {
+            let tile = tiles[(tile_row * tile_cols + tile_col) as usize];
+            bds.push(try!(BoolDecoder::new(tile)));
+        }
+        // Superblock rows are decoded across all tile columns, the same
+        // way as libvpx does, to keep blocks in decoding order.
+        let mut mi_row = mi_row_start;
+        while mi_row < mi_row_end {
+            for (tile_col, bd) in bds.iter_mut().enumerate() {
+                dec.mi_col_start = tile_offset(tile_col as u32, mi_cols, hdr.tile_cols_log2);
+                dec.mi_col_end = tile_offset(tile_col as u32 + 1, mi_cols, hdr.tile_cols_log2);
+                dec.left_partition = [0; 8];
+                dec.left_nonzero = [[0; 16]; 3];
+                let mut mi_col = dec.mi_col_start;
+                while mi_col < dec.mi_col_end {
+                    try!(dec.decode_partition(bd, mi_row, mi_col, BlockSize::Block64x64));
+                    mi_col += 8;
+                }
+            }
+            mi_row += 8;
+        }
+    }
+    Ok(TileData {
+        blocks: dec.map,
+        counts: dec.counts,
+        segment_ids: dec.segment_ids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::booldec::tests::BoolEncoder;
+    use super::super::header::tests::parse_frames;
+
+    fn compressed_header(tx_mode: TxMode) -> CompressedHeader {
+        CompressedHeader {
+            tx_mode: tx_mode,
+            reference_mode: ReferenceMode::Single,
+            comp_fixed_ref: 0,
+            comp_var_ref: [0; 2],
+            updates: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn keyframe() {
+        let (mut hdr, _) = parse_frames();
+        hdr.width = 16;
+        hdr.height = 16;
+        hdr.quantization.base_q_idx = 60;
+        hdr.quantization.lossless = false;
+        let chdr = compressed_header(TxMode::TxModeSelect);
+        let fc = FrameContext::default();
+        let mut e = BoolEncoder::new();
+        // 64x64 and 32x32 partitions are implied by the frame size, 16x16
+        // block without neighbours is split vertically.
+        let probs = KF_PARTITION_PROBS[4];
+        e.write_bool(true, probs[0]);
+        e.write_bool(true, probs[1]);
+        e.write_bool(false, probs[2]);
+        // Left 8x16 block is skipped, has 8x8 transform and DC_PRED.
+        e.write_bool(true, fc.skip[0]);
+        e.write_bool(true, fc.tx8x8[1][0]);
+        e.write_bool(false, KF_Y_MODE_PROBS[0][0][0]);
+        e.write_bool(false, KF_UV_MODE_PROBS[0][0]);
+        // Right one is coded with 4x4 transform and V_PRED.
+        e.write_bool(false, fc.skip[1]);
+        e.write_bool(false, fc.tx8x8[1][0]);
+        let probs = KF_Y_MODE_PROBS[0][0];
+        e.write_bool(true, probs[0]);
+        e.write_bool(true, probs[1]);
+        e.write_bool(false, probs[2]);
+        e.write_bool(false, KF_UV_MODE_PROBS[1][0]);
+        // All 8 luma and 2 + 2 chroma transform blocks are empty.
+        for _ in 0..8 {
+            e.write_bool(false, fc.coef[0][0][0][0][0][0]);
+        }
+        for _ in 0..4 {
+            e.write_bool(false, fc.coef[0][1][0][0][0][0]);
+        }
+        let data = e.finish();
+
+        let tile = decode(&data, &hdr, &chdr, &fc, None, &[]).unwrap();
+        let blocks = &tile.blocks;
+        let partitions: Vec<_> = blocks.partitions.iter()
+                                       .map(|p| (p.mi_row, p.mi_col, p.size, p.partition))
+                                       .collect();
+        assert_eq!(partitions, [(0, 0, BlockSize::Block64x64, Partition::Split),
+                                (0, 0, BlockSize::Block32x32, Partition::Split),
+                                (0, 0, BlockSize::Block16x16, Partition::Vert)]);
+        assert_eq!(blocks.blocks.len(), 2);
+        let left = blocks.get(1, 0).unwrap();
+        assert_eq!((left.mi_row, left.mi_col, left.size), (0, 0, BlockSize::Block8x16));
+        assert!(left.skip && !left.is_inter());
+        assert_eq!(left.tx_size, 1);
+        assert_eq!((left.y_mode, left.uv_mode), (PredictionMode::DcPred, PredictionMode::DcPred));
+        let right = blocks.get(1, 1).unwrap();
+        assert_eq!((right.mi_row, right.mi_col, right.size), (0, 1, BlockSize::Block8x16));
+        assert!(!right.skip);
+        assert_eq!(right.tx_size, 0);
+        assert_eq!((right.y_mode, right.uv_mode), (PredictionMode::VPred, PredictionMode::DcPred));
+        assert_eq!(right.eob_total, 0);
+        assert_eq!(tile.counts.partition[4], [0, 0, 1, 0]);
+        assert_eq!(tile.counts.skip, [[0, 1], [1, 0], [0, 0]]);
+        assert_eq!(tile.counts.tx8x8, [[0, 0], [1, 1]]);
+        assert_eq!(tile.counts.eob_branch[0][0][0][0][0], 8);
+        assert_eq!(tile.segment_ids, [0; 4]);
+    }
+
+    #[test]
+    fn truncated_tile() {
+        let (hdr, _) = parse_frames();
+        let chdr = compressed_header(TxMode::Only4x4);
+        match decode(&[], &hdr, &chdr, &FrameContext::default(), None, &[]) {
+            Err(Error::NotEnoughData) => {},
+            res => panic!("unexpected result {:?}", res.map(|tile| tile.blocks.blocks.len())),
+        }
+    }
+
+    #[test]
+    fn tiles() {
+        let data = [0, 0, 0, 2, 1, 2, 0, 0, 0, 0, 3];
+        assert_eq!(split_tiles(&data, 3).unwrap(), [&[1, 2][..], &[], &[3]]);
+        assert!(split_tiles(&data[..3], 2).is_err());
+        assert!(split_tiles(&data[..5], 2).is_err());
+        // 44 columns of 8x8 units take 6 superblocks.
+        assert_eq!(tile_offset(0, 44, 1), 0);
+        assert_eq!(tile_offset(1, 44, 1), 24);
+        assert_eq!(tile_offset(2, 44, 1), 44);
+        assert_eq!(tile_offset(3, 44, 2), 32);
+    }
+}
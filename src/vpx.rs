@@ -4,48 +4,118 @@ use std::u16;
 use std::fmt;
 use std::ptr;
 use std::mem;
+use std::cmp;
+use std::slice;
+use std::thread;
+use std::ops::Deref;
 use libc::{c_int, c_uint, c_long, c_void, c_uchar};
+use ::common;
+use ::colorspace::{self, Matrix, Converter};
+use ::hdr::ToneMapper;
+use ::simd::Sample;
 
 // Safe wrapper.
 
 #[derive(Debug)]
-pub struct Error(vpx_codec_err_t);
+pub enum Error {
+    CodecError(vpx_codec_err_t),
+    /// Image format and bit depth we can't convert.
+    UnsupportedFormat(String),
+    /// Zero-size compressed frame, libvpx doesn't accept those.
+    EmptyFrame,
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error(ref codec_err) => write!(f, "VPx error: {:?}", codec_err)
+            Error::CodecError(ref codec_err) => write!(f, "VPx error: {:?}", codec_err),
+            Error::UnsupportedFormat(ref fmt) => write!(f, "Unsupported image format: {}", fmt),
+            Error::EmptyFrame => write!(f, "Empty VPx frame"),
         }
     }
 }
 
 pub struct Decoder {
     codec: Box<vpx_codec_ctx_t>,
+    iface: *mut vpx_codec_iface_t,
+}
+
+/// Get decoder interface suitable for the given container FourCC.
+fn get_iface(fourcc: u32) -> Option<*mut vpx_codec_iface_t> {
+    unsafe {
+        match fourcc {
+            common::VP8_FOURCC => Some(&mut vpx_codec_vp8_dx_algo),
+            common::VP9_FOURCC => Some(&mut vpx_codec_vp9_dx_algo),
+            _ => None,
+        }
+    }
 }
 
 impl Decoder {
-    pub fn init() -> Result<Decoder, Error> {
+    pub fn init(fourcc: u32) -> Result<Decoder, Error> {
+        Decoder::init_with_threads(fourcc, 0)
+    }
+
+    /// Init decoder which uses the given number of threads, 0 leaves the
+    /// libvpx default.
+    pub fn init_with_threads(fourcc: u32, threads: usize) -> Result<Decoder, Error> {
+        let iface = match get_iface(fourcc) {
+            Some(iface) => iface,
+            None => return Err(Error::CodecError(vpx_codec_err_t::VPX_CODEC_INCAPABLE)),
+        };
         let mut codec = Box::new(Default::default());
+        let cfg = vpx_codec_dec_cfg_t {threads: threads as c_uint, w: 0, h: 0};
+        let cfg_ptr = if threads > 0 { &cfg as *const _ } else { ptr::null() };
         unsafe {
             let res = vpx_codec_dec_init_ver(&mut *codec,
-                                             &mut vpx_codec_vp9_dx_algo,
-                                             ptr::null(),
+                                             iface,
+                                             cfg_ptr,
                                              0,
                                              VPX_DECODER_ABI_VERSION);
             if res == vpx_codec_err_t::VPX_CODEC_OK {
-                Ok(Decoder {codec: codec})
+                Ok(Decoder {codec: codec, iface: iface})
+            } else {
+                Err(Error::CodecError(res))
+            }
+        }
+    }
+
+    /// Check whether the given compressed frame is a keyframe without
+    /// actually decoding it.
+    pub fn is_keyframe(&self, data: &[u8]) -> Result<bool, Error> {
+        if data.is_empty() {
+            return Err(Error::EmptyFrame);
+        }
+        let mut si = vpx_codec_stream_info_t {
+            sz: mem::size_of::<vpx_codec_stream_info_t>() as c_uint,
+            w: 0,
+            h: 0,
+            is_kf: 0,
+        };
+        unsafe {
+            let res = vpx_codec_peek_stream_info(self.iface,
+                                                 data.as_ptr(),
+                                                 data.len() as c_uint,
+                                                 &mut si);
+            if res == vpx_codec_err_t::VPX_CODEC_OK {
+                Ok(si.is_kf != 0)
             } else {
-                Err(Error(res))
+                Err(Error::CodecError(res))
             }
         }
     }
 
-    // FIXME(Kagami): Seems like `vpx_codec_decode` is stateful, i.e. we can't
-    // run it again if we're already iterating. How can we fix it?
-    pub fn decode_many(&mut self, data: &[u8]) -> Result<Frames, Error> {
+    /// Decode the compressed frame and iterate over the resulting images.
+    /// Images borrow the decoder because next `decode` call reuses their
+    /// buffers, use `Image::to_owned` to keep them around.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Frames, Error> {
+        // NOTE(Kagami): Null data with zero size flushes the decoder instead.
+        if data.is_empty() {
+            return Err(Error::EmptyFrame);
+        }
         unsafe {
             let res = vpx_codec_decode(&mut *self.codec,
-                                       &data[0],
+                                       data.as_ptr(),
                                        data.len() as c_uint,
                                        ptr::null_mut(),
                                        0);
@@ -53,15 +123,19 @@ impl Decoder {
                 Ok(Frames {
                     end: false,
                     codec: &mut *self.codec,
-                    iter: Box::new(ptr::null_mut()),
+                    iter: ptr::null_mut(),
                 })
             } else {
-                Err(Error(res))
+                Err(Error::CodecError(res))
             }
         }
     }
 }
 
+// NOTE(Kagami): Decoder context is not bound to the thread it was created
+// on, it just shouldn't be used from several threads at once.
+unsafe impl Send for Decoder {}
+
 impl Drop for Decoder {
     fn drop(&mut self) {
         unsafe {
@@ -70,10 +144,10 @@ impl Drop for Decoder {
     }
 }
 
-pub struct Frames<'c> {
+pub struct Frames<'d> {
     end: bool,
-    codec: &'c mut vpx_codec_ctx_t,
-    iter: Box<vpx_codec_iter_t>,
+    codec: &'d mut vpx_codec_ctx_t,
+    iter: vpx_codec_iter_t,
 }
 
 // NOTE(Kagami): We don't allow dimensions larger than u16 because:
@@ -82,15 +156,18 @@ pub struct Frames<'c> {
 // 3) Larger values are not practical anyway
 const DIMENSION_MAX: c_uint = u16::MAX as c_uint;
 
-impl<'c> Iterator for Frames<'c> {
-    type Item = Image;
+/// Smaller images are not worth splitting between threads.
+const MIN_THREAD_ROWS: usize = 64;
+
+impl<'d> Iterator for Frames<'d> {
+    type Item = Image<'d>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.end {
             return None;
         }
         unsafe {
-            let img_data = vpx_codec_get_frame(self.codec, &mut *self.iter);
+            let img_data = vpx_codec_get_frame(self.codec, &mut self.iter);
             if img_data.is_null() {
                 self.end = true;
                 None
@@ -99,120 +176,503 @@ impl<'c> Iterator for Frames<'c> {
                 assert!((*img_data).d_w <= DIMENSION_MAX);
                 assert!((*img_data).d_h > 0);
                 assert!((*img_data).d_h <= DIMENSION_MAX);
-                Some(Image {data: img_data})
+                Some(Image {data: &*img_data})
             }
         }
     }
 }
 
-pub struct Image {
-    data: *mut vpx_image_t,
+/// Decoded image. Its buffers belong to the decoder and are reused by the
+/// next decode call, hence the lifetime.
+pub struct Image<'d> {
+    data: &'d vpx_image_t,
 }
 
-impl Image {
+/// Copy of the decoded image, stays valid after the next decoder call.
+pub struct OwnedImage {
+    // NOTE(Kagami): Image is allocated by us and freed on drop. Static
+    // lifetime never leaks out: image is only lent through `Deref` and
+    // nothing borrowed from it outlives `&self`.
+    image: Image<'static>,
+}
+
+/// Samples of the single image plane.
+pub struct Plane<'a, T: 'a> {
+    data: &'a [T],
+    /// Dimensions in samples.
+    pub width: usize,
+    pub height: usize,
+    /// Distance between the rows in samples.
+    pub stride: usize,
+}
+
+impl<'a, T: 'a> Plane<'a, T> {
+    /// Samples of the given row.
+    pub fn row(&self, i: usize) -> &'a [T] {
+        &self.data[i * self.stride..i * self.stride + self.width]
+    }
+}
+
+/// Samples of the single pixel, at the stored bit depth.
+#[derive(Debug, Clone, Copy)]
+pub struct Pixel {
+    pub y: u16,
+    pub u: u16,
+    pub v: u16,
+    /// Converted color.
+    pub rgb: [u8; 3],
+}
+
+impl<'d> Image<'d> {
     pub fn get_display_width(&self) -> u16 {
-        unsafe { (*self.data).d_w as u16 }
+        self.data.d_w as u16
     }
 
     pub fn get_display_height(&self) -> u16 {
-        unsafe { (*self.data).d_h as u16 }
+        self.data.d_h as u16
     }
 
-    #[inline]
-    fn clamp0(val: i32) -> i32 {
-        (-val >> 31) & val
+    pub fn get_bit_depth(&self) -> u32 {
+        self.data.bit_depth
     }
 
-    #[inline]
-    fn clamp255(val: i32) -> i32 {
-        (((255 - val) >> 31) | val) & 255
+    /// Short name of the pixel format, e.g. "I42016".
+    pub fn get_format_name(&self) -> String {
+        let name = format!("{:?}", self.data.fmt);
+        name.trim_start_matches("VPX_IMG_FMT_").to_string()
     }
 
-    // Branchless min/max should be faster than 2 ifs. See `YuvPixel` from
-    // libyuv for details.
-    #[inline]
-    fn clamp(val: i32) -> u32 {
-        Self::clamp255(Self::clamp0(val)) as u32
+    /// Only planar YUV formats are supported, decoders don't output the
+    /// packed ones anyway. Alpha plane of 444A is ignored.
+    pub fn check_format(&self) -> Result<(), Error> {
+        let fmt = self.data.fmt as isize;
+        let bit_depth = self.get_bit_depth();
+        let max_depth = if self.is_high_bitdepth() { 16 } else { 8 };
+        if fmt & VPX_IMG_FMT_PLANAR != 0 && bit_depth >= 8 && bit_depth <= max_depth {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFormat(format!("{} {}-bit",
+                                                 self.get_format_name(), bit_depth)))
+        }
     }
 
-    // TODO(Kagami): Use the colorspace image attribute. If it's unknown we may
-    // try mpv's heuristic: use BT.709 colormatrix for dimensions larger than
-    // 1279x719 (i.e. HD).
-    // TODO(Kagami): SIMD!
-    /// Convert YUV 8-bit pixel to RGBA8 (fully opacity) using BT.601 limited
-    /// range profile. Resulting value is 4 sequential bytes representing R, G,
-    /// B and A components, in that order.
-    #[inline]
-    fn yuv_to_rgba(y: u8, u: u8, v: u8) -> u32 {
-        let (c, d, e) = (y as i32 - 16, u as i32 - 128, v as i32 - 128);
-        let y1 = 298 * c + 128;
-        let r = Self::clamp((y1           + 409 * e) >> 8);
-        let g = Self::clamp((y1 - 100 * d - 208 * e) >> 8);
-        let b = Self::clamp((y1 + 516 * d          ) >> 8);
-        let a = 255;
-        // TODO(Kagami): Non-LE architectures.
-        a << 24 | b << 16 | g << 8 | r
+    /// Copy the image data. Only planar formats are supported, alpha plane
+    /// is not copied.
+    pub fn to_owned(&self) -> Result<OwnedImage, Error> {
+        try!(self.check_format());
+        let src = self.data;
+        unsafe {
+            let dst = vpx_img_alloc(ptr::null_mut(), src.fmt, src.d_w, src.d_h, 16);
+            if dst.is_null() {
+                return Err(Error::CodecError(vpx_codec_err_t::VPX_CODEC_MEM_ERROR));
+            }
+            (*dst).cs = src.cs;
+            (*dst).bit_depth = src.bit_depth;
+            let sample_size = if self.is_high_bitdepth() { 2 } else { 1 };
+            for plane in 0..3 {
+                let (width, height) = self.get_plane_size(plane);
+                for i in 0..height as isize {
+                    ptr::copy_nonoverlapping(
+                        src.planes[plane].offset(i * src.stride[plane] as isize),
+                        (*dst).planes[plane].offset(i * (*dst).stride[plane] as isize),
+                        width * sample_size);
+                }
+            }
+            Ok(OwnedImage {image: Image {data: &*dst}})
+        }
     }
 
-    /// Convert image pixels data to RGBA8 array.
-    pub fn get_rgba8(&self) -> Box<[u8]> {
-        unsafe {
-            let d = self.data;
-            // TODO(Kagami): Support other subsamplings and bit dephts.
-            assert_eq!((*d).fmt, vpx_img_fmt_t::VPX_IMG_FMT_I420);
-            assert_eq!((*d).bit_depth, 8);
-
-            let y_step = (*d).stride[0] as usize;
-            let u_step = (*d).stride[1] as usize;
-            let v_step = (*d).stride[2] as usize;
-            let mut y_offset = 0;
-            let mut u_offset = 0;
-            let mut v_offset = 0;
-            let w = (*d).d_w as usize;
-            let h = (*d).d_h as usize;
-            let len = w * h;
-            let mut pixels: Vec<u32> = Vec::with_capacity(len);
-            pixels.set_len(len);
-
-            for i in 0..h {
-                for j in 0..w {
-                    let y = *(*d).planes[0].offset((y_offset + j) as isize);
-                    let u = *(*d).planes[1].offset((u_offset + j / 2) as isize);
-                    let v = *(*d).planes[2].offset((v_offset + j / 2) as isize);
-                    *pixels.get_unchecked_mut(i * w + j) = Self::yuv_to_rgba(y, u, v);
+    /// Number of bytes taken by the planes.
+    pub fn get_memory_size(&self) -> usize {
+        (0..3).map(|plane| {
+            self.data.stride[plane] as usize * self.get_plane_size(plane).1
+        }).sum()
+    }
+
+    /// Horizontal and vertical chroma subsampling as power of 2.
+    pub fn get_chroma_shift(&self) -> (usize, usize) {
+        self.get_plane_shift(1)
+    }
+
+    /// Chroma subsampling of the plane.
+    fn get_plane_shift(&self, plane: usize) -> (usize, usize) {
+        if plane == 0 {
+            (0, 0)
+        } else {
+            (self.data.x_chroma_shift as usize, self.data.y_chroma_shift as usize)
+        }
+    }
+
+    /// Dimensions of the plane in samples.
+    fn get_plane_size(&self, plane: usize) -> (usize, usize) {
+        let (x_shift, y_shift) = self.get_plane_shift(plane);
+        let width = self.get_display_width() as usize;
+        let height = self.get_display_height() as usize;
+        ((width + (1 << x_shift) - 1) >> x_shift, (height + (1 << y_shift) - 1) >> y_shift)
+    }
+
+    /// Samples take 2 bytes.
+    fn is_high_bitdepth(&self) -> bool {
+        self.data.fmt as isize & VPX_IMG_FMT_HIGHBITDEPTH != 0
+    }
+
+    /// Samples of the plane (0 is Y, 1 is U and 2 is V). Sample type should
+    /// match the storage: u16 for high bit depth formats, u8 otherwise.
+    pub fn get_plane<T: Sample>(&self, plane: usize) -> Result<Plane<T>, Error> {
+        assert!(plane < 3);
+        try!(self.check_format());
+        let sample_size = if self.is_high_bitdepth() { 2 } else { 1 };
+        if mem::size_of::<T>() != sample_size {
+            return Err(Error::UnsupportedFormat(format!("{} as {}-byte samples",
+                                                        self.get_format_name(),
+                                                        mem::size_of::<T>())));
+        }
+        assert!(self.data.stride[plane] > 0);
+        let (width, height) = self.get_plane_size(plane);
+        let stride = self.data.stride[plane] as usize / sample_size;
+        let len = stride * (height - 1) + width;
+        // NOTE(Kagami): Planes are valid for the lifetime of the image data
+        // and have `height` rows of `stride` samples.
+        let data = unsafe { slice::from_raw_parts(self.data.planes[plane] as *const T, len) };
+        Ok(Plane {data: data, width: width, height: height, stride: stride})
+    }
+
+    /// Bytes of the plane, high bit depth samples take 2 bytes in native
+    /// byte order. Width and stride of the plane are in bytes too.
+    pub fn get_raw_plane(&self, plane: usize) -> Result<Plane<u8>, Error> {
+        assert!(plane < 3);
+        try!(self.check_format());
+        assert!(self.data.stride[plane] > 0);
+        let sample_size = if self.is_high_bitdepth() { 2 } else { 1 };
+        let (width, height) = self.get_plane_size(plane);
+        let stride = self.data.stride[plane] as usize;
+        let len = stride * (height - 1) + width * sample_size;
+        let data = unsafe { slice::from_raw_parts(self.data.planes[plane] as *const u8, len) };
+        Ok(Plane {data: data, width: width * sample_size, height: height, stride: stride})
+    }
+
+    /// Pass rows of the Y, U and V planes without padding to the callback,
+    /// one after another. High bit depth samples take 2 little-endian bytes,
+    /// the same as libvpx writes them.
+    pub fn for_each_le_row<F, E>(&self, mut f: F) -> Result<(), E>
+        where F: FnMut(&[u8]) -> Result<(), E>,
+              E: From<Error>
+    {
+        let swap = cfg!(target_endian = "big") && self.is_high_bitdepth();
+        let mut swapped = Vec::new();
+        for plane in 0..3 {
+            let samples = try!(self.get_raw_plane(plane));
+            for i in 0..samples.height {
+                let row = samples.row(i);
+                if !swap {
+                    try!(f(row));
+                    continue;
                 }
-                y_offset += y_step;
-                if i % 2 != 0 {
-                    u_offset += u_step;
-                    v_offset += v_step;
+                swapped.clear();
+                for sample in row.chunks(2) {
+                    swapped.push(sample[1]);
+                    swapped.push(sample[0]);
                 }
+                try!(f(&swapped));
             }
+        }
+        Ok(())
+    }
 
-            // Vec<u32> -> Box<[u8]>
-            let p = pixels.as_mut_ptr() as *mut u8;
-            mem::forget(pixels);
-            let pixels8: Vec<u8> = Vec::from_raw_parts(p, len * 4, len * 4);
-            pixels8.into_boxed_slice()
+    fn get_sample_as<T: Sample>(&self, plane: usize, x: usize, y: usize) -> Result<u16, Error> {
+        let samples = try!(self.get_plane::<T>(plane));
+        Ok(samples.row(y)[x].widen())
+    }
+
+    /// Sample of the plane (0 is Y, 1 is U and 2 is V) covering given luma
+    /// position.
+    pub fn get_sample(&self, plane: usize, x: u16, y: u16) -> Result<u16, Error> {
+        assert!(x < self.get_display_width() && y < self.get_display_height());
+        let (x_shift, y_shift) = self.get_plane_shift(plane);
+        let (x, y) = (x as usize >> x_shift, y as usize >> y_shift);
+        if self.is_high_bitdepth() {
+            self.get_sample_as::<u16>(plane, x, y)
+        } else {
+            self.get_sample_as::<u8>(plane, x, y)
         }
     }
+
+    /// Samples of the pixel at the given position.
+    pub fn get_pixel(&self, x: u16, y: u16, conv: &Converter) -> Result<Pixel, Error> {
+        let samples = [try!(self.get_sample(0, x, y)),
+                       try!(self.get_sample(1, x, y)),
+                       try!(self.get_sample(2, x, y))];
+        let rgba = conv.convert(samples[0], samples[1], samples[2]);
+        Ok(Pixel {
+            y: samples[0],
+            u: samples[1],
+            v: samples[2],
+            rgb: [rgba as u8, (rgba >> 8) as u8, (rgba >> 16) as u8],
+        })
+    }
+
+    /// Matrix specified by the stream, if any.
+    pub fn get_matrix(&self) -> Option<Matrix> {
+        match self.data.cs {
+            vpx_color_space_t::VPX_CS_BT_601 | vpx_color_space_t::VPX_CS_SMPTE_170 => {
+                Some(Matrix::Bt601)
+            },
+            vpx_color_space_t::VPX_CS_BT_709 => Some(Matrix::Bt709),
+            vpx_color_space_t::VPX_CS_SMPTE_240 => Some(Matrix::Smpte240),
+            vpx_color_space_t::VPX_CS_BT_2020 => Some(Matrix::Bt2020),
+            vpx_color_space_t::VPX_CS_SRGB => Some(Matrix::Identity),
+            _ => None,
+        }
+    }
+
+    /// Convert image pixels data to RGBA8 array.
+    pub fn get_rgba8(&self, conv: &Converter) -> Result<Box<[u8]>, Error> {
+        self.convert_image(conv, get_thread_count())
+    }
+
+    /// Convert HDR image to SDR RGBA8 array.
+    pub fn get_tone_mapped_rgba8(&self, conv: &Converter,
+                                 tone_mapper: &ToneMapper) -> Result<Box<[u8]>, Error> {
+        let tone_mapped = ToneMapped {conv: conv, tone_mapper: tone_mapper};
+        self.convert_image(&tone_mapped, get_thread_count())
+    }
+
+    fn convert_image<C: RowConvert>(&self, conv: &C,
+                                    threads: usize) -> Result<Box<[u8]>, Error> {
+        if self.is_high_bitdepth() {
+            self.convert_planes::<u16, C>(conv, threads)
+        } else {
+            self.convert_planes::<u8, C>(conv, threads)
+        }
+    }
+
+    /// Convert all rows, splitting them between threads in equal chunks.
+    fn convert_planes<T: Sample, C: RowConvert>(&self, conv: &C,
+                                                threads: usize) -> Result<Box<[u8]>, Error> {
+        let w = self.get_display_width() as usize;
+        let h = self.get_display_height() as usize;
+        let (y, u, v) = (try!(self.get_plane::<T>(0)),
+                         try!(self.get_plane::<T>(1)),
+                         try!(self.get_plane::<T>(2)));
+        let (x_shift, y_shift) = self.get_plane_shift(1);
+        let convert_rows = |first_row: usize, out: &mut [u32]| {
+            for (k, out_row) in out.chunks_mut(w).enumerate() {
+                let i = first_row + k;
+                conv.convert_row(y.row(i), u.row(i >> y_shift), v.row(i >> y_shift),
+                                 x_shift, out_row);
+            }
+        };
+        let mut pixels = vec![0; w * h];
+        let threads = cmp::max(threads, 1);
+        let chunk_rows = cmp::max((h + threads - 1) / threads, MIN_THREAD_ROWS);
+        if chunk_rows >= h {
+            convert_rows(0, &mut pixels);
+        } else {
+            let convert_rows = &convert_rows;
+            thread::scope(|scope| {
+                for (n, chunk) in pixels.chunks_mut(chunk_rows * w).enumerate() {
+                    scope.spawn(move || convert_rows(n * chunk_rows, chunk));
+                }
+            });
+        }
+        Ok(pixels_to_bytes(pixels))
+    }
+
+    /// Render image in the given mode to RGBA8 array. Colors are tone
+    /// mapped if mapper is given.
+    pub fn render(&self, mode: DisplayMode, conv: &Converter,
+                  tone_mapper: Option<&ToneMapper>) -> Result<Box<[u8]>, Error> {
+        match (mode, tone_mapper) {
+            (DisplayMode::Color, Some(tone_mapper)) => {
+                self.get_tone_mapped_rgba8(conv, tone_mapper)
+            },
+            (DisplayMode::Color, None) => self.get_rgba8(conv),
+            (DisplayMode::Y, _) => self.get_plane_rgba8(0, true),
+            (DisplayMode::U, _) => self.get_plane_rgba8(1, true),
+            (DisplayMode::V, _) => self.get_plane_rgba8(2, true),
+            (DisplayMode::UNative, _) => self.get_plane_rgba8(1, false),
+            (DisplayMode::VNative, _) => self.get_plane_rgba8(2, false),
+            (DisplayMode::FalseColor, _) => self.get_false_color_rgba8(conv),
+        }
+    }
+
+    /// Show single plane as grayscale. Chroma planes are either upscaled
+    /// to the frame size or put at the top-left corner at their native
+    /// size, the rest is black.
+    pub fn get_plane_rgba8(&self, plane: usize, upscale: bool) -> Result<Box<[u8]>, Error> {
+        if self.is_high_bitdepth() {
+            self.plane_to_rgba8::<u16>(plane, upscale)
+        } else {
+            self.plane_to_rgba8::<u8>(plane, upscale)
+        }
+    }
+
+    fn plane_to_rgba8<T: Sample>(&self, plane: usize,
+                                 upscale: bool) -> Result<Box<[u8]>, Error> {
+        let w = self.get_display_width() as usize;
+        let samples = try!(self.get_plane::<T>(plane));
+        let (x_shift, y_shift, rows, cols) = if upscale {
+            let (x_shift, y_shift) = self.get_plane_shift(plane);
+            (x_shift, y_shift, self.get_display_height() as usize, w)
+        } else {
+            (0, 0, samples.height, samples.width)
+        };
+        let shift = self.get_bit_depth().saturating_sub(8);
+        let black = colorspace::pack_rgba(0, 0, 0);
+        let mut pixels = vec![black; w * self.get_display_height() as usize];
+        for i in 0..rows {
+            let row = samples.row(i >> y_shift);
+            for j in 0..cols {
+                let gray = (row[j >> x_shift].widen() >> shift) as u32 & 255;
+                pixels[i * w + j] = colorspace::pack_rgba(gray, gray, gray);
+            }
+        }
+        Ok(pixels_to_bytes(pixels))
+    }
+
+    /// Show only chroma by converting with the constant mid-gray luma.
+    pub fn get_false_color_rgba8(&self, conv: &Converter) -> Result<Box<[u8]>, Error> {
+        if self.is_high_bitdepth() {
+            self.false_color_to_rgba8::<u16>(conv)
+        } else {
+            self.false_color_to_rgba8::<u8>(conv)
+        }
+    }
+
+    fn false_color_to_rgba8<T: Sample>(&self, conv: &Converter) -> Result<Box<[u8]>, Error> {
+        let w = self.get_display_width() as usize;
+        let h = self.get_display_height() as usize;
+        let (u, v) = (try!(self.get_plane::<T>(1)), try!(self.get_plane::<T>(2)));
+        let (x_shift, y_shift) = self.get_plane_shift(1);
+        let gray = 1 << (self.get_bit_depth() - 1);
+        let mut pixels = Vec::with_capacity(w * h);
+        for i in 0..h {
+            let (u_row, v_row) = (u.row(i >> y_shift), v.row(i >> y_shift));
+            for j in 0..w {
+                pixels.push(conv.convert(gray, u_row[j >> x_shift].widen(),
+                                         v_row[j >> x_shift].widen()));
+            }
+        }
+        Ok(pixels_to_bytes(pixels))
+    }
 }
 
-impl fmt::Debug for Image {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        unsafe {
-            fmt::Debug::fmt(&*self.data, f)
+/// What part of the image to show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+    Color,
+    Y,
+    /// Chroma planes upscaled to the frame size.
+    U,
+    V,
+    /// Chroma planes at their own resolution.
+    UNative,
+    VNative,
+    /// Chroma with the constant luma.
+    FalseColor,
+}
+
+impl DisplayMode {
+    /// Next mode in the cycle.
+    pub fn next(&self) -> DisplayMode {
+        match *self {
+            DisplayMode::Color => DisplayMode::Y,
+            DisplayMode::Y => DisplayMode::U,
+            DisplayMode::U => DisplayMode::V,
+            DisplayMode::V => DisplayMode::UNative,
+            DisplayMode::UNative => DisplayMode::VNative,
+            DisplayMode::VNative => DisplayMode::FalseColor,
+            DisplayMode::FalseColor => DisplayMode::Color,
         }
     }
 }
 
-impl Drop for Image {
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descr = match *self {
+            DisplayMode::Color => "color",
+            DisplayMode::Y => "Y plane",
+            DisplayMode::U => "U plane (upscaled)",
+            DisplayMode::V => "V plane (upscaled)",
+            DisplayMode::UNative => "U plane (native size)",
+            DisplayMode::VNative => "V plane (native size)",
+            DisplayMode::FalseColor => "chroma false-color",
+        };
+        f.write_str(descr)
+    }
+}
+
+/// Conversion of the image rows to RGBA8.
+trait RowConvert: Sync {
+    fn convert_row<T: Sample>(&self, y: &[T], u: &[T], v: &[T], x_shift: usize,
+                              out: &mut [u32]);
+}
+
+impl RowConvert for Converter {
+    fn convert_row<T: Sample>(&self, y: &[T], u: &[T], v: &[T], x_shift: usize,
+                              out: &mut [u32]) {
+        Converter::convert_row(self, y, u, v, x_shift, out)
+    }
+}
+
+struct ToneMapped<'a> {
+    conv: &'a Converter,
+    tone_mapper: &'a ToneMapper,
+}
+
+impl<'a> RowConvert for ToneMapped<'a> {
+    fn convert_row<T: Sample>(&self, y: &[T], u: &[T], v: &[T], x_shift: usize,
+                              out: &mut [u32]) {
+        for j in 0..out.len() {
+            let rgb = self.conv.convert_f32(y[j].widen(),
+                                            u[j >> x_shift].widen(),
+                                            v[j >> x_shift].widen());
+            out[j] = self.tone_mapper.convert(rgb);
+        }
+    }
+}
+
+/// Number of threads to convert images with.
+fn get_thread_count() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Vec<u32> -> Box<[u8]>, pixel's lowest byte goes first.
+fn pixels_to_bytes(pixels: Vec<u32>) -> Box<[u8]> {
+    let mut bytes = vec![0; pixels.len() * 4];
+    for (chunk, pixel) in bytes.chunks_mut(4).zip(pixels) {
+        chunk.copy_from_slice(&pixel.to_le_bytes());
+    }
+    bytes.into_boxed_slice()
+}
+
+impl Deref for OwnedImage {
+    type Target = Image<'static>;
+
+    fn deref(&self) -> &Image<'static> {
+        &self.image
+    }
+}
+
+impl Drop for OwnedImage {
     fn drop(&mut self) {
         unsafe {
-            vpx_img_free(self.data);
+            vpx_img_free(self.image.data as *const vpx_image_t as *mut vpx_image_t);
         }
     }
 }
 
+// NOTE(Kagami): Owned image doesn't share data with decoder and is never
+// mutated so it's safe to pass it between threads.
+unsafe impl Send for OwnedImage {}
+unsafe impl Sync for OwnedImage {}
+
+impl<'d> fmt::Debug for Image<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt::Debug::fmt(self.data, f)
+    }
+}
+
 // FFI mappings.
 
 const VPX_IMAGE_ABI_VERSION: c_int = 3;
@@ -224,7 +684,7 @@ const VPX_CODEC_CTX_SIZE: usize = 56;
 #[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 #[repr(C)]
-enum vpx_codec_err_t {
+pub enum vpx_codec_err_t {
     VPX_CODEC_OK,
     VPX_CODEC_ERROR,
     VPX_CODEC_MEM_ERROR,
@@ -252,11 +712,23 @@ struct vpx_codec_iface_t;
 
 #[derive(Debug)]
 #[repr(C)]
-struct vpx_codec_dec_cfg_t;
+struct vpx_codec_dec_cfg_t {
+    threads: c_uint,
+    w: c_uint,
+    h: c_uint,
+}
 
 #[allow(non_camel_case_types)]
 type vpx_codec_flags_t = c_long;
 
+#[repr(C)]
+struct vpx_codec_stream_info_t {
+    sz: c_uint,
+    w: c_uint,
+    h: c_uint,
+    is_kf: c_uint,
+}
+
 #[allow(non_camel_case_types)]
 type vpx_codec_iter_t = *mut c_void;
 
@@ -270,7 +742,7 @@ const VPX_IMG_FMT_I444: isize = VPX_IMG_FMT_PLANAR | 6;
 const VPX_IMG_FMT_I440: isize = VPX_IMG_FMT_PLANAR | 7;
 
 #[allow(dead_code)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[repr(C)]
 enum vpx_img_fmt_t {
     VPX_IMG_FMT_NONE,
@@ -302,7 +774,7 @@ enum vpx_img_fmt_t {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[repr(C)]
 enum vpx_color_space_t {
     VPX_CS_UNKNOWN = 0,
@@ -364,6 +836,7 @@ impl fmt::Debug for vpx_image_t {
 
 #[link(name = "vpx")]
 extern {
+    static mut vpx_codec_vp8_dx_algo: vpx_codec_iface_t;
     static mut vpx_codec_vp9_dx_algo: vpx_codec_iface_t;
 
     fn vpx_codec_dec_init_ver(
@@ -373,6 +846,12 @@ extern {
         flags: vpx_codec_flags_t,
         ver: c_int) -> vpx_codec_err_t;
 
+    fn vpx_codec_peek_stream_info(
+        iface: *mut vpx_codec_iface_t,
+        data: *const u8,
+        data_sz: c_uint,
+        si: *mut vpx_codec_stream_info_t) -> vpx_codec_err_t;
+
     fn vpx_codec_decode(
         ctx: *mut vpx_codec_ctx_t,
         data: *const u8,
@@ -384,6 +863,13 @@ extern {
         ctx: *mut vpx_codec_ctx_t,
         iter: *mut vpx_codec_iter_t) -> *mut vpx_image_t;
 
+    fn vpx_img_alloc(
+        img: *mut vpx_image_t,
+        fmt: vpx_img_fmt_t,
+        d_w: c_uint,
+        d_h: c_uint,
+        align: c_uint) -> *mut vpx_image_t;
+
     fn vpx_img_free(img: *mut vpx_image_t);
 
     fn vpx_codec_destroy(ctx: *mut vpx_codec_ctx_t) -> vpx_codec_err_t;
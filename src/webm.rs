@@ -0,0 +1,1119 @@
+//! WebM (i.e. restricted Matroska) container parser.
+//! Reference: <http://www.webmproject.org/docs/container/>,
+//! <http://matroska.org/technical/specs/index.html>.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use ::common;
+use ::demux::{Demuxer, FrameEntry, Source};
+
+/// EBML header ID, every WebM file starts with it.
+pub const SIGNATURE: [u8; 4] = [0x1a, 0x45, 0xdf, 0xa3];
+
+const EBML_ID: u32 = 0x1a45dfa3;
+const DOC_TYPE_ID: u32 = 0x4282;
+const SEGMENT_ID: u32 = 0x18538067;
+const SEEK_HEAD_ID: u32 = 0x114d9b74;
+const SEEK_ID: u32 = 0x4dbb;
+const SEEK_ID_ID: u32 = 0x53ab;
+const SEEK_POSITION_ID: u32 = 0x53ac;
+const INFO_ID: u32 = 0x1549a966;
+const TIMECODE_SCALE_ID: u32 = 0x2ad7b1;
+const TRACKS_ID: u32 = 0x1654ae6b;
+const TRACK_ENTRY_ID: u32 = 0xae;
+const TRACK_NUMBER_ID: u32 = 0xd7;
+const TRACK_TYPE_ID: u32 = 0x83;
+const CODEC_ID_ID: u32 = 0x86;
+const VIDEO_ID: u32 = 0xe0;
+const PIXEL_WIDTH_ID: u32 = 0xb0;
+const PIXEL_HEIGHT_ID: u32 = 0xba;
+const COLOUR_ID: u32 = 0x55b0;
+const TRANSFER_CHARACTERISTICS_ID: u32 = 0x55ba;
+const CLUSTER_ID: u32 = 0x1f43b675;
+const TIMECODE_ID: u32 = 0xe7;
+const SIMPLE_BLOCK_ID: u32 = 0xa3;
+const BLOCK_GROUP_ID: u32 = 0xa0;
+const BLOCK_ID: u32 = 0xa1;
+const REFERENCE_BLOCK_ID: u32 = 0xfb;
+const CUES_ID: u32 = 0x1c53bb6b;
+const CUE_POINT_ID: u32 = 0xbb;
+const CUE_TIME_ID: u32 = 0xb3;
+const CUE_TRACK_POSITIONS_ID: u32 = 0xb7;
+const CUE_TRACK_ID: u32 = 0xf7;
+const CUE_CLUSTER_POSITION_ID: u32 = 0xf1;
+const TAGS_ID: u32 = 0x1254c367;
+const CHAPTERS_ID: u32 = 0x1043a770;
+const ATTACHMENTS_ID: u32 = 0x1941a469;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const DEFAULT_TIMECODE_SCALE: u64 = 1000000;
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(io::Error),
+    // TODO(Kagami): Better granularity of parse errors.
+    ParseError,
+    NoVideoTrack,
+    UnsupportedCodec(String),
+    FrameOutOfRange(usize),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::IoError(err) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descr = match *self {
+            Error::IoError(ref err) => format!("IO error: {}", err),
+            Error::ParseError => format!("Parse error"),
+            Error::NoVideoTrack => format!("No video track in file"),
+            Error::UnsupportedCodec(ref codec_id) => format!("Unsupported codec {}", codec_id),
+            Error::FrameOutOfRange(pos) => format!("No frame #{} in file", pos),
+        };
+        f.write_str(&descr)
+    }
+}
+
+/// Header of the EBML element.
+struct Element {
+    id: u32,
+    /// Offset of the element header.
+    start: u64,
+    /// Offset of the element data.
+    offset: u64,
+    /// Size of the element data, `None` for elements of unknown size.
+    size: Option<u64>,
+}
+
+impl Element {
+    fn end(&self) -> Result<u64, Error> {
+        self.size.and_then(|size| self.offset.checked_add(size)).ok_or(Error::ParseError)
+    }
+}
+
+/// Buffered reader which keeps track of the current file position.
+struct EbmlReader<R> {
+    breader: R,
+    pos: u64,
+    file_size: u64,
+}
+
+impl<R: Read + Seek> EbmlReader<R> {
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut buf = [0];
+        try!(self.read_exact(&mut buf));
+        Ok(buf[0])
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let mut collected = 0;
+        while collected < buf.len() {
+            let chunk_size = try!(self.breader.read(&mut buf[collected..]));
+            if chunk_size == 0 {
+                return Err(Error::ParseError);
+            }
+            collected += chunk_size;
+        }
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<Box<[u8]>, Error> {
+        let mut buf = common::alloc(count);
+        try!(self.read_exact(&mut buf));
+        Ok(buf)
+    }
+
+    fn seek(&mut self, pos: u64) -> Result<(), Error> {
+        if pos != self.pos {
+            try!(self.breader.seek(SeekFrom::Start(pos)));
+            self.pos = pos;
+        }
+        Ok(())
+    }
+
+    /// Read variable size integer. Return raw value (i.e. with length marker
+    /// included) and its length in bytes.
+    fn read_vint(&mut self) -> Result<(u64, usize), Error> {
+        let first = try!(self.read_byte());
+        if first == 0 {
+            return Err(Error::ParseError);
+        }
+        let len = first.leading_zeros() as usize + 1;
+        let mut val = first as u64;
+        for _ in 1..len {
+            val = val << 8 | try!(self.read_byte()) as u64;
+        }
+        Ok((val, len))
+    }
+
+    /// Read variable size integer with length marker stripped.
+    fn read_vint_value(&mut self) -> Result<(u64, usize), Error> {
+        let (val, len) = try!(self.read_vint());
+        Ok((val & ((1 << (7 * len)) - 1), len))
+    }
+
+    fn read_header(&mut self) -> Result<Element, Error> {
+        let start = self.pos;
+        let (id, id_len) = try!(self.read_vint());
+        if id_len > 4 {
+            return Err(Error::ParseError);
+        }
+        let (size, size_len) = try!(self.read_vint_value());
+        // All ones means unknown size.
+        let unknown = size == (1 << (7 * size_len)) - 1;
+        Ok(Element {
+            id: id as u32,
+            start: start,
+            offset: self.pos,
+            size: if unknown { None } else { Some(size) },
+        })
+    }
+
+    fn read_uint(&mut self, el: &Element) -> Result<u64, Error> {
+        let size = try!(el.size.ok_or(Error::ParseError));
+        if size > 8 {
+            return Err(Error::ParseError);
+        }
+        let mut val = 0;
+        for _ in 0..size {
+            val = val << 8 | try!(self.read_byte()) as u64;
+        }
+        Ok(val)
+    }
+
+    fn read_string(&mut self, el: &Element) -> Result<String, Error> {
+        let size = try!(el.size.ok_or(Error::ParseError));
+        if size > 1024 {
+            return Err(Error::ParseError);
+        }
+        let data = try!(self.read_bytes(size as usize));
+        // Strings may be zero-padded.
+        let data: Vec<u8> = data.iter().cloned().take_while(|&c| c != 0).collect();
+        String::from_utf8(data).map_err(|_| Error::ParseError)
+    }
+}
+
+struct Track {
+    number: u64,
+    kind: u64,
+    codec_id: String,
+    width: u64,
+    height: u64,
+    transfer: Option<u64>,
+}
+
+struct CuePoint {
+    time: u64,
+    track: u64,
+    /// Cluster offset relative to the segment data.
+    cluster_pos: u64,
+}
+
+/// Frame of any track, as found in cluster.
+struct RawFrame {
+    track: u64,
+    /// Offset of the cluster this frame belongs to.
+    cluster: u64,
+    pts: i64,
+    keyframe: Option<bool>,
+    offset: u64,
+    size: u32,
+}
+
+fn is_level1(id: u32) -> bool {
+    match id {
+        SEEK_HEAD_ID | INFO_ID | TRACKS_ID | CLUSTER_ID | CUES_ID |
+        TAGS_ID | CHAPTERS_ID | ATTACHMENTS_ID | EBML_ID | SEGMENT_ID => true,
+        _ => false,
+    }
+}
+
+/// Positions of the level 1 elements relative to the segment data, by ID.
+fn parse_seek_head<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                   seek_head: &Element) -> Result<Vec<(u32, u64)>, Error> {
+    let end = try!(seek_head.end());
+    let mut entries = Vec::new();
+    while r.pos < end {
+        let el = try!(r.read_header());
+        if el.id == SEEK_ID {
+            let seek_end = try!(el.end());
+            let (mut id, mut pos) = (None, None);
+            while r.pos < seek_end {
+                let child = try!(r.read_header());
+                match child.id {
+                    SEEK_ID_ID => {
+                        let size = try!(child.size.ok_or(Error::ParseError));
+                        if size > 4 {
+                            return Err(Error::ParseError);
+                        }
+                        // Element ID is stored as is, with the length marker.
+                        let data = try!(r.read_bytes(size as usize));
+                        id = Some(data.iter().fold(0, |acc, &b| acc << 8 | b as u32));
+                    },
+                    SEEK_POSITION_ID => pos = Some(try!(r.read_uint(&child))),
+                    _ => {},
+                }
+                try!(r.seek(try!(child.end())));
+            }
+            if let (Some(id), Some(pos)) = (id, pos) {
+                entries.push((id, pos));
+            }
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    Ok(entries)
+}
+
+/// Read Cues element at the given file offset and return to the current
+/// position. Broken or missing Cues are ignored since they are optional.
+fn read_cues_at<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                offset: u64) -> Result<Vec<CuePoint>, Error> {
+    let pos = r.pos;
+    let cues = match r.seek(offset).and_then(|_| r.read_header()) {
+        Ok(ref el) if el.id == CUES_ID => parse_cues(r, el).unwrap_or_else(|_| Vec::new()),
+        _ => Vec::new(),
+    };
+    try!(r.seek(pos));
+    Ok(cues)
+}
+
+fn parse_info<R: Read + Seek>(r: &mut EbmlReader<R>, info: &Element) -> Result<u64, Error> {
+    let end = try!(info.end());
+    let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+    while r.pos < end {
+        let el = try!(r.read_header());
+        if el.id == TIMECODE_SCALE_ID {
+            timecode_scale = try!(r.read_uint(&el));
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    if timecode_scale == 0 {
+        return Err(Error::ParseError);
+    }
+    Ok(timecode_scale)
+}
+
+fn parse_video<R: Read + Seek>(r: &mut EbmlReader<R>,
+                               video: &Element,
+                               track: &mut Track) -> Result<(), Error> {
+    let end = try!(video.end());
+    while r.pos < end {
+        let el = try!(r.read_header());
+        match el.id {
+            PIXEL_WIDTH_ID => track.width = try!(r.read_uint(&el)),
+            PIXEL_HEIGHT_ID => track.height = try!(r.read_uint(&el)),
+            COLOUR_ID => try!(parse_colour(r, &el, track)),
+            _ => {},
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    Ok(())
+}
+
+fn parse_colour<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                colour: &Element,
+                                track: &mut Track) -> Result<(), Error> {
+    let end = try!(colour.end());
+    while r.pos < end {
+        let el = try!(r.read_header());
+        if el.id == TRANSFER_CHARACTERISTICS_ID {
+            track.transfer = Some(try!(r.read_uint(&el)));
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    Ok(())
+}
+
+fn parse_track_entry<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                     entry: &Element) -> Result<Track, Error> {
+    let end = try!(entry.end());
+    let mut track = Track {
+        number: 0,
+        kind: 0,
+        codec_id: String::new(),
+        width: 0,
+        height: 0,
+        transfer: None,
+    };
+    while r.pos < end {
+        let el = try!(r.read_header());
+        match el.id {
+            TRACK_NUMBER_ID => track.number = try!(r.read_uint(&el)),
+            TRACK_TYPE_ID => track.kind = try!(r.read_uint(&el)),
+            CODEC_ID_ID => track.codec_id = try!(r.read_string(&el)),
+            VIDEO_ID => try!(parse_video(r, &el, &mut track)),
+            _ => {},
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    Ok(track)
+}
+
+fn parse_tracks<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                tracks: &Element) -> Result<Vec<Track>, Error> {
+    let end = try!(tracks.end());
+    let mut entries = Vec::new();
+    while r.pos < end {
+        let el = try!(r.read_header());
+        if el.id == TRACK_ENTRY_ID {
+            entries.push(try!(parse_track_entry(r, &el)));
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    Ok(entries)
+}
+
+fn parse_cue_point<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                   point: &Element,
+                                   cues: &mut Vec<CuePoint>) -> Result<(), Error> {
+    let end = try!(point.end());
+    let mut time = 0;
+    let mut positions = Vec::new();
+    while r.pos < end {
+        let el = try!(r.read_header());
+        match el.id {
+            CUE_TIME_ID => time = try!(r.read_uint(&el)),
+            CUE_TRACK_POSITIONS_ID => {
+                let pos_end = try!(el.end());
+                let (mut track, mut cluster_pos) = (0, 0);
+                while r.pos < pos_end {
+                    let child = try!(r.read_header());
+                    match child.id {
+                        CUE_TRACK_ID => track = try!(r.read_uint(&child)),
+                        CUE_CLUSTER_POSITION_ID => cluster_pos = try!(r.read_uint(&child)),
+                        _ => {},
+                    }
+                    try!(r.seek(try!(child.end())));
+                }
+                positions.push((track, cluster_pos));
+            },
+            _ => {},
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    // CueTime may follow track positions in theory.
+    for (track, cluster_pos) in positions {
+        cues.push(CuePoint {time: time, track: track, cluster_pos: cluster_pos});
+    }
+    Ok(())
+}
+
+fn parse_cues<R: Read + Seek>(r: &mut EbmlReader<R>,
+                              cues_el: &Element) -> Result<Vec<CuePoint>, Error> {
+    let end = try!(cues_el.end());
+    let mut cues = Vec::new();
+    while r.pos < end {
+        let el = try!(r.read_header());
+        if el.id == CUE_POINT_ID {
+            try!(parse_cue_point(r, &el, &mut cues));
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    Ok(cues)
+}
+
+/// Sum of the sizes or offsets read from file, these can be arbitrarily large.
+fn checked_add(a: u64, b: u64) -> Result<u64, Error> {
+    a.checked_add(b).ok_or(Error::ParseError)
+}
+
+/// Parse Block or SimpleBlock header and collect frames it consists of.
+fn parse_block<R: Read + Seek>(r: &mut EbmlReader<R>,
+                               block: &Element,
+                               cluster: &Element,
+                               cluster_timecode: i64,
+                               frames: &mut Vec<RawFrame>) -> Result<(), Error> {
+    let end = try!(block.end());
+    if end > r.file_size {
+        // Truncated block at the end of file, skip it.
+        return Ok(());
+    }
+    let (track, _) = try!(r.read_vint_value());
+    let timecode = try!(r.read_bytes(2));
+    let timecode = ((timecode[0] as u16) << 8 | timecode[1] as u16) as i16 as i64;
+    let flags = try!(r.read_byte());
+    // Keyframe flag is defined only for SimpleBlock.
+    let keyframe = if block.id == SIMPLE_BLOCK_ID {
+        Some(flags & 0x80 != 0)
+    } else {
+        None
+    };
+    let lacing = (flags >> 1) & 3;
+    let mut sizes = Vec::new();
+    if lacing != 0 {
+        let count = try!(r.read_byte()) as usize + 1;
+        match lacing {
+            // Xiph lacing.
+            1 => for _ in 0..count - 1 {
+                let mut size = 0;
+                loop {
+                    let b = try!(r.read_byte());
+                    size = try!(checked_add(size, b as u64));
+                    if b != 255 {
+                        break;
+                    }
+                }
+                sizes.push(size);
+            },
+            // Fixed-size lacing.
+            2 => {
+                let total = end.saturating_sub(r.pos);
+                if total % count as u64 != 0 {
+                    return Err(Error::ParseError);
+                }
+                for _ in 0..count - 1 {
+                    sizes.push(total / count as u64);
+                }
+            },
+            // EBML lacing.
+            _ => if count > 1 {
+                let (first, _) = try!(r.read_vint_value());
+                let mut size = first as i64;
+                sizes.push(first);
+                for _ in 1..count - 1 {
+                    // Signed difference with the previous size, biased to
+                    // be stored as unsigned.
+                    let (val, len) = try!(r.read_vint_value());
+                    let diff = val as i64 - ((1 << (7 * len - 1)) - 1);
+                    size = try!(size.checked_add(diff).ok_or(Error::ParseError));
+                    if size < 0 {
+                        return Err(Error::ParseError);
+                    }
+                    sizes.push(size as u64);
+                }
+            },
+        }
+    }
+    let mut used = 0;
+    for &size in &sizes {
+        used = try!(checked_add(used, size));
+    }
+    let data_end = try!(checked_add(r.pos, used));
+    if data_end > end {
+        return Err(Error::ParseError);
+    }
+    sizes.push(end - data_end);
+    let pts = try!(cluster_timecode.checked_add(timecode).ok_or(Error::ParseError));
+    let mut offset = r.pos;
+    for (i, &size) in sizes.iter().enumerate() {
+        if size > u32::max_value() as u64 {
+            return Err(Error::ParseError);
+        }
+        frames.push(RawFrame {
+            track: track,
+            cluster: cluster.start,
+            pts: pts,
+            // Flags describe the whole block, i.e. the first frame.
+            keyframe: if i == 0 { keyframe } else { None },
+            offset: offset,
+            size: size as u32,
+        });
+        offset = try!(checked_add(offset, size));
+    }
+    Ok(())
+}
+
+fn parse_block_group<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                     group: &Element,
+                                     cluster: &Element,
+                                     cluster_timecode: i64,
+                                     frames: &mut Vec<RawFrame>) -> Result<(), Error> {
+    let end = try!(group.end());
+    let first = frames.len();
+    let mut has_reference = false;
+    let mut has_block = false;
+    while r.pos < end {
+        let el = try!(r.read_header());
+        match el.id {
+            BLOCK_ID if !has_block => {
+                has_block = true;
+                try!(parse_block(r, &el, cluster, cluster_timecode, frames));
+            },
+            REFERENCE_BLOCK_ID => has_reference = true,
+            _ => {},
+        }
+        try!(r.seek(try!(el.end())));
+    }
+    // Block without references to other blocks is a keyframe.
+    if let Some(frame) = frames.get_mut(first) {
+        frame.keyframe = Some(!has_reference);
+    }
+    Ok(())
+}
+
+/// Parse cluster and leave reader at the start of the next element. Cluster
+/// may have unknown size (e.g. in live streams), in that case it lasts until
+/// the next top-level element.
+fn parse_cluster<R: Read + Seek>(r: &mut EbmlReader<R>,
+                                 cluster: &Element,
+                                 segment_end: u64,
+                                 frames: &mut Vec<RawFrame>) -> Result<(), Error> {
+    let end = match cluster.size {
+        Some(size) => cluster.offset + size,
+        None => segment_end,
+    };
+    let mut timecode = 0;
+    while r.pos < end {
+        let el = match r.read_header() {
+            Ok(el) => el,
+            // Truncated file.
+            Err(_) => return r.seek(end),
+        };
+        if cluster.size.is_none() && is_level1(el.id) {
+            return r.seek(el.start);
+        }
+        match el.id {
+            TIMECODE_ID => timecode = try!(r.read_uint(&el)) as i64,
+            SIMPLE_BLOCK_ID => try!(parse_block(r, &el, cluster, timecode, frames)),
+            BLOCK_GROUP_ID => try!(parse_block_group(r, &el, cluster, timecode, frames)),
+            _ => {},
+        }
+        match el.size {
+            Some(size) => try!(r.seek(el.offset + size)),
+            None => return Err(Error::ParseError),
+        }
+    }
+    r.seek(end)
+}
+
+/// State of the lazy frame indexing. Clusters are parsed in file order when
+/// frames past the indexed ones are requested, so the file isn't read through
+/// at open.
+struct Index {
+    /// Number of the selected video track.
+    track: u64,
+    segment_offset: u64,
+    segment_end: u64,
+    /// Offset of the next top-level element to parse, `None` once all frames
+    /// are indexed.
+    next: Option<u64>,
+    has_cues: bool,
+    /// Cluster offsets from the Cues, to resync on broken cluster.
+    cluster_offsets: Vec<u64>,
+    /// Cluster offset and timecode of the frames cue points refer to.
+    cue_frames: HashSet<(u64, i64)>,
+    /// Positions of the indexed frames by cluster offset and timecode, to
+    /// mark them when Cues follow the clusters.
+    positions: HashMap<(u64, i64), usize>,
+}
+
+pub struct Reader {
+    r: EbmlReader<io::BufReader<Box<Source>>>,
+    filename: String,
+    fourcc: u32,
+    width: u16,
+    height: u16,
+    timebase: (u32, u32),
+    transfer: Option<u64>,
+    /// Frames of the selected video track indexed so far.
+    frames: Vec<FrameEntry>,
+    index: Index,
+}
+
+/// Matroska timecodes are in TimecodeScale nanoseconds units. Scale which
+/// doesn't fit the timebase is rejected, it's more than 4 seconds per tick
+/// anyway.
+fn get_timebase(timecode_scale: u64) -> Result<(u32, u32), Error> {
+    let den = 1000000000;
+    let div = common::gcd(timecode_scale, den);
+    let num = timecode_scale / div;
+    if num > u32::max_value() as u64 {
+        return Err(Error::ParseError);
+    }
+    Ok((num as u32, (den / div) as u32))
+}
+
+/// Pick the first VPx video track.
+fn select_track(tracks: &[Track]) -> Result<(&Track, u32), Error> {
+    let mut unsupported = None;
+    for track in tracks.iter().filter(|t| t.kind == TRACK_TYPE_VIDEO) {
+        match &track.codec_id[..] {
+            "V_VP8" => return Ok((track, common::VP8_FOURCC)),
+            "V_VP9" => return Ok((track, common::VP9_FOURCC)),
+            _ => if unsupported.is_none() {
+                unsupported = Some(track.codec_id.clone());
+            },
+        }
+    }
+    Err(match unsupported {
+        Some(codec_id) => Error::UnsupportedCodec(codec_id),
+        None => Error::NoVideoTrack,
+    })
+}
+
+impl Reader {
+    pub fn open(filename: &str) -> Result<Reader, Error> {
+        let fh = try!(File::open(&filename));
+        let file_size = try!(fh.metadata()).len();
+        Reader::from_source(filename, Box::new(fh), file_size)
+    }
+
+    /// Read the headers and index the frames of the first cluster.
+    fn from_source(filename: &str, source: Box<Source>,
+                   file_size: u64) -> Result<Reader, Error> {
+        let mut r = EbmlReader {
+            breader: io::BufReader::new(source),
+            pos: 0,
+            file_size: file_size,
+        };
+
+        let ebml = try!(r.read_header());
+        if ebml.id != EBML_ID {
+            return Err(Error::ParseError);
+        }
+        let ebml_end = try!(ebml.end());
+        while r.pos < ebml_end {
+            let el = try!(r.read_header());
+            if el.id == DOC_TYPE_ID {
+                let doc_type = try!(r.read_string(&el));
+                if doc_type != "webm" && doc_type != "matroska" {
+                    return Err(Error::ParseError);
+                }
+            }
+            try!(r.seek(try!(el.end())));
+        }
+
+        let segment = try!(r.read_header());
+        if segment.id != SEGMENT_ID {
+            return Err(Error::ParseError);
+        }
+        let segment_end = match segment.size {
+            Some(size) if size < file_size - segment.offset => segment.offset + size,
+            _ => file_size,
+        };
+        let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+        let mut tracks = Vec::new();
+        let mut cues = Vec::new();
+        let mut first_cluster = None;
+        while r.pos < segment_end {
+            let el = match r.read_header() {
+                Ok(el) => el,
+                // Truncated file, use what we already have.
+                Err(_) => break,
+            };
+            match el.id {
+                INFO_ID => timecode_scale = try!(parse_info(&mut r, &el)),
+                TRACKS_ID => tracks = try!(parse_tracks(&mut r, &el)),
+                // Cues usually follow the clusters, read them in advance.
+                SEEK_HEAD_ID if cues.is_empty() => {
+                    let entries = parse_seek_head(&mut r, &el).unwrap_or_else(|_| Vec::new());
+                    if let Some(&(_, pos)) = entries.iter().find(|entry| entry.0 == CUES_ID) {
+                        if let Some(offset) = segment.offset.checked_add(pos) {
+                            cues = try!(read_cues_at(&mut r, offset));
+                        }
+                    }
+                },
+                CUES_ID if cues.is_empty() => {
+                    cues = parse_cues(&mut r, &el).unwrap_or_else(|_| Vec::new());
+                },
+                // Clusters are indexed on demand.
+                CLUSTER_ID => {
+                    first_cluster = Some(el.start);
+                    break;
+                },
+                _ => {},
+            }
+            match el.size {
+                Some(size) => try!(r.seek(el.offset + size)),
+                None => return Err(Error::ParseError),
+            }
+        }
+
+        let (track, fourcc) = try!(select_track(&tracks));
+        if track.width == 0 || track.width > 0xffff ||
+           track.height == 0 || track.height > 0xffff {
+            return Err(Error::ParseError);
+        }
+        let mut reader = Reader {
+            r: r,
+            filename: filename.to_string(),
+            fourcc: fourcc,
+            width: track.width as u16,
+            height: track.height as u16,
+            timebase: try!(get_timebase(timecode_scale)),
+            transfer: track.transfer,
+            frames: Vec::new(),
+            index: Index {
+                track: track.number,
+                segment_offset: segment.offset,
+                segment_end: segment_end,
+                next: first_cluster,
+                has_cues: false,
+                cluster_offsets: Vec::new(),
+                cue_frames: HashSet::new(),
+                positions: HashMap::new(),
+            },
+        };
+        reader.set_cues(&cues);
+        try!(reader.index_frames(0));
+        Ok(reader)
+    }
+
+    /// Cue points are seek points, i.e. decoding can start from there. This
+    /// is the only source of keyframe info for BlockGroup-only files with
+    /// broken references.
+    fn set_cues(&mut self, cues: &[CuePoint]) {
+        let index = &mut self.index;
+        let (segment_offset, track) = (index.segment_offset, index.track);
+        index.has_cues = !cues.is_empty();
+        index.cluster_offsets = cues.iter()
+                                    .filter_map(|cue| segment_offset.checked_add(cue.cluster_pos))
+                                    .collect();
+        index.cluster_offsets.sort();
+        index.cluster_offsets.dedup();
+        for cue in cues.iter().filter(|cue| cue.track == track) {
+            let key = (segment_offset.wrapping_add(cue.cluster_pos), cue.time as i64);
+            if let Some(&pos) = index.positions.get(&key) {
+                self.frames[pos].keyframe = Some(true);
+            }
+            index.cue_frames.insert(key);
+        }
+    }
+
+    fn add_frames(&mut self, raw_frames: &[RawFrame]) {
+        let track = self.index.track;
+        for raw in raw_frames.iter().filter(|raw| raw.track == track) {
+            let key = (raw.cluster, raw.pts);
+            self.index.positions.insert(key, self.frames.len());
+            self.frames.push(FrameEntry {
+                offset: raw.offset,
+                size: raw.size,
+                pts: if raw.pts < 0 { 0 } else { raw.pts as u64 },
+                keyframe: if self.index.cue_frames.contains(&key) {
+                    Some(true)
+                } else {
+                    raw.keyframe
+                },
+            });
+        }
+    }
+
+    /// Parse clusters until the frame at the given position is indexed or
+    /// there are no more of them. Indexing stops at the first error.
+    fn index_frames(&mut self, pos: usize) -> Result<(), Error> {
+        while self.frames.len() <= pos && self.index.next.is_some() {
+            if let Err(err) = self.index_element() {
+                self.index.next = None;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the next top-level element, collect frames if it's a cluster.
+    fn index_element(&mut self) -> Result<(), Error> {
+        let segment_end = self.index.segment_end;
+        let start = match self.index.next {
+            Some(start) if start < segment_end => start,
+            _ => {
+                self.index.next = None;
+                return Ok(());
+            },
+        };
+        try!(self.r.seek(start));
+        let el = match self.r.read_header() {
+            Ok(el) => el,
+            // Truncated file, use what we already have.
+            Err(_) => {
+                self.index.next = None;
+                return Ok(());
+            },
+        };
+        match el.id {
+            CUES_ID if !self.index.has_cues => {
+                let cues = parse_cues(&mut self.r, &el).unwrap_or_else(|_| Vec::new());
+                self.set_cues(&cues);
+            },
+            CLUSTER_ID => {
+                let mut raw_frames = Vec::new();
+                let res = parse_cluster(&mut self.r, &el, segment_end, &mut raw_frames);
+                self.add_frames(&raw_frames);
+                if let Err(err) = res {
+                    // Skip the rest of broken cluster up to the next indexed
+                    // one.
+                    let offsets = &self.index.cluster_offsets;
+                    match offsets.iter().find(|&&offset| offset > el.start) {
+                        Some(&offset) if offset < segment_end => self.index.next = Some(offset),
+                        _ => return Err(err),
+                    }
+                } else {
+                    self.index.next = Some(self.r.pos);
+                }
+                return Ok(());
+            },
+            _ => {},
+        }
+        match el.size {
+            Some(size) => self.index.next = Some(try!(checked_add(el.offset, size))),
+            None => return Err(Error::ParseError),
+        }
+        Ok(())
+    }
+
+    fn read_frame_data(&mut self, pos: usize) -> Result<Box<[u8]>, Error> {
+        try!(self.index_frames(pos));
+        let entry = match self.frames.get(pos) {
+            Some(entry) => *entry,
+            None => return Err(Error::FrameOutOfRange(pos)),
+        };
+        try!(self.r.seek(entry.offset));
+        self.r.read_bytes(entry.size as usize)
+    }
+}
+
+impl Demuxer for Reader {
+    fn get_filename(&self) -> &str { &self.filename }
+    fn get_format_name(&self) -> &'static str { "WebM" }
+    fn get_fourcc(&self) -> u32 { self.fourcc }
+    fn get_width(&self) -> u16 { self.width }
+    fn get_height(&self) -> u16 { self.height }
+    fn get_timebase(&self) -> (u32, u32) { self.timebase }
+    fn get_frames(&self) -> &[FrameEntry] { &self.frames }
+
+    fn read_frame(&mut self, pos: usize) -> Result<Box<[u8]>, common::Error> {
+        self.read_frame_data(pos).map_err(From::from)
+    }
+
+    fn index_until(&mut self, pos: usize) -> Result<(), common::Error> {
+        self.index_frames(pos).map_err(From::from)
+    }
+
+    fn is_indexed(&self) -> bool {
+        self.index.next.is_none()
+    }
+
+    fn get_transfer_characteristics(&self) -> Option<u8> {
+        self.transfer.and_then(|code| if code <= 0xff { Some(code as u8) } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::*;
+
+    fn reader(data: &[u8]) -> EbmlReader<io::Cursor<Vec<u8>>> {
+        EbmlReader {
+            breader: io::Cursor::new(data.to_vec()),
+            pos: 0,
+            file_size: data.len() as u64,
+        }
+    }
+
+    /// SimpleBlock with the given data, its size is written in 8 bytes.
+    fn parse(body: &[u8]) -> Result<Vec<RawFrame>, Error> {
+        let mut data = vec![0xa3, 0x01];
+        data.extend((0..7).rev().map(|i| (body.len() as u64 >> (i * 8)) as u8));
+        data.extend_from_slice(body);
+        let mut r = reader(&data);
+        let block = try!(r.read_header());
+        let cluster = Element {id: CLUSTER_ID, start: 0, offset: 0, size: None};
+        let mut frames = Vec::new();
+        try!(parse_block(&mut r, &block, &cluster, 100, &mut frames));
+        Ok(frames)
+    }
+
+    fn get_sizes(frames: &[RawFrame]) -> Vec<(u64, u32)> {
+        frames.iter().map(|frame| (frame.offset, frame.size)).collect()
+    }
+
+    fn assert_parse_error<T>(res: Result<T, Error>) {
+        match res {
+            Err(Error::ParseError) => {},
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("no error"),
+        }
+    }
+
+    #[test]
+    fn vints() {
+        assert_eq!(reader(&[0x81]).read_vint().unwrap(), (0x81, 1));
+        assert_eq!(reader(&[0x81]).read_vint_value().unwrap(), (1, 1));
+        assert_eq!(reader(&[0x40, 0x02]).read_vint_value().unwrap(), (2, 2));
+        assert_eq!(reader(&[0x1a, 0x45, 0xdf, 0xa3]).read_vint().unwrap(), (0x1a45dfa3, 4));
+        let data = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+        assert_eq!(reader(&data).read_vint_value().unwrap(), ((1 << 56) - 2, 8));
+        assert_parse_error(reader(&[0x00, 0x81]).read_vint());
+        assert_parse_error(reader(&[0x40]).read_vint());
+    }
+
+    #[test]
+    fn headers() {
+        let mut r = reader(&[0x1a, 0x45, 0xdf, 0xa3, 0x84, 0x42, 0x82, 0x81, 0x00]);
+        let el = r.read_header().unwrap();
+        assert_eq!((el.id, el.start, el.offset, el.size), (EBML_ID, 0, 5, Some(4)));
+        assert_eq!(el.end().unwrap(), 9);
+        let el = r.read_header().unwrap();
+        assert_eq!((el.id, el.start, el.offset, el.size), (DOC_TYPE_ID, 5, 8, Some(1)));
+        // All ones is unknown size.
+        let el = reader(&[0x1f, 0x43, 0xb6, 0x75, 0xff]).read_header().unwrap();
+        assert_eq!((el.id, el.size), (CLUSTER_ID, None));
+        assert_parse_error(el.end());
+        // IDs are at most 4 bytes long.
+        assert_parse_error(reader(&[0x08, 0, 0, 0, 0, 0x80]).read_header());
+    }
+
+    #[test]
+    fn no_lacing() {
+        let frames = parse(&[0x81, 0x00, 0x10, 0x80, 1, 2, 3]).unwrap();
+        assert_eq!(get_sizes(&frames), [(13, 3)]);
+        assert_eq!((frames[0].track, frames[0].pts, frames[0].keyframe), (1, 116, Some(true)));
+        let frames = parse(&[0x82, 0xff, 0xf0, 0x00, 1, 2, 3]).unwrap();
+        assert_eq!((frames[0].track, frames[0].pts, frames[0].keyframe), (2, 84, Some(false)));
+    }
+
+    #[test]
+    fn xiph_lacing() {
+        let mut body = vec![0x81, 0x00, 0x00, 0x82, 2, 2, 3];
+        body.extend_from_slice(&[0; 9]);
+        let frames = parse(&body).unwrap();
+        assert_eq!(get_sizes(&frames), [(16, 2), (18, 3), (21, 4)]);
+        // Only the first frame is marked.
+        let keyframes: Vec<_> = frames.iter().map(|frame| frame.keyframe).collect();
+        assert_eq!(keyframes, [Some(true), None, None]);
+        let mut body = vec![0x81, 0x00, 0x00, 0x02, 1, 255, 1];
+        body.extend_from_slice(&[0; 258]);
+        assert_eq!(get_sizes(&parse(&body).unwrap()), [(16, 256), (272, 2)]);
+        // Sizes exceed the block.
+        let mut body = vec![0x81, 0x00, 0x00, 0x02, 1, 255, 255, 255, 0];
+        body.extend_from_slice(&[0; 100]);
+        assert_parse_error(parse(&body));
+    }
+
+    #[test]
+    fn fixed_lacing() {
+        let mut body = vec![0x81, 0x00, 0x00, 0x84, 2];
+        body.extend_from_slice(&[0; 9]);
+        assert_eq!(get_sizes(&parse(&body).unwrap()), [(14, 3), (17, 3), (20, 3)]);
+        let mut body = vec![0x81, 0x00, 0x00, 0x84, 2];
+        body.extend_from_slice(&[0; 10]);
+        assert_parse_error(parse(&body));
+    }
+
+    #[test]
+    fn ebml_lacing() {
+        // Sizes 3, 3 + 2 and the rest.
+        let mut body = vec![0x81, 0x00, 0x00, 0x86, 2, 0x83, 0xc1];
+        body.extend_from_slice(&[0; 9]);
+        assert_eq!(get_sizes(&parse(&body).unwrap()), [(16, 3), (19, 5), (24, 1)]);
+        // Sizes 1, 1 - 2.
+        let mut body = vec![0x81, 0x00, 0x00, 0x86, 2, 0x81, 0xbd];
+        body.extend_from_slice(&[0; 9]);
+        assert_parse_error(parse(&body));
+    }
+
+    #[test]
+    fn ebml_lacing_overflow() {
+        // 255 frames of growing 8-byte sizes, their sum doesn't fit u64.
+        let max = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+        let mut body = vec![0x81, 0x00, 0x00, 0x06, 254];
+        for _ in 0..254 {
+            body.extend_from_slice(&max);
+        }
+        assert_parse_error(parse(&body));
+    }
+
+    /// Element with the data size written in 8 bytes.
+    fn element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut el: Vec<u8> = (0..4).rev().map(|i| (id >> (i * 8)) as u8)
+                                    .skip_while(|&b| b == 0).collect();
+        el.push(0x01);
+        el.extend((0..7).rev().map(|i| (data.len() as u64 >> (i * 8)) as u8));
+        el.extend_from_slice(data);
+        el
+    }
+
+    fn uint(id: u32, val: u64) -> Vec<u8> {
+        let data: Vec<u8> = (0..8).rev().map(|i| (val >> (i * 8)) as u8).collect();
+        element(id, &data)
+    }
+
+    fn track(number: u64, kind: u64, codec_id: &str) -> Vec<u8> {
+        let mut entry = uint(TRACK_NUMBER_ID, number);
+        entry.extend(uint(TRACK_TYPE_ID, kind));
+        entry.extend(element(CODEC_ID_ID, codec_id.as_bytes()));
+        let mut video = uint(PIXEL_WIDTH_ID, 64);
+        video.extend(uint(PIXEL_HEIGHT_ID, 48));
+        entry.extend(element(VIDEO_ID, &video));
+        element(TRACK_ENTRY_ID, &entry)
+    }
+
+    fn simple_block(track: u8, timecode: u8, flags: u8, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x80 | track, 0, timecode, flags];
+        body.extend_from_slice(data);
+        element(SIMPLE_BLOCK_ID, &body)
+    }
+
+    /// Three clusters with two video and one audio frame each and Cues of
+    /// the second cluster at the end.
+    fn open_file() -> Reader {
+        let mut segment = element(INFO_ID, &[]);
+        let mut tracks = track(1, TRACK_TYPE_VIDEO, "V_VP9");
+        tracks.extend(track(2, 2, "A_OPUS"));
+        segment.extend(element(TRACKS_ID, &tracks));
+        let mut cluster_positions = Vec::new();
+        for i in 0..3 {
+            let mut cluster = uint(TIMECODE_ID, i * 100);
+            let keyframe = if i == 0 { 0x80 } else { 0 };
+            cluster.extend(simple_block(1, 0, keyframe, &[i as u8, 0]));
+            cluster.extend(simple_block(2, 0, 0x80, &[0xaa]));
+            cluster.extend(simple_block(1, 50, 0, &[i as u8, 1]));
+            cluster_positions.push(segment.len() as u64);
+            segment.extend(element(CLUSTER_ID, &cluster));
+        }
+        let mut positions = uint(CUE_TRACK_ID, 1);
+        positions.extend(uint(CUE_CLUSTER_POSITION_ID, cluster_positions[1]));
+        let mut point = uint(CUE_TIME_ID, 100);
+        point.extend(element(CUE_TRACK_POSITIONS_ID, &positions));
+        segment.extend(element(CUES_ID, &element(CUE_POINT_ID, &point)));
+        let mut data = element(EBML_ID, &element(DOC_TYPE_ID, b"webm"));
+        data.extend(element(SEGMENT_ID, &segment));
+        let size = data.len() as u64;
+        Reader::from_source("test.webm", Box::new(io::Cursor::new(data)), size).unwrap()
+    }
+
+    #[test]
+    fn lazy_index() {
+        let mut r = open_file();
+        assert_eq!((r.get_width(), r.get_height()), (64, 48));
+        assert_eq!(r.get_timebase(), (1, 1000));
+        // Only the first cluster is parsed at open.
+        assert_eq!(r.get_frame_count(), 2);
+        assert!(!r.is_indexed());
+        r.index_until(2).unwrap();
+        assert_eq!(r.get_frame_count(), 4);
+        assert_eq!(&*r.read_frame(5).unwrap(), [2, 1]);
+        assert_eq!(r.get_frame_count(), 6);
+        assert!(!r.is_indexed());
+        // Cues at the end mark the already indexed frame.
+        assert_eq!(r.is_keyframe(2), Some(false));
+        r.index_all().unwrap();
+        assert!(r.is_indexed());
+        assert_eq!(r.get_frame_count(), 6);
+        let pts: Vec<_> = (0..6).filter_map(|pos| r.get_frame_pts(pos)).collect();
+        assert_eq!(pts, [0, 50, 100, 150, 200, 250]);
+        let keyframes: Vec<_> = (0..6).map(|pos| r.is_keyframe(pos)).collect();
+        assert_eq!(keyframes, [Some(true), Some(false), Some(true),
+                               Some(false), Some(false), Some(false)]);
+        assert_eq!(&*r.read_frame(0).unwrap(), [0, 0]);
+        match r.read_frame_data(6) {
+            Err(Error::FrameOutOfRange(6)) => {},
+            _ => panic!("frame out of range is read"),
+        }
+    }
+
+    #[test]
+    fn timebases() {
+        assert_eq!(get_timebase(1000000).unwrap(), (1, 1000));
+        assert_eq!(get_timebase(1).unwrap(), (1, 1000000000));
+        assert_eq!(get_timebase(1000000000 * 4).unwrap(), (4, 1));
+        assert_eq!(get_timebase(1000000000 * 5).unwrap(), (5, 1));
+        assert_parse_error(get_timebase(4294967311));
+        assert_parse_error(get_timebase(u64::max_value()));
+    }
+}
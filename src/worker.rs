@@ -0,0 +1,551 @@
+//! Background decoding. Worker thread owns decoder, decodes frames asked by
+//! the viewer, prefetches the following ones and keeps decoded frames in
+//! the memory-bounded cache.
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender, Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+use ::common;
+use ::demux::Demuxer;
+use ::superframe::{self, SubFrame};
+use ::vpx;
+use ::vp9;
+
+/// Position of the VPx frame: container frame and sub-frame in it.
+pub type FramePos = (usize, usize);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Memory budget of the decoded frames cache in bytes.
+    pub cache_size: usize,
+    /// Number of container frames to decode ahead of the shown one.
+    pub prefetch: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            cache_size: 512 << 20,
+            prefetch: 8,
+        }
+    }
+}
+
+/// Decoded VPx frame.
+pub struct Frame {
+    /// Position of the container frame.
+    pub pos: usize,
+    /// VPx frames of the container frame.
+    pub subframes: Vec<SubFrame>,
+    /// Position of this VPx frame.
+    pub subframe_pos: usize,
+    /// Image output by decoder, missing for hidden frames.
+    pub image: Option<vpx::OwnedImage>,
+    /// Headers of this VPx frame (VP9 only).
+    pub vp9_header: Option<vp9::FrameHeader>,
+}
+
+impl Frame {
+    fn get_memory_size(&self) -> usize {
+        let image_size = self.image.as_ref().map_or(0, |image| image.get_memory_size());
+        let blocks_size = match self.vp9_header {
+            Some(vp9::FrameHeader {blocks: Some(ref blocks), ..}) => blocks.get_memory_size(),
+            _ => 0,
+        };
+        image_size + blocks_size
+    }
+}
+
+pub enum Request {
+    /// Show VPx frame at the given position, the last one of the container
+    /// frame if sub-frame is not specified.
+    Seek(usize, Option<usize>),
+    /// Show the next VPx frame.
+    Next,
+    /// Show the previous VPx frame.
+    Prev,
+}
+
+pub enum Response {
+    /// Frame asked by the last request.
+    Frame(Arc<Frame>),
+    /// Cache contents have changed.
+    CacheStats(CacheStats),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub frames: usize,
+    pub size: usize,
+    pub budget: usize,
+}
+
+struct CacheEntry {
+    frame: Arc<Frame>,
+    size: usize,
+    last_used: u64,
+}
+
+/// Least recently used frames are evicted when the budget is exceeded.
+struct Cache {
+    entries: HashMap<FramePos, CacheEntry>,
+    /// Keys with their access time, from the oldest access. Stale records
+    /// (the frame was accessed again or evicted) are skipped on eviction.
+    order: VecDeque<(u64, FramePos)>,
+    size: usize,
+    budget: usize,
+    /// Incremented on every access.
+    clock: u64,
+}
+
+impl Cache {
+    fn new(budget: usize) -> Cache {
+        Cache {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            size: 0,
+            budget: budget,
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, pos: FramePos) -> Option<Arc<Frame>> {
+        if !self.entries.contains_key(&pos) {
+            return None;
+        }
+        self.touch(pos);
+        Some(self.entries[&pos].frame.clone())
+    }
+
+    /// Mark the entry as the most recently used one.
+    fn touch(&mut self, pos: FramePos) {
+        self.clock += 1;
+        self.entries.get_mut(&pos).unwrap().last_used = self.clock;
+        self.order.push_back((self.clock, pos));
+        // Don't let stale records pile up when the same frames are
+        // accessed over and over.
+        if self.order.len() > self.entries.len() * 2 {
+            let entries = &self.entries;
+            self.order.retain(|&(time, key)| {
+                entries.get(&key).map(|entry| entry.last_used) == Some(time)
+            });
+        }
+    }
+
+    /// Insert frame taking `size` bytes and evict old ones, the frame itself
+    /// is always kept.
+    fn insert(&mut self, pos: FramePos, frame: Arc<Frame>, size: usize) {
+        if let Some(old) = self.entries.insert(pos, CacheEntry {
+            frame: frame,
+            size: size,
+            last_used: 0,
+        }) {
+            self.size -= old.size;
+        }
+        self.size += size;
+        self.touch(pos);
+        // The inserted frame is the most recent one so another frame is
+        // always found before it.
+        while self.size > self.budget && self.entries.len() > 1 {
+            let (time, oldest) = self.order.pop_front().unwrap();
+            if self.entries.get(&oldest).map(|entry| entry.last_used) != Some(time) {
+                continue;
+            }
+            let entry = self.entries.remove(&oldest).unwrap();
+            self.size -= entry.size;
+        }
+    }
+
+    fn get_stats(&self) -> CacheStats {
+        CacheStats {
+            frames: self.entries.len(),
+            size: self.size,
+            budget: self.budget,
+        }
+    }
+}
+
+struct State {
+    reader: Box<Demuxer>,
+    decoder: vpx::Decoder,
+    /// Bitstream parser, fed with the same data as decoder (VP9 only).
+    vp9_parser: Option<vp9::Parser>,
+    /// Cached results of keyframe checks, filled on demand.
+    keyframes: Vec<Option<bool>>,
+    /// Number of VPx frames in every container frame, filled on demand.
+    subframe_counts: Vec<Option<usize>>,
+    /// Last VPx frame fed to decoder.
+    decoded: Option<FramePos>,
+    /// VPx frame asked by the viewer.
+    target: Option<FramePos>,
+    /// Whether the target frame was sent.
+    target_sent: bool,
+    cache: Cache,
+    prefetch: usize,
+    responses: Sender<Response>,
+}
+
+impl State {
+    fn run(&mut self, requests: Receiver<Request>) {
+        loop {
+            // Take all pending requests before doing any work so stepping
+            // is not slowed down by decoding intermediate frames.
+            let request = if self.has_work() {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return,
+                }
+            };
+            match request {
+                Some(request) => self.handle_request(request),
+                None => self.step(),
+            }
+        }
+    }
+
+    fn handle_request(&mut self, request: Request) {
+        if let Request::Seek(pos, _) = request {
+            self.index_until(pos);
+        }
+        let frame_count = self.reader.get_frame_count();
+        let target = match (request, self.target) {
+            (Request::Seek(pos, _), _) if pos >= frame_count => {
+                printerr!("No frame #{} in file", pos + 1);
+                None
+            },
+            (Request::Seek(pos, sub), _) => {
+                let count = self.get_subframe_count(pos);
+                Some((pos, sub.map_or(count - 1, |s| cmp::min(s, count - 1))))
+            },
+            (Request::Next, None) if frame_count == 0 => None,
+            (Request::Next, None) => Some((0, 0)),
+            (Request::Next, Some(cur)) => {
+                let next = self.get_next_pos(cur);
+                if next.is_none() {
+                    printerr!("End of file");
+                }
+                next
+            },
+            (Request::Prev, None) => None,
+            (Request::Prev, Some((pos, sub))) if sub > 0 => Some((pos, sub - 1)),
+            (Request::Prev, Some((pos, _))) if pos > 0 => {
+                Some((pos - 1, self.get_subframe_count(pos - 1) - 1))
+            },
+            (Request::Prev, Some(_)) => {
+                printerr!("Beginning of file");
+                None
+            },
+        };
+        if target.is_some() && target != self.target {
+            self.target = target;
+            self.target_sent = false;
+        }
+    }
+
+    fn has_work(&mut self) -> bool {
+        !self.target_sent || self.get_prefetch_pos().is_some()
+    }
+
+    /// Next frame to decode ahead of the target, if it's not too far.
+    fn get_prefetch_pos(&mut self) -> Option<FramePos> {
+        let (target, decoded) = match (self.target, self.decoded) {
+            (Some(target), Some(decoded)) if decoded >= target => (target, decoded),
+            _ => return None,
+        };
+        match self.get_next_pos(decoded) {
+            Some(next) if next.0 <= target.0 + self.prefetch => Some(next),
+            _ => None,
+        }
+    }
+
+    /// Decode one frame towards the target or ahead of it.
+    fn step(&mut self) {
+        if !self.target_sent {
+            let target = self.target.unwrap();
+            if let Some(frame) = self.cache.get(target) {
+                self.send_target(frame);
+                return;
+            }
+            // Keyframe closer to the target than the last decoded frame
+            // resets decoder so there is no need to decode frames up to it.
+            let decoded = self.decoded;
+            let pos = match decoded {
+                Some(decoded) if decoded < target && !self.has_keyframe(decoded.0, target.0) => {
+                    self.get_next_pos(decoded).unwrap()
+                },
+                _ => (self.find_keyframe(target.0), 0),
+            };
+            let frame = self.decode(pos);
+            if pos == target {
+                self.send_target(frame);
+            }
+        } else if let Some(pos) = self.get_prefetch_pos() {
+            // Frame may be cached already but decoder needs it anyway.
+            self.decode(pos);
+            let _ = self.responses.send(Response::CacheStats(self.cache.get_stats()));
+        }
+    }
+
+    fn send_target(&mut self, frame: Arc<Frame>) {
+        self.target_sent = true;
+        let _ = self.responses.send(Response::Frame(frame));
+        let _ = self.responses.send(Response::CacheStats(self.cache.get_stats()));
+    }
+
+    fn get_subframes(&mut self, pos: usize) -> Result<(Box<[u8]>, Vec<SubFrame>), common::Error> {
+        let data = try!(self.reader.read_frame(pos));
+        let subframes = if self.reader.get_fourcc() == common::VP9_FOURCC {
+            superframe::split(&data)
+        } else {
+            vec![SubFrame {offset: 0, size: data.len()}]
+        };
+        self.subframe_counts[pos] = Some(subframes.len());
+        Ok((data, subframes))
+    }
+
+    fn get_subframe_count(&mut self, pos: usize) -> usize {
+        if let Some(count) = self.subframe_counts[pos] {
+            return count;
+        }
+        let count = self.get_subframes(pos).map_or(1, |(_, subframes)| subframes.len());
+        self.subframe_counts[pos] = Some(count);
+        count
+    }
+
+    fn get_next_pos(&mut self, (pos, sub): FramePos) -> Option<FramePos> {
+        if sub + 1 < self.get_subframe_count(pos) {
+            return Some((pos, sub + 1));
+        }
+        self.index_until(pos + 1);
+        if pos + 1 < self.reader.get_frame_count() {
+            Some((pos + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    /// Index frames of the reader up to the given position, if file has
+    /// that many.
+    fn index_until(&mut self, pos: usize) {
+        if let Err(err) = self.reader.index_until(pos) {
+            printerr!("Cannot index frames: {}", err);
+        }
+        let count = self.reader.get_frame_count();
+        self.keyframes.resize(count, None);
+        self.subframe_counts.resize(count, None);
+    }
+
+    fn is_keyframe(&mut self, pos: usize) -> bool {
+        if let Some(is_kf) = self.keyframes[pos].or(self.reader.is_keyframe(pos)) {
+            return is_kf;
+        }
+        let is_kf = match self.reader.read_frame(pos) {
+            Ok(ref frame) if !frame.is_empty() => {
+                self.decoder.is_keyframe(frame).unwrap_or(false)
+            },
+            _ => false,
+        };
+        self.keyframes[pos] = Some(is_kf);
+        is_kf
+    }
+
+    /// Whether there is a keyframe after the first frame up to the last one
+    /// (inclusive).
+    fn has_keyframe(&mut self, first: usize, last: usize) -> bool {
+        (first + 1..last + 1).rev().any(|p| self.is_keyframe(p))
+    }
+
+    /// Find position of the nearest keyframe preceding the given frame
+    /// (inclusive). Fallback to the first frame if there is none.
+    fn find_keyframe(&mut self, pos: usize) -> usize {
+        (0..pos + 1).rev().find(|&p| self.is_keyframe(p)).unwrap_or(0)
+    }
+
+    /// Feed VPx frame to the parser and decoder and put the result into the
+    /// cache. Empty frames (unreadable ones included) have no image.
+    fn decode(&mut self, pos: FramePos) -> Arc<Frame> {
+        self.decoded = Some(pos);
+        let (data, subframes) = match self.get_subframes(pos.0) {
+            Ok(res) => res,
+            Err(err) => {
+                printerr!("Cannot read frame: {}", err);
+                (Box::new([]) as Box<[u8]>, vec![SubFrame {offset: 0, size: 0}])
+            },
+        };
+        let mut frame = Frame {
+            pos: pos.0,
+            subframe_pos: pos.1,
+            subframes: Vec::new(),
+            image: None,
+            vp9_header: None,
+        };
+        match subframes.get(pos.1).cloned() {
+            Some(subframe) if subframe.size > 0 => {
+                let data = &data[subframe.offset..subframe.offset + subframe.size];
+                if let Some(ref mut parser) = self.vp9_parser {
+                    match parser.parse_frame(data) {
+                        Ok(header) => frame.vp9_header = Some(header),
+                        Err(err) => printerr!("{}", err),
+                    }
+                }
+                match self.decoder.decode(data) {
+                    Ok(mut iter) => {
+                        frame.image = match iter.next().map(|image| image.to_owned()) {
+                            Some(Ok(image)) => Some(image),
+                            Some(Err(err)) => {
+                                printerr!("Cannot copy image: {}", err);
+                                None
+                            },
+                            None => None,
+                        };
+                    },
+                    Err(err) => printerr!("Cannot decode frame: {}", err),
+                }
+            },
+            // Nothing to decode, frame is shown as not having image.
+            _ => {},
+        }
+        frame.subframes = subframes;
+        let frame = Arc::new(frame);
+        let size = frame.get_memory_size();
+        self.cache.insert(pos, frame.clone(), size);
+        frame
+    }
+}
+
+/// Handle of the worker thread. Thread exits when handle is dropped.
+pub struct Worker {
+    requests: Option<Sender<Request>>,
+    responses: Receiver<Response>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Start the thread which decodes frames of the given reader. Reader
+    /// should be separate from the one used by the viewer.
+    pub fn spawn(reader: Box<Demuxer>, decoder: vpx::Decoder, config: Config) -> Worker {
+        let (request_tx, request_rx) = mpsc::channel();
+        let (response_tx, response_rx) = mpsc::channel();
+        let mut state = State {
+            vp9_parser: if reader.get_fourcc() == common::VP9_FOURCC {
+                Some(vp9::Parser::new())
+            } else {
+                None
+            },
+            keyframes: vec![None; reader.get_frame_count()],
+            subframe_counts: vec![None; reader.get_frame_count()],
+            reader: reader,
+            decoder: decoder,
+            decoded: None,
+            target: None,
+            target_sent: true,
+            cache: Cache::new(config.cache_size),
+            prefetch: config.prefetch,
+            responses: response_tx,
+        };
+        let thread = thread::spawn(move || state.run(request_rx));
+        Worker {
+            requests: Some(request_tx),
+            responses: response_rx,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn request(&self, request: Request) {
+        if let Some(ref requests) = self.requests {
+            let _ = requests.send(request);
+        }
+    }
+
+    /// Get the next response without blocking.
+    pub fn poll(&self) -> Option<Response> {
+        self.responses.try_recv().ok()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // Closed channel stops the worker.
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pos: usize) -> Arc<Frame> {
+        Arc::new(Frame {
+            pos: pos,
+            subframes: Vec::new(),
+            subframe_pos: 0,
+            image: None,
+            vp9_header: None,
+        })
+    }
+
+    fn cached(cache: &Cache) -> Vec<usize> {
+        let mut positions: Vec<_> = cache.entries.keys().map(|&(pos, _)| pos).collect();
+        positions.sort();
+        positions
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let mut cache = Cache::new(30);
+        for pos in 0..3 {
+            cache.insert((pos, 0), frame(pos), 10);
+        }
+        assert_eq!(cached(&cache), [0, 1, 2]);
+        assert_eq!(cache.get((0, 0)).unwrap().pos, 0);
+        cache.insert((3, 0), frame(3), 10);
+        assert_eq!(cached(&cache), [0, 2, 3]);
+        assert!(cache.get((1, 0)).is_none());
+        // Records of the repeated accesses are compacted.
+        for _ in 0..100 {
+            cache.get((2, 0));
+        }
+        assert!(cache.order.len() <= cache.entries.len() * 2);
+        cache.insert((4, 0), frame(4), 20);
+        assert_eq!(cached(&cache), [2, 4]);
+        assert_eq!(cache.get_stats().size, 30);
+    }
+
+    #[test]
+    fn reinsert() {
+        let mut cache = Cache::new(30);
+        cache.insert((0, 0), frame(0), 10);
+        cache.insert((0, 0), frame(0), 20);
+        let stats = cache.get_stats();
+        assert_eq!((stats.frames, stats.size), (1, 20));
+        cache.insert((1, 0), frame(1), 5);
+        assert_eq!(cache.get_stats().size, 25);
+        // Re-inserted frame is still the least recently used one.
+        cache.insert((2, 0), frame(2), 10);
+        assert_eq!(cached(&cache), [1, 2]);
+        assert_eq!(cache.get_stats().size, 15);
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut cache = Cache::new(30);
+        cache.insert((0, 0), frame(0), 10);
+        cache.insert((1, 0), frame(1), 100);
+        let stats = cache.get_stats();
+        assert_eq!((stats.frames, stats.size), (1, 100));
+        assert_eq!(cache.get((1, 0)).unwrap().pos, 1);
+        cache.insert((2, 0), frame(2), 10);
+        assert_eq!(cached(&cache), [2]);
+    }
+}